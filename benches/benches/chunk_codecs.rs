@@ -0,0 +1,77 @@
+//! Compares every [`ChunkCodec`] on the sphere chunk from the `compress_chunk_with_sphere_sdf` test: compression ratio and the
+//! time to compress and decompress, with [`Sd8`] and [`Sd16`] distances.
+//!
+//! The ratios are printed once before the timings, since criterion only measures time.
+
+use feldspar_map::{
+    chunk::ChunkData,
+    codec::ChunkCodec,
+    core::glam::IVec3,
+    core::ilattice::prelude::Extent,
+    palette::PaletteId8,
+    sdf::{Sd16, Sd8, SdfValue},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::mem;
+
+const CODECS: [(ChunkCodec, &str); 4] = [
+    (ChunkCodec::Lz4Frame, "lz4_frame"),
+    (ChunkCodec::Lz4Block, "lz4_block"),
+    (ChunkCodec::SdfPredictive, "sdf_predictive"),
+    (ChunkCodec::PaletteRle, "palette_rle"),
+];
+
+/// A sphere of radius 8 centered in the chunk, the same as in the `compress_chunk_with_sphere_sdf` test.
+fn sphere_chunk<S: SdfValue>() -> ChunkData<S, PaletteId8> {
+    let mut chunk = ChunkData::default();
+    let extent = Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(16));
+    let center = (extent.minimum + extent.least_upper_bound()) / 2;
+    for p in extent.iter3() {
+        let d = p.as_vec3a().distance(center.as_vec3a());
+        let palette_id = if d < 8.0 { 1 } else { 0 };
+        chunk.set_voxel(p, palette_id, S::from(d - 8.0));
+    }
+    chunk
+}
+
+fn report<S: SdfValue>(name: &str) {
+    let chunk = sphere_chunk::<S>();
+    let uncompressed = mem::size_of::<ChunkData<S, PaletteId8>>();
+    for (codec, codec_name) in CODECS {
+        let compressed = chunk.compress_with(codec).bytes.len();
+        println!(
+            "{}_{}: {} bytes compressed, ratio = {:.3}",
+            name,
+            codec_name,
+            compressed,
+            compressed as f32 / uncompressed as f32
+        );
+    }
+}
+
+fn bench_codecs<S: SdfValue>(c: &mut Criterion, name: &str) {
+    let chunk = sphere_chunk::<S>();
+    for (codec, codec_name) in CODECS {
+        let compressed = chunk.compress_with(codec);
+        c.bench_function(&format!("{}_{}_compress", name, codec_name), |b| {
+            b.iter(|| black_box(&chunk).compress_with(codec))
+        });
+        c.bench_function(&format!("{}_{}_decompress", name, codec_name), |b| {
+            b.iter(|| {
+                ChunkData::<S, PaletteId8>::from_compressed_bytes(black_box(&compressed.bytes))
+            })
+        });
+    }
+}
+
+fn chunk_codecs(c: &mut Criterion) {
+    report::<Sd8>("sd8");
+    report::<Sd16>("sd16");
+
+    bench_codecs::<Sd8>(c, "sd8");
+    bench_codecs::<Sd16>(c, "sd16");
+}
+
+criterion_group!(benches, chunk_codecs);
+criterion_main!(benches);
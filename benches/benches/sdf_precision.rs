@@ -0,0 +1,72 @@
+//! Compares chunks with [`Sd8`] and [`Sd16`] distances: uncompressed memory, LZ4 compression ratio, and the time to compress,
+//! decompress, and downsample.
+//!
+//! The ratios are printed once before the timings, since criterion only measures time.
+
+use feldspar_map::{
+    chunk::{ChunkData, CHUNK_SIZE},
+    core::glam::IVec3,
+    core::ilattice::prelude::Extent,
+    palette::PaletteId8,
+    sampling::OctantKernel,
+    sdf::{Sd16, Sd8, SdfValue},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::mem;
+
+/// Rolling hills with gentle slopes, where `Sd8` bands the most.
+fn hills_chunk<S: SdfValue>() -> ChunkData<S, PaletteId8> {
+    let mut chunk = ChunkData::default();
+    for p in Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(16)).iter3() {
+        let q = p.as_vec3a();
+        let height = 8.0 + 3.0 * (q.x / 9.0).sin() * (q.z / 13.0).cos();
+        let palette_id = if q.y < height - 2.0 { 1 } else { 2 };
+        chunk.set_voxel(p, palette_id, S::from(q.y - height));
+    }
+    chunk
+}
+
+fn report<S: SdfValue>(name: &str) {
+    let chunk = hills_chunk::<S>();
+    let uncompressed = mem::size_of::<ChunkData<S, PaletteId8>>();
+    let compressed = chunk.compress().bytes.len();
+    println!(
+        "{}: {} bytes uncompressed ({} per voxel), {} bytes compressed, ratio = {:.3}",
+        name,
+        uncompressed,
+        uncompressed / CHUNK_SIZE,
+        compressed,
+        compressed as f32 / uncompressed as f32
+    );
+}
+
+fn bench_layout<S: SdfValue>(c: &mut Criterion, name: &str) {
+    let chunk = hills_chunk::<S>();
+    let compressed = chunk.compress();
+
+    c.bench_function(&format!("{}_compress", name), |b| {
+        b.iter(|| black_box(&chunk).compress())
+    });
+    c.bench_function(&format!("{}_decompress", name), |b| {
+        b.iter(|| ChunkData::<S, PaletteId8>::from_compressed_bytes(black_box(&compressed.bytes)))
+    });
+    c.bench_function(&format!("{}_downsample", name), |b| {
+        let mut kernel = OctantKernel::new();
+        let mut parent = ChunkData::default();
+        b.iter(|| {
+            black_box(&chunk).downsample_into(&mut kernel, IVec3::ZERO, IVec3::ZERO, &mut parent)
+        })
+    });
+}
+
+fn sdf_precision(c: &mut Criterion) {
+    report::<Sd8>("sd8");
+    report::<Sd16>("sd16");
+
+    bench_layout::<Sd8>(c, "sd8");
+    bench_layout::<Sd16>(c, "sd16");
+}
+
+criterion_group!(benches, sdf_precision);
+criterion_main!(benches);
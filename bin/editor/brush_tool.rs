@@ -0,0 +1,139 @@
+use crate::map_prompt::MapPrompt;
+use crate::{EditorCamera, EditorState};
+
+use bevy::prelude::*;
+use feldspar_map::brush::{Brush, BrushShape};
+use feldspar_map::chunk::{first_solid_voxel_on_ray, Chunk};
+use feldspar_map::clipmap::{ChunkClipMap, LoadedChunk, NodeKey};
+use feldspar_map::core::geometry::Ray;
+use feldspar_map::core::glam::Vec3A;
+use feldspar_map::csg::CsgOp;
+use feldspar_map::database::{Change, ChangeEncoder, ChunkDbKey, WriteBehindMapDb};
+use feldspar_map::units::{ChunkUnits, VoxelUnits};
+use feldspar_map::MapConfig;
+use std::sync::Arc;
+
+/// How far away from the camera the surface can be edited.
+const MAX_EDIT_DISTANCE: f32 = 256.0;
+/// Where the brush goes when there is no surface under the crosshair, so empty maps can be edited.
+const DEFAULT_BRUSH_DISTANCE: f32 = 16.0;
+const MIN_BRUSH_RADIUS: f32 = 1.0;
+const MAX_BRUSH_RADIUS: f32 = 64.0;
+
+const PALETTE_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+pub fn brush_settings_system(
+    keys: Res<Input<KeyCode>>,
+    prompt: Res<MapPrompt>,
+    mut state: ResMut<EditorState>,
+) {
+    if prompt.is_open() {
+        return;
+    }
+    for (id, key) in (1..).zip(PALETTE_KEYS) {
+        if keys.just_pressed(key) {
+            state.palette_id = id;
+        }
+    }
+
+    let radius = match state.brush_shape {
+        BrushShape::Sphere { radius } => radius,
+        BrushShape::Cube { half_edge_length } => half_edge_length,
+    };
+    let mut new_radius = radius;
+    if keys.just_pressed(KeyCode::LBracket) {
+        new_radius = (radius / 2.0).max(MIN_BRUSH_RADIUS);
+    }
+    if keys.just_pressed(KeyCode::RBracket) {
+        new_radius = (radius * 2.0).min(MAX_BRUSH_RADIUS);
+    }
+    let sphere = matches!(state.brush_shape, BrushShape::Sphere { .. });
+    let toggle = keys.just_pressed(KeyCode::Tab);
+    state.brush_shape = if sphere != toggle {
+        BrushShape::Sphere { radius: new_radius }
+    } else {
+        BrushShape::Cube {
+            half_edge_length: new_radius,
+        }
+    };
+}
+
+/// Applies the brush where the camera is aiming, at every level of detail, and writes the edited chunks to both the database
+/// and the clipmap.
+pub fn brush_system(
+    mouse: Res<Input<MouseButton>>,
+    config: Res<MapConfig>,
+    prompt: Res<MapPrompt>,
+    db: Res<Arc<WriteBehindMapDb>>,
+    cameras: Query<&Transform, With<EditorCamera>>,
+    mut clipmap: ResMut<ChunkClipMap>,
+    mut state: ResMut<EditorState>,
+) {
+    let csg_op = if mouse.just_pressed(MouseButton::Left) {
+        CsgOp::Union
+    } else if mouse.just_pressed(MouseButton::Right) {
+        CsgOp::Subtract
+    } else {
+        return;
+    };
+    if prompt.is_open() {
+        return;
+    }
+    let camera = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let ray = Ray::new(
+        Vec3A::from(camera.translation.to_array()),
+        Vec3A::from(camera.forward().to_array()),
+    );
+    let hit = first_solid_voxel_on_ray(&ray, MAX_EDIT_DISTANCE, |ChunkUnits(coords)| {
+        read_chunk(&db, ChunkDbKey::new(0, coords.into()))
+    });
+    let center = match (hit, csg_op) {
+        (Some((_, voxel)), _) => voxel.as_vec3a(),
+        (None, CsgOp::Union) => ray.position_at(DEFAULT_BRUSH_DISTANCE),
+        // Nothing to carve.
+        (None, _) => return,
+    };
+
+    let brush = Brush {
+        shape: state.brush_shape,
+        center: VoxelUnits(center),
+        palette_id: state.palette_id,
+    };
+    let codec = db.db().chunk_codec();
+    let mut encoder = ChangeEncoder::default();
+    for level in 0..config.num_lods {
+        for (ChunkUnits(coords), brush_chunk) in brush.voxelize(level).into_iter() {
+            let key = ChunkDbKey::new(level, coords.into());
+            let mut chunk = read_chunk(&db, key).unwrap_or_default();
+            csg_op.apply(&mut chunk, &brush_chunk);
+            let compressed = chunk.compress_with(codec);
+            let loaded = LoadedChunk::new(&chunk, compressed.clone());
+            clipmap.write_chunk(NodeKey::new(level, coords), Some(loaded));
+            encoder.add_compressed_change(key, Change::Insert(compressed));
+        }
+    }
+    db.write(encoder.encode());
+    state.redo_stack.clear();
+}
+
+/// Reads through the pending edits, so consecutive strokes build on each other before they are committed.
+fn read_chunk(db: &WriteBehindMapDb, key: ChunkDbKey) -> Option<Chunk> {
+    db.read_working_chunk_as(key).unwrap_or_else(|e| {
+        warn!("Failed to read chunk {:?}: {:?}", key, e);
+        None
+    })
+}
@@ -0,0 +1,84 @@
+use crate::map_prompt::MapPrompt;
+use crate::EditorState;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use feldspar_map::database::WriteBehindMapDb;
+use feldspar_map::ReloadMap;
+use std::sync::Arc;
+
+enum HistoryAction {
+    Commit,
+    Undo,
+    Redo,
+}
+
+/// Commits, undoes and redoes versions of the map. Undo moves the working version back to the grandparent of the latest commit,
+/// so the undone version stays in the version graph and can be redone.
+pub fn history_system(
+    keys: Res<Input<KeyCode>>,
+    prompt: Res<MapPrompt>,
+    db: Res<Arc<WriteBehindMapDb>>,
+    mut state: ResMut<EditorState>,
+    mut reload: EventWriter<ReloadMap>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if !ctrl || shift || prompt.is_open() {
+        return;
+    }
+    let action = if keys.just_pressed(KeyCode::S) {
+        HistoryAction::Commit
+    } else if keys.just_pressed(KeyCode::Z) {
+        HistoryAction::Undo
+    } else if keys.just_pressed(KeyCode::Y) {
+        HistoryAction::Redo
+    } else {
+        return;
+    };
+
+    let mut map = match db.flushed_db() {
+        Ok(map) => map,
+        Err(e) => {
+            error!("Failed to write edits: {:?}", e);
+            return;
+        }
+    };
+    let result = match action {
+        HistoryAction::Commit => map.commit_working_version(),
+        HistoryAction::Undo => map.commit_working_version().and_then(|()| {
+            let meta = *map.cached_meta();
+            match (meta.parent_version, meta.grandparent_version) {
+                (Some(parent), Some(grandparent)) => {
+                    map.branch_from_version(grandparent)?;
+                    state.redo_stack.push(parent);
+                    reload.send(ReloadMap);
+                }
+                _ => info!("Nothing to undo"),
+            }
+            Ok(())
+        }),
+        HistoryAction::Redo => match state.redo_stack.pop() {
+            Some(version) => map.branch_from_version(version).map(|()| {
+                reload.send(ReloadMap);
+            }),
+            None => {
+                info!("Nothing to redo");
+                Ok(())
+            }
+        },
+    };
+    if let Err(e) = result {
+        error!("Failed to change versions: {:?}", e);
+    }
+}
+
+/// Bevy exits the process without dropping resources, so pending edits are flushed here.
+pub fn flush_on_exit_system(mut exit: EventReader<AppExit>, db: Res<Arc<WriteBehindMapDb>>) {
+    if exit.iter().count() == 0 {
+        return;
+    }
+    if let Err(e) = db.flush() {
+        error!("Failed to write edits: {:?}", e);
+    }
+}
@@ -1 +1,174 @@
-fn main() {}
+//! A map editor built on the [`MapPlugin`] and [`RenderPlugin`].
+//!
+//! ```text
+//! editor [db_path] [map_name]
+//! ```
+//!
+//! Opens (or creates) `map_name` in the sled database at `db_path`.
+//!
+//! # Controls
+//!
+//! - WASD and mouse: fly the camera
+//! - Left click: add the brush at the surface under the crosshair
+//! - Right click: carve the brush out of the surface under the crosshair
+//! - Tab: switch between sphere and cube brushes
+//! - `[` and `]`: shrink and grow the brush
+//! - 1 through 9: select the palette ID of added voxels
+//! - Ctrl+S: commit the working version
+//! - Ctrl+Z and Ctrl+Y: undo and redo commits
+//! - Ctrl+Shift+S: save a copy of the map under a new name, and continue editing the copy
+//! - Ctrl+O: open another map by name
+//!
+//! The current state of the editor is shown in the window title.
+
+mod brush_tool;
+mod history;
+mod map_prompt;
+
+use brush_tool::{brush_settings_system, brush_system};
+use history::{flush_on_exit_system, history_system};
+use map_prompt::{map_prompt_system, MapPrompt};
+
+use bevy::{
+    prelude::*,
+    render::{settings::WgpuFeatures, settings::WgpuSettings},
+};
+use feldspar_map::brush::BrushShape;
+use feldspar_map::chunk::ChunkLayout;
+use feldspar_map::database::{Version, WriteBehindMapDb};
+use feldspar_map::palette::PaletteId8;
+use feldspar_map::{MapConfig, MapPlugin, Witness};
+use feldspar_renderer::{RenderConfig, RenderPlugin};
+use smooth_bevy_cameras::{
+    controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
+    LookTransformPlugin,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The tool settings and undo history of the editor.
+pub struct EditorState {
+    pub brush_shape: BrushShape,
+    pub palette_id: PaletteId8,
+    /// Versions that were undone, most recent last. Cleared by new edits.
+    pub redo_stack: Vec<Version>,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self {
+            brush_shape: BrushShape::Sphere { radius: 4.0 },
+            palette_id: 1,
+            redo_stack: Vec::new(),
+        }
+    }
+}
+
+/// The camera that edits are aimed with.
+#[derive(Component)]
+pub struct EditorCamera;
+
+fn main() {
+    let mut map_config = MapConfig::default();
+    let mut args = std::env::args().skip(1);
+    if let Some(db_path) = args.next() {
+        map_config.db_path = PathBuf::from(db_path);
+    }
+    if let Some(map_name) = args.next() {
+        map_config.map_name = map_name;
+    }
+
+    let window_desc = WindowDescriptor {
+        width: 1600.0,
+        height: 900.0,
+        title: "Feldspar Map Editor".to_string(),
+        ..Default::default()
+    };
+    App::new()
+        // Bevy
+        .insert_resource(window_desc)
+        .insert_resource(WgpuSettings {
+            features: WgpuFeatures::POLYGON_MODE_LINE,
+            ..Default::default()
+        })
+        .add_plugins(DefaultPlugins)
+        // Feldspar
+        .add_plugin(MapPlugin::new(map_config))
+        .add_plugin(RenderPlugin::new(RenderConfig {
+            wireframes: true,
+            ..Default::default()
+        }))
+        // Editor
+        .add_plugin(LookTransformPlugin)
+        .add_plugin(FpsCameraPlugin::default())
+        .init_resource::<EditorState>()
+        .init_resource::<MapPrompt>()
+        .add_startup_system(setup)
+        .add_system(map_prompt_system)
+        .add_system(brush_settings_system.after(map_prompt_system))
+        .add_system(brush_system.after(map_prompt_system))
+        .add_system(history_system.after(map_prompt_system))
+        .add_system(title_system)
+        .add_system_to_stage(CoreStage::Last, flush_on_exit_system)
+        .run();
+}
+
+fn setup(mut commands: Commands, db: Res<Arc<WriteBehindMapDb>>) {
+    // The map plugin also loads maps with 16-bit signed distances, but the brush writes chunks in the default layout.
+    assert_eq!(
+        db.db().chunk_layout(),
+        ChunkLayout::Palette8,
+        "The editor only supports maps with the default chunk layout"
+    );
+
+    commands.spawn_bundle(PointLightBundle {
+        transform: Transform::from_translation(Vec3::new(25.0, 25.0, 25.0)),
+        point_light: PointLight {
+            range: 200.0,
+            intensity: 8000.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let eye = Vec3::new(50.0, 15.0, 50.0);
+    let target = Vec3::new(0.0, 0.0, 0.0);
+    commands
+        .spawn_bundle(FpsCameraBundle::new(
+            FpsCameraController::default(),
+            eye,
+            target,
+        ))
+        .insert(EditorCamera)
+        .insert(Witness::default());
+}
+
+fn title_system(
+    config: Res<MapConfig>,
+    state: Res<EditorState>,
+    prompt: Res<MapPrompt>,
+    db: Res<Arc<WriteBehindMapDb>>,
+    mut windows: ResMut<Windows>,
+    mut last_title: Local<String>,
+) {
+    let title = match prompt.as_ref() {
+        MapPrompt::Closed => {
+            let brush = match state.brush_shape {
+                BrushShape::Sphere { radius } => format!("sphere r={}", radius),
+                BrushShape::Cube { half_edge_length } => format!("cube r={}", half_edge_length),
+            };
+            let working_version = db.db().cached_meta().working_version;
+            format!(
+                "Feldspar Map Editor - {} v{} - {} - palette {}",
+                config.map_name, working_version.number, brush, state.palette_id
+            )
+        }
+        MapPrompt::SaveAs(name) => format!("Save map as: {}_", name),
+        MapPrompt::Open(name) => format!("Open map: {}_", name),
+    };
+    if *last_title != title {
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_title(title.clone());
+        }
+        *last_title = title;
+    }
+}
@@ -0,0 +1,137 @@
+use crate::EditorState;
+
+use bevy::prelude::*;
+use feldspar_map::chunk::ChunkLayout;
+use feldspar_map::database::{MapCatalog, MapDb, WriteBehindMapDb};
+use feldspar_map::{sled, MapConfig, ReloadMap};
+use smooth_bevy_cameras::controllers::fps::FpsCameraController;
+use std::sync::Arc;
+
+/// A prompt for the name of a map, typed while the camera is disabled.
+pub enum MapPrompt {
+    Closed,
+    /// Copy the current map to a new map with this name, then open it.
+    SaveAs(String),
+    Open(String),
+}
+
+impl Default for MapPrompt {
+    fn default() -> Self {
+        Self::Closed
+    }
+}
+
+impl MapPrompt {
+    pub fn is_open(&self) -> bool {
+        !matches!(self, Self::Closed)
+    }
+
+    fn name_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Self::Closed => None,
+            Self::SaveAs(name) | Self::Open(name) => Some(name),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn map_prompt_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    store: Res<sled::Db>,
+    db: Res<Arc<WriteBehindMapDb>>,
+    mut prompt: ResMut<MapPrompt>,
+    mut config: ResMut<MapConfig>,
+    mut state: ResMut<EditorState>,
+    mut reload: EventWriter<ReloadMap>,
+    mut cameras: Query<&mut FpsCameraController>,
+) {
+    let typed: Vec<char> = chars.iter().map(|c| c.char).collect();
+
+    if !prompt.is_open() {
+        let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+        let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+        if ctrl && shift && keys.just_pressed(KeyCode::S) {
+            *prompt = MapPrompt::SaveAs(String::new());
+        } else if ctrl && keys.just_pressed(KeyCode::O) {
+            *prompt = MapPrompt::Open(String::new());
+        } else {
+            return;
+        }
+        set_cameras_enabled(&mut cameras, false);
+        // The characters of the shortcut are not part of the name.
+        return;
+    }
+
+    let name = prompt.name_mut().unwrap();
+    for c in typed {
+        // Map names become tree names, so keep them simple.
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            name.push(c);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        name.pop();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        *prompt = MapPrompt::Closed;
+        set_cameras_enabled(&mut cameras, true);
+        return;
+    }
+    if !keys.just_pressed(KeyCode::Return) || name.is_empty() {
+        return;
+    }
+
+    let name = match std::mem::take(&mut *prompt) {
+        MapPrompt::SaveAs(name) => match save_as(&db, &store, &config.map_name, &name) {
+            Ok(()) => name,
+            Err(e) => {
+                error!("Failed to save map as {}: {}", name, e);
+                return;
+            }
+        },
+        MapPrompt::Open(name) => name,
+        MapPrompt::Closed => unreachable!(),
+    };
+    set_cameras_enabled(&mut cameras, true);
+    if name == config.map_name {
+        return;
+    }
+
+    // Opening creates the map if it doesn't exist. Like the map plugin, the editor only supports the default layout.
+    let map = match MapDb::open_with_layout(&*store, &name, ChunkLayout::Palette8) {
+        Ok(map) => map,
+        Err(e) => {
+            error!("Failed to open map {}: {:?}", name, e);
+            return;
+        }
+    };
+    info!("Opened map {}", name);
+    // Replacing the resource drops the old map, which writes its pending edits.
+    commands.insert_resource(Arc::new(WriteBehindMapDb::new(map, config.write_behind)));
+    config.map_name = name;
+    state.redo_stack.clear();
+    reload.send(ReloadMap);
+}
+
+/// Commits the working version of `map_name` and copies the map to `new_name`.
+fn save_as(
+    db: &WriteBehindMapDb,
+    store: &sled::Db,
+    map_name: &str,
+    new_name: &str,
+) -> Result<(), String> {
+    db.flushed_db()
+        .and_then(|mut map| map.commit_working_version())
+        .map_err(|e| format!("{:?}", e))?;
+    MapCatalog::new(store)
+        .clone_map(map_name, new_name)
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn set_cameras_enabled(cameras: &mut Query<&mut FpsCameraController>, enabled: bool) {
+    for mut controller in cameras.iter_mut() {
+        controller.enabled = enabled;
+    }
+}
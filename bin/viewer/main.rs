@@ -1,34 +1,98 @@
+//! A map viewer built on the [`MapPlugin`] and [`RenderPlugin`].
+//!
+//! ```text
+//! viewer [--config <config.ron>] [--db <db_path>] [--map <map_name>] [--checkout <version>]
+//! ```
+//!
+//! The config file has the schema of [`ViewerConfig`] (see `example_config.ron`), and any field that it leaves out gets the
+//! default value. The other arguments override the corresponding fields of the config file. Only `--checkout` changes the
+//! stored map; see [`MapConfig::checkout_version`].
+
 use bevy::{
-    pbr::wireframe::{WireframeConfig, WireframePlugin},
     prelude::*,
-    render::{settings::WgpuSettings, settings::WgpuFeatures},
+    render::{settings::WgpuFeatures, settings::WgpuSettings},
 };
-use feldspar_map::MapPlugin;
+use feldspar_map::{MapConfig, MapPlugin};
+use feldspar_renderer::{RenderConfig, RenderPlugin};
+use serde::Deserialize;
 use smooth_bevy_cameras::{
     controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
     LookTransformPlugin,
 };
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str =
+    "Usage: viewer [--config <config.ron>] [--db <db_path>] [--map <map_name>] [--checkout <version>]";
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct ViewerConfig {
+    pub map: MapConfig,
+    pub render: RenderConfig,
+}
+
+impl ViewerConfig {
+    fn from_args() -> Self {
+        let mut config_path = None;
+        let mut db_path = None;
+        let mut map_name = None;
+        let mut checkout_version = None;
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config_path = Some(parse_arg::<PathBuf>(args.next())),
+                "--db" => db_path = Some(parse_arg::<PathBuf>(args.next())),
+                "--map" => map_name = Some(parse_arg::<String>(args.next())),
+                "--checkout" => checkout_version = Some(parse_arg::<u64>(args.next())),
+                _ => usage(),
+            }
+        }
+
+        let mut config = config_path.map_or_else(Self::default, |path| {
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                eprintln!("Failed to read {:?}: {}", path, e);
+                exit(2);
+            });
+            ron::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("Failed to parse {:?}: {}", path, e);
+                exit(2);
+            })
+        });
+        if let Some(db_path) = db_path {
+            config.map.db_path = db_path;
+        }
+        if let Some(map_name) = map_name {
+            config.map.map_name = map_name;
+        }
+        if checkout_version.is_some() {
+            config.map.checkout_version = checkout_version;
+        }
+        config
+    }
+}
 
 fn main() {
+    let ViewerConfig { map, render } = ViewerConfig::from_args();
+
     let window_desc = WindowDescriptor {
         width: 1600.0,
         height: 900.0,
         title: "Feldspar Map Viewer".to_string(),
         ..Default::default()
     };
+    let mut wgpu_settings = WgpuSettings::default();
+    if render.wireframes {
+        wgpu_settings.features |= WgpuFeatures::POLYGON_MODE_LINE;
+    }
     App::new()
         // Bevy
         .insert_resource(window_desc)
-        .insert_resource(WgpuSettings {
-            features: WgpuFeatures::POLYGON_MODE_LINE,
-            ..Default::default()
-        })
-        .insert_resource(Msaa { samples: 4 })
+        .insert_resource(wgpu_settings)
         .add_plugins(DefaultPlugins)
-        .add_plugin(WireframePlugin)
         // Feldspar
-        .add_plugin(MapPlugin::default())
-        // .add_plugin(RenderPlugin)
+        .add_plugin(MapPlugin::new(map))
+        .add_plugin(RenderPlugin::new(render))
         // Viewer
         .add_plugin(LookTransformPlugin)
         .add_plugin(FpsCameraPlugin::default())
@@ -36,9 +100,7 @@ fn main() {
         .run();
 }
 
-fn setup(mut commands: Commands, mut wireframe_config: ResMut<WireframeConfig>) {
-    wireframe_config.global = true;
-
+fn setup(mut commands: Commands) {
     commands.spawn_bundle(PointLightBundle {
         transform: Transform::from_translation(Vec3::new(25.0, 25.0, 25.0)),
         point_light: PointLight {
@@ -56,3 +118,42 @@ fn setup(mut commands: Commands, mut wireframe_config: ResMut<WireframeConfig>)
         target,
     ));
 }
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_config_parses() {
+        let config: ViewerConfig = ron::from_str(include_str!("../../example_config.ron")).unwrap();
+        assert_eq!(config.map.map_name, "main");
+        assert_eq!(config.map.checkout_version, None);
+        assert!(config.render.wireframes);
+    }
+
+    #[test]
+    fn missing_fields_get_defaults() {
+        let config: ViewerConfig =
+            ron::from_str("(map: (num_lods: 4, streaming: (detail: (8.0))))").unwrap();
+        assert_eq!(config.map.num_lods, 4);
+        assert_eq!(config.map.streaming.detail.0, 8.0);
+        assert_eq!(config.map.db_path, PathBuf::from("tmp"));
+        assert_eq!(config.render.msaa, Some(4));
+    }
+}
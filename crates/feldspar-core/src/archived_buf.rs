@@ -1,13 +1,21 @@
-use rkyv::{archived_root, Archive, Archived, Deserialize, Infallible};
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{
+    archived_root, check_archived_root, AlignedVec, Archive, Archived, CheckBytes, Deserialize,
+    Infallible,
+};
+use std::fmt;
 use std::marker::PhantomData;
+use std::sync::OnceLock;
 
 /// A wrapper around a byte buffer `B` that denotes the bytes represent an [`Archived<T>`].
 ///
 /// Note: This is not intended for use with archived structures that utilize shared memory like `ArchivedRc` and
 /// `ArchivedArc`.
-#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ArchivedBuf<T, B> {
     bytes: B,
+    /// A copy of `bytes` that is read instead whenever `bytes` are not aligned. Alignment is checked on every read, because
+    /// the address of `bytes` can change when they are stored inline and the buffer is moved or cloned.
+    aligned: OnceLock<AlignedVec>,
     marker: PhantomData<T>,
 }
 
@@ -23,10 +31,32 @@ where
     pub unsafe fn new(bytes: B) -> Self {
         Self {
             bytes,
+            aligned: OnceLock::new(),
             marker: PhantomData,
         }
     }
 
+    /// Validates that `bytes` represent an [`Archived<T>`], so that untrusted or possibly corrupted bytes can be read without
+    /// undefined behavior.
+    ///
+    /// Bytes are always validated and read at an address that is as aligned as an [`AlignedVec`]. Bytes that are less
+    /// aligned are copied into one, and the copy is what gets validated and read. [`ArchivedBuf::as_bytes`] and
+    /// [`ArchivedBuf::take_bytes`] still return the original buffer.
+    pub fn try_new(bytes: B) -> Result<Self, InvalidArchive>
+    where
+        T::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+    {
+        let buf = Self {
+            bytes,
+            aligned: OnceLock::new(),
+            marker: PhantomData,
+        };
+        if check_archived_root::<T>(buf.aligned_bytes()).is_err() {
+            return Err(InvalidArchive);
+        }
+        Ok(buf)
+    }
+
     pub fn deserialize(&self) -> T
     where
         T::Archived: Deserialize<T, Infallible>,
@@ -41,14 +71,144 @@ where
     pub fn take_bytes(self) -> B {
         self.bytes
     }
+
+    /// The bytes at their current address if that is aligned, otherwise an aligned copy of them.
+    fn aligned_bytes(&self) -> &[u8] {
+        let bytes = self.bytes.as_ref();
+        if (bytes.as_ptr() as usize).is_multiple_of(AlignedVec::ALIGNMENT) {
+            bytes
+        } else {
+            self.aligned.get_or_init(|| aligned_copy(bytes)).as_slice()
+        }
+    }
 }
 
+/// Returned by [`ArchivedBuf::try_new`] when the bytes are not a valid archive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidArchive;
+
 impl<T, B> AsRef<Archived<T>> for ArchivedBuf<T, B>
 where
     T: Archive,
     B: AsRef<[u8]>,
 {
     fn as_ref(&self) -> &Archived<T> {
-        unsafe { archived_root::<T>(self.bytes.as_ref()) }
+        unsafe { archived_root::<T>(self.aligned_bytes()) }
+    }
+}
+
+/// The clone checks the alignment of its own bytes when it is read, since they may live at a different address.
+impl<T, B: Clone> Clone for ArchivedBuf<T, B> {
+    fn clone(&self) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            aligned: OnceLock::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B: fmt::Debug> fmt::Debug for ArchivedBuf<T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchivedBuf")
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+/// Buffers are equal if they hold the same bytes, regardless of where the bytes are read from.
+impl<T, B: PartialEq> PartialEq for ArchivedBuf<T, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl<T, B: Eq> Eq for ArchivedBuf<T, B> {}
+
+fn aligned_copy(bytes: &[u8]) -> AlignedVec {
+    let mut aligned = AlignedVec::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+    aligned
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_new_rejects_invalid_bytes() {
+        let bytes = rkyv::to_bytes::<_, 64>(&vec![1u32, 2, 3]).unwrap();
+        let buf = ArchivedBuf::<Vec<u32>, _>::try_new(bytes.as_slice()).unwrap();
+        assert_eq!(buf.deserialize(), vec![1, 2, 3]);
+
+        // Misaligned bytes are read from an aligned copy.
+        let mut shifted = vec![0; bytes.len() + 1];
+        shifted[1..].copy_from_slice(&bytes);
+        let buf = ArchivedBuf::<Vec<u32>, _>::try_new(&shifted[1..]).unwrap();
+        assert_eq!(
+            buf.as_ref().as_ptr() as usize % std::mem::align_of::<u32>(),
+            0
+        );
+        assert_eq!(buf.as_ref().as_slice(), &[1, 2, 3]);
+        assert_eq!(buf.as_bytes(), bytes.as_slice());
+
+        // The relative pointer of the vector points past the end.
+        assert_eq!(
+            ArchivedBuf::<Vec<u32>, _>::try_new(&bytes[bytes.len() - 8..]),
+            Err(InvalidArchive)
+        );
+    }
+
+    type InlineBuf = ArchivedBuf<u128, [u8; 16]>;
+
+    /// Places `buf` at an offset of `8 * P` bytes from a 16-byte aligned address.
+    #[repr(C, align(16))]
+    struct Slot<const P: usize> {
+        _pad: [u64; P],
+        buf: InlineBuf,
+    }
+
+    impl<const P: usize> Slot<P> {
+        fn new(buf: InlineBuf) -> Box<Self> {
+            Box::new(Self { _pad: [0; P], buf })
+        }
+    }
+
+    fn assert_reads(buf: &InlineBuf, expected: u128) {
+        let archived: &u128 = buf.as_ref();
+        assert_eq!(
+            archived as *const u128 as usize % std::mem::align_of::<u128>(),
+            0
+        );
+        assert_eq!(*archived, expected);
+    }
+
+    #[test]
+    fn moved_and_cloned_inline_bytes_are_realigned() {
+        let value = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128;
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&rkyv::to_bytes::<_, 16>(&value).unwrap());
+
+        // The inline bytes move with the buffer, so one of these slots puts them at a misaligned address.
+        let odd = Slot::<1>::new(InlineBuf::try_new(bytes).unwrap());
+        let even = Slot::<2>::new(InlineBuf::try_new(bytes).unwrap());
+        assert!([&odd.buf, &even.buf]
+            .iter()
+            .any(|b| !(b.as_bytes().as_ptr() as usize).is_multiple_of(AlignedVec::ALIGNMENT)));
+        assert_reads(&odd.buf, value);
+        assert_reads(&even.buf, value);
+
+        // Clones are moved into the opposite slots.
+        let odd_clone = Slot::<2>::new(odd.buf.clone());
+        let even_clone = Slot::<1>::new(even.buf.clone());
+        assert_reads(&odd_clone.buf, value);
+        assert_reads(&even_clone.buf, value);
     }
 }
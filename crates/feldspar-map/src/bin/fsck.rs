@@ -0,0 +1,72 @@
+//! Checks the integrity of a map stored in a sled database.
+//!
+//! ```text
+//! feldspar-fsck <db_path> <map_name> [--repair]
+//! ```
+//!
+//! Repairing also drops the trees that unfinished map copies, renames and drops left behind in the database.
+//!
+//! Exits with status 1 if any problems remain.
+
+use feldspar_map::database::{MapCatalog, MapDb};
+
+use std::process::exit;
+
+fn main() {
+    let mut repair = false;
+    let mut positional = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            _ => positional.push(arg),
+        }
+    }
+    let (db_path, map_name) = match positional.as_slice() {
+        [db_path, map_name] => (db_path, map_name),
+        _ => {
+            eprintln!("Usage: feldspar-fsck <db_path> <map_name> [--repair]");
+            exit(2);
+        }
+    };
+
+    let db = sled::open(db_path).unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {:?}", db_path, e);
+        exit(2);
+    });
+    if repair {
+        let orphans = MapCatalog::new(&db)
+            .collect_orphans()
+            .expect("Dropping orphaned trees failed");
+        for tree_name in orphans.iter() {
+            println!("Dropped orphaned tree {}", tree_name);
+        }
+    }
+    let mut map = MapDb::open(&db, map_name).unwrap_or_else(|e| {
+        eprintln!("Failed to open map {}: {:?}", map_name, e);
+        exit(2);
+    });
+
+    let problems = if repair {
+        map.repair().expect("Repair failed")
+    } else {
+        map.fsck().expect("Check failed")
+    };
+    for problem in problems.iter() {
+        println!("{:?}", problem);
+    }
+    println!("{} problem(s) found", problems.len());
+
+    let remaining = if repair {
+        db.flush().expect("Failed to flush");
+        let remaining = map.fsck().expect("Check failed");
+        for problem in remaining.iter() {
+            println!("Not repaired: {:?}", problem);
+        }
+        remaining.len()
+    } else {
+        problems.len()
+    };
+    if remaining > 0 {
+        exit(1);
+    }
+}
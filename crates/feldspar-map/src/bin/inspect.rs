@@ -0,0 +1,255 @@
+//! Prints statistics of a map stored in a sled database.
+//!
+//! ```text
+//! feldspar-inspect <db_path> <map_name> [--chunk <level> <x> <y> <z> [--slice <z>]]
+//! ```
+//!
+//! With `--chunk`, also prints one XY slice of the voxels of the chunk at those chunk coordinates. Solid voxels show their
+//! palette ID in base 36, and empty voxels show as `.`.
+
+use feldspar_map::chunk::{ChunkData, ChunkLayout, ChunkShape, HALF_CHUNK_EDGE_LENGTH};
+use feldspar_map::clipmap::Level;
+use feldspar_map::core::glam::IVec3;
+use feldspar_map::database::{
+    ChunkDbKey, MapDb, MapStats, Version, VersionStats, MAP_FORMAT_VERSION,
+};
+use feldspar_map::palette::{PaletteId16, PaletteId8};
+use feldspar_map::sdf::{Sd16, Sd8, SdfValue};
+
+use bytemuck::Pod;
+use ndshape::ConstShape;
+use std::collections::BTreeMap;
+use std::process::exit;
+
+const USAGE: &str =
+    "Usage: feldspar-inspect <db_path> <map_name> [--chunk <level> <x> <y> <z> [--slice <z>]]";
+
+/// The width of the longest bar of the palette usage histogram.
+const HISTOGRAM_WIDTH: u64 = 50;
+
+fn main() {
+    let mut positional = Vec::new();
+    let mut chunk_key = None;
+    let mut slice_z = HALF_CHUNK_EDGE_LENGTH;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--chunk" => {
+                let level: Level = parse_arg(args.next());
+                let coords = IVec3::new(
+                    parse_arg(args.next()),
+                    parse_arg(args.next()),
+                    parse_arg(args.next()),
+                );
+                chunk_key = Some(ChunkDbKey::new(level, coords.into()));
+            }
+            "--slice" => slice_z = parse_arg(args.next()),
+            _ => positional.push(arg),
+        }
+    }
+    let (db_path, map_name) = match positional.as_slice() {
+        [db_path, map_name] => (db_path, map_name),
+        _ => usage(),
+    };
+
+    let db = sled::open(db_path).unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {:?}", db_path, e);
+        exit(2);
+    });
+    let map = MapDb::open(&db, map_name).unwrap_or_else(|e| {
+        eprintln!("Failed to open map {}: {:?}", map_name, e);
+        exit(2);
+    });
+
+    let meta = map.cached_meta();
+    let layout = map.chunk_layout();
+    println!("Map {}", map_name);
+    // Opening migrates the map to the format of this build.
+    println!("  Format:  {}", MAP_FORMAT_VERSION);
+    println!("  Layout:  {:?}", layout);
+    println!("  Codec:   {:?}", map.chunk_codec());
+    println!(
+        "  Working: {} (parent {}, grandparent {})",
+        version_name(meta.working_version),
+        optional_version_name(meta.parent_version),
+        optional_version_name(meta.grandparent_version),
+    );
+
+    let stats = map.stats().expect("Failed to read map");
+    print_levels(&stats, layout);
+    print_versions(&stats.versions, meta.parent_version);
+    println!();
+    println!(
+        "Backup tree: {} entries, {} bytes{}",
+        stats.backup_entries,
+        stats.backup_bytes,
+        if stats.backup_entries > 0 {
+            " (uncommitted changes)"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "Tiles: {} tiles, {} bytes",
+        stats.tile_count, stats.tile_bytes
+    );
+
+    if layout == ChunkLayout::Palette8 {
+        let usage = map.palette_usage().expect("Failed to read map");
+        print_histogram(usage.voxel_counts.iter().copied().enumerate());
+    }
+
+    if let Some(key) = chunk_key {
+        if !(0..16).contains(&slice_z) {
+            usage();
+        }
+        println!();
+        println!("Chunk {:?}, slice z = {}", key, slice_z);
+        let printed = match layout {
+            ChunkLayout::Palette8 => {
+                print_slice(map.read_working_chunk_as::<Sd8, PaletteId8>(key), slice_z)
+            }
+            ChunkLayout::Palette16 => {
+                print_slice(map.read_working_chunk_as::<Sd8, PaletteId16>(key), slice_z)
+            }
+            ChunkLayout::Sd16Palette8 => {
+                print_slice(map.read_working_chunk_as::<Sd16, PaletteId8>(key), slice_z)
+            }
+            ChunkLayout::Sd16Palette16 => {
+                print_slice(map.read_working_chunk_as::<Sd16, PaletteId16>(key), slice_z)
+            }
+        };
+        if !printed {
+            println!("  (no chunk)");
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn version_name(version: Version) -> String {
+    format!("v{}", version.number)
+}
+
+fn optional_version_name(version: Option<Version>) -> String {
+    version.map_or("none".into(), version_name)
+}
+
+fn print_levels(stats: &MapStats, layout: ChunkLayout) {
+    println!();
+    println!(
+        "{:>5} {:>10} {:>10} {:>14} {:>7}  Bounds (chunk units)",
+        "Level", "Chunks", "Tile refs", "Stored bytes", "Ratio"
+    );
+    for (level, level_stats) in stats.levels.iter() {
+        let bounds = match level_stats.bounds {
+            Some(bounds) => {
+                let bounds = bounds.into_inner();
+                format!(
+                    "{:?} ..= {:?}",
+                    bounds.minimum.to_array(),
+                    bounds.max().to_array()
+                )
+            }
+            None => String::new(),
+        };
+        println!(
+            "{:>5} {:>10} {:>10} {:>14} {:>7.2}  {}",
+            level,
+            level_stats.chunk_count,
+            level_stats.tile_references,
+            level_stats.stored_bytes,
+            level_stats.compression_ratio(layout),
+            bounds
+        );
+    }
+}
+
+/// Prints the version graph as an indented tree, starting from each root.
+fn print_versions(versions: &[VersionStats], parent_version: Option<Version>) {
+    println!();
+    println!("Versions:");
+    let known: BTreeMap<_, _> = versions.iter().map(|v| (v.version, v)).collect();
+    let mut children = BTreeMap::<Version, Vec<Version>>::new();
+    let mut roots = Vec::new();
+    for v in versions.iter() {
+        match v.parent_version {
+            Some(parent) if known.contains_key(&parent) => {
+                children.entry(parent).or_default().push(v.version)
+            }
+            _ => roots.push(v.version),
+        }
+    }
+
+    // Iterative so that long histories don't overflow the stack.
+    let mut stack: Vec<_> = roots.into_iter().rev().map(|root| (root, 0)).collect();
+    while let Some((version, depth)) = stack.pop() {
+        let v = known[&version];
+        let changes = match v.archived_changes {
+            Some(count) => format!("{} archived changes", count),
+            None if Some(version) == parent_version => "parent of working version".into(),
+            None => "no archived changes".into(),
+        };
+        println!(
+            "  {:indent$}{} ({})",
+            "",
+            version_name(version),
+            changes,
+            indent = 2 * depth
+        );
+        if let Some(version_children) = children.get(&version) {
+            stack.extend(
+                version_children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, depth + 1)),
+            );
+        }
+    }
+}
+
+fn print_histogram(counts: impl Iterator<Item = (usize, u64)>) {
+    let counts: Vec<_> = counts.filter(|&(_, count)| count > 0).collect();
+    let max_count = counts.iter().map(|&(_, count)| count).max().unwrap_or(0);
+    println!();
+    println!("Palette usage (voxels):");
+    for (id, count) in counts {
+        let width = (count * HISTOGRAM_WIDTH + max_count - 1) / max_count;
+        println!("{:>5} {:>12} {}", id, count, "#".repeat(width as usize));
+    }
+}
+
+/// Returns `false` if there is no chunk.
+fn print_slice<Sd: SdfValue, L: Pod + Into<u32>>(
+    chunk: Result<Option<ChunkData<Sd, L>>, impl std::fmt::Debug>,
+    z: i32,
+) -> bool {
+    let chunk = match chunk.expect("Failed to read chunk") {
+        Some(chunk) => chunk,
+        None => return false,
+    };
+    let edge = ChunkShape::ARRAY[0];
+    for y in (0..edge).rev() {
+        let row: String = (0..edge)
+            .map(|x| {
+                let index = ChunkShape::linearize([x, y, z]) as usize;
+                let distance: f32 = chunk.sdf[index].into();
+                if distance < 0.0 {
+                    let id: u32 = chunk.palette_ids[index].into();
+                    std::char::from_digit(id % 36, 36).unwrap()
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("  {:>3} {}", y, row);
+    }
+    true
+}
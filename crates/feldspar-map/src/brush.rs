@@ -0,0 +1,155 @@
+//! Simple shapes that are sampled into chunks, to be combined into a map with a [`CsgOp`](crate::csg::CsgOp).
+
+use crate::chunk::{Chunk, ChunkShape};
+use crate::clipmap::Level;
+use crate::coordinates::{chunk_extent_ivec3, in_chunk_extent};
+use crate::core::glam::{IVec3, Vec3A};
+use crate::core::ilattice::prelude::Extent;
+use crate::core::SmallKeyHashMap;
+use crate::palette::PaletteId8;
+use crate::sdf::Sd8;
+use crate::units::*;
+
+use ndshape::ConstShape;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum BrushShape {
+    Sphere {
+        radius: f32,
+    },
+    /// An axis-aligned cube.
+    Cube {
+        half_edge_length: f32,
+    },
+}
+
+impl BrushShape {
+    /// The signed distance from the surface of the shape, centered at the origin, to `p`.
+    pub fn signed_distance(self, p: Vec3A) -> f32 {
+        match self {
+            Self::Sphere { radius } => p.length() - radius,
+            Self::Cube { half_edge_length } => {
+                let q = p.abs() - Vec3A::splat(half_edge_length);
+                q.max(Vec3A::ZERO).length() + q.max_element().min(0.0)
+            }
+        }
+    }
+
+    /// The radius of a sphere that contains the shape.
+    pub fn bounding_radius(self) -> f32 {
+        match self {
+            Self::Sphere { radius } => radius,
+            Self::Cube { half_edge_length } => half_edge_length * 3f32.sqrt(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub shape: BrushShape,
+    /// In LOD0 voxel coordinates.
+    pub center: VoxelUnits<Vec3A>,
+    /// The ID of every voxel inside of the brush.
+    pub palette_id: PaletteId8,
+}
+
+impl Brush {
+    /// Samples the brush in every chunk at `level` that it overlaps. Voxels farther than one voxel from the brush are left at
+    /// the ambient value.
+    ///
+    /// Like [`voxelize_mesh`](crate::mesh_import::voxelize_mesh), the sample for voxel `p` at level `L` is at the center of the
+    /// voxel's LOD0 footprint, and distances are measured in voxels of level `L`.
+    pub fn voxelize(&self, level: Level) -> SmallKeyHashMap<ChunkUnits<IVec3>, Chunk> {
+        let VoxelUnits(center) = self.center;
+        let voxel_size = (1 << level) as f32;
+        let center_offset = (voxel_size - 1.0) / 2.0;
+        // Transform into voxel coordinates at `level`, where voxel samples are at integer coordinates.
+        let level_center = (center - Vec3A::splat(center_offset)) / voxel_size;
+        let level_radius = self.shape.bounding_radius() / voxel_size;
+        let bounds = Extent::from_min_and_max(
+            (level_center - Vec3A::splat(level_radius))
+                .floor()
+                .as_ivec3()
+                - IVec3::ONE,
+            (level_center + Vec3A::splat(level_radius))
+                .ceil()
+                .as_ivec3()
+                + IVec3::ONE,
+        );
+
+        let mut chunks = SmallKeyHashMap::default();
+        let ChunkUnits(chunk_extent) = in_chunk_extent(VoxelUnits(bounds));
+        for chunk_coords in chunk_extent.iter3() {
+            let chunk_coords = ChunkUnits(chunk_coords);
+            let VoxelUnits(this_chunk_extent) = chunk_extent_ivec3(chunk_coords);
+            let mut chunk = Chunk::default();
+            let mut touched = false;
+            for p in this_chunk_extent.intersection(&bounds).iter3() {
+                let lod0_sample = p.as_vec3a() * voxel_size + Vec3A::splat(center_offset);
+                let distance = self.shape.signed_distance(lod0_sample - center) / voxel_size;
+                if distance >= 1.0 {
+                    continue;
+                }
+                let index =
+                    ChunkShape::linearize((p - this_chunk_extent.minimum).to_array()) as usize;
+                chunk.sdf[index] = Sd8::from(distance);
+                chunk.palette_ids[index] = self.palette_id;
+                touched = true;
+            }
+            if touched {
+                chunks.insert(chunk_coords, chunk);
+            }
+        }
+        chunks
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_brush_spans_chunks_and_levels() {
+        let brush = Brush {
+            shape: BrushShape::Sphere { radius: 3.0 },
+            center: VoxelUnits(Vec3A::ZERO),
+            palette_id: 5,
+        };
+
+        // The sphere straddles the corner of 8 chunks at LOD0.
+        let chunks = brush.voxelize(0);
+        assert_eq!(chunks.len(), 8);
+        let center_chunk = &chunks[&ChunkUnits(IVec3::ZERO)];
+        let center = ChunkShape::linearize([0; 3]) as usize;
+        assert_eq!(center_chunk.sdf[center], Sd8::MIN);
+        assert_eq!(center_chunk.palette_ids[center], 5);
+        let outside = ChunkShape::linearize([10; 3]) as usize;
+        assert_eq!(center_chunk.sdf[outside], Sd8::MAX);
+        assert_eq!(center_chunk.palette_ids[outside], 0);
+
+        // At LOD2, voxels are 4 units wide, so only a few samples are inside of the sphere.
+        let chunks = brush.voxelize(2);
+        let solid_voxels: usize = chunks
+            .values()
+            .map(|chunk| chunk.sdf.iter().filter(|&&d| d < Sd8::ZERO).count())
+            .sum();
+        assert!(solid_voxels > 0 && solid_voxels <= 8);
+    }
+
+    #[test]
+    fn cube_distance() {
+        let cube = BrushShape::Cube {
+            half_edge_length: 2.0,
+        };
+        assert_eq!(cube.signed_distance(Vec3A::ZERO), -2.0);
+        assert_eq!(cube.signed_distance(Vec3A::new(3.0, 0.0, 0.0)), 1.0);
+        assert_eq!(cube.signed_distance(Vec3A::new(3.0, 3.0, 2.0)), 2f32.sqrt());
+    }
+}
@@ -1,15 +1,21 @@
+use crate::codec::{self, ChunkCodec, CodecHeader};
 use crate::core::geometry::Ray;
 use crate::core::glam::{const_ivec3, const_vec3a, IVec3, Vec3A};
 use crate::core::rkyv::{Archive, Deserialize, Serialize};
 use crate::core::static_assertions::const_assert_eq;
 use crate::sampling::OctantKernel;
-use crate::{coordinates::*, ndview::NdView, palette::PaletteId8, sdf::Sd8, units::*};
-
+use crate::{
+    coordinates::*,
+    ndview::NdView,
+    palette::{PaletteId16, PaletteId8},
+    sdf::{Sd16, Sd8, SdfValue},
+    units::*,
+};
+
+use bytecheck::CheckBytes;
 use bytemuck::{bytes_of, bytes_of_mut, Pod, Zeroable};
 use grid_ray::GridRayIter3;
-use lz4_flex::frame::{FrameDecoder, FrameEncoder};
 use ndshape::{ConstPow2Shape3i32, ConstShape, ConstShape3i32};
-use std::io;
 use std::mem;
 
 /// The standard 3D array shape for chunks.
@@ -32,31 +38,96 @@ pub const PADDED_CHUNK_SIZE: usize = PaddedChunkShape::SIZE as usize;
 /// "As far *outside* of the terrain surface as possible."
 pub const AMBIENT_SD8: Sd8 = Sd8::MAX;
 
-/// The fundamental unit of voxel storage.
+/// The fundamental unit of voxel storage, generic over the [`SdfValue`] and palette ID types.
+///
+/// Most code uses the default [`Chunk`]. The other aliases are for maps with a different [`ChunkLayout`].
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Chunk {
+#[repr(C)]
+pub struct ChunkData<S, L> {
     /// Signed distance field for geometry.
-    pub sdf: SdfChunk,
+    pub sdf: [S; CHUNK_SIZE],
     /// Voxel "materials" that map into attributes of some [`Palette8`](crate::Palette8).
-    pub palette_ids: PaletteIdChunk,
+    pub palette_ids: [L; CHUNK_SIZE],
 }
 
-unsafe impl Zeroable for Chunk {}
-unsafe impl Pod for Chunk {}
-
-impl Default for Chunk {
+/// The default chunk, with [`Sd8`] distances and [`PaletteId8`] labels.
+pub type Chunk = ChunkData<Sd8, PaletteId8>;
+/// A chunk with 16-bit palette IDs, for maps with the [`ChunkLayout::Palette16`] layout.
+pub type Chunk16 = ChunkData<Sd8, PaletteId16>;
+/// A chunk with 16-bit distances, for maps with the [`ChunkLayout::Sd16Palette8`] layout.
+pub type ChunkSd16 = ChunkData<Sd16, PaletteId8>;
+/// A chunk with 16-bit distances and palette IDs, for maps with the [`ChunkLayout::Sd16Palette16`] layout.
+pub type ChunkSd16Palette16 = ChunkData<Sd16, PaletteId16>;
+
+// SAFETY: Both arrays have CHUNK_SIZE elements, so the size of the first is a multiple of the alignment of the second, and
+// there is no padding.
+unsafe impl<S: Zeroable, L: Zeroable> Zeroable for ChunkData<S, L> {}
+unsafe impl<S: Pod, L: Pod> Pod for ChunkData<S, L> {}
+
+impl<S: SdfValue, L: Copy + Default> Default for ChunkData<S, L> {
     fn default() -> Self {
         Self {
-            sdf: [AMBIENT_SD8; CHUNK_SIZE],
-            palette_ids: [0; CHUNK_SIZE],
+            sdf: [S::AMBIENT; CHUNK_SIZE],
+            palette_ids: [L::default(); CHUNK_SIZE],
         }
     }
 }
 
 const_assert_eq!(mem::size_of::<Chunk>(), 8192);
+const_assert_eq!(mem::size_of::<Chunk16>(), 12288);
+const_assert_eq!(mem::size_of::<ChunkSd16>(), 12288);
+const_assert_eq!(mem::size_of::<ChunkSd16Palette16>(), 16384);
 
 pub type SdfChunk = [Sd8; CHUNK_SIZE];
 pub type PaletteIdChunk = [PaletteId8; CHUNK_SIZE];
+pub type PaletteId16Chunk = [PaletteId16; CHUNK_SIZE];
+
+/// Which [`ChunkData`] type a map stores. Every chunk of a map has the same layout, which is chosen when the map is created
+/// with [`MapDb::open_with_layout`](crate::database::MapDb::open_with_layout).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[repr(u8)]
+pub enum ChunkLayout {
+    /// [`Chunk`] with [`PaletteId8`] labels. Up to 256 materials.
+    #[default]
+    Palette8 = 0,
+    /// [`Chunk16`] with [`PaletteId16`] labels. Up to 65536 materials, at the cost of 50% more memory per chunk.
+    Palette16 = 1,
+    /// [`ChunkSd16`] with [`Sd16`] distances, which avoids visible banding on gentle slopes, at the cost of 50% more memory
+    /// per chunk.
+    Sd16Palette8 = 2,
+    /// [`ChunkSd16Palette16`], twice the memory of [`Chunk`].
+    Sd16Palette16 = 3,
+}
+
+impl ChunkLayout {
+    pub fn from_u8(layout: u8) -> Option<Self> {
+        Some(match layout {
+            0 => Self::Palette8,
+            1 => Self::Palette16,
+            2 => Self::Sd16Palette8,
+            3 => Self::Sd16Palette16,
+            _ => return None,
+        })
+    }
+
+    pub fn has_sd16(self) -> bool {
+        matches!(self, Self::Sd16Palette8 | Self::Sd16Palette16)
+    }
+
+    pub fn has_palette16(self) -> bool {
+        matches!(self, Self::Palette16 | Self::Sd16Palette16)
+    }
+
+    /// The uncompressed size of one chunk.
+    pub fn chunk_size_bytes(self) -> usize {
+        match self {
+            Self::Palette8 => mem::size_of::<Chunk>(),
+            Self::Palette16 => mem::size_of::<Chunk16>(),
+            Self::Sd16Palette8 => mem::size_of::<ChunkSd16>(),
+            Self::Sd16Palette16 => mem::size_of::<ChunkSd16Palette16>(),
+        }
+    }
+}
 
 const_assert_eq!(mem::size_of::<SdfChunk>(), 4096);
 const_assert_eq!(mem::size_of::<PaletteIdChunk>(), 4096);
@@ -78,51 +149,23 @@ impl Chunk {
         NdView::new(&mut self.palette_ids, ChunkShape {})
     }
 
-    pub fn set_voxel(&mut self, offset: IVec3, palette_id: PaletteId8, sdf: Sd8) {
-        let index = ChunkShape::linearize(offset.to_array()) as usize;
-        self.sdf[index] = sdf;
-        self.palette_ids[index] = palette_id;
+    /// Returns the value of every voxel if they are all the same.
+    pub fn as_uniform(&self) -> Option<UniformChunk> {
+        let sdf = self.sdf[0];
+        let palette_id = self.palette_ids[0];
+        (self.sdf.iter().all(|&d| d == sdf) && self.palette_ids.iter().all(|&id| id == palette_id))
+            .then(|| UniformChunk { sdf, palette_id })
     }
 
-    pub fn compress(&self) -> CompressedChunk {
-        let mut encoder = FrameEncoder::new(Vec::new());
-        let mut reader = bytes_of(self);
-        io::copy(&mut reader, &mut encoder).unwrap();
-        CompressedChunk {
-            bytes: encoder.finish().unwrap().into_boxed_slice(),
+    /// Replaces every palette ID `i` with `mapping[i]`. Returns `true` if any ID changed.
+    pub fn remap_palette(&mut self, mapping: &[PaletteId8; 256]) -> bool {
+        let mut changed = false;
+        for id in self.palette_ids.iter_mut() {
+            let new_id = mapping[*id as usize];
+            changed |= new_id != *id;
+            *id = new_id;
         }
-    }
-
-    pub fn from_compressed_bytes(bytes: &[u8]) -> Chunk {
-        let mut chunk = Chunk {
-            sdf: [Sd8(0); CHUNK_SIZE],
-            palette_ids: [0; CHUNK_SIZE],
-        };
-        let mut decoder = FrameDecoder::new(bytes);
-        let mut writer = bytes_of_mut(&mut chunk);
-        io::copy(&mut decoder, &mut writer).unwrap();
-        chunk
-    }
-
-    /// Downsamples the SDF and palette IDs from `self` at half resolution into one octant of a parent chunk.
-    pub fn downsample_into(
-        &self,
-        kernel: &mut OctantKernel,
-        self_coords: IVec3,
-        parent_coords: IVec3,
-        parent_chunk: &mut Chunk,
-    ) {
-        let min_child = min_child_coords(parent_coords);
-        let child_offset = self_coords - min_child;
-        let dst_offset =
-            ChunkShape::linearize((child_offset << HALF_CHUNK_SHAPE_LOG2_IVEC3).to_array())
-                as usize;
-
-        // SDF is downsampled as a mean of the 8 children.
-        kernel.downsample_sdf(&self.sdf, dst_offset, &mut parent_chunk.sdf);
-
-        // Palette IDs are downsampled as the mode of the 8 children.
-        kernel.downsample_labels(&self.palette_ids, dst_offset, &mut parent_chunk.palette_ids);
+        changed
     }
 
     /// Visit every voxel in `chunk` that intersects the ray. Return `false` to stop the traversal.
@@ -168,8 +211,193 @@ impl Chunk {
     }
 }
 
+/// Finds the first voxel with a negative distance along `ray`, visiting the LOD0 chunks that the ray passes through in order,
+/// until time `max_t`. `read_chunk` returns the chunk at the given coordinates, or `None` if it is empty.
+///
+/// Returns the time when the ray enters the voxel and the voxel's coordinates.
+pub fn first_solid_voxel_on_ray(
+    ray: &Ray,
+    max_t: f32,
+    mut read_chunk: impl FnMut(ChunkUnits<IVec3>) -> Option<Chunk>,
+) -> Option<(f32, IVec3)> {
+    // Scaling both the start and velocity keeps the same times in chunk coordinates.
+    let chunk_scale = 1.0 / CHUNK_SHAPE_VEC3A;
+    let chunk_iter = GridRayIter3::new(ray.start * chunk_scale, ray.velocity() * chunk_scale);
+    for (t_enter_chunk, chunk_coords) in chunk_iter {
+        if t_enter_chunk > max_t {
+            break;
+        }
+        let chunk = match read_chunk(ChunkUnits(chunk_coords)) {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+        let mut hit = None;
+        chunk.ray_intersections(ChunkUnits(chunk_coords), ray, |t, p, sdf, _| {
+            if sdf < Sd8::ZERO && t <= max_t {
+                hit = Some((t, p));
+                return false;
+            }
+            true
+        });
+        if hit.is_some() {
+            return hit;
+        }
+    }
+    None
+}
+
+/// The value of every voxel in a chunk where all voxels are the same, e.g. entirely ambient or entirely solid. This is stored
+/// in place of a [`Chunk`] without any allocation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UniformChunk {
+    pub sdf: Sd8,
+    pub palette_id: PaletteId8,
+}
+
+impl Default for UniformChunk {
+    fn default() -> Self {
+        Self::AMBIENT
+    }
+}
+
+impl UniformChunk {
+    pub const AMBIENT: Self = Self {
+        sdf: AMBIENT_SD8,
+        palette_id: 0,
+    };
+
+    pub fn to_chunk(self) -> Chunk {
+        Chunk {
+            sdf: [self.sdf; CHUNK_SIZE],
+            palette_ids: [self.palette_id; CHUNK_SIZE],
+        }
+    }
+
+    /// The value of the parent of 8 chunks with this value, the same as [`Chunk::downsample_into`] would produce.
+    pub fn downsampled(self) -> Self {
+        Self {
+            sdf: Sd8::from(f32::from(self.sdf) * 0.5),
+            palette_id: self.palette_id,
+        }
+    }
+}
+
+/// Identifies a chunk in the tile table of a [`MapDb`](crate::database::MapDb). Tile IDs are unique within a map.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TileId(pub u64);
+
+impl TileId {
+    pub const fn into_sled_key(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    /// # Panics
+    ///
+    /// If `bytes` is not 8 bytes long. Use [`Self::try_from_sled_key`] for keys that might be corrupt.
+    pub fn from_sled_key(bytes: &[u8]) -> Self {
+        Self::try_from_sled_key(bytes).expect("Invalid tile key")
+    }
+
+    /// Returns `None` if `bytes` is not 8 bytes long.
+    pub fn try_from_sled_key(bytes: &[u8]) -> Option<Self> {
+        Some(Self(u64::from_be_bytes(bytes.try_into().ok()?)))
+    }
+}
+
+/// A decompressed tile, shared by every [`ChunkNode`](crate::clipmap::ChunkNode) that instances it. Nodes copy the chunk before
+/// editing it.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Tile {
+    pub id: TileId,
+    pub chunk: Chunk,
+}
+
+impl<S: SdfValue, L: Pod> ChunkData<S, L> {
+    pub fn set_voxel(&mut self, offset: IVec3, palette_id: L, sdf: S) {
+        let index = ChunkShape::linearize(offset.to_array()) as usize;
+        self.sdf[index] = sdf;
+        self.palette_ids[index] = palette_id;
+    }
+
+    /// Every layout is compressed the same way, so they all share the [`CompressedChunk`] type and the
+    /// [`ChangeEncoder`](crate::database::ChangeEncoder).
+    pub fn compress(&self) -> CompressedChunk {
+        self.compress_with(ChunkCodec::Lz4Frame)
+    }
+
+    pub fn compress_with(&self, codec: ChunkCodec) -> CompressedChunk {
+        let header = CodecHeader::new(codec, mem::size_of::<S>(), mem::size_of::<L>());
+        CompressedChunk {
+            bytes: codec::encode(header, bytes_of(self)).into_boxed_slice(),
+        }
+    }
+
+    /// Decompresses bytes written by any [`ChunkCodec`].
+    ///
+    /// # Panics
+    ///
+    /// If `bytes` are not compressed voxels of this layout. Use [`ChunkData::try_from_compressed_bytes`] for bytes that were
+    /// read from storage.
+    pub fn from_compressed_bytes(bytes: &[u8]) -> Self {
+        Self::try_from_compressed_bytes(bytes).expect("Invalid compressed chunk")
+    }
+
+    /// Like [`ChunkData::from_compressed_bytes`], but returns `None` if `bytes` are not compressed voxels of this layout, e.g.
+    /// because the database was corrupted.
+    pub fn try_from_compressed_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut chunk = Self::zeroed();
+        codec::decode_into(bytes, bytes_of_mut(&mut chunk))?;
+        Some(chunk)
+    }
+
+    /// Converts every distance and palette ID to another layout, e.g. to write a [`Chunk`] into a map with wider types.
+    pub fn convert<S2: SdfValue + From<S>, L2: Pod + From<L>>(&self) -> ChunkData<S2, L2> {
+        ChunkData {
+            sdf: self.sdf.map(S2::from),
+            palette_ids: self.palette_ids.map(L2::from),
+        }
+    }
+
+    /// Downsamples the SDF and palette IDs from `self` at half resolution into one octant of a parent chunk.
+    pub fn downsample_into(
+        &self,
+        kernel: &mut OctantKernel,
+        self_coords: IVec3,
+        parent_coords: IVec3,
+        parent_chunk: &mut Self,
+    ) where
+        L: Eq,
+    {
+        let min_child = min_child_coords(parent_coords);
+        let child_offset = self_coords - min_child;
+        let dst_offset =
+            ChunkShape::linearize((child_offset << HALF_CHUNK_SHAPE_LOG2_IVEC3).to_array())
+                as usize;
+
+        // SDF is downsampled as a mean of the 8 children.
+        kernel.downsample_sdf(&self.sdf, dst_offset, &mut parent_chunk.sdf);
+
+        // Palette IDs are downsampled as the mode of the 8 children.
+        kernel.downsample_labels(&self.palette_ids, dst_offset, &mut parent_chunk.palette_ids);
+    }
+}
+
+impl Chunk16 {
+    /// Converts a [`Chunk`] and adds `palette_offset` to every palette ID, e.g. to import 8-bit content into its own range of
+    /// IDs.
+    pub fn from_chunk_with_offset(chunk: &Chunk, palette_offset: PaletteId16) -> Self {
+        Self {
+            sdf: chunk.sdf,
+            palette_ids: chunk
+                .palette_ids
+                .map(|id| PaletteId16::from(id).wrapping_add(palette_offset)),
+        }
+    }
+}
+
 #[derive(Archive, Clone, Deserialize, Debug, Eq, PartialEq, Serialize)]
 #[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
 pub struct CompressedChunk {
     pub bytes: Box<[u8]>,
 }
@@ -179,10 +407,52 @@ const_assert_eq!(
     2 * mem::size_of::<usize>()
 );
 
+/// Starts the bytes of a [`CompressedChunk`] that references a tile instead of holding voxels. Compressed voxels always start
+/// with the LZ4 frame magic number or a [`CodecHeader`], so the two can't be confused.
+const TILE_REFERENCE_MAGIC: [u8; 4] = *b"TILE";
+
 impl CompressedChunk {
+    /// A chunk that shares the voxels of `tile` instead of storing its own.
+    pub fn tile_reference(tile: TileId) -> Self {
+        let mut bytes = Vec::with_capacity(12);
+        bytes.extend_from_slice(&TILE_REFERENCE_MAGIC);
+        bytes.extend_from_slice(&tile.0.to_le_bytes());
+        Self {
+            bytes: bytes.into_boxed_slice(),
+        }
+    }
+
+    /// Returns the tile referenced by `bytes` if they are a [`CompressedChunk::tile_reference`] rather than compressed voxels.
+    pub fn referenced_tile(bytes: &[u8]) -> Option<TileId> {
+        let id_bytes = bytes.strip_prefix(&TILE_REFERENCE_MAGIC)?;
+        Some(TileId(u64::from_le_bytes(id_bytes.try_into().ok()?)))
+    }
+
+    pub fn tile_id(&self) -> Option<TileId> {
+        Self::referenced_tile(&self.bytes)
+    }
+
+    /// Only for maps with the [`ChunkLayout::Palette8`] layout.
+    ///
+    /// Tile references must be resolved by the [`MapDb`](crate::database::MapDb) before decompressing.
     pub fn decompress(&self) -> Chunk {
         Chunk::from_compressed_bytes(&self.bytes)
     }
+
+    /// Only for maps with the [`ChunkLayout::Palette16`] layout.
+    pub fn decompress16(&self) -> Chunk16 {
+        Chunk16::from_compressed_bytes(&self.bytes)
+    }
+
+    /// Decompresses into any layout. The layout must be the same one that was compressed.
+    pub fn decompress_as<S: SdfValue, L: Pod>(&self) -> ChunkData<S, L> {
+        ChunkData::from_compressed_bytes(&self.bytes)
+    }
+
+    /// Like [`CompressedChunk::decompress_as`], but returns `None` if the bytes are not compressed voxels of that layout.
+    pub fn try_decompress_as<S: SdfValue, L: Pod>(&self) -> Option<ChunkData<S, L>> {
+        ChunkData::try_from_compressed_bytes(&self.bytes)
+    }
 }
 
 // ████████╗███████╗███████╗████████╗
@@ -195,6 +465,7 @@ impl CompressedChunk {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::core::ilattice::prelude::Extent;
     use crate::{chunk::AMBIENT_SD8, coordinates::chunk_extent_from_min_ivec3, units::VoxelUnits};
 
     #[test]
@@ -206,8 +477,7 @@ mod test {
         assert_eq!(compressed.decompress(), chunk);
     }
 
-    #[test]
-    fn compress_chunk_with_sphere_sdf() {
+    fn sphere_chunk() -> Chunk {
         let mut chunk = Chunk::default();
         let VoxelUnits(extent) = chunk_extent_from_min_ivec3(VoxelUnits(IVec3::ZERO));
         let center = (extent.minimum + extent.least_upper_bound()) / 2;
@@ -219,13 +489,127 @@ mod test {
                 chunk.palette_ids[i] = 1;
             }
         }
+        chunk
+    }
 
+    #[test]
+    fn compress_chunk_with_sphere_sdf() {
+        let chunk = sphere_chunk();
         let compressed = chunk.compress();
         let compression_ratio = compressed.bytes.len() as f32 / (mem::size_of::<Chunk>() as f32);
         assert!(compression_ratio < 0.19, "{}", compression_ratio);
         assert_eq!(compressed.decompress(), chunk);
     }
 
+    #[test]
+    fn ray_hits_first_solid_voxel() {
+        // The sphere in chunk (1, 0, 0) is centered at (24, 8, 8).
+        let chunk = sphere_chunk();
+        let mut visited = Vec::new();
+        let ray = Ray::new(Vec3A::new(-20.5, 8.5, 8.5), Vec3A::X);
+        let hit = first_solid_voxel_on_ray(&ray, 100.0, |coords| {
+            visited.push(coords);
+            (coords == ChunkUnits(IVec3::X)).then(|| chunk)
+        });
+        assert_eq!(hit.map(|(_, p)| p), Some(IVec3::new(17, 8, 8)));
+        assert_eq!(
+            visited,
+            [
+                ChunkUnits(IVec3::new(-2, 0, 0)),
+                ChunkUnits(IVec3::new(-1, 0, 0)),
+                ChunkUnits(IVec3::ZERO),
+                ChunkUnits(IVec3::X)
+            ]
+        );
+
+        // Stop before reaching the chunk.
+        let hit = first_solid_voxel_on_ray(&ray, 30.0, |coords| (coords.0.x >= 1).then(|| chunk));
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn every_codec_reads_back() {
+        let chunk = sphere_chunk();
+        let sd16 = chunk.convert::<Sd16, PaletteId16>();
+        for codec in [
+            ChunkCodec::Lz4Frame,
+            ChunkCodec::Lz4Block,
+            ChunkCodec::SdfPredictive,
+            ChunkCodec::PaletteRle,
+        ] {
+            let compressed = chunk.compress_with(codec);
+            assert!(compressed.bytes.len() < mem::size_of::<Chunk>() / 2);
+            assert_eq!(compressed.decompress(), chunk);
+            assert_eq!(
+                sd16.compress_with(codec)
+                    .decompress_as::<Sd16, PaletteId16>(),
+                sd16
+            );
+        }
+        // Chunks compressed before codecs existed are plain LZ4 frames.
+        assert!(chunk.compress().bytes.starts_with(&codec::LZ4_FRAME_MAGIC));
+    }
+
+    #[test]
+    fn chunk16_round_trip_and_downsample() {
+        // The first octant has 6 voxels of 1000 and 2 of 40000.
+        let mut chunk = Chunk16::default();
+        for p in Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(2)).iter3() {
+            let id = if p.z == 0 && p.y == 1 { 40000 } else { 1000 };
+            chunk.set_voxel(p, id, Sd8::MIN);
+        }
+        assert_eq!(chunk.compress().decompress16(), chunk);
+
+        let mut kernel = OctantKernel::new();
+        let mut parent = Chunk16::default();
+        chunk.downsample_into(&mut kernel, IVec3::ZERO, IVec3::ZERO, &mut parent);
+        assert_eq!(parent.palette_ids[0], 1000);
+    }
+
+    #[test]
+    fn chunk16_from_chunk_with_offset() {
+        let mut chunk = Chunk::default();
+        chunk.palette_ids[5] = 255;
+        let wide = Chunk16::from_chunk_with_offset(&chunk, 300);
+        assert_eq!(wide.palette_ids[0], 300);
+        assert_eq!(wide.palette_ids[5], 555);
+        assert_eq!(wide.sdf, chunk.sdf);
+    }
+
+    #[test]
+    fn uniform_chunk_detection() {
+        assert_eq!(Chunk::default().as_uniform(), Some(UniformChunk::AMBIENT));
+
+        let solid = UniformChunk {
+            sdf: Sd8::MIN,
+            palette_id: 3,
+        };
+        let mut chunk = solid.to_chunk();
+        assert_eq!(chunk.as_uniform(), Some(solid));
+
+        chunk.palette_ids[100] = 4;
+        assert_eq!(chunk.as_uniform(), None);
+
+        // Matches downsampling a full chunk.
+        let mut kernel = OctantKernel::new();
+        let mut parent = Chunk::default();
+        for child in Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(2)).iter3() {
+            solid
+                .to_chunk()
+                .downsample_into(&mut kernel, child, IVec3::ZERO, &mut parent);
+        }
+        assert_eq!(parent.as_uniform(), Some(solid.downsampled()));
+    }
+
+    #[test]
+    fn tile_reference_is_not_compressed_voxels() {
+        let reference = CompressedChunk::tile_reference(TileId(0x0102030405));
+        assert_eq!(reference.tile_id(), Some(TileId(0x0102030405)));
+
+        assert_eq!(Chunk::default().compress().tile_id(), None);
+        assert_eq!(UniformChunk::AMBIENT.to_chunk().compress().tile_id(), None);
+    }
+
     #[test]
     fn ray_intersections_pass_through() {
         let ray = Ray::new(Vec3A::new(-0.5, 0.5, 0.5), Vec3A::new(1.0, 0.0, 0.0));
@@ -3,15 +3,18 @@ mod node;
 mod raycast;
 mod streaming;
 
-use crate::chunk::CompressedChunk;
+use crate::chunk::{Chunk, CompressedChunk, Tile, UniformChunk, CHUNK_SHAPE_IVEC3};
 use crate::coordinates::{
-    ancestor_extent, child_index, chunk_bounding_sphere, chunk_extent_at_level_ivec3,
+    ancestor_extent, child_index, chunk_bounding_sphere, chunk_extent_at_level_ivec3, chunk_min,
     descendant_extent, in_chunk_extent, sphere_intersecting_ancestor_chunk_extent,
 };
 use crate::core::geometry::Sphere;
 use crate::core::glam::IVec3;
 use crate::core::ilattice::prelude::Extent;
+use crate::core::SmallKeyHashMap;
+use crate::palette::Palette8;
 use crate::units::{ChunkUnits, VoxelUnits};
+use crate::vox::{chunks_to_vox_scene, VoxColor, VoxScene};
 
 pub use grid_tree::{
     BranchShape, ChildIndex, Level, NodeKey, NodePtr, OctreeShapeI32, VisitCommand, EMPTY_ALLOC_PTR,
@@ -19,8 +22,9 @@ pub use grid_tree::{
 pub use node::*;
 pub use streaming::*;
 
-use grid_tree::OctreeI32;
+use grid_tree::{OctreeI32, Relation};
 use smallvec::SmallVec;
+use std::sync::Arc;
 
 pub const CHILDREN: ChildIndex = OctreeI32::<()>::CHILDREN;
 pub const CHILDREN_USIZE: usize = CHILDREN as usize;
@@ -166,10 +170,142 @@ impl ChunkClipMap {
         }
     }
 
+    /// Exports the voxels of `extent` at `level` as a [`VoxScene`]. Only chunks that are currently loaded are included.
+    ///
+    /// See [`chunks_to_vox_scene`] for details.
+    pub fn export_vox(
+        &self,
+        level: Level,
+        extent: VoxelUnits<Extent<IVec3>>,
+        palette: &Palette8<VoxColor>,
+    ) -> VoxScene {
+        let mut chunks = self.loaded_chunks(level, in_chunk_extent(extent));
+        chunks_to_vox_scene(extent, palette, |coords| chunks.remove(&coords))
+    }
+
+    /// Decompresses every chunk at `level` with coordinates in `chunk_extent` that is currently loaded.
+    pub fn loaded_chunks(
+        &self,
+        level: Level,
+        chunk_extent: ChunkUnits<Extent<IVec3>>,
+    ) -> SmallKeyHashMap<ChunkUnits<IVec3>, Chunk> {
+        let ChunkUnits(chunk_extent) = chunk_extent;
+        let VoxelUnits(min) = chunk_min(ChunkUnits(chunk_extent.minimum));
+        let voxel_extent = Extent::from_min_and_shape(min, chunk_extent.shape * CHUNK_SHAPE_IVEC3);
+        let lod0_extent = descendant_extent(level, voxel_extent);
+
+        let mut chunks = SmallKeyHashMap::default();
+        self.visit_extent_intersections(level, VoxelUnits(lod0_extent), |ptr, coords| {
+            if ptr.level() > level {
+                return VisitCommand::Continue;
+            }
+            if chunk_extent.contains(coords.0) {
+                if let Some(chunk) = self
+                    .octree
+                    .get_value(ptr)
+                    .and_then(|node| node.get_decompressed())
+                {
+                    chunks.insert(coords, *chunk.as_ref());
+                }
+            }
+            VisitCommand::SkipDescendants
+        });
+        chunks
+    }
+
+    /// Replaces the chunk of the node at `key` after it was edited in the database. Returns `false` if there is no such node, in
+    /// which case the edit will be read when the node is loaded.
+    ///
+    /// The edit takes precedence over a load of the node that is still in flight. See
+    /// [`complete_pending_load`](Self::complete_pending_load). If the edit made the chunk uniform, then it may collapse with its
+    /// siblings.
+    pub fn write_chunk(&mut self, key: NodeKey<IVec3>, chunk: Option<LoadedChunk>) -> bool {
+        let node = match self
+            .octree
+            .find_node(key)
+            .and_then(|ptr| self.octree.get_value_mut(ptr))
+        {
+            Some(node) => node,
+            None => return false,
+        };
+        node.state().clear_loading();
+        let is_uniform = matches!(chunk, Some(LoadedChunk::Uniform(_)));
+        node.put_loaded(chunk);
+        if is_uniform {
+            self.try_collapse_key(key);
+        }
+        true
+    }
+
     /// Tries to collapse nodes with the same homogeneous value, starting from `key` and working up the line of ancestors.
+    ///
+    /// When all 8 children of a node are leaves holding the same [`UniformChunk`](crate::chunk::UniformChunk), they are
+    /// removed, and the parent holds the downsampled uniform value. This repeats for each ancestor until a set of siblings can't
+    /// be collapsed.
     pub fn try_collapse_key(&mut self, key: NodeKey<IVec3>) {
-        // NOTE: We can't collapse nodes with a load pending!
-        todo!()
+        let mut parent_key = key;
+        while parent_key.level < self.octree.root_level() {
+            parent_key = NodeKey::new(parent_key.level + 1, parent_key.coordinates >> 1);
+            let collapsed = self
+                .octree
+                .find_node(parent_key)
+                .map_or(false, |parent_ptr| self.try_collapse_children(parent_ptr));
+            if !collapsed {
+                return;
+            }
+        }
+    }
+
+    fn try_collapse_children(&mut self, parent_ptr: NodePtr) -> bool {
+        let children = match self.octree.child_pointers(parent_ptr) {
+            Some(c) => c,
+            None => return false,
+        };
+
+        let mut child_ptrs = [NodePtr::new(0, EMPTY_ALLOC_PTR); CHILDREN_USIZE];
+        let mut value = None;
+        for (child_index, child_ptr) in (0..CHILDREN).zip(child_ptrs.iter_mut()) {
+            *child_ptr = match children.get_child(child_index) {
+                Some(p) => p,
+                None => return false,
+            };
+            if self.has_children(*child_ptr) {
+                return false;
+            }
+            let child = self.octree.get_value(*child_ptr).unwrap();
+            // NOTE: We can't collapse nodes with a load pending!
+            let state = child.state();
+            if state.is_loading() || state.has_load_pending() || state.is_rendering() {
+                return false;
+            }
+            let uniform = match child.get_uniform() {
+                Some(u) => u,
+                None => return false,
+            };
+            if value.map_or(false, |v| v != uniform) {
+                return false;
+            }
+            value = Some(uniform);
+        }
+
+        let parent = self.octree.get_value_mut(parent_ptr).unwrap();
+        if parent.state().has_load_pending() {
+            return false;
+        }
+        parent.put_uniform(value.unwrap().downsampled());
+        for (child_index, child) in (0..CHILDREN).zip(child_ptrs) {
+            self.octree.drop_tree(&Relation {
+                parent: Some((parent_ptr, child_index)),
+                child,
+            });
+        }
+        true
+    }
+
+    fn has_children(&self, ptr: NodePtr) -> bool {
+        self.octree.child_pointers(ptr).map_or(false, |children| {
+            (0..CHILDREN).any(|i| children.get_child(i).is_some())
+        })
     }
 
     /// # Load vs Edit Conflict Resolution
@@ -212,11 +348,7 @@ impl ChunkClipMap {
                     return;
                 }
 
-                if let Some(chunk) = chunk {
-                    node.put_compressed(chunk);
-                } else {
-                    node.take_chunk();
-                }
+                node.put_loaded(chunk);
 
                 // If this is the last load of this subtree, then clear the descendant is loading bit on the parent.
                 if node.state().descendant_is_loading.none() {
@@ -280,7 +412,27 @@ impl ChunkClipMap {
 pub struct PendingLoad {
     pub loaded_key: NodeKey<IVec3>,
     pub link_ptr: LinkPointer,
-    pub chunk: Option<CompressedChunk>,
+    pub chunk: Option<LoadedChunk>,
+}
+
+/// A chunk read by [`MapDb::read_working_loaded_chunk`](crate::database::MapDb::read_working_loaded_chunk).
+pub enum LoadedChunk {
+    Compressed(CompressedChunk),
+    /// Every voxel is the same, so the node doesn't need to store any chunk data, and uniform siblings can collapse into their
+    /// parent.
+    Uniform(UniformChunk),
+    /// Shared with every other node that instances the same tile.
+    Tile(Arc<Tile>),
+}
+
+impl LoadedChunk {
+    /// Detects whether `chunk` is uniform, and otherwise keeps `compressed`, which must hold the same voxels as `chunk`.
+    pub fn new(chunk: &Chunk, compressed: CompressedChunk) -> Self {
+        match chunk.as_uniform() {
+            Some(uniform) => Self::Uniform(uniform),
+            None => Self::Compressed(compressed),
+        }
+    }
 }
 
 pub enum LinkPointer {
@@ -304,7 +456,7 @@ mod test {
     use super::*;
     use crate::core::{geometry::Ray, glam::Vec3A};
     use crate::{
-        chunk::Chunk,
+        chunk::{Chunk, UniformChunk},
         coordinates::{chunk_extent_from_min_ivec3, in_chunk_extent},
         ndview::NdView,
     };
@@ -350,6 +502,100 @@ mod test {
         }
     }
 
+    #[test]
+    fn collapse_uniform_siblings() {
+        let mut tree = ChunkClipMap::new(3, StreamingConfig::default());
+        let solid = UniformChunk {
+            sdf: crate::sdf::Sd8::MIN,
+            palette_id: 1,
+        };
+
+        // Fill all 8 children of the level 1 node at the origin, but leave one of them decompressed.
+        let mut child_keys = Vec::new();
+        for child in Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(2)).iter3() {
+            let key = NodeKey::new(0, child);
+            child_keys.push(key);
+            tree.octree
+                .fill_path_to_node_from_root(key, |node_key, entry| {
+                    let (_ptr, node) =
+                        entry.or_insert_with(|| ChunkNode::new_empty(NodeState::new_zeroed()));
+                    if node_key == key {
+                        node.put_decompressed(Box::new(solid.to_chunk()));
+                    }
+                    VisitCommand::Continue
+                });
+        }
+        let last_ptr = tree.octree.find_node(child_keys[7]).unwrap();
+        tree.try_collapse_key(child_keys[0]);
+        assert!(tree.octree.find_node(child_keys[0]).is_some());
+
+        // Compressing detects that it is uniform, and then all siblings can collapse.
+        tree.octree.get_value_mut(last_ptr).unwrap().compress();
+        for &key in child_keys[..7].iter() {
+            let ptr = tree.octree.find_node(key).unwrap();
+            tree.octree.get_value_mut(ptr).unwrap().compress();
+        }
+        tree.try_collapse_key(child_keys[0]);
+
+        for &key in child_keys.iter() {
+            assert!(tree.octree.find_node(key).is_none());
+        }
+        let parent_ptr = tree.octree.find_node(NodeKey::new(1, IVec3::ZERO)).unwrap();
+        assert_eq!(
+            tree.octree.get_value(parent_ptr).unwrap().get_uniform(),
+            Some(solid.downsampled())
+        );
+    }
+
+    #[test]
+    fn uniform_edits_collapse_siblings() {
+        let mut tree = ChunkClipMap::new(3, StreamingConfig::default());
+        let solid = UniformChunk {
+            sdf: crate::sdf::Sd8::MIN,
+            palette_id: 1,
+        };
+        let solid_chunk = solid.to_chunk();
+        let mut varied_chunk = solid_chunk;
+        varied_chunk.palette_ids[0] = 2;
+
+        let child_keys: Vec<_> = Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(2))
+            .iter3()
+            .map(|coords| NodeKey::new(0, coords))
+            .collect();
+        for &key in child_keys.iter() {
+            tree.octree
+                .fill_path_to_node_from_root(key, |_node_key, entry| {
+                    entry.or_insert_with(|| ChunkNode::new_empty(NodeState::new_zeroed()));
+                    VisitCommand::Continue
+                });
+        }
+
+        let write = |tree: &mut ChunkClipMap, key, chunk: &Chunk| {
+            assert!(tree.write_chunk(key, Some(LoadedChunk::new(chunk, chunk.compress()))));
+        };
+        for &key in child_keys[..7].iter() {
+            write(&mut tree, key, &solid_chunk);
+        }
+        write(&mut tree, child_keys[7], &varied_chunk);
+        assert!(tree.octree.find_node(child_keys[0]).is_some());
+
+        // The last sibling becomes uniform, so all of them collapse into the parent.
+        write(&mut tree, child_keys[7], &solid_chunk);
+        for &key in child_keys.iter() {
+            assert!(tree.octree.find_node(key).is_none());
+        }
+        let parent_ptr = tree.octree.find_node(NodeKey::new(1, IVec3::ZERO)).unwrap();
+        let parent = tree.octree.get_value(parent_ptr).unwrap();
+        assert_eq!(parent.get_uniform(), Some(solid.downsampled()));
+
+        // Readers see a full chunk, but the parent stays uniform.
+        assert_eq!(
+            parent.get_decompressed().unwrap().as_ref(),
+            &solid.downsampled().to_chunk()
+        );
+        assert_eq!(parent.state().slot_state(), SlotState::Uniform);
+    }
+
     #[test]
     fn earliest_ray_intersection() {
         let mut tree = ChunkClipMap::new(3, StreamingConfig::default());
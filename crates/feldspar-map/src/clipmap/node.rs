@@ -1,11 +1,12 @@
-use crate::chunk::{Chunk, CompressedChunk};
+use super::LoadedChunk;
+use crate::chunk::{Chunk, CompressedChunk, Tile, UniformChunk};
 use crate::core::bitset::{AtomicBitset8, Bitset8};
 use crate::core::static_assertions::const_assert_eq;
 
-use either::Either;
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::mem::{self, ManuallyDrop};
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 /// A single node in the [`ChunkClipMap`](crate::ChunkClipMap).
 ///
@@ -14,6 +15,9 @@ use std::sync::atomic::Ordering;
 ///
 /// While the chunk is compressed, readers will take an exclusive lock and wait for one of the readers to decompress the chunk
 /// before continuing. Decompression should happen at most once per frame.
+///
+/// Chunks where every voxel is the same are stored as a [`UniformChunk`] without any allocation. Instances of a [`Tile`] share
+/// one decompressed chunk, which is copied when the node is edited.
 pub struct ChunkNode {
     chunk: RwLock<ChunkSlot>,
     state: NodeState,
@@ -39,7 +43,7 @@ impl ChunkNode {
     }
 
     pub fn new_empty(state: NodeState) -> Self {
-        state.state.clear_bit(StateBit::Occupied as u8);
+        state.set_slot_state(SlotState::Empty);
         Self {
             state,
             chunk: RwLock::new(ChunkSlot { empty: () }),
@@ -47,8 +51,7 @@ impl ChunkNode {
     }
 
     pub fn new_compressed(chunk: CompressedChunk, state: NodeState) -> Self {
-        state.state.set_bit(StateBit::Occupied as u8);
-        state.state.set_bit(StateBit::Compressed as u8);
+        state.set_slot_state(SlotState::Compressed);
         Self {
             state,
             chunk: RwLock::new(ChunkSlot {
@@ -58,8 +61,7 @@ impl ChunkNode {
     }
 
     pub fn new_decompressed(chunk: Box<Chunk>, state: NodeState) -> Self {
-        state.state.set_bit(StateBit::Occupied as u8);
-        state.state.clear_bit(StateBit::Compressed as u8);
+        state.set_slot_state(SlotState::Decompressed);
         Self {
             state,
             chunk: RwLock::new(ChunkSlot {
@@ -68,17 +70,104 @@ impl ChunkNode {
         }
     }
 
+    pub fn new_uniform(chunk: UniformChunk, state: NodeState) -> Self {
+        state.set_slot_state(SlotState::Uniform);
+        Self {
+            state,
+            chunk: RwLock::new(ChunkSlot { uniform: chunk }),
+        }
+    }
+
+    pub fn new_tile(tile: Arc<Tile>, state: NodeState) -> Self {
+        state.set_slot_state(SlotState::Tile);
+        Self {
+            state,
+            chunk: RwLock::new(ChunkSlot {
+                tile: ManuallyDrop::new(tile),
+            }),
+        }
+    }
+
     /// If the slot is currently compressed, then the compressed value is dropped.
+    ///
+    /// A [`UniformChunk`] is expanded into a temporary [`Chunk`] for the reader and stays uniform in the slot. Readers that can
+    /// handle uniform chunks should check [`ChunkNode::get_uniform`] first.
     pub fn get_decompressed(&self) -> Option<DecompressedChunk<'_>> {
-        match self.state.slot_state() {
-            SlotState::Compressed => self.decompress_for_read(),
+        let source = match self.state.slot_state() {
+            SlotState::Compressed => return self.decompress_for_read(),
             SlotState::Decompressed => {
                 // Fast path for when the chunk is already decompressed.
-                Some(DecompressedChunk {
-                    read_guard: self.chunk.read(),
-                })
+                ChunkSource::Locked(self.chunk.read())
+            }
+            SlotState::Uniform => ChunkSource::Expanded(self.get_uniform()?.to_chunk()),
+            SlotState::Tile => ChunkSource::Shared(self.get_tile()?),
+            SlotState::Empty => return None,
+        };
+        Some(DecompressedChunk { source })
+    }
+
+    /// Returns the shared [`Tile`] if this node is an unedited instance of one.
+    pub fn get_tile(&self) -> Option<Arc<Tile>> {
+        if self.state.slot_state() == SlotState::Tile {
+            // SAFE: Tiles are only replaced with exclusive access.
+            Some(Arc::clone(unsafe { &self.chunk.read().tile }))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value of a [`UniformChunk`] without expanding it.
+    pub fn get_uniform(&self) -> Option<UniformChunk> {
+        if self.state.slot_state() == SlotState::Uniform {
+            // SAFE: Uniform chunks are only replaced with exclusive access.
+            Some(unsafe { self.chunk.read().uniform })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the chunk for editing, first decompressing it, expanding a [`UniformChunk`], or copying a shared [`Tile`] if
+    /// necessary.
+    pub fn get_mut_decompressed(&mut self) -> Option<&mut Chunk> {
+        let full_chunk = match self.state.slot_state() {
+            SlotState::Empty => return None,
+            SlotState::Decompressed => None,
+            SlotState::Compressed => Some(Box::new(
+                unsafe { &self.chunk.get_mut().compressed }.decompress(),
+            )),
+            SlotState::Uniform => {
+                Some(Box::new(unsafe { self.chunk.get_mut().uniform }.to_chunk()))
+            }
+            SlotState::Tile => Some(Box::new(unsafe { &self.chunk.get_mut().tile }.chunk)),
+        };
+        if let Some(full_chunk) = full_chunk {
+            self.put_decompressed(full_chunk);
+        }
+        Some(unsafe { self.chunk.get_mut().decompressed.as_mut() })
+    }
+
+    /// Replaces the existing chunk value with a chunk that was loaded or edited, or empties the slot for `None`.
+    pub fn put_loaded(&mut self, chunk: Option<LoadedChunk>) -> Option<SlotValue> {
+        match chunk {
+            Some(LoadedChunk::Compressed(chunk)) => self.put_compressed(chunk),
+            Some(LoadedChunk::Uniform(uniform)) => self.put_uniform(uniform),
+            Some(LoadedChunk::Tile(tile)) => self.put_tile(tile),
+            None => self.take_chunk(),
+        }
+    }
+
+    /// Compresses a decompressed chunk, or stores it as a [`UniformChunk`] if every voxel is the same. Chunks in any other state
+    /// are left alone.
+    pub fn compress(&mut self) {
+        if self.state.slot_state() != SlotState::Decompressed {
+            return;
+        }
+        if let Some(SlotValue::Decompressed(chunk)) = self.take_chunk() {
+            if let Some(uniform) = chunk.as_uniform() {
+                self.put_uniform(uniform);
+            } else {
+                self.put_compressed(chunk.compress());
             }
-            SlotState::Empty => None,
         }
     }
 
@@ -100,60 +189,80 @@ impl ChunkNode {
                 self.state.state.clear_bit(StateBit::Compressed as u8);
 
                 Some(DecompressedChunk {
-                    read_guard: RwLockWriteGuard::downgrade(write_guard),
+                    source: ChunkSource::Locked(RwLockWriteGuard::downgrade(write_guard)),
                 })
             }
             SlotState::Decompressed => {
                 // Some other thread already decompressed for us. Downgrade to a read lock.
                 Some(DecompressedChunk {
-                    read_guard: RwLockWriteGuard::downgrade(write_guard),
+                    source: ChunkSource::Locked(RwLockWriteGuard::downgrade(write_guard)),
                 })
             }
+            // Only changed with exclusive access, so it can't have been compressed by another thread.
+            SlotState::Uniform | SlotState::Tile => unreachable!(),
             SlotState::Empty => None,
         }
     }
 
     /// Replace the existing chunk value with a [`CompressedChunk`].
-    pub fn put_compressed(
-        &mut self,
-        compressed: CompressedChunk,
-    ) -> Option<Either<Box<Chunk>, CompressedChunk>> {
+    pub fn put_compressed(&mut self, compressed: CompressedChunk) -> Option<SlotValue> {
         let old_value = self.replace_slot(ChunkSlot {
             compressed: ManuallyDrop::new(compressed),
         });
-        self.state.state.set_bit(StateBit::Occupied as u8);
-        self.state.state.set_bit(StateBit::Compressed as u8);
+        self.state.set_slot_state(SlotState::Compressed);
         old_value
     }
 
     /// Replace the existing chunk value with a [`Box<Chunk>`].
-    pub fn put_decompressed(
-        &mut self,
-        decompressed: Box<Chunk>,
-    ) -> Option<Either<Box<Chunk>, CompressedChunk>> {
+    pub fn put_decompressed(&mut self, decompressed: Box<Chunk>) -> Option<SlotValue> {
         let old_value = self.replace_slot(ChunkSlot {
             decompressed: ManuallyDrop::new(decompressed),
         });
-        self.state.state.set_bit(StateBit::Occupied as u8);
-        self.state.state.clear_bit(StateBit::Compressed as u8);
+        self.state.set_slot_state(SlotState::Decompressed);
+        old_value
+    }
+
+    /// Replace the existing chunk value with a [`UniformChunk`].
+    pub fn put_uniform(&mut self, uniform: UniformChunk) -> Option<SlotValue> {
+        let old_value = self.replace_slot(ChunkSlot { uniform });
+        self.state.set_slot_state(SlotState::Uniform);
+        old_value
+    }
+
+    /// Replace the existing chunk value with a shared [`Tile`].
+    pub fn put_tile(&mut self, tile: Arc<Tile>) -> Option<SlotValue> {
+        let old_value = self.replace_slot(ChunkSlot {
+            tile: ManuallyDrop::new(tile),
+        });
+        self.state.set_slot_state(SlotState::Tile);
         old_value
     }
 
     /// Take the existing chunk value, leaving the slot empty.
-    pub fn take_chunk(&mut self) -> Option<Either<Box<Chunk>, CompressedChunk>> {
+    pub fn take_chunk(&mut self) -> Option<SlotValue> {
         let old_value = self.replace_slot(ChunkSlot { empty: () });
-        self.state.state.clear_bit(StateBit::Occupied as u8);
+        self.state.set_slot_state(SlotState::Empty);
         old_value
     }
 
-    fn replace_slot(&mut self, new_slot: ChunkSlot) -> Option<Either<Box<Chunk>, CompressedChunk>> {
+    fn replace_slot(&mut self, new_slot: ChunkSlot) -> Option<SlotValue> {
         let mut_slot = self.chunk.get_mut();
         match self.state.slot_state() {
-            SlotState::Compressed => Some(Either::Right(ManuallyDrop::into_inner(unsafe {
-                mem::replace(&mut *mut_slot, new_slot).compressed
-            }))),
-            SlotState::Decompressed => Some(Either::Left(ManuallyDrop::into_inner(unsafe {
-                mem::replace(&mut *mut_slot, new_slot).decompressed
+            SlotState::Compressed => {
+                Some(SlotValue::Compressed(ManuallyDrop::into_inner(unsafe {
+                    mem::replace(&mut *mut_slot, new_slot).compressed
+                })))
+            }
+            SlotState::Decompressed => {
+                Some(SlotValue::Decompressed(ManuallyDrop::into_inner(unsafe {
+                    mem::replace(&mut *mut_slot, new_slot).decompressed
+                })))
+            }
+            SlotState::Uniform => Some(SlotValue::Uniform(unsafe {
+                mem::replace(&mut *mut_slot, new_slot).uniform
+            })),
+            SlotState::Tile => Some(SlotValue::Tile(ManuallyDrop::into_inner(unsafe {
+                mem::replace(&mut *mut_slot, new_slot).tile
             }))),
             SlotState::Empty => {
                 drop(mem::replace(&mut *mut_slot, new_slot));
@@ -177,6 +286,10 @@ enum StateBit {
     LoadPending = 3,
     /// This bit is set if the node is currently being rendered.
     Render = 4,
+    /// This bit is set if the slot holds a [`UniformChunk`].
+    Uniform = 5,
+    /// This bit is set if the slot holds a shared [`Tile`].
+    Tile = 6,
 }
 
 impl StateBit {
@@ -187,6 +300,8 @@ impl StateBit {
 
 const OCCUPIED_MASK: u8 = StateBit::Occupied.mask();
 const COMPRESSED_MASK: u8 = StateBit::Compressed.mask();
+const UNIFORM_MASK: u8 = StateBit::Uniform.mask();
+const TILE_MASK: u8 = StateBit::Tile.mask();
 
 pub struct NodeState {
     pub(crate) state: AtomicBitset8,
@@ -211,15 +326,41 @@ impl NodeState {
 
     #[inline]
     pub fn slot_state(&self) -> SlotState {
-        const MASK: u8 = OCCUPIED_MASK | COMPRESSED_MASK;
-        let and_mask = self.state.bits.fetch_and(MASK, Ordering::SeqCst);
-        match (
-            and_mask & OCCUPIED_MASK != 0,
-            and_mask & COMPRESSED_MASK != 0,
-        ) {
-            (true, true) => SlotState::Compressed,
-            (true, false) => SlotState::Decompressed,
-            (false, _) => SlotState::Empty,
+        // Just a load; fetch_and would clear the other bits.
+        let bits = self.state.bits.load(Ordering::SeqCst);
+        if bits & OCCUPIED_MASK == 0 {
+            SlotState::Empty
+        } else if bits & UNIFORM_MASK != 0 {
+            SlotState::Uniform
+        } else if bits & TILE_MASK != 0 {
+            SlotState::Tile
+        } else if bits & COMPRESSED_MASK != 0 {
+            SlotState::Compressed
+        } else {
+            SlotState::Decompressed
+        }
+    }
+
+    /// Sets all of the bits that determine the [`SlotState`]. The slot must be accessed exclusively.
+    fn set_slot_state(&self, slot_state: SlotState) {
+        let set_bits: &[StateBit] = match slot_state {
+            SlotState::Empty => &[],
+            SlotState::Compressed => &[StateBit::Occupied, StateBit::Compressed],
+            SlotState::Decompressed => &[StateBit::Occupied],
+            SlotState::Uniform => &[StateBit::Occupied, StateBit::Uniform],
+            SlotState::Tile => &[StateBit::Occupied, StateBit::Tile],
+        };
+        for bit in [
+            StateBit::Occupied,
+            StateBit::Compressed,
+            StateBit::Uniform,
+            StateBit::Tile,
+        ] {
+            if set_bits.contains(&bit) {
+                self.state.set_bit(bit as u8);
+            } else {
+                self.state.clear_bit(bit as u8);
+            }
         }
     }
 
@@ -289,35 +430,64 @@ pub enum SlotState {
     Empty,
     Compressed,
     Decompressed,
+    Uniform,
+    Tile,
+}
+
+/// The value taken out of a [`ChunkNode`]'s slot.
+#[derive(Debug, Eq, PartialEq)]
+pub enum SlotValue {
+    Compressed(CompressedChunk),
+    Decompressed(Box<Chunk>),
+    Uniform(UniformChunk),
+    Tile(Arc<Tile>),
 }
 
-/// A safe wrapper around a [`Chunk`] protected by an [`RwLockReadGuard`].
+/// A safe wrapper around a [`Chunk`] protected by an [`RwLockReadGuard`], a [`UniformChunk`] expanded for the reader, or a
+/// shared [`Tile`].
 pub struct DecompressedChunk<'a> {
-    read_guard: RwLockReadGuard<'a, ChunkSlot>,
+    source: ChunkSource<'a>,
+}
+
+// Boxing the expanded chunk would allocate on every read of a uniform slot.
+#[allow(clippy::large_enum_variant)]
+enum ChunkSource<'a> {
+    Locked(RwLockReadGuard<'a, ChunkSlot>),
+    Expanded(Chunk),
+    Shared(Arc<Tile>),
 }
 
 impl<'a> AsRef<Chunk> for DecompressedChunk<'a> {
     fn as_ref(&self) -> &Chunk {
-        // SAFE: Internals of ChunkNode guarantee this is a decompressed chunk.
-        unsafe { self.read_guard.decompressed.as_ref() }
+        match &self.source {
+            // SAFE: Internals of ChunkNode guarantee this is a decompressed chunk.
+            ChunkSource::Locked(read_guard) => unsafe { read_guard.decompressed.as_ref() },
+            ChunkSource::Expanded(chunk) => chunk,
+            ChunkSource::Shared(tile) => &tile.chunk,
+        }
     }
 }
 
 /// This slot type is nearly equivalent to this enum:
 /// ```
-/// # use feldspar_map::chunk::{Chunk, CompressedChunk};
+/// # use feldspar_map::chunk::{Chunk, CompressedChunk, Tile, UniformChunk};
+/// # use std::sync::Arc;
 /// enum ChunkSlot {
 ///     Empty,
 ///     Compressed(CompressedChunk),
 ///     Decompressed(Box<Chunk>),
+///     Uniform(UniformChunk),
+///     Tile(Arc<Tile>),
 /// }
 /// ```
 /// except that its discriminant lives on the [`ChunkNode`] that owns it, and fields must be manually dropped based on that
 /// discriminant.
 union ChunkSlot {
     empty: (),
+    uniform: UniformChunk,
     compressed: mem::ManuallyDrop<CompressedChunk>,
     decompressed: mem::ManuallyDrop<Box<Chunk>>,
+    tile: mem::ManuallyDrop<Arc<Tile>>,
 }
 
 impl Default for ChunkSlot {
@@ -338,6 +508,7 @@ const_assert_eq!(mem::size_of::<ChunkSlot>(), 2 * mem::size_of::<*const ()>());
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::chunk::TileId;
 
     #[test]
     fn chunk_node_data_slot_round_trip() {
@@ -370,7 +541,75 @@ mod test {
         assert_eq!(node.state().slot_state(), SlotState::Compressed);
 
         let chunk = node.take_chunk();
-        assert_eq!(chunk, Some(Either::Right(compressed_chunk.clone())));
+        assert_eq!(chunk, Some(SlotValue::Compressed(compressed_chunk.clone())));
+        assert_eq!(node.state().slot_state(), SlotState::Empty);
+    }
+
+    #[test]
+    fn uniform_slot_round_trip() {
+        let mut node = ChunkNode::new_empty(NodeState::new_zeroed());
+        node.state().set_load_pending();
+
+        // Compression detects uniform chunks.
+        node.put_decompressed(Box::new(Chunk::default()));
+        node.compress();
+        assert_eq!(node.state().slot_state(), SlotState::Uniform);
+        assert_eq!(node.get_uniform(), Some(UniformChunk::AMBIENT));
+        assert!(node.state().has_load_pending());
+
+        // Readers see a full chunk, but the slot stays uniform.
+        assert_eq!(node.get_decompressed().unwrap().as_ref(), &Chunk::default());
+        assert_eq!(node.state().slot_state(), SlotState::Uniform);
+
+        // Editing promotes to a full chunk.
+        node.get_mut_decompressed().unwrap().palette_ids[0] = 1;
+        assert_eq!(node.state().slot_state(), SlotState::Decompressed);
+        node.compress();
+        assert_eq!(node.state().slot_state(), SlotState::Compressed);
+
+        let uniform = UniformChunk {
+            sdf: crate::sdf::Sd8::MIN,
+            palette_id: 2,
+        };
+        assert!(matches!(
+            node.put_uniform(uniform),
+            Some(SlotValue::Compressed(_))
+        ));
+        assert_eq!(node.take_chunk(), Some(SlotValue::Uniform(uniform)));
         assert_eq!(node.state().slot_state(), SlotState::Empty);
     }
+
+    #[test]
+    fn tile_slot_copies_on_write() {
+        let mut chunk = Chunk::default();
+        chunk.palette_ids[0] = 1;
+        let tile = Arc::new(Tile {
+            id: TileId(3),
+            chunk,
+        });
+
+        let mut node1 = ChunkNode::new_tile(tile.clone(), NodeState::new_zeroed());
+        let mut node2 = ChunkNode::new_empty(NodeState::new_zeroed());
+        assert_eq!(node2.put_tile(tile.clone()), None);
+        assert_eq!(node1.state().slot_state(), SlotState::Tile);
+        assert_eq!(Arc::strong_count(&tile), 3);
+
+        // Readers share the tile.
+        assert_eq!(node1.get_decompressed().unwrap().as_ref(), &chunk);
+        assert!(Arc::ptr_eq(&node2.get_tile().unwrap(), &tile));
+
+        // Compression leaves the tile alone.
+        node1.compress();
+        assert_eq!(node1.state().slot_state(), SlotState::Tile);
+
+        // Editing copies the tile.
+        node1.get_mut_decompressed().unwrap().palette_ids[0] = 2;
+        assert_eq!(node1.state().slot_state(), SlotState::Decompressed);
+        assert_eq!(Arc::strong_count(&tile), 2);
+        assert_eq!(tile.chunk.palette_ids[0], 1);
+        assert_eq!(node2.get_decompressed().unwrap().as_ref(), &chunk);
+
+        assert_eq!(node2.take_chunk(), Some(SlotValue::Tile(tile.clone())));
+        assert_eq!(Arc::strong_count(&tile), 1);
+    }
 }
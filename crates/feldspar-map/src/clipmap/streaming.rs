@@ -9,6 +9,7 @@ use crate::units::VoxelUnits;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct StreamingConfig {
     /// A chunk is a *render candidate* if
     ///
@@ -0,0 +1,487 @@
+//! Codecs for the bytes of a [`CompressedChunk`](crate::chunk::CompressedChunk).
+//!
+//! Chunks were originally only compressed as LZ4 frames, which start with the LZ4 frame magic number. Every other codec writes
+//! a [`CodecHeader`] first, whose codec byte can't be confused with an LZ4 frame, a tile reference, or a version delta. So any
+//! stored chunk can be decompressed without knowing which codec the map was using when it was written.
+//!
+//! The header also records the widths of the distances and palette IDs, since some codecs treat the two halves of a chunk
+//! differently.
+
+mod huffman;
+
+use crate::chunk::CHUNK_SIZE;
+
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+use std::io::{self, Read};
+
+pub(crate) const LZ4_FRAME_MAGIC: [u8; 4] = 0x184D2204u32.to_le_bytes();
+
+/// Voxels in a row along the X axis are adjacent in a chunk.
+const ROW_LENGTH: usize = 16;
+
+/// How a map compresses the chunks it writes. Chunks written with any codec can always be read.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[repr(u8)]
+pub enum ChunkCodec {
+    /// The whole chunk as an LZ4 frame. Fast, and good at the large uniform regions of most chunks.
+    #[default]
+    Lz4Frame = 0,
+    /// The whole chunk as an LZ4 block, which saves the few bytes of frame overhead.
+    Lz4Block = 1,
+    /// Each distance is predicted from its neighbor along X, and the residuals are Huffman-coded along with the run lengths of
+    /// the palette IDs. Slower, but much smaller for smooth surfaces, especially with [`Sd16`](crate::sdf::Sd16) distances.
+    SdfPredictive = 2,
+    /// Distances as an LZ4 block and palette IDs as runs, for chunks with few materials.
+    PaletteRle = 3,
+}
+
+impl ChunkCodec {
+    pub fn from_u8(codec: u8) -> Option<Self> {
+        Some(match codec {
+            0 => Self::Lz4Frame,
+            1 => Self::Lz4Block,
+            2 => Self::SdfPredictive,
+            3 => Self::PaletteRle,
+            _ => return None,
+        })
+    }
+}
+
+/// Starts the bytes of every codec other than [`ChunkCodec::Lz4Frame`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CodecHeader {
+    pub codec: ChunkCodec,
+    /// Bytes per distance. Unknown (zero) for LZ4 frames, which don't need it.
+    pub sdf_width: u8,
+    /// Bytes per palette ID. Unknown (zero) for LZ4 frames, which don't need it.
+    pub palette_width: u8,
+}
+
+impl CodecHeader {
+    pub const LEN: usize = 3;
+
+    /// # Panics
+    ///
+    /// If either width is more than 4 bytes.
+    pub fn new(codec: ChunkCodec, sdf_width: usize, palette_width: usize) -> Self {
+        assert!(
+            (1..=4).contains(&sdf_width) && (1..=4).contains(&palette_width),
+            "Chunk codecs only support values up to 32 bits"
+        );
+        Self {
+            codec,
+            sdf_width: sdf_width as u8,
+            palette_width: palette_width as u8,
+        }
+    }
+
+    /// Reads the header of compressed bytes. Returns `None` if they are not compressed voxels, e.g. a tile reference.
+    pub fn read(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&LZ4_FRAME_MAGIC) {
+            return Some(Self {
+                codec: ChunkCodec::Lz4Frame,
+                sdf_width: 0,
+                palette_width: 0,
+            });
+        }
+        let header = bytes.get(..Self::LEN)?;
+        let codec = ChunkCodec::from_u8(header[0])?;
+        if codec == ChunkCodec::Lz4Frame
+            || !(1..=4).contains(&header[1])
+            || !(1..=4).contains(&header[2])
+        {
+            return None;
+        }
+        Some(Self {
+            codec,
+            sdf_width: header[1],
+            palette_width: header[2],
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        [self.codec as u8, self.sdf_width, self.palette_width]
+    }
+
+    fn sdf_len(&self) -> usize {
+        CHUNK_SIZE * self.sdf_width as usize
+    }
+
+    fn raw_len(&self) -> usize {
+        CHUNK_SIZE * (self.sdf_width + self.palette_width) as usize
+    }
+}
+
+/// Compresses the `raw` bytes of a chunk, which are all of its distances followed by all of its palette IDs.
+pub fn encode(header: CodecHeader, raw: &[u8]) -> Vec<u8> {
+    if header.codec == ChunkCodec::Lz4Frame {
+        let mut out = Vec::new();
+        compress_frame(raw, &mut out);
+        return out;
+    }
+
+    assert_eq!(raw.len(), header.raw_len());
+    let (sdf, palette_ids) = raw.split_at(header.sdf_len());
+    let mut out = header.to_bytes().to_vec();
+    match header.codec {
+        ChunkCodec::Lz4Frame => unreachable!(),
+        ChunkCodec::Lz4Block => {
+            out.extend_from_slice(&lz4_flex::block::compress_prepend_size(raw));
+        }
+        ChunkCodec::SdfPredictive => {
+            let residuals = encode_residuals(sdf, header.sdf_width as usize);
+            write_huffman(&encode_zero_runs(&residuals), &mut out);
+            write_huffman(
+                &encode_runs(palette_ids, header.palette_width as usize),
+                &mut out,
+            );
+        }
+        ChunkCodec::PaletteRle => {
+            let sdf = lz4_flex::block::compress_prepend_size(sdf);
+            out.extend_from_slice(&(sdf.len() as u32).to_le_bytes());
+            out.extend_from_slice(&sdf);
+            out.extend_from_slice(&encode_runs(palette_ids, header.palette_width as usize));
+        }
+    }
+    out
+}
+
+/// Decompresses `bytes` into `out`, which must be the size of the raw chunk. Returns `None` if `bytes` are not compressed
+/// voxels of that size.
+pub fn decode_into(bytes: &[u8], out: &mut [u8]) -> Option<CodecHeader> {
+    let header = CodecHeader::read(bytes)?;
+    if header.codec == ChunkCodec::Lz4Frame {
+        // Frames don't record the raw size up front, so a frame for a different layout is only caught by reading past `out`.
+        let mut decoder = FrameDecoder::new(bytes);
+        decoder.read_exact(out).ok()?;
+        if decoder.read(&mut [0]).ok()? != 0 {
+            return None;
+        }
+        return Some(header);
+    }
+    if out.len() != header.raw_len() {
+        return None;
+    }
+    let raw = decode_payload(header, &bytes[CodecHeader::LEN..])?;
+    out.copy_from_slice(&raw);
+    Some(header)
+}
+
+/// Decompresses `bytes` without knowing the size of the raw chunk. Returns `None` if `bytes` are not compressed voxels.
+pub fn decode(bytes: &[u8]) -> Option<(CodecHeader, Vec<u8>)> {
+    let header = CodecHeader::read(bytes)?;
+    if header.codec == ChunkCodec::Lz4Frame {
+        return Some((header, decompress_frame(bytes)?));
+    }
+    Some((header, decode_payload(header, &bytes[CodecHeader::LEN..])?))
+}
+
+pub(crate) fn compress_frame(raw: &[u8], out: &mut Vec<u8>) {
+    let mut encoder = FrameEncoder::new(out);
+    io::copy(&mut &*raw, &mut encoder).unwrap();
+    encoder.finish().unwrap();
+}
+
+pub(crate) fn decompress_frame(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut raw = Vec::new();
+    FrameDecoder::new(bytes).read_to_end(&mut raw).ok()?;
+    Some(raw)
+}
+
+fn decode_payload(header: CodecHeader, mut payload: &[u8]) -> Option<Vec<u8>> {
+    let sdf_len = header.sdf_len();
+    let palette_len = header.raw_len() - sdf_len;
+    let raw = match header.codec {
+        ChunkCodec::Lz4Frame => unreachable!(),
+        ChunkCodec::Lz4Block => decompress_block(payload, header.raw_len())?,
+        ChunkCodec::SdfPredictive => {
+            let residuals = decode_zero_runs(&read_huffman(&mut payload)?, sdf_len)?;
+            let mut raw = decode_residuals(&residuals, header.sdf_width as usize);
+            let runs = read_huffman(&mut payload)?;
+            raw.extend(decode_runs(
+                &runs,
+                header.palette_width as usize,
+                palette_len,
+            )?);
+            raw
+        }
+        ChunkCodec::PaletteRle => {
+            let sdf_bytes = read_u32(&mut payload)? as usize;
+            let mut raw = decompress_block(payload.get(..sdf_bytes)?, sdf_len)?;
+            raw.extend(decode_runs(
+                &payload[sdf_bytes..],
+                header.palette_width as usize,
+                palette_len,
+            )?);
+            raw
+        }
+    };
+    (raw.len() == header.raw_len()).then(|| raw)
+}
+
+/// Checks the prepended size before decompressing, so corrupt bytes can't cause a huge allocation.
+fn decompress_block(bytes: &[u8], len: usize) -> Option<Vec<u8>> {
+    if read_u32(&mut &*bytes)? as usize != len {
+        return None;
+    }
+    lz4_flex::block::decompress_size_prepended(bytes).ok()
+}
+
+/// Replaces each `width`-byte little-endian value with its zigzagged difference from the previous value in its row. Byte `k` of
+/// every residual goes into plane `k`, so the high bytes of small residuals form a long run of zeros.
+fn encode_residuals(values: &[u8], width: usize) -> Vec<u8> {
+    let mask = value_mask(width);
+    let shift = 32 - 8 * width as u32;
+    let num_values = values.len() / width;
+    let mut planes = vec![0; values.len()];
+    for (i, value) in values.chunks_exact(width).enumerate() {
+        let prev = if i % ROW_LENGTH == 0 {
+            0
+        } else {
+            read_value(&values[(i - 1) * width..i * width])
+        };
+        let residual = read_value(value).wrapping_sub(prev) & mask;
+        let signed = ((residual << shift) as i32) >> shift;
+        let zigzag = ((signed << 1) ^ (signed >> 31)) as u32 & mask;
+        for (k, byte) in zigzag.to_le_bytes()[..width].iter().enumerate() {
+            planes[k * num_values + i] = *byte;
+        }
+    }
+    planes
+}
+
+fn decode_residuals(planes: &[u8], width: usize) -> Vec<u8> {
+    let mask = value_mask(width);
+    let num_values = planes.len() / width;
+    let mut values = Vec::with_capacity(planes.len());
+    let mut prev = 0u32;
+    for i in 0..num_values {
+        let mut zigzag = [0; 4];
+        for (k, byte) in zigzag[..width].iter_mut().enumerate() {
+            *byte = planes[k * num_values + i];
+        }
+        let zigzag = u32::from_le_bytes(zigzag);
+        let residual = ((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32)) as u32;
+        if i % ROW_LENGTH == 0 {
+            prev = 0;
+        }
+        let value = prev.wrapping_add(residual) & mask;
+        values.extend_from_slice(&value.to_le_bytes()[..width]);
+        prev = value;
+    }
+    values
+}
+
+fn value_mask(width: usize) -> u32 {
+    ((1u64 << (8 * width)) - 1) as u32
+}
+
+fn read_value(bytes: &[u8]) -> u32 {
+    let mut value = [0; 4];
+    value[..bytes.len()].copy_from_slice(bytes);
+    u32::from_le_bytes(value)
+}
+
+/// Replaces each run of up to 256 zeros with a zero followed by the length of the run minus one.
+fn encode_zero_runs(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len() / 2);
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == 0 {
+            let run = bytes[i..].iter().take(256).take_while(|&&b| b == 0).count();
+            out.extend_from_slice(&[0, (run - 1) as u8]);
+            i += run;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn decode_zero_runs(bytes: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b == 0 {
+            let run = *iter.next()? as usize + 1;
+            out.resize(out.len() + run, 0);
+        } else {
+            out.push(b);
+        }
+    }
+    (out.len() == len).then(|| out)
+}
+
+/// Encodes runs of equal `width`-byte values as a LEB128 run length followed by the value.
+fn encode_runs(values: &[u8], width: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut values = values.chunks_exact(width).peekable();
+    while let Some(value) = values.next() {
+        let mut run = 1;
+        while values.next_if_eq(&value).is_some() {
+            run += 1;
+        }
+        while run >= 0x80 {
+            out.push(run as u8 | 0x80);
+            run >>= 7;
+        }
+        out.push(run as u8);
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+fn decode_runs(mut bytes: &[u8], width: usize, len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    while !bytes.is_empty() {
+        let mut run = 0usize;
+        let mut shift = 0;
+        loop {
+            let (&b, rest) = bytes.split_first()?;
+            bytes = rest;
+            run |= ((b & 0x7F) as usize) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+            if shift > 28 {
+                return None;
+            }
+        }
+        let value = bytes.get(..width)?;
+        bytes = &bytes[width..];
+        if out.len() + run * width > len {
+            return None;
+        }
+        for _ in 0..run {
+            out.extend_from_slice(value);
+        }
+    }
+    (out.len() == len).then(|| out)
+}
+
+/// Writes the number of symbols and the length of their Huffman coding before the coding itself.
+fn write_huffman(symbols: &[u8], out: &mut Vec<u8>) {
+    let coded = huffman::encode(symbols);
+    out.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(coded.len() as u32).to_le_bytes());
+    out.extend_from_slice(&coded);
+}
+
+fn read_huffman(bytes: &mut &[u8]) -> Option<Vec<u8>> {
+    let num_symbols = read_u32(bytes)? as usize;
+    let len = read_u32(bytes)? as usize;
+    let coded = bytes.get(..len)?;
+    *bytes = &bytes[len..];
+    // Every symbol takes at least one bit.
+    if num_symbols > 8 * coded.len() {
+        return None;
+    }
+    huffman::decode(coded, num_symbols)
+}
+
+fn read_u32(bytes: &mut &[u8]) -> Option<u32> {
+    let value = u32::from_le_bytes(bytes.get(..4)?.try_into().unwrap());
+    *bytes = &bytes[4..];
+    Some(value)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [ChunkCodec; 4] = [
+        ChunkCodec::Lz4Frame,
+        ChunkCodec::Lz4Block,
+        ChunkCodec::SdfPredictive,
+        ChunkCodec::PaletteRle,
+    ];
+
+    fn varied_raw(sdf_width: usize, palette_width: usize) -> Vec<u8> {
+        let mut rng = 777u32;
+        (0..CHUNK_SIZE * (sdf_width + palette_width))
+            .map(|i| {
+                rng = rng.wrapping_mul(1664525).wrapping_add(1013904223);
+                // A mix of runs and noise.
+                if (i / 300) % 2 == 0 {
+                    (i / 100) as u8
+                } else {
+                    (rng >> 24) as u8
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        for (sdf_width, palette_width) in [(1, 1), (1, 2), (2, 1), (2, 2), (4, 4)] {
+            let raw = varied_raw(sdf_width, palette_width);
+            for codec in CODECS {
+                let header = CodecHeader::new(codec, sdf_width, palette_width);
+                let bytes = encode(header, &raw);
+                let (decoded_header, decoded) = decode(&bytes).unwrap();
+                assert_eq!(decoded, raw, "{:?}", header);
+                assert_eq!(decoded_header.codec, codec);
+
+                let mut out = vec![0; raw.len()];
+                assert!(decode_into(&bytes, &mut out).is_some());
+                assert_eq!(out, raw);
+            }
+        }
+    }
+
+    #[test]
+    fn headers_are_not_other_formats() {
+        for codec in CODECS {
+            let bytes = encode(CodecHeader::new(codec, 1, 1), &varied_raw(1, 1));
+            assert!(!bytes.starts_with(b"TILE") && !bytes.starts_with(b"XDLT"));
+        }
+        assert_eq!(CodecHeader::read(b"TILE\0\0\0\0\0\0\0\0"), None);
+        assert_eq!(CodecHeader::read(&[ChunkCodec::Lz4Frame as u8, 1, 1]), None);
+        assert_eq!(CodecHeader::read(&[ChunkCodec::Lz4Block as u8, 0, 1]), None);
+    }
+
+    #[test]
+    fn corrupt_payloads_are_rejected() {
+        let raw = varied_raw(1, 1);
+        for codec in &CODECS[1..] {
+            let bytes = encode(CodecHeader::new(*codec, 1, 1), &raw);
+            assert_eq!(decode(&bytes[..bytes.len() / 2]), None);
+            let mut out = vec![0; raw.len()];
+            assert_eq!(decode_into(&bytes, &mut out[1..]), None);
+        }
+    }
+
+    #[test]
+    fn frames_of_the_wrong_length_are_rejected() {
+        let raw = varied_raw(1, 1);
+        let bytes = encode(CodecHeader::new(ChunkCodec::Lz4Frame, 1, 1), &raw);
+        let mut out = vec![0; raw.len() + 1];
+        assert_eq!(decode_into(&bytes, &mut out[2..]), None);
+        assert_eq!(decode_into(&bytes, &mut out), None);
+        assert!(decode_into(&bytes, &mut out[1..]).is_some());
+    }
+
+    #[test]
+    fn residuals_of_smooth_rows_are_small() {
+        let values: Vec<u8> = (0..32u16)
+            .flat_map(|v| (1000 + 3 * v).to_le_bytes())
+            .collect();
+        let planes = encode_residuals(&values, 2);
+        // Only the first value of each row is far from its predecessor.
+        assert!(planes[1..16].iter().all(|&b| b == 6));
+        assert!(planes[32..]
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b == 0 || i % 16 == 0));
+        assert_eq!(decode_residuals(&planes, 2), values);
+    }
+}
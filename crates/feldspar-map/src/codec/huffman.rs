@@ -0,0 +1,179 @@
+//! Canonical Huffman coding of bytes.
+//!
+//! Inputs are at most the size of one chunk, which bounds the code lengths far below 32 bits.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Encodes `symbols` as a table of code lengths followed by the MSB-first bitstream.
+///
+/// ```text
+/// num_used: u16
+/// [symbol: u8, code_length: u8; num_used]
+/// bits: [u8]
+/// ```
+pub fn encode(symbols: &[u8]) -> Vec<u8> {
+    let mut freqs = [0u32; 256];
+    for &s in symbols {
+        freqs[s as usize] += 1;
+    }
+    let lengths = code_lengths(&freqs);
+    let codes = canonical_codes(&lengths);
+
+    let num_used = lengths.iter().filter(|&&l| l > 0).count();
+    let mut out = Vec::with_capacity(2 + 2 * num_used + symbols.len() / 2);
+    out.extend_from_slice(&(num_used as u16).to_le_bytes());
+    for (symbol, &length) in lengths.iter().enumerate() {
+        if length > 0 {
+            out.push(symbol as u8);
+            out.push(length);
+        }
+    }
+
+    let mut writer = BitWriter {
+        out,
+        acc: 0,
+        num_bits: 0,
+    };
+    for &s in symbols {
+        writer.write(codes[s as usize], lengths[s as usize]);
+    }
+    writer.finish()
+}
+
+/// Decodes exactly `num_symbols` symbols. Returns `None` if `bytes` were not produced by [`encode`].
+pub fn decode(bytes: &[u8], num_symbols: usize) -> Option<Vec<u8>> {
+    let num_used = u16::from_le_bytes(bytes.get(..2)?.try_into().unwrap()) as usize;
+    let table = bytes.get(2..2 + 2 * num_used)?;
+    let mut lengths = [0u8; 256];
+    for entry in table.chunks_exact(2) {
+        if entry[1] == 0 || entry[1] > 32 {
+            return None;
+        }
+        lengths[entry[0] as usize] = entry[1];
+    }
+
+    // Canonical codes of each length are consecutive, so a code of length `l` is valid iff it's less than
+    // `first_code[l] + counts[l]`.
+    let mut counts = [0u32; 33];
+    for &l in lengths.iter() {
+        counts[l as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut sorted_symbols: Vec<u8> = (0..=255u8).filter(|&s| lengths[s as usize] > 0).collect();
+    sorted_symbols.sort_by_key(|&s| lengths[s as usize]);
+    let mut first_code = [0u64; 33];
+    let mut first_index = [0u32; 33];
+    for l in 1..32 {
+        first_code[l + 1] = (first_code[l] + counts[l] as u64) << 1;
+        first_index[l + 1] = first_index[l] + counts[l];
+    }
+
+    let mut reader = BitReader {
+        bytes: &bytes[2 + 2 * num_used..],
+        bit: 0,
+    };
+    let mut out = Vec::with_capacity(num_symbols);
+    for _ in 0..num_symbols {
+        let mut code = 0u64;
+        let mut length = 0;
+        loop {
+            code = (code << 1) | reader.read()? as u64;
+            length += 1;
+            if length > 32 {
+                return None;
+            }
+            if code < first_code[length] + counts[length] as u64 {
+                let index = first_index[length] + (code - first_code[length]) as u32;
+                out.push(sorted_symbols[index as usize]);
+                break;
+            }
+        }
+    }
+    Some(out)
+}
+
+fn code_lengths(freqs: &[u32; 256]) -> [u8; 256] {
+    let mut lengths = [0u8; 256];
+    let used: Vec<usize> = (0..256).filter(|&s| freqs[s] > 0).collect();
+    if used.len() == 1 {
+        lengths[used[0]] = 1;
+        return lengths;
+    }
+
+    // Nodes 0..256 are leaves, and the rest are internal nodes in the order they were created.
+    let mut parents = vec![usize::MAX; 256];
+    let mut heap: BinaryHeap<_> = used.iter().map(|&s| Reverse((freqs[s], s))).collect();
+    while heap.len() > 1 {
+        let Reverse((f1, n1)) = heap.pop().unwrap();
+        let Reverse((f2, n2)) = heap.pop().unwrap();
+        let parent = parents.len();
+        parents.push(usize::MAX);
+        parents[n1] = parent;
+        parents[n2] = parent;
+        heap.push(Reverse((f1 + f2, parent)));
+    }
+    for &s in used.iter() {
+        let mut depth = 0;
+        let mut node = s;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[s] = depth;
+    }
+    lengths
+}
+
+fn canonical_codes(lengths: &[u8; 256]) -> [u32; 256] {
+    let mut symbols: Vec<usize> = (0..256).filter(|&s| lengths[s] > 0).collect();
+    symbols.sort_by_key(|&s| lengths[s]);
+    let mut codes = [0u32; 256];
+    let mut code = 0u32;
+    let mut prev_length = 0;
+    for s in symbols {
+        code <<= lengths[s] - prev_length;
+        codes[s] = code;
+        code += 1;
+        prev_length = lengths[s];
+    }
+    codes
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    num_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u32, length: u8) {
+        self.acc = (self.acc << length) | code as u64;
+        self.num_bits += length as u32;
+        while self.num_bits >= 8 {
+            self.num_bits -= 8;
+            self.out.push((self.acc >> self.num_bits) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.num_bits > 0 {
+            self.out.push((self.acc << (8 - self.num_bits)) as u8);
+        }
+        self.out
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self) -> Option<u8> {
+        let byte = self.bytes.get(self.bit / 8)?;
+        let b = (byte >> (7 - self.bit % 8)) & 1;
+        self.bit += 1;
+        Some(b)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::chunk::{Chunk, Chunk16, ChunkData};
+use crate::sdf::SdfValue;
+
+/// A constructive solid geometry operation that combines a "brush" SDF into an existing SDF.
+///
+/// Since distances are clamped to one voxel, every operation is a per-voxel `min` or `max`. Voxels where the brush is at the
+/// ambient (outside) value are unaffected by [`CsgOp::Union`] and [`CsgOp::Subtract`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum CsgOp {
+    /// Adds the brush to the existing solid. Voxels where the brush is strictly nearer take the brush's palette ID, so ties
+    /// (including ambient brush voxels) keep the existing palette ID.
+    Union,
+    /// Carves the brush out of the existing solid. Palette IDs are unchanged.
+    Subtract,
+    /// Keeps only the parts of the existing solid that are also inside of the brush. Palette IDs are unchanged.
+    Intersect,
+}
+
+impl CsgOp {
+    /// Combines `brush` into `dst`.
+    pub fn apply(self, dst: &mut Chunk, brush: &Chunk) {
+        self.apply_generic(dst, brush)
+    }
+
+    /// Same as [`CsgOp::apply`] for the 16-bit palette ID layout.
+    pub fn apply16(self, dst: &mut Chunk16, brush: &Chunk16) {
+        self.apply_generic(dst, brush)
+    }
+
+    /// Same as [`CsgOp::apply`] for any [`ChunkLayout`](crate::chunk::ChunkLayout).
+    pub fn apply_generic<S: SdfValue, L: Copy>(
+        self,
+        dst: &mut ChunkData<S, L>,
+        brush: &ChunkData<S, L>,
+    ) {
+        let voxels = dst
+            .sdf
+            .iter_mut()
+            .zip(dst.palette_ids.iter_mut())
+            .zip(brush.sdf.iter().zip(brush.palette_ids.iter()));
+        match self {
+            CsgOp::Union => {
+                for ((dst_sdf, dst_id), (brush_sdf, brush_id)) in voxels {
+                    if *brush_sdf < *dst_sdf {
+                        *dst_sdf = *brush_sdf;
+                        *dst_id = *brush_id;
+                    }
+                }
+            }
+            CsgOp::Subtract => {
+                for ((dst_sdf, _), (brush_sdf, _)) in voxels {
+                    *dst_sdf = (*dst_sdf).max(-*brush_sdf);
+                }
+            }
+            CsgOp::Intersect => {
+                for ((dst_sdf, _), (brush_sdf, _)) in voxels {
+                    *dst_sdf = (*dst_sdf).max(*brush_sdf);
+                }
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf::Sd8;
+
+    #[test]
+    fn ambient_brush_only_affects_intersect() {
+        let mut dst = Chunk::default();
+        dst.sdf[0] = Sd8::MIN;
+        dst.palette_ids[0] = 1;
+        let brush = Chunk::default();
+
+        for op in [CsgOp::Union, CsgOp::Subtract] {
+            let mut result = dst;
+            op.apply(&mut result, &brush);
+            assert_eq!(result, dst);
+        }
+
+        let mut result = dst;
+        CsgOp::Intersect.apply(&mut result, &brush);
+        assert_eq!(result.sdf[0], Sd8::MAX);
+        assert_eq!(result.palette_ids[0], 1);
+    }
+
+    #[test]
+    fn union_keeps_palette_ids_where_brush_is_not_nearer() {
+        let mut dst = Chunk::default();
+        dst.palette_ids[0] = 1;
+        dst.sdf[1] = Sd8::ZERO;
+        dst.palette_ids[1] = 1;
+        let mut brush = Chunk::default();
+        brush.palette_ids[0] = 2;
+        brush.sdf[1] = Sd8::ZERO;
+        brush.palette_ids[1] = 2;
+
+        let mut result = dst;
+        CsgOp::Union.apply(&mut result, &brush);
+        assert_eq!(result, dst);
+    }
+
+    #[test]
+    fn solid_brush() {
+        let dst = Chunk::default();
+        let mut brush = Chunk::default();
+        brush.sdf[0] = Sd8::MIN;
+        brush.palette_ids[0] = 2;
+
+        let mut result = dst;
+        CsgOp::Union.apply(&mut result, &brush);
+        assert_eq!((result.sdf[0], result.palette_ids[0]), (Sd8::MIN, 2));
+
+        CsgOp::Subtract.apply(&mut result, &brush);
+        assert_eq!((result.sdf[0], result.palette_ids[0]), (Sd8::MAX, 2));
+    }
+}
@@ -1,46 +1,90 @@
 mod backup_tree;
+mod catalog;
 mod change_encoder;
 mod chunk_key;
+mod fsck;
+mod merge;
 mod meta_tree;
+mod migration;
+mod palette_remap;
+mod portable;
+mod stats;
+mod store;
+mod tile_tree;
 mod version_change_tree;
+mod version_delta;
 mod version_graph_tree;
 mod working_tree;
+mod write_behind;
 
+pub use catalog::{CatalogError, MapCatalog};
 pub use change_encoder::*;
 pub use chunk_key::ChunkDbKey;
+pub use fsck::{FsckProblem, FsckTree};
+pub use merge::{MergeConflict, MergeSummary};
+pub use migration::MAP_FORMAT_VERSION;
+pub use palette_remap::PaletteUsage;
+pub use portable::{PortableMapError, PORTABLE_FORMAT_VERSION};
+pub use stats::{LevelStats, MapStats, VersionStats};
+pub use store::*;
+pub use tile_tree::SharedTileCache;
 pub use version_change_tree::VersionChanges;
+pub use write_behind::{MapDbGuard, WriteBehindConfig, WriteBehindMapDb, MAX_COMMIT_ATTEMPTS};
 
 use backup_tree::{
     clear_backup, commit_backup, open_backup_tree, write_changes_to_backup_tree, BackupKeyCache,
 };
-use meta_tree::{open_meta_tree, write_meta};
+use merge::accumulate_archived_changes;
+use meta_tree::{
+    material_registry_key, open_meta_tree, read_chunk_codec, remove_material_registry,
+    write_chunk_codec, write_material_registry, write_meta,
+};
+use migration::migrate;
+use tile_tree::open_tile_tree;
 use version_change_tree::{archive_version, open_version_change_tree, remove_archived_version};
+use version_delta::{encode_version_deltas, is_delta_change, read_working_change, resolve_delta};
 use version_graph_tree::{
-    find_path_between_versions, link_version, open_version_graph_tree, VersionNode,
+    find_common_ancestor_paths, find_path_between_versions, link_version, open_version_graph_tree,
+    VersionNode,
 };
 use working_tree::{open_working_tree, write_changes_to_working_tree};
 
+use crate::chunk::{Chunk, Chunk16, ChunkData, ChunkLayout, CompressedChunk, TileId};
+use crate::clipmap::Level;
+use crate::codec::ChunkCodec;
+use crate::coordinates::in_chunk_extent;
 use crate::core::archived_buf::ArchivedBuf;
+use crate::core::glam::IVec3;
+use crate::core::ilattice::prelude::Extent;
 use crate::core::rkyv::{Archive, Deserialize, Infallible, Serialize};
-use crate::chunk::CompressedChunk;
-use crate::clipmap::Level;
+use crate::core::SmallKeyHashMap;
+use crate::csg::CsgOp;
+use crate::heightmap::{visit_heightmap_chunks, Heightmap, HeightmapConfig, SplatMap};
+use crate::material_registry::MaterialRegistry;
+use crate::mesh_import::{voxelize_mesh, TriangleMesh, VoxelizeConfig};
+use crate::palette::{Palette8, PaletteId16, PaletteId8};
+use crate::sdf::{Sd16, Sd8, SdfValue};
 use crate::units::*;
-use crate::vox::convert_vox_model_to_chunks;
+use crate::vox::{
+    chunks_to_vox_scene, convert_vox_model_to_chunks, occupancy_to_chunks, VoxColor, VoxModel,
+    VoxScene,
+};
 
+use bytecheck::CheckBytes;
+use bytemuck::Pod;
 use itertools::Itertools;
-use sled::transaction::{abort, TransactionError};
-use sled::{IVec, Transactional, Tree};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use self::meta_tree::MapDbMetadata;
 
-type ArchivedIVec<T> = ArchivedBuf<T, IVec>;
+type ArchivedIVec<T> = ArchivedBuf<T, StoreBytes>;
 
 #[derive(
     Archive, Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, PartialOrd, Ord, Serialize,
 )]
 #[archive(crate = "crate::core::rkyv")]
-#[archive_attr(derive(Debug, Eq, PartialEq, PartialOrd, Ord))]
+#[archive_attr(derive(CheckBytes, Debug, Eq, PartialEq, PartialOrd, Ord))]
 pub struct Version {
     pub number: u64,
 }
@@ -53,6 +97,18 @@ impl Version {
     pub const fn into_sled_key(self) -> [u8; 8] {
         self.number.to_be_bytes()
     }
+
+    /// # Panics
+    ///
+    /// If `bytes` is not 8 bytes long. Use [`Self::try_from_sled_key`] for keys that might be corrupt.
+    pub fn from_sled_key(bytes: &[u8]) -> Self {
+        Self::try_from_sled_key(bytes).expect("Invalid version key")
+    }
+
+    /// Returns `None` if `bytes` is not 8 bytes long.
+    pub fn try_from_sled_key(bytes: &[u8]) -> Option<Self> {
+        Some(Self::new(u64::from_be_bytes(bytes.try_into().ok()?)))
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -63,6 +119,82 @@ pub enum AbortReason {
     NoPathExistsToRoot,
     /// Tried to reference [`VersionChanges`] that don't exist in the change tree.
     MissingVersionChanges,
+    /// Tried to open an existing map with a different [`ChunkLayout`] than it was created with.
+    ChunkLayoutMismatch { stored: ChunkLayout },
+    /// A chunk can't reference a tile that isn't in the tile table.
+    MissingTile { tile: TileId },
+    /// An archived delta for `key` could not be applied to the value it was supposed to be encoded against.
+    InvalidVersionDelta { key: ChunkDbKey },
+    /// The stored value of `key` in the working or backup tree is not a valid archive.
+    InvalidChunkArchive { key: ChunkDbKey },
+    /// The stored [`VersionChanges`] of `version` are not a valid archive.
+    InvalidVersionArchive { version: Version },
+    /// The stored node of `version` in the version graph is not a valid archive.
+    InvalidVersionNode { version: Version },
+    /// The stored [`MaterialRegistry`] of `version` is not a valid archive.
+    InvalidMaterialRegistry { version: Version },
+    /// The stored bytes of `tile` are not a valid compressed chunk.
+    InvalidTile { tile: TileId },
+    /// The map was written by a newer build of feldspar with a format this build doesn't understand. See
+    /// [`MAP_FORMAT_VERSION`].
+    UnsupportedFormat { format_version: u32 },
+    /// The stored map metadata can't be read in any known format.
+    InvalidMetadata,
+    /// The operation doesn't support the map's [`ChunkLayout`].
+    UnsupportedLayout { layout: ChunkLayout },
+}
+
+/// An error from reading the working version outside of a transaction.
+#[derive(Debug, PartialEq)]
+pub enum ReadError {
+    Storage(StoreError),
+    /// The stored value of `key` is not a valid archive or compressed chunk, e.g. because the database was corrupted.
+    InvalidArchive {
+        key: ChunkDbKey,
+    },
+    /// The stored bytes of `tile` are not a valid compressed chunk.
+    InvalidTile {
+        tile: TileId,
+    },
+    /// The stored node of `version` in the version graph is not a valid archive.
+    InvalidVersionNode {
+        version: Version,
+    },
+    /// The stored [`MaterialRegistry`] of `version` is not a valid archive.
+    InvalidMaterialRegistry {
+        version: Version,
+    },
+    /// The operation doesn't support the map's [`ChunkLayout`].
+    UnsupportedLayout {
+        layout: ChunkLayout,
+    },
+}
+
+impl From<StoreError> for ReadError {
+    fn from(e: StoreError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl From<ReadError> for TransactionError<AbortReason> {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Storage(e) => Self::Storage(e),
+            ReadError::InvalidArchive { key } => {
+                Self::Abort(AbortReason::InvalidChunkArchive { key })
+            }
+            ReadError::InvalidTile { tile } => Self::Abort(AbortReason::InvalidTile { tile }),
+            ReadError::InvalidVersionNode { version } => {
+                Self::Abort(AbortReason::InvalidVersionNode { version })
+            }
+            ReadError::InvalidMaterialRegistry { version } => {
+                Self::Abort(AbortReason::InvalidMaterialRegistry { version })
+            }
+            ReadError::UnsupportedLayout { layout } => {
+                Self::Abort(AbortReason::UnsupportedLayout { layout })
+            }
+        }
+    }
 }
 
 /// # Map Database
@@ -72,49 +204,107 @@ pub enum AbortReason {
 ///
 /// ## Implementation
 ///
-/// All user data is stored in three [`sled::Tree`]s.
+/// All user data is stored in the trees of a [`MapStore`], which is a [`sled::Db`] by default. The trees are described below.
 ///
 /// ### Working Tree
 ///
 /// One tree is used for the *working* [`Version`] of the map, and it stores all of the [`CompressedChunk`] data for the working
 /// version. All new changes are written to this tree.
 ///
+/// Chunks written by the map are compressed with its [`ChunkCodec`], which is stored in the meta tree. Every codec is
+/// self-describing, so changing the codec only affects chunks written afterwards.
+///
 /// ### Backup Tree
 ///
 /// As new changes are written, the old values are moved into the "backup tree." The backup tree is just a persistent buffer
 /// that eventually gets archived when the working version is committed.
 ///
+/// ### Tile Tree
+///
+/// Chunks that are repeated all over the map, e.g. by procedural generation, can be stored once in the "tile tree." A chunk in
+/// the working tree may then be a [`CompressedChunk::tile_reference`] instead of voxels. Tiles are immutable, so editing an
+/// instance of a tile writes a copy of the edited chunk.
+///
 /// ### Version Tree
 ///
 /// Archived versions get an entry in the "version tree." This stores an actual tree structure where each node has a parent
 /// version (except for the root version). To "revert" to a parent version, all of the backed up values must be re-applied in
 /// reverse order, while the corresponding newer values are archived. By transitivity, any archived version can be reached from
 /// the current working version.
-pub struct MapDb {
-    meta_tree: Tree,
-    working_tree: Tree,
-    backup_tree: Tree,
+///
+/// Brush strokes usually change a few voxels of each chunk, so an archived chunk is stored as an LZ4-compressed XOR delta
+/// against the same chunk in the neighboring version on the path to the working version, whenever that is smaller. Since
+/// archived changes are only applied to the working tree when it holds that neighboring version, the delta base is always
+/// available.
+pub struct MapDb<S: MapStore = sled::Db> {
+    meta_tree: S::Tree,
+    working_tree: S::Tree,
+    backup_tree: S::Tree,
 
     // We keep the change tree and graph trees separate so that finding a path between versions does not require reading all of
     // the changes associated with each version.
-    version_change_tree: Tree,
-    version_graph_tree: Tree,
+    version_change_tree: S::Tree,
+    version_graph_tree: S::Tree,
+    tile_tree: S::Tree,
 
     /// HACK: We only have this type to work around sled's lack of transactional iteration. When archiving a version, we iterate
     /// over this set of keys and put the entries into the archive.
     backup_key_cache: BackupKeyCache,
     // Zero-copy isn't super important for this tiny struct, so we just copy it for convenience.
     cached_meta: MapDbMetadata,
+    /// The registry of the working version.
+    cached_material_registry: MaterialRegistry,
+    /// See [`MapDb::material_registry_revision`].
+    material_registry_revision: u64,
+    /// Never changes after the map is created.
+    chunk_layout: ChunkLayout,
+    chunk_codec: ChunkCodec,
+    shared_tiles: SharedTileCache,
 }
 
-impl MapDb {
+impl<S: MapStore> MapDb<S> {
     /// Opens the database. On first open, a single working version will be created with no parent version.
-    pub fn open(db: &sled::Db, map_name: &str) -> Result<Self, TransactionError<AbortReason>> {
-        let (meta_tree, cached_meta) = open_meta_tree(map_name, db)?;
-        let version_change_tree = open_version_change_tree(map_name, db)?;
-        let version_graph_tree = open_version_graph_tree(map_name, db)?;
-        let (backup_tree, backup_key_cache) = open_backup_tree(map_name, db)?;
-        let working_tree = open_working_tree(map_name, db)?;
+    ///
+    /// New maps use the [`ChunkLayout::Palette8`] layout, while existing maps keep the layout they were created with.
+    ///
+    /// Existing maps in an older format are migrated to [`MAP_FORMAT_VERSION`] first, and maps in a newer format fail to open.
+    pub fn open(store: &S, map_name: &str) -> Result<Self, TransactionError<AbortReason>> {
+        Self::open_inner(store, map_name, None)
+    }
+
+    /// Same as [`MapDb::open`], but a new map is created with `layout`, and an existing map must already have `layout`.
+    pub fn open_with_layout(
+        store: &S,
+        map_name: &str,
+        layout: ChunkLayout,
+    ) -> Result<Self, TransactionError<AbortReason>> {
+        Self::open_inner(store, map_name, Some(layout))
+    }
+
+    fn open_inner(
+        store: &S,
+        map_name: &str,
+        required_layout: Option<ChunkLayout>,
+    ) -> Result<Self, TransactionError<AbortReason>> {
+        migrate(map_name, store)?;
+        let (meta_tree, cached_meta, chunk_layout) =
+            open_meta_tree(map_name, store, required_layout.unwrap_or_default())?;
+        if let Some(required) = required_layout {
+            if required != chunk_layout {
+                return Err(TransactionError::Abort(AbortReason::ChunkLayoutMismatch {
+                    stored: chunk_layout,
+                }));
+            }
+        }
+        let version_change_tree = open_version_change_tree(map_name, store)?;
+        let version_graph_tree = open_version_graph_tree(map_name, store)?;
+        let (backup_tree, backup_key_cache) = open_backup_tree(map_name, store)?;
+        let working_tree = open_working_tree(map_name, store)?;
+        let tile_tree = open_tile_tree(map_name, store)?;
+        let cached_material_registry =
+            read_stored_material_registry(&meta_tree, &version_graph_tree, &cached_meta)?;
+        let chunk_codec =
+            S::transaction([&meta_tree], |[meta_txn]| Ok(read_chunk_codec(meta_txn)?))?;
 
         Ok(Self {
             meta_tree,
@@ -122,31 +312,256 @@ impl MapDb {
             backup_tree,
             version_change_tree,
             version_graph_tree,
+            tile_tree,
             backup_key_cache,
             cached_meta,
+            cached_material_registry,
+            material_registry_revision: next_material_registry_revision(),
+            chunk_layout,
+            chunk_codec,
+            shared_tiles: SharedTileCache::default(),
         })
     }
 
+    pub fn chunk_layout(&self) -> ChunkLayout {
+        self.chunk_layout
+    }
+
+    pub fn chunk_codec(&self) -> ChunkCodec {
+        self.chunk_codec
+    }
+
+    /// Chooses how chunks written from now on are compressed. Unlike the layout, the codec can be changed at any time, since
+    /// chunks written with every codec remain readable.
+    pub fn set_chunk_codec(
+        &mut self,
+        codec: ChunkCodec,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        S::transaction([&self.meta_tree], |[meta_txn]| {
+            write_chunk_codec(meta_txn, codec)?;
+            Ok(())
+        })?;
+        self.chunk_codec = codec;
+        Ok(())
+    }
+
+    /// Fails with [`ReadError::UnsupportedLayout`] unless the map has `layout`, for the methods that only support one layout.
+    fn require_layout(&self, layout: ChunkLayout) -> Result<(), ReadError> {
+        if self.chunk_layout == layout {
+            Ok(())
+        } else {
+            Err(ReadError::UnsupportedLayout {
+                layout: self.chunk_layout,
+            })
+        }
+    }
+
+    /// Compresses `chunk` with this map's [`ChunkCodec`] after converting it to this map's [`ChunkLayout`].
+    fn compress_in_layout(&self, chunk: &Chunk) -> CompressedChunk {
+        let codec = self.chunk_codec;
+        match self.chunk_layout {
+            ChunkLayout::Palette8 => chunk.compress_with(codec),
+            ChunkLayout::Palette16 => chunk.convert::<Sd8, PaletteId16>().compress_with(codec),
+            ChunkLayout::Sd16Palette8 => chunk.convert::<Sd16, PaletteId8>().compress_with(codec),
+            ChunkLayout::Sd16Palette16 => chunk.convert::<Sd16, PaletteId16>().compress_with(codec),
+        }
+    }
+
+    /// Combines `brush` with the existing working chunk at `key` by CSG union, after converting it to this map's
+    /// [`ChunkLayout`].
+    fn union_in_layout(
+        &self,
+        key: ChunkDbKey,
+        brush: &Chunk,
+    ) -> Result<CompressedChunk, ReadError> {
+        match self.chunk_layout {
+            ChunkLayout::Palette8 => self.union_with_working_chunk(key, *brush),
+            ChunkLayout::Palette16 => {
+                self.union_with_working_chunk(key, brush.convert::<Sd8, PaletteId16>())
+            }
+            ChunkLayout::Sd16Palette8 => {
+                self.union_with_working_chunk(key, brush.convert::<Sd16, PaletteId8>())
+            }
+            ChunkLayout::Sd16Palette16 => {
+                self.union_with_working_chunk(key, brush.convert::<Sd16, PaletteId16>())
+            }
+        }
+    }
+
+    fn union_with_working_chunk<Sd: SdfValue, L: Pod + Default>(
+        &self,
+        key: ChunkDbKey,
+        brush: ChunkData<Sd, L>,
+    ) -> Result<CompressedChunk, ReadError> {
+        let mut existing = self.read_working_chunk_as(key)?.unwrap_or_default();
+        CsgOp::Union.apply_generic(&mut existing, &brush);
+        Ok(existing.compress_with(self.chunk_codec))
+    }
+
     /// Writes all data from `model` into `target_lod` of the working version.
-    pub fn import_vox(&mut self, target_lod: Level, model: &vox_format::types::Model) -> Result<(), TransactionError> {
+    pub fn import_vox(
+        &mut self,
+        target_lod: Level,
+        model: &VoxModel,
+    ) -> Result<(), TransactionError<AbortReason>> {
         let chunks = convert_vox_model_to_chunks(model);
         // Write the chunks into the database.
         let mut encoder = ChangeEncoder::default();
         for (ChunkUnits(chunk_coords), chunk) in chunks.into_iter() {
-            encoder.add_compressed_change(ChunkDbKey::new(target_lod, chunk_coords.into()), Change::Insert(chunk.compress()));
+            encoder.add_compressed_change(
+                ChunkDbKey::new(target_lod, chunk_coords.into()),
+                Change::Insert(self.compress_in_layout(&chunk)),
+            );
+        }
+        self.write_working_version(encoder.encode())
+    }
+
+    /// Writes every model instance in `scene` into `level` of the working version, translated by `offset` voxels at `level`.
+    ///
+    /// The imported voxels are combined with any existing chunks by CSG union, so existing terrain near the imported models is
+    /// preserved. Returns the palette that maps each imported [`PaletteId8`](crate::palette::PaletteId8) to its vox color. In other
+    /// [`ChunkLayout`]s, the imported IDs are widened without an offset.
+    pub fn import_vox_scene(
+        &mut self,
+        scene: &VoxScene,
+        offset: VoxelUnits<IVec3>,
+        level: Level,
+    ) -> Result<Palette8<VoxColor>, TransactionError<AbortReason>> {
+        let chunks = occupancy_to_chunks(&scene.occupancy(offset));
+        let mut encoder = ChangeEncoder::default();
+        for (ChunkUnits(chunk_coords), chunk) in chunks.into_iter() {
+            let key = ChunkDbKey::new(level, chunk_coords.into());
+            let combined = self.union_in_layout(key, &chunk)?;
+            encoder.add_compressed_change(key, Change::Insert(combined));
+        }
+        self.write_working_version(encoder.encode())?;
+        Ok(scene.palette8())
+    }
+
+    /// Voxelizes `mesh` and combines it into the working version with `config.csg_op`, as a single set of changes.
+    ///
+    /// Only chunks overlapping the mesh's bounding box are touched, so [`CsgOp::Intersect`] leaves chunks outside of it
+    /// unchanged. See [`voxelize_mesh`] for details.
+    pub fn import_mesh(
+        &mut self,
+        mesh: &TriangleMesh,
+        config: &VoxelizeConfig,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        self.require_layout(ChunkLayout::Palette8)?;
+        let mut encoder = ChangeEncoder::default();
+        for (ChunkUnits(chunk_coords), brush) in voxelize_mesh(mesh, config).into_iter() {
+            let key = ChunkDbKey::new(config.level, chunk_coords.into());
+            let mut existing = self.read_working_chunk(key)?.unwrap_or_default();
+            config.csg_op.apply(&mut existing, &brush);
+            encoder.add_compressed_change(
+                key,
+                Change::Insert(existing.compress_with(self.chunk_codec)),
+            );
         }
         self.write_working_version(encoder.encode())
     }
 
+    /// Writes the terrain described by `heightmap` into the working version as a single set of changes.
+    ///
+    /// The terrain is combined with any existing chunks by CSG union, like [`MapDb::import_vox_scene`]. See
+    /// [`visit_heightmap_chunks`] for details.
+    pub fn import_heightmap(
+        &mut self,
+        heightmap: &Heightmap,
+        splat_map: Option<&SplatMap>,
+        config: &HeightmapConfig,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        let mut encoder = ChangeEncoder::default();
+        visit_heightmap_chunks(heightmap, splat_map, config, |key, chunk| {
+            let combined = self.union_in_layout(key, &chunk)?;
+            encoder.add_compressed_change(key, Change::Insert(combined));
+            Ok::<_, ReadError>(())
+        })?;
+        self.write_working_version(encoder.encode())
+    }
+
+    /// Exports the voxels of `extent` at `level` of the working version as a [`VoxScene`].
+    ///
+    /// See [`chunks_to_vox_scene`] for details.
+    pub fn export_vox(
+        &self,
+        level: Level,
+        extent: VoxelUnits<Extent<IVec3>>,
+        palette: &Palette8<VoxColor>,
+    ) -> Result<VoxScene, ReadError> {
+        self.require_layout(ChunkLayout::Palette8)?;
+        let mut chunks = SmallKeyHashMap::default();
+        for result in self.read_extent(level, in_chunk_extent(extent)) {
+            let (key, change) = result?;
+            if let Some(chunk) = change.as_ref().get_insert_data() {
+                if let Some(chunk) = self.decompress_stored_chunk(key, &chunk.bytes)? {
+                    chunks.insert(ChunkUnits(IVec3::from(key.morton)), chunk);
+                }
+            }
+        }
+        Ok(chunks_to_vox_scene(extent, palette, |coords| {
+            chunks.remove(&coords)
+        }))
+    }
+
     pub fn cached_meta(&self) -> &MapDbMetadata {
         &self.cached_meta
     }
 
+    /// The [`MaterialRegistry`] of the working version.
+    pub fn material_registry(&self) -> &MaterialRegistry {
+        &self.cached_material_registry
+    }
+
+    /// Changes whenever [`MapDb::material_registry`] might have changed. Revisions are unique across all maps in the process, so
+    /// a system can tell when the registry of a replaced map differs from the one it has seen.
+    pub fn material_registry_revision(&self) -> u64 {
+        self.material_registry_revision
+    }
+
+    /// Replaces the [`MaterialRegistry`] of the working version. Like chunk changes, this is recorded in the working version and
+    /// committed along with it.
+    pub fn write_material_registry(
+        &mut self,
+        registry: MaterialRegistry,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        let working_version = self.cached_meta.working_version;
+        S::transaction([&self.meta_tree], |[meta_txn]| {
+            write_material_registry(meta_txn, working_version, &registry)?;
+            Ok(())
+        })?;
+        self.set_cached_material_registry(registry);
+        Ok(())
+    }
+
+    fn set_cached_material_registry(&mut self, registry: MaterialRegistry) {
+        self.cached_material_registry = registry;
+        self.material_registry_revision = next_material_registry_revision();
+    }
+
+    /// Returns `Some(changed)` if the working version stores its own [`MaterialRegistry`], where `changed` is `true` if it differs
+    /// from the registry that the working version would otherwise inherit from its parent.
+    fn working_material_registry_changed(&self) -> Result<Option<bool>, ReadError> {
+        let working = match self
+            .meta_tree
+            .get(&material_registry_key(self.cached_meta.working_version))?
+        {
+            Some(working) => working,
+            None => return Ok(None),
+        };
+        let inherited = inherited_material_registry_bytes(
+            &self.meta_tree,
+            &self.version_graph_tree,
+            self.cached_meta.parent_version,
+        )?;
+        Ok(Some(inherited.map(|(_, bytes)| bytes) != Some(working)))
+    }
+
     /// Writes `changes` to the working version and stores the old values in the backup tree.
     pub fn write_working_version(
         &mut self,
         changes: EncodedChanges<CompressedChunk>,
-    ) -> Result<(), TransactionError> {
+    ) -> Result<(), TransactionError<AbortReason>> {
         log::trace!("Writing to {:?}", self.cached_meta.working_version);
         let Self {
             working_tree,
@@ -154,8 +569,9 @@ impl MapDb {
             backup_key_cache,
             ..
         } = self;
-        let new_backup_keys: Vec<_> =
-            (&*working_tree, &*backup_tree).transaction(|(working_txn, backup_txn)| {
+        let new_backup_keys: Vec<_> = S::transaction(
+            [&*working_tree, &*backup_tree],
+            |[working_txn, backup_txn]| {
                 let reverse_changes =
                     write_changes_to_working_tree(working_txn, backup_key_cache, changes.clone())?;
                 let new_backup_keys = reverse_changes
@@ -165,7 +581,8 @@ impl MapDb {
                     .collect();
                 write_changes_to_backup_tree(backup_txn, reverse_changes)?;
                 Ok(new_backup_keys)
-            })?;
+            },
+        )?;
         // Transaction succeeded, so add the new keys to the backup cache.
         for key in new_backup_keys.into_iter() {
             debug_assert!(!backup_key_cache.keys.contains(&key));
@@ -175,21 +592,87 @@ impl MapDb {
     }
 
     /// Reads the compressed bytes of the chunk at `key` for the working version.
+    ///
+    /// The bytes are validated, so a corrupted database returns [`ReadError::InvalidArchive`] instead of undefined behavior.
     pub fn read_working_version(
         &self,
         key: ChunkDbKey,
-    ) -> Result<Option<ArchivedChangeIVec<CompressedChunk>>, sled::Error> {
-        let bytes = self.working_tree.get(IVec::from(&key.into_sled_key()))?;
-        Ok(bytes.map(|b| unsafe { ArchivedIVec::<Change<CompressedChunk>>::new(b) }))
+    ) -> Result<Option<ArchivedChangeIVec<CompressedChunk>>, ReadError> {
+        match self.working_tree.get(&key.into_sled_key())? {
+            Some(bytes) => ArchivedChangeIVec::try_new(bytes)
+                .map(Some)
+                .map_err(|_| ReadError::InvalidArchive { key }),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads and decompresses the chunk at `key` for the working version of a map with the [`ChunkLayout::Palette8`] layout.
+    pub fn read_working_chunk(&self, key: ChunkDbKey) -> Result<Option<Chunk>, ReadError> {
+        self.require_layout(ChunkLayout::Palette8)?;
+        self.read_working_chunk_as(key)
+    }
+
+    /// Reads and decompresses the chunk at `key` for the working version of a map with the [`ChunkLayout::Palette16`] layout.
+    pub fn read_working_chunk16(&self, key: ChunkDbKey) -> Result<Option<Chunk16>, ReadError> {
+        self.require_layout(ChunkLayout::Palette16)?;
+        self.read_working_chunk_as(key)
+    }
+
+    /// Reads and decompresses the chunk at `key` for the working version as any [`ChunkData`] type, which must match the map's
+    /// [`ChunkLayout`]. Tile references are resolved to the voxels of the tile.
+    pub fn read_working_chunk_as<Sd: SdfValue, L: Pod>(
+        &self,
+        key: ChunkDbKey,
+    ) -> Result<Option<ChunkData<Sd, L>>, ReadError> {
+        let change = match self.read_working_version(key)? {
+            Some(change) => change,
+            None => return Ok(None),
+        };
+        match change.as_ref().get_insert_data() {
+            Some(chunk) => self.decompress_stored_chunk(key, &chunk.bytes),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the compressed bytes of every chunk at `level` whose coordinates are in `extent` for the working version.
+    ///
+    /// This is a single range scan over the Morton-ordered keys. The Morton range of an extent also covers some keys outside
+    /// of the extent, so those are filtered out. Like [`MapDb::read_working_version`], the bytes are validated.
+    pub fn read_extent(
+        &self,
+        level: Level,
+        extent: ChunkUnits<Extent<IVec3>>,
+    ) -> impl Iterator<Item = Result<(ChunkDbKey, ArchivedChangeIVec<CompressedChunk>), ReadError>>
+    {
+        let ChunkUnits(extent) = extent;
+        let key_range = ChunkDbKey::extent_range(level, extent);
+        self.working_tree
+            .range(
+                &key_range.start().into_sled_key(),
+                &key_range.end().into_sled_key(),
+            )
+            .filter_map(move |iter_result| match iter_result {
+                Ok((key_bytes, value_bytes)) => {
+                    let key = ChunkDbKey::from_sled_key(&key_bytes);
+                    extent.contains(IVec3::from(key.morton)).then(|| {
+                        ArchivedChangeIVec::<CompressedChunk>::try_new(value_bytes)
+                            .map(|change| (key, change))
+                            .map_err(|_| ReadError::InvalidArchive { key })
+                    })
+                }
+                Err(e) => Some(Err(e.into())),
+            })
     }
 
     /// Archives the backup tree entries into a [`VersionChanges`] that gets serialized and stored in the version change tree
     /// with the current working [`Version`]. A new working version is generated and the old working version becomes the parent
     /// version.
     ///
-    /// Nothing happens if the working version has no changes.
+    /// Nothing happens if the working version has no chunk changes and the same [`MaterialRegistry`] as its parent. The new
+    /// working version starts with a copy of the committed registry.
     pub fn commit_working_version(&mut self) -> Result<(), TransactionError<AbortReason>> {
-        if self.backup_key_cache.keys.is_empty() {
+        let registry_changed = self.working_material_registry_changed()?;
+        if self.backup_key_cache.keys.is_empty() && registry_changed != Some(true) {
             return Ok(());
         }
 
@@ -198,20 +681,20 @@ impl MapDb {
             self.cached_meta.working_version
         );
 
-        let new_meta = (
-            &self.backup_tree,
-            &self.version_graph_tree,
-            &self.version_change_tree,
-            &self.meta_tree,
-        )
-            .transaction(|(backup_txn, graph_txn, changes_txn, meta_txn)| {
+        let new_meta = S::transaction(
+            [
+                &self.backup_tree,
+                &self.version_graph_tree,
+                &self.version_change_tree,
+                &self.meta_tree,
+                &self.working_tree,
+            ],
+            |[backup_txn, graph_txn, changes_txn, meta_txn, working_txn]| {
                 if let Some(parent) = self.cached_meta.parent_version {
                     log::trace!("Archiving {:?} from backup", parent);
-                    archive_version(
-                        changes_txn,
-                        parent,
-                        &commit_backup(backup_txn, &self.backup_key_cache)?,
-                    )?;
+                    let mut changes = commit_backup(backup_txn, &self.backup_key_cache)?;
+                    encode_version_deltas(working_txn, &mut changes)?;
+                    archive_version(changes_txn, parent, &changes)?;
                 } else {
                     // We only need to do this once, but it's important for correctness.
                     clear_backup(backup_txn, &self.backup_key_cache)?;
@@ -228,9 +711,14 @@ impl MapDb {
                     parent_version: Some(self.cached_meta.working_version),
                     working_version: Version::new(graph_txn.generate_id()?),
                 };
+                if registry_changed == Some(false) {
+                    // The new working version inherits the registry either way, so there's no reason to keep a copy.
+                    remove_material_registry(meta_txn, self.cached_meta.working_version)?;
+                }
                 write_meta(meta_txn, &new_meta)?;
                 Ok(new_meta)
-            })?;
+            },
+        )?;
         self.backup_key_cache.keys.clear();
         self.cached_meta = new_meta;
         Ok(())
@@ -251,13 +739,14 @@ impl MapDb {
         let old_meta = self.cached_meta;
 
         if let Some(old_parent_version) = old_meta.parent_version {
-            let new_meta = (
-                &self.meta_tree,
-                &self.version_graph_tree,
-                &self.version_change_tree,
-                &self.working_tree,
-            )
-                .transaction(|(meta_txn, graph_txn, change_txn, working_txn)| {
+            let new_meta = S::transaction(
+                [
+                    &self.meta_tree,
+                    &self.version_graph_tree,
+                    &self.version_change_tree,
+                    &self.working_tree,
+                ],
+                |[meta_txn, graph_txn, change_txn, working_txn]| {
                     // Apply the archived changes from all versions between the old parent version and the new parent version,
                     // leaving behind the inverse changes.
                     let path = find_path_between_versions(
@@ -280,7 +769,17 @@ impl MapDb {
                                 let key: ChunkDbKey = key.deserialize(&mut Infallible).unwrap();
                                 // PERF: in principle we should be able to copy the compressed bytes directly from the archived
                                 // change, but the types aren't set up for that yet
-                                let change = change.deserialize(&mut Infallible).unwrap();
+                                let mut change = change.deserialize(&mut Infallible).unwrap();
+                                if is_delta_change(&change) {
+                                    // The working tree still has the values of prev_version.
+                                    let base = read_working_change(working_txn, key)?;
+                                    change = match resolve_delta(change, &base) {
+                                        Some(change) => change,
+                                        None => {
+                                            return abort(AbortReason::InvalidVersionDelta { key })
+                                        }
+                                    };
+                                }
                                 encoder.add_compressed_change(key, change);
                             }
                             let reverse_changes = write_changes_to_working_tree(
@@ -288,7 +787,8 @@ impl MapDb {
                                 &empty_backup_keys,
                                 encoder.encode(),
                             )?;
-                            let prev_version_changes = VersionChanges::from(&reverse_changes);
+                            let mut prev_version_changes = VersionChanges::from(&reverse_changes);
+                            encode_version_deltas(working_txn, &mut prev_version_changes)?;
                             log::trace!("Archiving {:?} from working tree", prev_version,);
                             archive_version(change_txn, prev_version, &prev_version_changes)?;
                         } else {
@@ -301,14 +801,181 @@ impl MapDb {
                         parent_version: Some(new_parent_version),
                         working_version: new_working_version,
                     };
+                    // The old working version is abandoned, and the new one inherits the registry of its parent.
+                    remove_material_registry(meta_txn, old_meta.working_version)?;
                     write_meta(meta_txn, &new_meta)?;
                     Ok(new_meta)
-                })?;
+                },
+            )?;
             self.cached_meta = new_meta;
+            let registry = read_stored_material_registry(
+                &self.meta_tree,
+                &self.version_graph_tree,
+                &new_meta,
+            )?;
+            self.set_cached_material_registry(registry);
         }
 
         Ok(())
     }
+
+    /// Performs a three-way merge of `other_version` into the working version.
+    ///
+    /// The working version is committed first. Then the nearest common ancestor of the parent version and `other_version` is
+    /// found, and every chunk that `other_version` changed since that ancestor is written into the new working version. If
+    /// both branches changed the same key to different values, then `resolve` chooses the value to keep.
+    ///
+    /// The merged changes are not committed, so they can be inspected or amended before calling `commit_working_version`. The
+    /// [`MaterialRegistry`] is not merged; the working version keeps its own.
+    pub fn merge(
+        &mut self,
+        other_version: Version,
+        mut resolve: impl FnMut(&MergeConflict) -> Change<CompressedChunk>,
+    ) -> Result<MergeSummary, TransactionError<AbortReason>> {
+        self.commit_working_version()?;
+
+        let parent_version = if let Some(parent) = self.cached_meta.parent_version {
+            parent
+        } else {
+            // Nothing has been committed, so there is nothing to merge with.
+            return Ok(MergeSummary::default());
+        };
+
+        log::trace!(
+            "Merging {:?} into parent {:?}",
+            other_version,
+            parent_version
+        );
+
+        let (base_values, their_values) = S::transaction(
+            [
+                &self.version_graph_tree,
+                &self.version_change_tree,
+                &self.working_tree,
+            ],
+            |[graph_txn, change_txn, working_txn]| {
+                let paths = find_common_ancestor_paths(graph_txn, parent_version, other_version)?;
+                log::trace!("Nearest common ancestor is {:?}", paths.common_ancestor());
+
+                // Walking up from our parent to the common ancestor yields the ancestor's values for every key we changed.
+                // The working version is empty, so the working tree has our parent's values.
+                let mut base_values = BTreeMap::new();
+                accumulate_archived_changes(
+                    change_txn,
+                    paths.start_path[1..].iter().copied(),
+                    |key| read_working_change(working_txn, key),
+                    &mut base_values,
+                )?;
+
+                // Walking down from the common ancestor to other_version yields their latest value for every key they
+                // changed.
+                let mut their_values = BTreeMap::new();
+                accumulate_archived_changes(
+                    change_txn,
+                    paths.end_path.iter().rev().skip(1).copied(),
+                    |key| match base_values.get(&key) {
+                        Some(base) => Ok(base.clone()),
+                        None => read_working_change(working_txn, key),
+                    },
+                    &mut their_values,
+                )?;
+
+                Ok((base_values, their_values))
+            },
+        )?;
+
+        let mut summary = MergeSummary::default();
+        let mut encoder = ChangeEncoder::default();
+        for (key, theirs) in their_values.into_iter() {
+            let ours = self
+                .read_working_version(key)?
+                .map(|c| c.deserialize())
+                .unwrap_or(Change::Remove);
+            if ours == theirs {
+                continue;
+            }
+
+            let merged = if let Some(base) = base_values.remove(&key) {
+                if base == theirs {
+                    // They reverted to the base value; keep our change.
+                    continue;
+                } else if base == ours {
+                    // We reverted to the base value; take their change.
+                    theirs
+                } else {
+                    let conflict = MergeConflict {
+                        key,
+                        base,
+                        ours,
+                        theirs,
+                    };
+                    summary.conflicts.push(key);
+                    let resolved = resolve(&conflict);
+                    if resolved == conflict.ours {
+                        continue;
+                    }
+                    resolved
+                }
+            } else {
+                // We never changed this key.
+                theirs
+            };
+
+            encoder.add_compressed_change(key, merged);
+            summary.num_changes_applied += 1;
+        }
+
+        self.write_working_version(encoder.encode())?;
+
+        Ok(summary)
+    }
+}
+
+/// Reads the [`MaterialRegistry`] of the working version described by `meta`, which may be inherited from an ancestor.
+fn read_stored_material_registry(
+    meta_tree: &impl StoreTree,
+    version_graph_tree: &impl StoreTree,
+    meta: &MapDbMetadata,
+) -> Result<MaterialRegistry, ReadError> {
+    let stored = match meta_tree.get(&material_registry_key(meta.working_version))? {
+        Some(bytes) => Some((meta.working_version, bytes)),
+        None => {
+            inherited_material_registry_bytes(meta_tree, version_graph_tree, meta.parent_version)?
+        }
+    };
+    match stored {
+        Some((version, bytes)) => match ArchivedIVec::<MaterialRegistry>::try_new(bytes) {
+            Ok(registry) => Ok(registry.deserialize()),
+            Err(_) => Err(ReadError::InvalidMaterialRegistry { version }),
+        },
+        None => Ok(MaterialRegistry::default()),
+    }
+}
+
+/// The stored registry of `version` or its nearest ancestor that stores one, along with the version that stores it.
+fn inherited_material_registry_bytes(
+    meta_tree: &impl StoreTree,
+    version_graph_tree: &impl StoreTree,
+    mut version: Option<Version>,
+) -> Result<Option<(Version, StoreBytes)>, ReadError> {
+    while let Some(v) = version {
+        if let Some(bytes) = meta_tree.get(&material_registry_key(v))? {
+            return Ok(Some((v, bytes)));
+        }
+        version = match version_graph_tree.get(&v.into_sled_key())? {
+            Some(node) => match ArchivedIVec::<VersionNode>::try_new(node) {
+                Ok(node) => node.deserialize().parent_version,
+                Err(_) => return Err(ReadError::InvalidVersionNode { version: v }),
+            },
+            None => None,
+        };
+    }
+    Ok(None)
+}
+
+fn next_material_registry_revision() -> u64 {
+    static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
 // ████████╗███████╗███████╗████████╗
@@ -321,13 +988,80 @@ impl MapDb {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::chunk::Chunk;
-    use crate::core::glam::IVec3;
+    use crate::chunk::{ChunkShape, CHUNK_SIZE};
+    use crate::codec::CodecHeader;
+    use crate::mesh_import::read_obj;
+    use crate::vox::{default_vox_palette, VoxInstance, VoxModel, VoxTransform};
 
-    #[test]
-    fn write_and_read_changes_same_version() {
-        let db = sled::Config::default().temporary(true).open().unwrap();
-        let mut map = MapDb::open(&db, "mymap").unwrap();
+    use ndshape::ConstShape;
+
+    /// Generates a test for each [`MapStore`] implementation that calls the generic test function with a new store.
+    macro_rules! test_all_stores {
+        ($($test_fn:ident),* $(,)?) => {
+            $(
+                mod $test_fn {
+                    #[test]
+                    fn sled_store() {
+                        super::$test_fn(sled::Config::default().temporary(true).open().unwrap());
+                    }
+
+                    #[test]
+                    fn mem_store() {
+                        super::$test_fn(super::MemStore::default());
+                    }
+                }
+            )*
+        };
+    }
+
+    /// A scene with one voxel of `color_index` at the origin.
+    pub(crate) fn single_voxel_scene(color_index: PaletteId8) -> VoxScene {
+        VoxScene {
+            models: vec![VoxModel {
+                size: IVec3::ONE,
+                voxels: vec![(IVec3::ZERO, color_index)],
+            }],
+            instances: vec![VoxInstance {
+                model: 0,
+                transform: VoxTransform::IDENTITY,
+            }],
+            palette: default_vox_palette(),
+        }
+    }
+
+    /// A chunk of random distances and palette IDs from a fixed `seed`. Noise doesn't compress well on its own, so it's useful
+    /// for testing how well small edits compress.
+    pub(crate) fn noise_chunk(seed: u32) -> Chunk {
+        let mut chunk = Chunk::default();
+        let mut rng = seed;
+        for i in 0..CHUNK_SIZE {
+            rng = rng.wrapping_mul(1664525).wrapping_add(1013904223);
+            chunk.sdf[i] = Sd8::from((rng >> 24) as f32 / 128.0 - 1.0);
+            chunk.palette_ids[i] = (rng >> 8) as u8 % 5;
+        }
+        chunk
+    }
+
+    test_all_stores!(
+        write_and_read_changes_same_version,
+        read_extent_filters_keys_outside_extent,
+        commit_empty_working_version_does_nothing,
+        commit_multiple_versions_with_changes_and_branch,
+        merge_divergent_branches,
+        brush_strokes_are_archived_as_deltas,
+        import_vox_scene_unions_with_existing_terrain,
+        import_heightmap_unions_with_existing_terrain,
+        import_mesh_subtracts_from_existing_terrain,
+        material_registry_is_versioned,
+        palette16_layout_is_persisted,
+        sd16_layout_imports_vox_scene,
+        chunk_codec_is_persisted,
+        corrupt_working_value_is_an_error,
+        corrupt_stored_values_are_errors,
+    );
+
+    fn write_and_read_changes_same_version<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
 
         let chunk_key = ChunkDbKey::new(1, IVec3::ZERO.into());
         let mut encoder = ChangeEncoder::default();
@@ -341,10 +1075,333 @@ mod tests {
         );
     }
 
-    #[test]
-    fn commit_empty_working_version_does_nothing() {
-        let db = sled::Config::default().temporary(true).open().unwrap();
-        let mut map = MapDb::open(&db, "mymap").unwrap();
+    fn import_vox_scene_unions_with_existing_terrain<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut existing = Chunk::default();
+        existing.set_voxel(IVec3::splat(5), 9, Sd8::MIN);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(existing.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let scene = single_voxel_scene(3);
+        let palette = map
+            .import_vox_scene(&scene, VoxelUnits(IVec3::ONE), 0)
+            .unwrap();
+        assert_eq!(palette[3], default_vox_palette()[3]);
+
+        let chunk = map
+            .read_working_version(key)
+            .unwrap()
+            .unwrap()
+            .deserialize()
+            .unwrap_insert()
+            .decompress();
+        let voxel = |p: IVec3| {
+            let i = ChunkShape::linearize(p.to_array()) as usize;
+            (chunk.sdf[i], chunk.palette_ids[i])
+        };
+        assert_eq!(voxel(IVec3::ONE), (Sd8::from(-0.5), 3));
+        assert_eq!(voxel(IVec3::new(2, 1, 1)), (Sd8::from(0.5), 3));
+        assert_eq!(voxel(IVec3::splat(5)), (Sd8::MIN, 9));
+    }
+
+    fn import_heightmap_unions_with_existing_terrain<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut existing = Chunk::default();
+        // One voxel above the heightmap's surface and one outside of its footprint.
+        existing.set_voxel(IVec3::new(0, 12, 0), 9, Sd8::MIN);
+        existing.set_voxel(IVec3::new(10, 1, 10), 9, Sd8::MIN);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(existing.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let heightmap = Heightmap::new(2, 2, vec![3; 4]).unwrap();
+        let config = HeightmapConfig {
+            palette_id: 7,
+            ..Default::default()
+        };
+        map.import_heightmap(&heightmap, None, &config).unwrap();
+
+        let chunk = map
+            .read_working_version(key)
+            .unwrap()
+            .unwrap()
+            .deserialize()
+            .unwrap_insert()
+            .decompress();
+        let voxel = |p: IVec3| {
+            let i = ChunkShape::linearize(p.to_array()) as usize;
+            (chunk.sdf[i], chunk.palette_ids[i])
+        };
+        let (terrain_sd, terrain_id) = voxel(IVec3::new(1, 1, 1));
+        assert!(terrain_sd.0 < 0);
+        assert_eq!(terrain_id, 7);
+        assert_eq!(voxel(IVec3::new(0, 12, 0)), (Sd8::MIN, 9));
+        assert_eq!(voxel(IVec3::new(10, 1, 10)), (Sd8::MIN, 9));
+    }
+
+    fn palette16_layout_is_persisted<S: MapStore>(store: S) {
+        let mut map = MapDb::open_with_layout(&store, "mymap", ChunkLayout::Palette16).unwrap();
+        assert_eq!(map.chunk_layout(), ChunkLayout::Palette16);
+
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut existing = Chunk16::default();
+        existing.set_voxel(IVec3::splat(5), 1000, Sd8::MIN);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(existing.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let scene = single_voxel_scene(3);
+        map.import_vox_scene(&scene, VoxelUnits(IVec3::ONE), 0)
+            .unwrap();
+        drop(map);
+
+        // Existing maps keep their layout, and it can't be changed.
+        assert!(matches!(
+            MapDb::open_with_layout(&store, "mymap", ChunkLayout::Palette8),
+            Err(TransactionError::Abort(AbortReason::ChunkLayoutMismatch {
+                stored: ChunkLayout::Palette16
+            }))
+        ));
+        let map = MapDb::open(&store, "mymap").unwrap();
+        assert_eq!(map.chunk_layout(), ChunkLayout::Palette16);
+
+        let chunk = map.read_working_chunk16(key).unwrap().unwrap();
+        let voxel = |p: IVec3| {
+            let i = ChunkShape::linearize(p.to_array()) as usize;
+            (chunk.sdf[i], chunk.palette_ids[i])
+        };
+        assert_eq!(voxel(IVec3::ONE), (Sd8::from(-0.5), 3));
+        assert_eq!(voxel(IVec3::splat(5)), (Sd8::MIN, 1000));
+
+        // Methods that only support 8-bit palette IDs report the layout instead of panicking.
+        let unsupported = || {
+            Some(ReadError::UnsupportedLayout {
+                layout: ChunkLayout::Palette16,
+            })
+        };
+        assert_eq!(map.read_working_chunk(key).err(), unsupported());
+        assert_eq!(map.palette_usage().err(), unsupported());
+        let extent = VoxelUnits(Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(16)));
+        assert_eq!(
+            map.export_vox(0, extent, &default_vox_palette()).err(),
+            unsupported()
+        );
+    }
+
+    fn chunk_codec_is_persisted<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        assert_eq!(map.chunk_codec(), ChunkCodec::Lz4Frame);
+
+        let scene = single_voxel_scene(3);
+        let old_key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        map.import_vox_scene(&scene, VoxelUnits(IVec3::ONE), 0)
+            .unwrap();
+
+        map.set_chunk_codec(ChunkCodec::SdfPredictive).unwrap();
+        let new_key = ChunkDbKey::new(0, IVec3::new(1, 0, 0).into());
+        map.import_vox_scene(&scene, VoxelUnits(IVec3::new(17, 1, 1)), 0)
+            .unwrap();
+        drop(map);
+
+        let map = MapDb::open(&store, "mymap").unwrap();
+        assert_eq!(map.chunk_codec(), ChunkCodec::SdfPredictive);
+
+        // Chunks written with either codec are readable.
+        let stored_codec = |key| {
+            let stored = map.read_working_version(key).unwrap().unwrap();
+            CodecHeader::read(&stored.deserialize().unwrap_insert().bytes)
+                .unwrap()
+                .codec
+        };
+        assert_eq!(stored_codec(old_key), ChunkCodec::Lz4Frame);
+        assert_eq!(stored_codec(new_key), ChunkCodec::SdfPredictive);
+        let old_chunk = map.read_working_chunk(old_key).unwrap().unwrap();
+        let new_chunk = map.read_working_chunk(new_key).unwrap().unwrap();
+        assert_eq!(
+            old_chunk.palette_ids[ChunkShape::linearize([1; 3]) as usize],
+            3
+        );
+        assert_eq!(
+            new_chunk.palette_ids[ChunkShape::linearize([1; 3]) as usize],
+            3
+        );
+    }
+
+    fn corrupt_working_value_is_an_error<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let result: Result<(), TransactionError<AbortReason>> =
+            S::transaction([&map.working_tree], |[working_txn]| {
+                working_txn.insert(&key.into_sled_key(), StoreBytes::from(&[0xFF; 12][..]))?;
+                Ok(())
+            });
+        result.unwrap();
+
+        assert_eq!(
+            map.read_working_version(key),
+            Err(ReadError::InvalidArchive { key })
+        );
+        assert_eq!(
+            map.read_working_chunk(key),
+            Err(ReadError::InvalidArchive { key })
+        );
+
+        // Importers read the existing chunks before writing.
+        let scene = single_voxel_scene(3);
+        assert!(matches!(
+            map.import_vox_scene(&scene, VoxelUnits(IVec3::ONE), 0),
+            Err(TransactionError::Abort(AbortReason::InvalidChunkArchive { key: k })) if k == key
+        ));
+    }
+
+    fn corrupt_stored_values_are_errors<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+
+        // A valid change whose bytes are not compressed voxels.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(
+            key,
+            Change::Insert(CompressedChunk {
+                bytes: vec![0xFF; 16].into_boxed_slice(),
+            }),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+        assert_eq!(
+            map.read_working_chunk(key),
+            Err(ReadError::InvalidArchive { key })
+        );
+        assert!(matches!(
+            map.read_working_loaded_chunk(key),
+            Err(ReadError::InvalidArchive { key: k }) if k == key
+        ));
+        assert_eq!(
+            map.palette_usage().err(),
+            Some(ReadError::InvalidArchive { key })
+        );
+
+        let working_version = map.cached_meta().working_version;
+        let result: Result<(), TransactionError<AbortReason>> =
+            S::transaction([&map.meta_tree], |[meta_txn]| {
+                meta_txn.insert(
+                    &material_registry_key(working_version),
+                    StoreBytes::from(&[0xFF; 12][..]),
+                )?;
+                Ok(())
+            });
+        result.unwrap();
+        assert!(matches!(
+            MapDb::open(&store, "mymap"),
+            Err(TransactionError::Abort(AbortReason::InvalidMaterialRegistry { version }))
+                if version == working_version
+        ));
+    }
+
+    fn sd16_layout_imports_vox_scene<S: MapStore>(store: S) {
+        let mut map = MapDb::open_with_layout(&store, "mymap", ChunkLayout::Sd16Palette8).unwrap();
+
+        let scene = single_voxel_scene(3);
+        map.import_vox_scene(&scene, VoxelUnits(IVec3::ONE), 0)
+            .unwrap();
+
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let chunk: ChunkData<Sd16, PaletteId8> = map.read_working_chunk_as(key).unwrap().unwrap();
+        let i = ChunkShape::linearize([1; 3]) as usize;
+        assert_eq!(chunk.sdf[i], Sd16::from(Sd8::from(-0.5)));
+        assert_eq!(chunk.palette_ids[i], 3);
+        assert_eq!(chunk.sdf[0], Sd16::MAX);
+    }
+
+    fn import_mesh_subtracts_from_existing_terrain<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let solid = Chunk {
+            sdf: [Sd8::MIN; CHUNK_SIZE],
+            palette_ids: [1; CHUNK_SIZE],
+        };
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(solid.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let cube_obj = "\
+v 2.5 2.5 2.5
+v 5.5 2.5 2.5
+v 2.5 5.5 2.5
+v 5.5 5.5 2.5
+v 2.5 2.5 5.5
+v 5.5 2.5 5.5
+v 2.5 5.5 5.5
+v 5.5 5.5 5.5
+f 1 5 7 3
+f 2 4 8 6
+f 1 2 6 5
+f 3 7 8 4
+f 1 3 4 2
+f 5 6 8 7
+";
+        let mesh = read_obj(cube_obj.as_bytes()).unwrap();
+        let config = VoxelizeConfig {
+            csg_op: CsgOp::Subtract,
+            ..Default::default()
+        };
+        map.import_mesh(&mesh, &config).unwrap();
+
+        let chunk = map.read_working_chunk(key).unwrap().unwrap();
+        let sdf = |p: IVec3| chunk.sdf[ChunkShape::linearize(p.to_array()) as usize];
+        assert_eq!(sdf(IVec3::splat(4)), Sd8::MAX);
+        assert_eq!(sdf(IVec3::splat(3)), Sd8::from(0.5));
+        assert_eq!(sdf(IVec3::splat(10)), Sd8::MIN);
+    }
+
+    fn read_extent_filters_keys_outside_extent<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let extent = Extent::from_min_and_shape(IVec3::ZERO, IVec3::splat(2));
+
+        let mut encoder = ChangeEncoder::default();
+        for p in Extent::from_min_and_shape(IVec3::splat(-1), IVec3::splat(4)).iter3() {
+            encoder.add_compressed_change(
+                ChunkDbKey::new(0, p.into()),
+                Change::Insert(Chunk::default().compress()),
+            );
+        }
+        // Same coordinates on a different level.
+        encoder.add_compressed_change(
+            ChunkDbKey::new(1, IVec3::ZERO.into()),
+            Change::Insert(Chunk::default().compress()),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let mut read_keys: Vec<_> = map
+            .read_extent(0, ChunkUnits(extent))
+            .map(|result| {
+                let (key, value) = result.unwrap();
+                assert_eq!(
+                    value.deserialize(),
+                    Change::Insert(Chunk::default().compress())
+                );
+                key
+            })
+            .collect();
+        read_keys.sort();
+
+        let mut expected_keys: Vec<_> = extent
+            .iter3()
+            .map(|p| ChunkDbKey::new(0, p.into()))
+            .collect();
+        expected_keys.sort();
+
+        assert_eq!(read_keys, expected_keys);
+    }
+
+    fn commit_empty_working_version_does_nothing<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
 
         assert_eq!(
             map.cached_meta(),
@@ -367,10 +1424,58 @@ mod tests {
         );
     }
 
-    #[test]
-    fn commit_multiple_versions_with_changes_and_branch() {
-        let db = sled::Config::default().temporary(true).open().unwrap();
-        let mut map = MapDb::open(&db, "mymap").unwrap();
+    fn material_registry_is_versioned<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        assert!(map.material_registry().is_empty());
+
+        let mut registry = MaterialRegistry::default();
+        let stone = registry.add("stone", Default::default()).unwrap();
+        map.write_material_registry(registry.clone()).unwrap();
+
+        // Changing only the registry is enough to commit.
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+        assert_eq!(map.cached_meta().parent_version, Some(v0));
+        assert_eq!(map.material_registry(), &registry);
+
+        // Nothing changed since the last commit.
+        map.commit_working_version().unwrap();
+        assert_eq!(map.cached_meta().parent_version, Some(v0));
+
+        let mut renamed = registry.clone();
+        renamed.rename(stone, "rock").unwrap();
+        map.write_material_registry(renamed.clone()).unwrap();
+        map.commit_working_version().unwrap();
+
+        let reopened = MapDb::open(&store, "mymap").unwrap();
+        assert_eq!(reopened.material_registry(), &renamed);
+
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(map.material_registry(), &registry);
+
+        // Only the versions that changed the registry store one.
+        let num_stored = |map: &MapDb<S>| {
+            map.meta_tree
+                .range(
+                    &material_registry_key(Version::new(0)),
+                    &material_registry_key(Version::new(u64::MAX)),
+                )
+                .count()
+        };
+        assert_eq!(num_stored(&map), 2);
+
+        // An unchanged registry isn't kept once the working version is abandoned.
+        let revision = map.material_registry_revision();
+        map.write_material_registry(registry.clone()).unwrap();
+        assert_ne!(map.material_registry_revision(), revision);
+        assert_eq!(num_stored(&map), 3);
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(num_stored(&map), 2);
+        assert_eq!(map.material_registry(), &registry);
+    }
+
+    fn commit_multiple_versions_with_changes_and_branch<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
 
         let chunk_key1 = ChunkDbKey::new(1, IVec3::ZERO.into());
         let mut encoder = ChangeEncoder::default();
@@ -404,7 +1509,7 @@ mod tests {
         map.branch_from_version(v0).unwrap();
 
         let expected_insert = Ok(Some(unsafe {
-            ArchivedChangeIVec::new(IVec::from(
+            ArchivedChangeIVec::new(StoreBytes::from(
                 Change::Insert(Chunk::default().compress())
                     .serialize()
                     .as_ref(),
@@ -431,4 +1536,110 @@ mod tests {
         assert_eq!(map.read_working_version(chunk_key1), expected_insert);
         assert_eq!(map.read_working_version(chunk_key2), expected_insert);
     }
+
+    fn brush_strokes_are_archived_as_deltas<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+
+        let mut chunk = noise_chunk(777);
+
+        let mut versions = Vec::new();
+        let mut chunks = Vec::new();
+        for stroke in 0..3 {
+            chunk.set_voxel(IVec3::splat(stroke), 1, Sd8::MIN);
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+            map.write_working_version(encoder.encode()).unwrap();
+            versions.push(map.cached_meta().working_version);
+            chunks.push(chunk);
+            map.commit_working_version().unwrap();
+        }
+
+        let archived_len = |map: &MapDb<S>, version| {
+            let archive = map
+                .version_change_tree
+                .get(&Version::into_sled_key(version));
+            archive.unwrap().unwrap().len()
+        };
+        let full_len = chunks[0].compress().bytes.len();
+        assert!(archived_len(&map, versions[0]) < full_len / 4);
+        assert!(archived_len(&map, versions[1]) < full_len / 4);
+
+        // Branching reconstructs every version from the deltas.
+        let read = |map: &MapDb<S>| map.read_working_chunk(key).unwrap().unwrap();
+        map.branch_from_version(versions[0]).unwrap();
+        assert_eq!(read(&map), chunks[0]);
+        map.branch_from_version(versions[1]).unwrap();
+        assert_eq!(read(&map), chunks[1]);
+
+        // Merging a descendant resolves the deltas on its path.
+        let summary = map.merge(versions[2], |_| unreachable!()).unwrap();
+        assert_eq!(summary.num_changes_applied, 1);
+        assert_eq!(read(&map), chunks[2]);
+    }
+
+    fn merge_divergent_branches<S: MapStore>(store: S) {
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let chunk_a = Chunk::default().compress();
+        let mut chunk_b = Chunk::default();
+        chunk_b.sdf[0] = Sd8::MIN;
+        let chunk_b = chunk_b.compress();
+
+        let key1 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key2 = ChunkDbKey::new(0, IVec3::ONE.into());
+        let key3 = ChunkDbKey::new(0, IVec3::new(2, 2, 2).into());
+        let key4 = ChunkDbKey::new(0, IVec3::new(3, 3, 3).into());
+
+        // Common ancestor.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key1, Change::Insert(chunk_a.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v0 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // Their branch.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key2, Change::Insert(chunk_a.clone()));
+        encoder.add_compressed_change(key4, Change::Insert(chunk_a.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        let v1 = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        // Our branch.
+        map.branch_from_version(v0).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key2, Change::Insert(chunk_b.clone()));
+        encoder.add_compressed_change(key3, Change::Insert(chunk_b.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        let summary = map
+            .merge(v1, |conflict| {
+                assert_eq!(conflict.key, key2);
+                assert_eq!(conflict.base, Change::Remove);
+                assert_eq!(conflict.ours, Change::Insert(chunk_b.clone()));
+                assert_eq!(conflict.theirs, Change::Insert(chunk_a.clone()));
+                conflict.take_theirs()
+            })
+            .unwrap();
+
+        assert_eq!(
+            summary,
+            MergeSummary {
+                num_changes_applied: 2,
+                conflicts: vec![key2],
+            }
+        );
+
+        let read = |key| {
+            map.read_working_version(key)
+                .unwrap()
+                .map(|c| c.deserialize())
+        };
+        assert_eq!(read(key1), Some(Change::Insert(chunk_a.clone())));
+        assert_eq!(read(key2), Some(Change::Insert(chunk_a.clone())));
+        assert_eq!(read(key3), Some(Change::Insert(chunk_b.clone())));
+        assert_eq!(read(key4), Some(Change::Insert(chunk_a.clone())));
+    }
 }
@@ -1,14 +1,17 @@
-use super::{AbortReason, ArchivedChangeIVec, ChunkDbKey, EncodedChanges, VersionChanges};
+use super::{
+    abort, AbortReason, ArchivedChangeIVec, ChunkDbKey, ConflictableTransactionError,
+    EncodedChanges, MapStore, StoreResult, StoreTree, TreeTxn, UnabortableTransactionError,
+    VersionChanges,
+};
 use crate::chunk::CompressedChunk;
 
-use sled::transaction::{
-    ConflictableTransactionError, TransactionalTree, UnabortableTransactionError,
-};
-use sled::Tree;
 use std::collections::{BTreeMap, BTreeSet};
 
-pub fn open_backup_tree(map_name: &str, db: &sled::Db) -> sled::Result<(Tree, BackupKeyCache)> {
-    let tree = db.open_tree(format!("{}-backup", map_name))?;
+pub fn open_backup_tree<S: MapStore>(
+    map_name: &str,
+    store: &S,
+) -> StoreResult<(S::Tree, BackupKeyCache)> {
+    let tree = store.open_tree(&format!("{}-backup", map_name))?;
     let mut keys = BTreeSet::default();
     for iter_result in tree.iter() {
         let (key_bytes, _) = iter_result?;
@@ -18,7 +21,7 @@ pub fn open_backup_tree(map_name: &str, db: &sled::Db) -> sled::Result<(Tree, Ba
 }
 
 pub fn write_changes_to_backup_tree(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     changes: EncodedChanges<CompressedChunk>,
 ) -> Result<(), UnabortableTransactionError> {
     for (key_bytes, change) in changes.changes.into_iter() {
@@ -28,14 +31,16 @@ pub fn write_changes_to_backup_tree(
 }
 
 pub fn commit_backup(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     keys: &BackupKeyCache,
 ) -> Result<VersionChanges, ConflictableTransactionError<AbortReason>> {
     let mut changes = BTreeMap::default();
     for &key in keys.keys.iter() {
         if let Some(change) = txn.remove(&key.into_sled_key())? {
-            let archived_change = unsafe { ArchivedChangeIVec::<CompressedChunk>::new(change) };
-            changes.insert(key, archived_change.deserialize());
+            match ArchivedChangeIVec::<CompressedChunk>::try_new(change) {
+                Ok(archived_change) => changes.insert(key, archived_change.deserialize()),
+                Err(_) => return abort(AbortReason::InvalidChunkArchive { key }),
+            };
         } else {
             panic!("BUG: failed to get change backup for {:?}", key);
         }
@@ -44,7 +49,7 @@ pub fn commit_backup(
 }
 
 pub fn clear_backup(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     keys: &BackupKeyCache,
 ) -> Result<(), UnabortableTransactionError> {
     for key in keys.keys.iter() {
@@ -70,12 +75,10 @@ pub struct BackupKeyCache {
 
 #[cfg(test)]
 mod tests {
-    use sled::transaction::TransactionError;
-
     use super::*;
     use crate::chunk::Chunk;
     use crate::core::glam::IVec3;
-    use crate::database::{Change, ChangeEncoder};
+    use crate::database::{Change, ChangeEncoder, TransactionError};
 
     #[test]
     fn write_and_commit_backup() {
@@ -94,17 +97,18 @@ mod tests {
         encoder.add_compressed_change(key2, Change::Insert(Chunk::default().compress()));
         let encoded_changes = encoder.encode();
 
-        let _: Result<_, TransactionError<AbortReason>> = tree.transaction(|txn| {
-            write_changes_to_backup_tree(txn, encoded_changes.clone())?;
-            let reverse_changes = commit_backup(txn, &backup_keys)?;
-            assert_eq!(
-                reverse_changes.changes,
-                BTreeMap::from([
-                    (key1, Change::Remove),
-                    (key2, Change::Insert(Chunk::default().compress()))
-                ])
-            );
-            Ok(())
-        });
+        let _: Result<_, TransactionError<AbortReason>> =
+            <sled::Db as MapStore>::transaction([&tree], |[txn]| {
+                write_changes_to_backup_tree(txn, encoded_changes.clone())?;
+                let reverse_changes = commit_backup(txn, &backup_keys)?;
+                assert_eq!(
+                    reverse_changes.changes,
+                    BTreeMap::from([
+                        (key1, Change::Remove),
+                        (key2, Change::Insert(Chunk::default().compress()))
+                    ])
+                );
+                Ok(())
+            });
     }
 }
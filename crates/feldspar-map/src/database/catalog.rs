@@ -0,0 +1,399 @@
+use super::meta_tree::META_KEY;
+use super::{MapStore, StoreBytes, StoreError, StoreResult, StoreTree, TransactionError, TreeTxn};
+
+/// The suffixes of every tree that belongs to a map. The tree of map `M` with suffix `s` is called `"M-s"`.
+pub(super) const MAP_TREE_SUFFIXES: [&str; 6] = [
+    "meta",
+    "working",
+    "backup",
+    "version-changes",
+    "version-graph",
+    "tiles",
+];
+
+/// The most entries that are copied in one transaction, so that copying a map never holds all of it in memory.
+const COPY_BATCH_LEN: usize = 1024;
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Storage(StoreError),
+    /// There is no map called `name`.
+    MapNotFound {
+        name: String,
+    },
+    /// A map called `name` already exists.
+    MapExists {
+        name: String,
+    },
+}
+
+impl From<StoreError> for CatalogError {
+    fn from(e: StoreError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl From<TransactionError<()>> for CatalogError {
+    fn from(e: TransactionError<()>) -> Self {
+        match e {
+            TransactionError::Storage(e) => Self::Storage(e),
+            TransactionError::Abort(()) => unreachable!("Catalog transactions never abort"),
+        }
+    }
+}
+
+/// Manages the maps stored in one [`MapStore`]. Each map is a set of trees whose names start with the map's name, as created by
+/// [`MapDb::open`](super::MapDb::open).
+///
+/// Only maps with metadata exist. Cloning and renaming copy the rest of a map in batches and write its metadata last, and
+/// dropping removes the metadata first, so a map is never left half-copied. A crash can leave behind trees without metadata,
+/// which are dropped by [`MapCatalog::collect_orphans`].
+///
+/// A map must not be open while it is renamed or dropped.
+pub struct MapCatalog<'a, S: MapStore = sled::Db> {
+    store: &'a S,
+}
+
+impl<'a, S: MapStore> MapCatalog<'a, S> {
+    pub fn new(store: &'a S) -> Self {
+        Self { store }
+    }
+
+    /// The names of all maps in the store, in sorted order.
+    pub fn map_names(&self) -> StoreResult<Vec<String>> {
+        let tree_names = self.store.tree_names()?;
+        let mut map_names = Vec::new();
+        for tree_name in tree_names.iter() {
+            if let Some(map_name) = tree_name.strip_suffix("-meta") {
+                if self.has_metadata(map_name)? {
+                    map_names.push(map_name.to_owned());
+                }
+            }
+        }
+        map_names.sort();
+        Ok(map_names)
+    }
+
+    pub fn contains(&self, map_name: &str) -> StoreResult<bool> {
+        let meta_tree_name = tree_name(map_name, MAP_TREE_SUFFIXES[0]);
+        if !self.store.tree_names()?.contains(&meta_tree_name) {
+            // Don't create the tree by opening it.
+            return Ok(false);
+        }
+        self.has_metadata(map_name)
+    }
+
+    /// Copies every tree of the map `from` into a new map called `to`.
+    pub fn clone_map(&self, from: &str, to: &str) -> Result<(), CatalogError> {
+        self.check_transfer(from, to)?;
+        let meta = self.copy_all_but_metadata(from, to)?;
+        let to_meta_tree = self.open_meta_tree(to)?;
+        S::transaction([&to_meta_tree], |[to_txn]| {
+            to_txn.insert(META_KEY.as_bytes(), meta.clone())?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Moves every tree of the map `from` to a new map called `to`.
+    pub fn rename_map(&self, from: &str, to: &str) -> Result<(), CatalogError> {
+        self.check_transfer(from, to)?;
+        let meta = self.copy_all_but_metadata(from, to)?;
+        // Moving the metadata is what renames the map.
+        let from_meta_tree = self.open_meta_tree(from)?;
+        let to_meta_tree = self.open_meta_tree(to)?;
+        S::transaction([&from_meta_tree, &to_meta_tree], |[from_txn, to_txn]| {
+            from_txn.remove(META_KEY.as_bytes())?;
+            to_txn.insert(META_KEY.as_bytes(), meta.clone())?;
+            Ok(())
+        })?;
+        self.drop_trees(from)?;
+        Ok(())
+    }
+
+    /// Deletes every tree of `map_name`. Returns `false` if there was no such map.
+    pub fn drop_map(&self, map_name: &str) -> Result<bool, CatalogError> {
+        if !self.contains(map_name)? {
+            return Ok(false);
+        }
+        // Removing the metadata is what drops the map. The trees are dropped one at a time afterwards.
+        let meta_tree = self.open_meta_tree(map_name)?;
+        S::transaction([&meta_tree], |[txn]| {
+            txn.remove(META_KEY.as_bytes())?;
+            Ok(())
+        })?;
+        self.drop_trees(map_name)?;
+        Ok(true)
+    }
+
+    /// Copies every entry of `from` into the trees of `to`, [`COPY_BATCH_LEN`] entries per transaction. Returns the metadata
+    /// of `from`, which is not copied, so `to` doesn't exist until the caller writes it.
+    fn copy_all_but_metadata(&self, from: &str, to: &str) -> Result<StoreBytes, CatalogError> {
+        // Trees of an unfinished copy to the same name may have been left behind.
+        self.drop_trees(to)?;
+        let from_trees = self.open_trees(from)?;
+        let to_trees = self.open_trees(to)?;
+        let mut meta = None;
+        for (i, (from_tree, to_tree)) in from_trees.iter().zip(to_trees.iter()).enumerate() {
+            let mut batch = Vec::with_capacity(COPY_BATCH_LEN);
+            for iter_result in from_tree.iter() {
+                let (key, value) = iter_result?;
+                if i == 0 && key.as_ref() == META_KEY.as_bytes() {
+                    meta = Some(value);
+                    continue;
+                }
+                batch.push((key, value));
+                if batch.len() == COPY_BATCH_LEN {
+                    write_batch::<S>(to_tree, &batch)?;
+                    batch.clear();
+                }
+            }
+            write_batch::<S>(to_tree, &batch)?;
+        }
+        meta.ok_or_else(|| CatalogError::MapNotFound {
+            name: from.to_owned(),
+        })
+    }
+
+    /// Drops the trees left behind by a clone, rename or drop that didn't finish, which are the trees of maps without
+    /// metadata. Returns the names of the dropped trees.
+    ///
+    /// A map that is being created also has no metadata yet, so no map may be opened or copied while this runs.
+    pub fn collect_orphans(&self) -> Result<Vec<String>, CatalogError> {
+        let tree_names = self.store.tree_names()?;
+        // Collect first, since checking the metadata of a map would recreate a meta tree that was already dropped.
+        let mut orphans = Vec::new();
+        for name in tree_names.iter() {
+            let map_name = MAP_TREE_SUFFIXES.iter().find_map(|suffix| {
+                name.strip_suffix(suffix)
+                    .and_then(|prefix| prefix.strip_suffix('-'))
+            });
+            let map_name = match map_name {
+                Some(map_name) => map_name,
+                None => continue,
+            };
+            // Check before opening the meta tree, which would create it.
+            let has_meta_tree = tree_names.contains(&tree_name(map_name, MAP_TREE_SUFFIXES[0]));
+            if !has_meta_tree || !self.has_metadata(map_name)? {
+                orphans.push(name.clone());
+            }
+        }
+        for name in orphans.iter() {
+            log::warn!("Dropping orphaned tree {}", name);
+            self.store.drop_tree(name)?;
+        }
+        Ok(orphans)
+    }
+
+    fn check_transfer(&self, from: &str, to: &str) -> Result<(), CatalogError> {
+        if !self.contains(from)? {
+            return Err(CatalogError::MapNotFound {
+                name: from.to_owned(),
+            });
+        }
+        if self.contains(to)? {
+            return Err(CatalogError::MapExists {
+                name: to.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Only maps with metadata exist.
+    fn has_metadata(&self, map_name: &str) -> StoreResult<bool> {
+        Ok(self
+            .open_meta_tree(map_name)?
+            .get(META_KEY.as_bytes())?
+            .is_some())
+    }
+
+    fn open_meta_tree(&self, map_name: &str) -> StoreResult<S::Tree> {
+        self.store
+            .open_tree(&tree_name(map_name, MAP_TREE_SUFFIXES[0]))
+    }
+
+    fn open_trees(&self, map_name: &str) -> StoreResult<[S::Tree; 6]> {
+        let [t0, t1, t2, t3, t4, t5] =
+            MAP_TREE_SUFFIXES.map(|suffix| self.store.open_tree(&tree_name(map_name, suffix)));
+        Ok([t0?, t1?, t2?, t3?, t4?, t5?])
+    }
+
+    fn drop_trees(&self, map_name: &str) -> StoreResult<()> {
+        for suffix in MAP_TREE_SUFFIXES {
+            self.store.drop_tree(&tree_name(map_name, suffix))?;
+        }
+        Ok(())
+    }
+}
+
+fn tree_name(map_name: &str, suffix: &str) -> String {
+    format!("{}-{}", map_name, suffix)
+}
+
+fn write_batch<S: MapStore>(
+    tree: &S::Tree,
+    batch: &[(StoreBytes, StoreBytes)],
+) -> Result<(), CatalogError> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    S::transaction([tree], |[txn]| {
+        for (key, value) in batch.iter() {
+            txn.insert(key, value.clone())?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::core::glam::IVec3;
+    use crate::database::{Change, ChangeEncoder, ChunkDbKey, MapDb, MemStore};
+    use crate::sdf::Sd8;
+
+    fn write_chunk<S: MapStore>(map: &mut MapDb<S>, key: ChunkDbKey, palette_id: u8) -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk.set_voxel(IVec3::ZERO, palette_id, Sd8::MIN);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+        chunk
+    }
+
+    #[test]
+    fn copy_rename_and_drop_maps() {
+        copy_rename_and_drop(MemStore::default());
+        copy_rename_and_drop(sled::Config::default().temporary(true).open().unwrap());
+    }
+
+    fn copy_rename_and_drop<S: MapStore>(store: S) {
+        let catalog = MapCatalog::new(&store);
+        assert_eq!(catalog.map_names().unwrap(), Vec::<String>::new());
+
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let chunk = {
+            let mut map = MapDb::open(&store, "main").unwrap();
+            let chunk = write_chunk(&mut map, key, 1);
+            map.commit_working_version().unwrap();
+            chunk
+        };
+        // Prefixes of other map names are not maps.
+        MapDb::open(&store, "main-backup").unwrap();
+        assert_eq!(catalog.map_names().unwrap(), ["main", "main-backup"]);
+
+        catalog.clone_map("main", "copy").unwrap();
+        assert!(matches!(
+            catalog.clone_map("main", "copy"),
+            Err(CatalogError::MapExists { name }) if name == "copy"
+        ));
+        {
+            // The copy has the same history, but it's edited separately.
+            let mut copy = MapDb::open(&store, "copy").unwrap();
+            assert_eq!(copy.read_working_chunk(key).unwrap(), Some(chunk));
+            write_chunk(&mut copy, key, 2);
+        }
+        let main = MapDb::open(&store, "main").unwrap();
+        assert_eq!(main.read_working_chunk(key).unwrap(), Some(chunk));
+        drop(main);
+
+        catalog.rename_map("main", "renamed").unwrap();
+        assert!(matches!(
+            catalog.rename_map("main", "other"),
+            Err(CatalogError::MapNotFound { name }) if name == "main"
+        ));
+        assert_eq!(
+            catalog.map_names().unwrap(),
+            ["copy", "main-backup", "renamed"]
+        );
+        let renamed = MapDb::open(&store, "renamed").unwrap();
+        assert_eq!(renamed.read_working_chunk(key).unwrap(), Some(chunk));
+        assert!(renamed.fsck().unwrap().is_empty());
+        drop(renamed);
+
+        assert!(catalog.drop_map("copy").unwrap());
+        assert!(!catalog.drop_map("copy").unwrap());
+        assert_eq!(catalog.map_names().unwrap(), ["main-backup", "renamed"]);
+        let tree_names = store.tree_names().unwrap();
+        assert!(!tree_names.iter().any(|name| name.starts_with("copy-")));
+    }
+
+    #[test]
+    fn maps_are_copied_in_batches() {
+        let store = MemStore::default();
+        let mut chunk = Chunk::default();
+        chunk.set_voxel(IVec3::ZERO, 1, Sd8::MIN);
+        {
+            let mut map = MapDb::open(&store, "main").unwrap();
+            let mut encoder = ChangeEncoder::default();
+            for i in 0..COPY_BATCH_LEN as i32 + 10 {
+                let key = ChunkDbKey::new(0, IVec3::new(i, 0, 0).into());
+                encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+            }
+            map.write_working_version(encoder.encode()).unwrap();
+        }
+
+        let catalog = MapCatalog::new(&store);
+        catalog.clone_map("main", "copy").unwrap();
+        let last_key = ChunkDbKey::new(0, IVec3::new(COPY_BATCH_LEN as i32 + 9, 0, 0).into());
+        let copy = MapDb::open(&store, "copy").unwrap();
+        assert_eq!(copy.read_working_chunk(last_key).unwrap(), Some(chunk));
+        assert!(copy.fsck().unwrap().is_empty());
+        assert_eq!(copy.working_tree.iter().count(), COPY_BATCH_LEN + 10);
+    }
+
+    #[test]
+    fn orphan_trees_are_only_dropped_when_collected() {
+        let store = MemStore::default();
+        MapDb::open(&store, "main").unwrap();
+        MapDb::open(&store, "main-backup").unwrap();
+        MapDb::open(&store, "dropped").unwrap();
+
+        // Crashes after removing the metadata of "dropped", and after copying part of a clone called "copy".
+        let remove_meta: Result<(), TransactionError<()>> =
+            MemStore::transaction([&store.open_tree("dropped-meta").unwrap()], |[txn]| {
+                txn.remove(META_KEY.as_bytes())?;
+                Ok(())
+            });
+        remove_meta.unwrap();
+        let partial_copy = store.open_tree("copy-working").unwrap();
+        write_batch::<MemStore>(
+            &partial_copy,
+            &[(StoreBytes::from(&[0; 13][..]), StoreBytes::from(&[1][..]))],
+        )
+        .unwrap();
+
+        let leftover_trees = || {
+            let mut tree_names = store.tree_names().unwrap();
+            tree_names.retain(|name| name.starts_with("copy-") || name.starts_with("dropped-"));
+            tree_names.sort();
+            tree_names
+        };
+        let leftovers = leftover_trees();
+        assert_eq!(leftovers.len(), MAP_TREE_SUFFIXES.len() + 1);
+
+        let catalog = MapCatalog::new(&store);
+        assert_eq!(catalog.map_names().unwrap(), ["main", "main-backup"]);
+        assert_eq!(leftover_trees(), leftovers);
+
+        let mut collected = catalog.collect_orphans().unwrap();
+        collected.sort();
+        assert_eq!(collected, leftovers);
+        assert_eq!(leftover_trees(), Vec::<String>::new());
+        assert_eq!(catalog.collect_orphans().unwrap(), Vec::<String>::new());
+
+        // The name can be used again without any leftovers.
+        catalog.clone_map("main", "copy").unwrap();
+        let copy = MapDb::open(&store, "copy").unwrap();
+        assert!(copy.fsck().unwrap().is_empty());
+    }
+}
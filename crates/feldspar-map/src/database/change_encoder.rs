@@ -1,4 +1,4 @@
-use super::{ArchivedIVec, ChunkDbKey};
+use super::{ArchivedIVec, ChunkDbKey, StoreBytes};
 use crate::chunk::CompressedChunk;
 use crate::core::rkyv::{
     ser::{serializers::CoreSerializer, Serializer},
@@ -6,10 +6,11 @@ use crate::core::rkyv::{
 };
 use crate::core::{NoSharedAllocSerializer, SmallKeyHashMap};
 
-use sled::IVec;
+use bytecheck::CheckBytes;
 
 #[derive(Archive, Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
 pub enum Change<T> {
     Insert(T),
     Remove,
@@ -74,7 +75,7 @@ impl ChangeEncoder {
         self.added_changes.insert(key, change);
     }
 
-    /// Sorts the changes by Morton key and converts them to `IVec` key-value pairs for `sled`.
+    /// Sorts the changes by Morton key and converts them to [`StoreBytes`] key-value pairs for the [`MapStore`](super::MapStore).
     pub fn encode(self) -> EncodedChanges<CompressedChunk> {
         // Serialize values.
         let mut changes: Vec<_> = self
@@ -82,8 +83,8 @@ impl ChangeEncoder {
             .into_iter()
             .map(|(key, change)| {
                 (key, unsafe {
-                    // PERF: sad that we can't serialize directly into an IVec
-                    ArchivedIVec::new(IVec::from(change.serialize().as_ref()))
+                    // PERF: sad that we can't serialize directly into a StoreBytes
+                    ArchivedIVec::new(StoreBytes::from(change.serialize().as_ref()))
                 })
             })
             .collect();
@@ -94,7 +95,7 @@ impl ChangeEncoder {
         // Serialize the keys.
         let changes: Vec<_> = changes
             .into_iter()
-            .map(|(key, change)| (IVec::from(key.into_sled_key().as_ref()), change))
+            .map(|(key, change)| (StoreBytes::from(key.into_sled_key().as_ref()), change))
             .collect();
 
         EncodedChanges { changes }
@@ -107,7 +108,7 @@ impl ChangeEncoder {
 /// latest changes.
 #[derive(Clone, Debug, Default)]
 pub struct EncodedChanges<T> {
-    pub changes: Vec<(IVec, ArchivedChangeIVec<T>)>,
+    pub changes: Vec<(StoreBytes, ArchivedChangeIVec<T>)>,
 }
 
 /// We use this format for all changes stored in the working tree and backup tree.
@@ -132,8 +133,6 @@ mod tests {
     use crate::chunk::Chunk;
     use crate::core::archived_buf::ArchivedBuf;
 
-    use sled::IVec;
-
     #[test]
     fn deserialize_remove_bytes() {
         // This needs to be 12! Leaving empty space at the end of the AlignedBytes will cause archive_root to fail.
@@ -146,7 +145,9 @@ mod tests {
     fn deserialize_insert_bytes() {
         let original = Change::Insert(Chunk::default().compress());
         let serialized = unsafe {
-            ArchivedIVec::<Change<CompressedChunk>>::new(IVec::from(original.serialize().as_ref()))
+            ArchivedIVec::<Change<CompressedChunk>>::new(StoreBytes::from(
+                original.serialize().as_ref(),
+            ))
         };
         let deserialized = serialized.deserialize();
         assert_eq!(deserialized, original);
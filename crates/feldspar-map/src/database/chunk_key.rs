@@ -3,6 +3,8 @@ use crate::core::glam::IVec3;
 use crate::core::ilattice::prelude::{Bounded, Extent, Morton3i32};
 use crate::core::rkyv::{Archive, Deserialize, Serialize};
 
+use bytecheck::CheckBytes;
+use core::convert::Infallible;
 use core::ops::RangeInclusive;
 
 #[derive(
@@ -31,13 +33,24 @@ impl ChunkDbKey {
         bytes
     }
 
+    /// # Panics
+    ///
+    /// If `bytes` is not 13 bytes long. Use [`Self::try_from_sled_key`] for keys that might be corrupt.
     pub fn from_sled_key(bytes: &[u8]) -> Self {
-        let level = bytes[0];
+        Self::try_from_sled_key(bytes).expect("Invalid chunk key")
+    }
+
+    /// Returns `None` if `bytes` is not 13 bytes long.
+    pub fn try_from_sled_key(bytes: &[u8]) -> Option<Self> {
+        let (&level, morton_key) = bytes.split_first()?;
+        if morton_key.len() != 12 {
+            return None;
+        }
         // The most significant 4 bytes of the u128 are not used.
         let mut morton_bytes = [0; 16];
-        morton_bytes[4..16].copy_from_slice(&bytes[1..]);
+        morton_bytes[4..16].copy_from_slice(morton_key);
         let morton_int = u128::from_be_bytes(morton_bytes);
-        Self::new(level, Morton3i32(morton_int))
+        Some(Self::new(level, Morton3i32(morton_int)))
     }
 
     pub fn extent_range(level: u8, extent: Extent<IVec3>) -> RangeInclusive<Self> {
@@ -55,6 +68,19 @@ impl ChunkDbKey {
     }
 }
 
+// SAFETY: Every bit pattern is a valid key, since it's only a level and the integer of a Morton code. This is implemented by hand
+// because the archived Morton code comes from `ilattice`.
+unsafe impl<C: ?Sized> CheckBytes<C> for ArchivedChunkDbKey {
+    type Error = Infallible;
+
+    unsafe fn check_bytes<'a>(
+        value: *const Self,
+        _context: &mut C,
+    ) -> Result<&'a Self, Self::Error> {
+        Ok(&*value)
+    }
+}
+
 impl From<NodeKey<IVec3>> for ChunkDbKey {
     #[inline]
     fn from(node: NodeKey<IVec3>) -> Self {
@@ -0,0 +1,774 @@
+use super::meta_tree::write_meta;
+use super::version_delta::{is_delta, is_delta_change, resolve_delta};
+use super::version_graph_tree::{link_version, VersionNode};
+use super::{
+    AbortReason, ArchivedChangeIVec, ArchivedIVec, BackupKeyCache, Change, ChunkDbKey, MapDb,
+    MapStore, StoreBytes, StoreError, StoreTree, TransactionError, TreeTxn, Version,
+    VersionChanges,
+};
+use crate::chunk::{ChunkLayout, CompressedChunk, TileId};
+use crate::codec;
+use crate::core::rkyv::{Deserialize, Infallible};
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// An inconsistency found by [`MapDb::fsck`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FsckProblem {
+    /// The parent of the working version has no valid node in the version graph.
+    MissingParentNode { parent: Version },
+    /// The grandparent version in the map metadata is not the parent of the parent version's node.
+    GrandparentMismatch {
+        stored: Option<Version>,
+        linked: Option<Version>,
+    },
+    /// The stored [`VersionNode`] of `version` is not a valid archive.
+    InvalidVersionNode { version: Version },
+    /// Following the parents of `version` never reaches a root, because some ancestor is missing or there is a cycle.
+    NoPathToRoot { version: Version },
+    /// `version` reaches a different root than the parent of the working version.
+    UnreachableVersion { version: Version },
+    /// `version` is in the version graph, but its [`VersionChanges`] were never archived.
+    MissingVersionChanges { version: Version },
+    /// The archived [`VersionChanges`] of `version` are not a valid archive.
+    InvalidVersionChanges { version: Version },
+    /// There are [`VersionChanges`] for a version that has no valid node, or for the parent of the working version, whose
+    /// state is stored in the working tree instead.
+    OrphanVersionChanges { version: Version },
+    /// The chunk archived for `key` in the changes of `version` can't be decompressed in the map's [`ChunkLayout`], or it is a
+    /// delta that can't be reconstructed from the value it was encoded against.
+    InvalidArchivedChunk { version: Version, key: ChunkDbKey },
+    /// `key` is in the backup tree but not in the [`BackupKeyCache`], so it would not be archived on commit.
+    UncachedBackupKey { key: ChunkDbKey },
+    /// `key` is in the [`BackupKeyCache`] but not in the backup tree, so committing would fail.
+    MissingBackupKey { key: ChunkDbKey },
+    /// The backed up value of `key` is not a valid archive or can't be decompressed.
+    InvalidBackupValue { key: ChunkDbKey },
+    /// The working value of `key` is not a valid archive, can't be decompressed, or references a missing tile.
+    InvalidWorkingValue { key: ChunkDbKey },
+    /// The voxels of `tile` can't be decompressed in the map's [`ChunkLayout`].
+    InvalidTile { tile: TileId },
+    /// `key` is not a valid key of `tree`, e.g. because it has the wrong length.
+    InvalidKey { tree: FsckTree, key: Vec<u8> },
+}
+
+/// The trees of a map that are scanned by [`MapDb::fsck`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FsckTree {
+    Working,
+    Backup,
+    VersionGraph,
+    VersionChanges,
+    Tiles,
+}
+
+impl<S: MapStore> MapDb<S> {
+    /// Checks the consistency of everything stored for this map, without changing anything. This reads and decompresses every
+    /// stored chunk, so it can take a while for large maps.
+    ///
+    /// The checks are:
+    /// - every node in the version graph has a path to the same root as the parent of the working version
+    /// - every version in the graph, except the parent of the working version, has archived [`VersionChanges`]
+    /// - the keys of the backup tree match the [`BackupKeyCache`]
+    /// - every working, backed up, and archived chunk decompresses in the map's [`ChunkLayout`], and every tile reference
+    ///   points to an existing tile
+    /// - every archived delta of a version that the working version can migrate to is reconstructed from the value it was
+    ///   encoded against, like [`MapDb::branch_from_version`] would, and the result decompresses in the map's [`ChunkLayout`]
+    /// - every key has the right length for its tree
+    pub fn fsck(&self) -> Result<Vec<FsckProblem>, StoreError> {
+        let mut problems = Vec::new();
+        let versions = self.scan_versions(&mut problems)?;
+        self.scan_deltas(&versions, &mut problems)?;
+        self.scan_backup(&mut problems)?;
+        self.scan_working_tree(&mut problems)?;
+        self.scan_tiles(&mut problems)?;
+        Ok(problems)
+    }
+
+    /// Runs [`MapDb::fsck`] and rebuilds what it can. Returns all of the problems that were found.
+    ///
+    /// - Versions that can no longer be reached from the working version are removed, along with their changes. If the parent
+    ///   of the working version has no path to a root, it becomes a new root.
+    /// - Orphaned and invalid [`VersionChanges`] are removed.
+    /// - Invalid working and backed up values are removed, and the [`BackupKeyCache`] is rebuilt from the backup tree. A
+    ///   removed backup value means that committing the working version archives the parent version without that chunk.
+    /// - Invalid keys are removed, since nothing can refer to them.
+    ///
+    /// Invalid tiles and invalid chunks inside of otherwise valid [`VersionChanges`] are only reported, since removing them
+    /// would change the contents of other versions.
+    pub fn repair(&mut self) -> Result<Vec<FsckProblem>, TransactionError<AbortReason>> {
+        let mut problems = Vec::new();
+        let versions = self.scan_versions(&mut problems)?;
+        self.scan_deltas(&versions, &mut problems)?;
+        let invalid_backup_keys = self.scan_backup(&mut problems)?;
+        let invalid_working_keys = self.scan_working_tree(&mut problems)?;
+        self.scan_tiles(&mut problems)?;
+        if problems.is_empty() {
+            return Ok(problems);
+        }
+
+        let parent_version = self.cached_meta.parent_version;
+        let new_meta = S::transaction(
+            [
+                &self.meta_tree,
+                &self.version_graph_tree,
+                &self.version_change_tree,
+                &self.backup_tree,
+                &self.working_tree,
+                &self.tile_tree,
+            ],
+            |[meta_txn, graph_txn, changes_txn, backup_txn, working_txn, tile_txn]| {
+                for version in versions.graph_keys.difference(&versions.reachable) {
+                    graph_txn.remove(&version.into_sled_key())?;
+                }
+                for (&version, &valid) in versions.changes.iter() {
+                    if !valid
+                        || Some(version) == parent_version
+                        || !versions.reachable.contains(&version)
+                    {
+                        changes_txn.remove(&version.into_sled_key())?;
+                    }
+                }
+
+                let mut meta = self.cached_meta;
+                if let Some(parent) = parent_version {
+                    if versions.relink_parent {
+                        log::warn!("Relinking {:?} as a root version", parent);
+                        link_version(
+                            graph_txn,
+                            parent,
+                            VersionNode {
+                                parent_version: None,
+                            },
+                        )?;
+                    }
+                    meta.grandparent_version = versions.nodes[&parent];
+                }
+                write_meta(meta_txn, &meta)?;
+
+                for key in invalid_backup_keys.iter() {
+                    backup_txn.remove(&key.into_sled_key())?;
+                }
+                for key in invalid_working_keys.iter() {
+                    working_txn.remove(&key.into_sled_key())?;
+                }
+                for problem in problems.iter() {
+                    if let FsckProblem::InvalidKey { tree, key } = problem {
+                        let txn = match tree {
+                            FsckTree::Working => working_txn,
+                            FsckTree::Backup => backup_txn,
+                            FsckTree::VersionGraph => graph_txn,
+                            FsckTree::VersionChanges => changes_txn,
+                            FsckTree::Tiles => tile_txn,
+                        };
+                        txn.remove(key)?;
+                    }
+                }
+                Ok(meta)
+            },
+        )?;
+        self.cached_meta = new_meta;
+
+        let mut keys = BTreeSet::default();
+        for iter_result in self.backup_tree.iter() {
+            let (key_bytes, _) = iter_result?;
+            keys.insert(ChunkDbKey::from_sled_key(&key_bytes));
+        }
+        self.backup_key_cache = BackupKeyCache { keys };
+
+        Ok(problems)
+    }
+
+    fn scan_versions(&self, problems: &mut Vec<FsckProblem>) -> Result<VersionScan, StoreError> {
+        let mut nodes = BTreeMap::new();
+        let mut graph_keys = BTreeSet::new();
+        for iter_result in self.version_graph_tree.iter() {
+            let (key_bytes, node_bytes) = iter_result?;
+            let version = match parse_key(
+                &key_bytes,
+                FsckTree::VersionGraph,
+                Version::try_from_sled_key,
+                problems,
+            ) {
+                Some(version) => version,
+                None => continue,
+            };
+            graph_keys.insert(version);
+            match ArchivedIVec::<VersionNode>::try_new(node_bytes) {
+                Ok(node) => {
+                    nodes.insert(version, node.deserialize().parent_version);
+                }
+                Err(_) => problems.push(FsckProblem::InvalidVersionNode { version }),
+            }
+        }
+
+        let mut roots = find_roots(&nodes);
+        let mut rootless = BTreeSet::new();
+        for (&version, root) in roots.iter() {
+            if root.is_none() {
+                problems.push(FsckProblem::NoPathToRoot { version });
+                rootless.insert(version);
+            }
+        }
+
+        // Every kept version must be in the same tree as the parent version, since that's where the working tree can migrate.
+        let parent_version = self.cached_meta.parent_version;
+        let mut relink_parent = false;
+        let mut parent_root = None;
+        if let Some(parent) = parent_version {
+            match nodes.get(&parent) {
+                Some(&linked) => {
+                    let stored = self.cached_meta.grandparent_version;
+                    if stored != linked {
+                        problems.push(FsckProblem::GrandparentMismatch { stored, linked });
+                    }
+                    relink_parent = roots[&parent].is_none();
+                }
+                None => {
+                    problems.push(FsckProblem::MissingParentNode { parent });
+                    relink_parent = true;
+                }
+            }
+            if relink_parent {
+                nodes.insert(parent, None);
+                roots = find_roots(&nodes);
+            }
+            parent_root = roots[&parent];
+        }
+        for (&version, &root) in roots.iter() {
+            if root.is_some() && root != parent_root && !rootless.contains(&version) {
+                problems.push(FsckProblem::UnreachableVersion { version });
+            }
+        }
+
+        let mut changes = BTreeMap::new();
+        for iter_result in self.version_change_tree.iter() {
+            let (key_bytes, changes_bytes) = iter_result?;
+            let version = match parse_key(
+                &key_bytes,
+                FsckTree::VersionChanges,
+                Version::try_from_sled_key,
+                problems,
+            ) {
+                Some(version) => version,
+                None => continue,
+            };
+            let valid = match ArchivedIVec::<VersionChanges>::try_new(changes_bytes) {
+                Ok(archived) => {
+                    for (key, change) in archived.as_ref().changes.iter() {
+                        if let Some(chunk) = change.get_insert_data() {
+                            if !is_delta(&chunk.bytes) && !self.is_valid_chunk(&chunk.bytes)? {
+                                let key = key.deserialize(&mut Infallible).unwrap();
+                                problems.push(FsckProblem::InvalidArchivedChunk { version, key });
+                            }
+                        }
+                    }
+                    true
+                }
+                Err(_) => {
+                    problems.push(FsckProblem::InvalidVersionChanges { version });
+                    false
+                }
+            };
+            if !nodes.contains_key(&version) || Some(version) == parent_version {
+                problems.push(FsckProblem::OrphanVersionChanges { version });
+            }
+            changes.insert(version, valid);
+        }
+        for &version in nodes.keys() {
+            if Some(version) != parent_version && !changes.contains_key(&version) {
+                problems.push(FsckProblem::MissingVersionChanges { version });
+            }
+        }
+
+        // Branching into a version applies its changes, so a version without valid changes cuts off everything behind it.
+        let mut children: BTreeMap<Version, Vec<Version>> = BTreeMap::new();
+        for (&version, &parent) in nodes.iter() {
+            if let Some(parent) = parent {
+                children.entry(parent).or_default().push(version);
+            }
+        }
+        let mut reachable = BTreeSet::new();
+        let mut migrations = Vec::new();
+        if let Some(parent) = parent_version {
+            let mut queue = VecDeque::from([parent]);
+            reachable.insert(parent);
+            while let Some(version) = queue.pop_front() {
+                let neighbors = nodes[&version]
+                    .into_iter()
+                    .chain(children.get(&version).into_iter().flatten().copied());
+                for next in neighbors {
+                    if changes.get(&next) == Some(&true) && reachable.insert(next) {
+                        migrations.push((version, next));
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        Ok(VersionScan {
+            nodes,
+            graph_keys,
+            changes,
+            reachable,
+            migrations,
+            relink_parent,
+        })
+    }
+
+    /// Reconstructs the archived deltas of every reachable version in the order that branching would migrate through them. Each
+    /// version's changes are deltas against the values of the version it is migrated to from.
+    ///
+    /// The resolved changes of every reachable version are kept in memory until the scan is done.
+    fn scan_deltas(
+        &self,
+        versions: &VersionScan,
+        problems: &mut Vec<FsckProblem>,
+    ) -> Result<(), StoreError> {
+        // `None` is a value that can't be resolved, so deltas against it are invalid too.
+        let mut resolved: BTreeMap<Version, BTreeMap<ChunkDbKey, Option<Change<CompressedChunk>>>> =
+            BTreeMap::new();
+        let mut from_versions = BTreeMap::new();
+        for &(from_version, version) in versions.migrations.iter() {
+            from_versions.insert(version, from_version);
+            let bytes = match self.version_change_tree.get(&version.into_sled_key())? {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            let archived = match ArchivedIVec::<VersionChanges>::try_new(bytes) {
+                Ok(archived) => archived,
+                Err(_) => continue,
+            };
+            let mut changes = BTreeMap::new();
+            for (key, change) in archived.as_ref().changes.iter() {
+                let key: ChunkDbKey = key.deserialize(&mut Infallible).unwrap();
+                let change: Change<CompressedChunk> = change.deserialize(&mut Infallible).unwrap();
+                if !is_delta_change(&change) {
+                    changes.insert(key, Some(change));
+                    continue;
+                }
+                let base =
+                    self.value_before_migration(&resolved, &from_versions, from_version, key)?;
+                let value = base
+                    .and_then(|base| resolve_delta(change, &base))
+                    .filter(|value| match value {
+                        Change::Insert(chunk) => {
+                            decompresses_in_layout(&chunk.bytes, self.chunk_layout)
+                        }
+                        Change::Remove => true,
+                    });
+                if value.is_none() {
+                    problems.push(FsckProblem::InvalidArchivedChunk { version, key });
+                }
+                changes.insert(key, value);
+            }
+            resolved.insert(version, changes);
+        }
+        Ok(())
+    }
+
+    /// The value of `key` in `version`, given the resolved changes of every version between it and the parent of the working
+    /// version.
+    fn value_before_migration(
+        &self,
+        resolved: &BTreeMap<Version, BTreeMap<ChunkDbKey, Option<Change<CompressedChunk>>>>,
+        from_versions: &BTreeMap<Version, Version>,
+        mut version: Version,
+        key: ChunkDbKey,
+    ) -> Result<Option<Change<CompressedChunk>>, StoreError> {
+        while let Some(&from_version) = from_versions.get(&version) {
+            if let Some(value) = resolved.get(&version).and_then(|changes| changes.get(&key)) {
+                return Ok(value.clone());
+            }
+            version = from_version;
+        }
+
+        // The parent of the working version has its values in the backup tree, or in the working tree if the working version
+        // didn't change them.
+        let key_bytes = key.into_sled_key();
+        let bytes = match self.backup_tree.get(&key_bytes)? {
+            Some(bytes) => Some(bytes),
+            None => self.working_tree.get(&key_bytes)?,
+        };
+        Ok(match bytes {
+            Some(bytes) => ArchivedChangeIVec::<CompressedChunk>::try_new(bytes)
+                .ok()
+                .map(|change| change.deserialize()),
+            None => Some(Change::Remove),
+        })
+    }
+
+    /// Returns the keys with invalid values.
+    fn scan_backup(&self, problems: &mut Vec<FsckProblem>) -> Result<Vec<ChunkDbKey>, StoreError> {
+        let mut stored_keys = BTreeSet::new();
+        let mut invalid_keys = Vec::new();
+        for iter_result in self.backup_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            let key = match parse_key(
+                &key_bytes,
+                FsckTree::Backup,
+                ChunkDbKey::try_from_sled_key,
+                problems,
+            ) {
+                Some(key) => key,
+                None => continue,
+            };
+            stored_keys.insert(key);
+            if !self.is_valid_change(value)? {
+                problems.push(FsckProblem::InvalidBackupValue { key });
+                invalid_keys.push(key);
+            }
+        }
+        for &key in stored_keys.difference(&self.backup_key_cache.keys) {
+            problems.push(FsckProblem::UncachedBackupKey { key });
+        }
+        for &key in self.backup_key_cache.keys.difference(&stored_keys) {
+            problems.push(FsckProblem::MissingBackupKey { key });
+        }
+        Ok(invalid_keys)
+    }
+
+    /// Returns the keys with invalid values.
+    fn scan_working_tree(
+        &self,
+        problems: &mut Vec<FsckProblem>,
+    ) -> Result<Vec<ChunkDbKey>, StoreError> {
+        let mut invalid_keys = Vec::new();
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            let key = match parse_key(
+                &key_bytes,
+                FsckTree::Working,
+                ChunkDbKey::try_from_sled_key,
+                problems,
+            ) {
+                Some(key) => key,
+                None => continue,
+            };
+            if !self.is_valid_change(value)? {
+                problems.push(FsckProblem::InvalidWorkingValue { key });
+                invalid_keys.push(key);
+            }
+        }
+        Ok(invalid_keys)
+    }
+
+    fn scan_tiles(&self, problems: &mut Vec<FsckProblem>) -> Result<(), StoreError> {
+        for iter_result in self.tile_tree.iter() {
+            let (key_bytes, bytes) = iter_result?;
+            let tile = match parse_key(
+                &key_bytes,
+                FsckTree::Tiles,
+                TileId::try_from_sled_key,
+                problems,
+            ) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            if !decompresses_in_layout(&bytes, self.chunk_layout) {
+                problems.push(FsckProblem::InvalidTile { tile });
+            }
+        }
+        Ok(())
+    }
+
+    fn is_valid_change(&self, bytes: StoreBytes) -> Result<bool, StoreError> {
+        match ArchivedChangeIVec::<CompressedChunk>::try_new(bytes) {
+            Ok(change) => match change.as_ref().get_insert_data() {
+                Some(chunk) => self.is_valid_chunk(&chunk.bytes),
+                None => Ok(true),
+            },
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn is_valid_chunk(&self, bytes: &[u8]) -> Result<bool, StoreError> {
+        match CompressedChunk::referenced_tile(bytes) {
+            Some(tile) => Ok(self.tile_tree.get(&tile.into_sled_key())?.is_some()),
+            None => Ok(decompresses_in_layout(bytes, self.chunk_layout)),
+        }
+    }
+}
+
+struct VersionScan {
+    /// The parent of every valid node in the version graph. Includes the parent of the working version if it will be relinked.
+    nodes: BTreeMap<Version, Option<Version>>,
+    /// Every key in the version graph tree, including invalid nodes.
+    graph_keys: BTreeSet<Version>,
+    /// Every key in the version change tree, and whether its [`VersionChanges`] are valid.
+    changes: BTreeMap<Version, bool>,
+    /// The versions that the working tree can still migrate to.
+    reachable: BTreeSet<Version>,
+    /// Every migration between neighboring versions on the way from the parent of the working version to each reachable
+    /// version, as `(from, to)` pairs in breadth-first order.
+    migrations: Vec<(Version, Version)>,
+    relink_parent: bool,
+}
+
+/// Finds the root ancestor of every node, or `None` if following the parents ends at a missing node or a cycle.
+fn find_roots(nodes: &BTreeMap<Version, Option<Version>>) -> BTreeMap<Version, Option<Version>> {
+    let mut roots = BTreeMap::new();
+    for &start in nodes.keys() {
+        let mut chain = Vec::new();
+        let mut on_chain = BTreeSet::new();
+        let mut version = start;
+        let root = loop {
+            if let Some(&root) = roots.get(&version) {
+                break root;
+            }
+            if !on_chain.insert(version) {
+                break None;
+            }
+            match nodes.get(&version) {
+                Some(&parent) => {
+                    chain.push(version);
+                    match parent {
+                        Some(parent) => version = parent,
+                        None => break Some(version),
+                    }
+                }
+                None => break None,
+            }
+        };
+        for version in chain {
+            roots.insert(version, root);
+        }
+    }
+    roots
+}
+
+/// Reports an [`FsckProblem::InvalidKey`] if `parse` fails.
+fn parse_key<T>(
+    key: &[u8],
+    tree: FsckTree,
+    parse: fn(&[u8]) -> Option<T>,
+    problems: &mut Vec<FsckProblem>,
+) -> Option<T> {
+    let parsed = parse(key);
+    if parsed.is_none() {
+        problems.push(FsckProblem::InvalidKey {
+            tree,
+            key: key.to_vec(),
+        });
+    }
+    parsed
+}
+
+fn decompresses_in_layout(bytes: &[u8], layout: ChunkLayout) -> bool {
+    matches!(codec::decode(bytes), Some((_, raw)) if raw.len() == layout.chunk_size_bytes())
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::core::glam::IVec3;
+    use crate::database::tests::noise_chunk;
+    use crate::database::{Change, ChangeEncoder, MemStore};
+    use crate::sdf::Sd8;
+
+    fn chunk(palette_id: u8) -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk.set_voxel(IVec3::ZERO, palette_id, Sd8::MIN);
+        chunk
+    }
+
+    fn write_chunk(map: &mut MapDb<MemStore>, key: ChunkDbKey, palette_id: u8) {
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk(palette_id).compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+    }
+
+    fn corrupt(tree: &<MemStore as MapStore>::Tree, key: &[u8], value: Option<&[u8]>) {
+        let result: Result<(), TransactionError<AbortReason>> =
+            MemStore::transaction([tree], |[txn]| {
+                match value {
+                    Some(value) => txn.insert(key, StoreBytes::from(value))?,
+                    None => txn.remove(key)?,
+                };
+                Ok(())
+            });
+        result.unwrap();
+    }
+
+    /// Commits 4 versions in a line. Returns the committed versions, oldest first.
+    fn linear_history(map: &mut MapDb<MemStore>) -> Vec<Version> {
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut versions = Vec::new();
+        for i in 0..4 {
+            versions.push(map.cached_meta().working_version);
+            write_chunk(map, key, i);
+            map.commit_working_version().unwrap();
+        }
+        versions
+    }
+
+    #[test]
+    fn healthy_map_has_no_problems() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let versions = linear_history(&mut map);
+        map.branch_from_version(versions[1]).unwrap();
+        write_chunk(&mut map, ChunkDbKey::new(0, IVec3::ONE.into()), 9);
+        let tile = map.insert_tile(&Chunk::default()).unwrap();
+        map.instance_tile(tile, [ChunkDbKey::new(1, IVec3::ZERO.into())])
+            .unwrap();
+
+        assert_eq!(map.fsck().unwrap(), vec![]);
+        assert_eq!(map.repair().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn repair_drops_versions_behind_missing_changes() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let versions = linear_history(&mut map);
+
+        // The parent of the working version is versions[3], so versions[0] can only be reached through versions[1].
+        corrupt(&map.version_change_tree, &versions[1].into_sled_key(), None);
+        let stray_key = ChunkDbKey::new(2, IVec3::ZERO.into());
+        corrupt(
+            &map.backup_tree,
+            &stray_key.into_sled_key(),
+            Some(&[0xFF; 12][..]),
+        );
+
+        let expected = vec![
+            FsckProblem::MissingVersionChanges {
+                version: versions[1],
+            },
+            FsckProblem::InvalidBackupValue { key: stray_key },
+            FsckProblem::UncachedBackupKey { key: stray_key },
+        ];
+        assert_eq!(map.fsck().unwrap(), expected);
+        assert_eq!(map.repair().unwrap(), expected);
+        assert_eq!(map.fsck().unwrap(), vec![]);
+
+        assert!(map.branch_from_version(versions[2]).is_ok());
+        assert_eq!(
+            map.branch_from_version(versions[0]),
+            Err(TransactionError::Abort(AbortReason::NoPathExistsToRoot))
+        );
+    }
+
+    #[test]
+    fn repair_relinks_orphaned_parent_as_root() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let versions = linear_history(&mut map);
+        corrupt(
+            &map.version_graph_tree,
+            &versions[1].into_sled_key(),
+            Some(&[0xFF; 3][..]),
+        );
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(
+            key,
+            Change::Insert(CompressedChunk::tile_reference(TileId(99))),
+        );
+        let (_, missing_tile_ref) = encoder.encode().changes.pop().unwrap();
+        corrupt(
+            &map.working_tree,
+            &key.into_sled_key(),
+            Some(missing_tile_ref.take_bytes().as_ref()),
+        );
+
+        assert_eq!(
+            map.fsck().unwrap(),
+            vec![
+                FsckProblem::InvalidVersionNode {
+                    version: versions[1]
+                },
+                FsckProblem::NoPathToRoot {
+                    version: versions[2]
+                },
+                FsckProblem::NoPathToRoot {
+                    version: versions[3]
+                },
+                FsckProblem::UnreachableVersion {
+                    version: versions[0]
+                },
+                FsckProblem::OrphanVersionChanges {
+                    version: versions[1]
+                },
+                FsckProblem::InvalidWorkingValue { key },
+            ]
+        );
+        map.repair().unwrap();
+        assert_eq!(map.fsck().unwrap(), vec![]);
+        assert_eq!(map.cached_meta().grandparent_version, None);
+        assert_eq!(map.read_working_chunk(key).unwrap(), None);
+
+        // The repaired map can still be edited and committed.
+        write_chunk(&mut map, key, 5);
+        map.commit_working_version().unwrap();
+        map.branch_from_version(versions[3]).unwrap();
+        assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk(3)));
+    }
+
+    #[test]
+    fn malformed_keys_are_reported_and_removed() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        linear_history(&mut map);
+        corrupt(&map.working_tree, &[1, 2, 3], Some(&[0xFF; 4][..]));
+        corrupt(&map.tile_tree, &[7; 9], Some(&[0xFF; 4][..]));
+
+        let expected = vec![
+            FsckProblem::InvalidKey {
+                tree: FsckTree::Working,
+                key: vec![1, 2, 3],
+            },
+            FsckProblem::InvalidKey {
+                tree: FsckTree::Tiles,
+                key: vec![7; 9],
+            },
+        ];
+        assert_eq!(map.fsck().unwrap(), expected);
+        assert_eq!(map.repair().unwrap(), expected);
+        assert_eq!(map.fsck().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn deltas_are_checked_against_their_base() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut chunk = noise_chunk(777);
+        let mut versions = Vec::new();
+        for stroke in 0..3 {
+            chunk.set_voxel(IVec3::splat(stroke), 1, Sd8::MIN);
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+            map.write_working_version(encoder.encode()).unwrap();
+            versions.push(map.cached_meta().working_version);
+            map.commit_working_version().unwrap();
+        }
+        assert_eq!(map.fsck().unwrap(), vec![]);
+
+        // versions[1] is a delta against the working value, and versions[0] is a delta against versions[1].
+        corrupt(&map.working_tree, &key.into_sled_key(), None);
+
+        assert_eq!(
+            map.fsck().unwrap(),
+            vec![
+                FsckProblem::InvalidArchivedChunk {
+                    version: versions[1],
+                    key
+                },
+                FsckProblem::InvalidArchivedChunk {
+                    version: versions[0],
+                    key
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use super::version_change_tree::read_archived_version;
+use super::version_delta::{is_delta_change, resolve_delta};
+use super::{
+    abort, AbortReason, Change, ChunkDbKey, ConflictableTransactionError, TreeTxn, Version,
+};
+use crate::chunk::CompressedChunk;
+use crate::core::rkyv::{Deserialize, Infallible};
+
+use std::collections::BTreeMap;
+
+/// Both branches of a merge made different changes to the same [`ChunkDbKey`] since their nearest common ancestor.
+///
+/// A [`Change::Remove`] means that there is no chunk at `key` in the corresponding version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeConflict {
+    pub key: ChunkDbKey,
+    /// The value in the nearest common ancestor.
+    pub base: Change<CompressedChunk>,
+    /// The value in the working version.
+    pub ours: Change<CompressedChunk>,
+    /// The value in the version being merged.
+    pub theirs: Change<CompressedChunk>,
+}
+
+impl MergeConflict {
+    /// Resolution strategy that keeps the working version's value.
+    pub fn take_ours(&self) -> Change<CompressedChunk> {
+        self.ours.clone()
+    }
+
+    /// Resolution strategy that overwrites the working version's value with the merged version's value.
+    pub fn take_theirs(&self) -> Change<CompressedChunk> {
+        self.theirs.clone()
+    }
+}
+
+/// What happened during a [`MapDb::merge`](crate::database::MapDb::merge).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MergeSummary {
+    /// The number of changes written to the working version, including resolved conflicts.
+    pub num_changes_applied: usize,
+    /// Every key that required conflict resolution, in sorted order.
+    pub conflicts: Vec<ChunkDbKey>,
+}
+
+/// Applies the archived changes for each of `versions` in order, so that the latest value for each key wins.
+///
+/// `versions` must be a path leading away from the working version. Archived deltas are resolved against the previous value in
+/// `accum`, or `start_value` for keys that haven't been accumulated yet, which must give the value in the version before the
+/// first of `versions`.
+pub fn accumulate_archived_changes<E>(
+    txn: &impl TreeTxn,
+    versions: impl IntoIterator<Item = Version>,
+    mut start_value: impl FnMut(ChunkDbKey) -> Result<Change<CompressedChunk>, E>,
+    accum: &mut BTreeMap<ChunkDbKey, Change<CompressedChunk>>,
+) -> Result<(), ConflictableTransactionError<AbortReason>>
+where
+    ConflictableTransactionError<AbortReason>: From<E>,
+{
+    for version in versions {
+        if let Some(changes) = read_archived_version(txn, version)? {
+            for (key, change) in changes.as_ref().changes.iter() {
+                let key: ChunkDbKey = key.deserialize(&mut Infallible).unwrap();
+                let mut change = change.deserialize(&mut Infallible).unwrap();
+                if is_delta_change(&change) {
+                    let resolved = match accum.get(&key) {
+                        Some(prev) => resolve_delta(change, prev),
+                        None => resolve_delta(change, &start_value(key)?),
+                    };
+                    change = match resolved {
+                        Some(change) => change,
+                        None => return abort(AbortReason::InvalidVersionDelta { key }),
+                    };
+                }
+                accum.insert(key, change);
+            }
+        } else {
+            return abort(AbortReason::MissingVersionChanges);
+        }
+    }
+    Ok(())
+}
@@ -1,34 +1,46 @@
-use super::{AbortReason, ArchivedIVec, Version};
+use super::{
+    abort, AbortReason, ArchivedIVec, ConflictableTransactionError, MapStore, StoreBytes,
+    TransactionError, TreeTxn, UnabortableTransactionError, Version, MAP_FORMAT_VERSION,
+};
+use crate::chunk::{ChunkLayout, TileId};
+use crate::codec::ChunkCodec;
 use crate::core::rkyv::{
     ser::{serializers::CoreSerializer, Serializer},
     Archive, Deserialize, Serialize,
 };
+use crate::core::NoSharedAllocSerializer;
+use crate::material_registry::MaterialRegistry;
 
-use sled::{
-    transaction::{TransactionError, TransactionalTree, UnabortableTransactionError},
-    Tree,
-};
+use bytecheck::CheckBytes;
 
-const META_KEY: &str = "META";
+pub const META_KEY: &str = "META";
+const FORMAT_VERSION_KEY: &str = "FORMAT_VERSION";
+const CHUNK_LAYOUT_KEY: &str = "CHUNK_LAYOUT";
+const CHUNK_CODEC_KEY: &str = "CHUNK_CODEC";
+const NEXT_TILE_ID_KEY: &str = "NEXT_TILE_ID";
+const MATERIALS_KEY_PREFIX: &[u8; 9] = b"MATERIALS";
 
 #[derive(Archive, Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[archive(crate = "crate::core::rkyv")]
-#[archive_attr(derive(Eq, PartialEq))]
+#[archive_attr(derive(CheckBytes, Eq, PartialEq))]
 pub struct MapDbMetadata {
     pub grandparent_version: Option<Version>,
     pub parent_version: Option<Version>,
     pub working_version: Version,
 }
 
-pub fn open_meta_tree(
+/// Opens the meta tree of `map_name`. A new map is created with `new_map_layout`, while an existing map keeps the layout it was
+/// created with.
+pub fn open_meta_tree<S: MapStore>(
     map_name: &str,
-    db: &sled::Db,
-) -> Result<(Tree, MapDbMetadata), TransactionError<AbortReason>> {
-    let tree = db.open_tree(format!("{}-meta", map_name))?;
+    store: &S,
+    new_map_layout: ChunkLayout,
+) -> Result<(S::Tree, MapDbMetadata, ChunkLayout), TransactionError<AbortReason>> {
+    let tree = store.open_tree(&format!("{}-meta", map_name))?;
 
-    let cached_meta = tree.transaction(|txn| {
+    let (cached_meta, chunk_layout) = S::transaction([&tree], |[txn]| {
         if let Some(cached_meta) = read_meta(txn)? {
-            Ok(cached_meta.deserialize())
+            Ok((cached_meta.deserialize(), read_chunk_layout(txn)?))
         } else {
             // First time opening this tree. Write the initial values.
             let working_version = Version::new(txn.generate_id()?);
@@ -38,15 +50,17 @@ pub fn open_meta_tree(
                 working_version,
             };
             write_meta(txn, &meta)?;
-            Ok(meta)
+            write_format_version(txn, MAP_FORMAT_VERSION)?;
+            write_chunk_layout(txn, new_map_layout)?;
+            Ok((meta, new_map_layout))
         }
     })?;
 
-    Ok((tree, cached_meta))
+    Ok((tree, cached_meta, chunk_layout))
 }
 
 pub fn write_meta(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     meta: &MapDbMetadata,
 ) -> Result<(), UnabortableTransactionError> {
     // TODO: one liner?
@@ -55,16 +69,162 @@ pub fn write_meta(
     serializer.serialize_value(meta).unwrap();
     let bytes = serializer.into_serializer().into_inner();
 
-    txn.insert(META_KEY, bytes.as_ref())?;
+    txn.insert(META_KEY.as_bytes(), StoreBytes::from(bytes.as_ref()))?;
 
     Ok(())
 }
 
+/// Aborts with [`AbortReason::InvalidMetadata`] if the stored bytes are not valid [`MapDbMetadata`].
 pub fn read_meta(
-    txn: &TransactionalTree,
-) -> Result<Option<ArchivedIVec<MapDbMetadata>>, UnabortableTransactionError> {
-    let data = txn.get(META_KEY)?;
-    Ok(data.map(|b| unsafe { ArchivedIVec::<MapDbMetadata>::new(b) }))
+    txn: &impl TreeTxn,
+) -> Result<Option<ArchivedIVec<MapDbMetadata>>, ConflictableTransactionError<AbortReason>> {
+    match txn.get(META_KEY.as_bytes())? {
+        Some(bytes) => match ArchivedIVec::<MapDbMetadata>::try_new(bytes) {
+            Ok(meta) => Ok(Some(meta)),
+            Err(_) => abort(AbortReason::InvalidMetadata),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The [`MAP_FORMAT_VERSION`] that the map is stored in. Maps created before the format was versioned have no entry. Aborts with
+/// [`AbortReason::InvalidMetadata`] if the entry is malformed.
+///
+/// The format is stored on its own, as a little-endian `u32`, so that any build of feldspar can read it no matter how the
+/// layout of everything else changed.
+pub fn read_format_version(
+    txn: &impl TreeTxn,
+) -> Result<Option<u32>, ConflictableTransactionError<AbortReason>> {
+    match txn.get(FORMAT_VERSION_KEY.as_bytes())? {
+        Some(bytes) => match <[u8; 4]>::try_from(bytes.as_ref()) {
+            Ok(bytes) => Ok(Some(u32::from_le_bytes(bytes))),
+            Err(_) => abort(AbortReason::InvalidMetadata),
+        },
+        None => Ok(None),
+    }
+}
+
+pub fn write_format_version(
+    txn: &impl TreeTxn,
+    format_version: u32,
+) -> Result<(), UnabortableTransactionError> {
+    txn.insert(
+        FORMAT_VERSION_KEY.as_bytes(),
+        StoreBytes::from(&format_version.to_le_bytes()[..]),
+    )?;
+    Ok(())
+}
+
+/// Maps created before chunk layouts existed have no layout entry, and they always use [`ChunkLayout::Palette8`].
+///
+/// Like the codec, the layout is kept out of [`MapDbMetadata`]. The metadata is rewritten by every commit while the layout is
+/// written once, and a missing entry already describes every older map, so no format migration was needed.
+pub fn read_chunk_layout(txn: &impl TreeTxn) -> Result<ChunkLayout, UnabortableTransactionError> {
+    let data = txn.get(CHUNK_LAYOUT_KEY.as_bytes())?;
+    Ok(data
+        .and_then(|b| b.first().copied())
+        .and_then(ChunkLayout::from_u8)
+        .unwrap_or(ChunkLayout::Palette8))
+}
+
+pub fn write_chunk_layout(
+    txn: &impl TreeTxn,
+    layout: ChunkLayout,
+) -> Result<(), UnabortableTransactionError> {
+    txn.insert(
+        CHUNK_LAYOUT_KEY.as_bytes(),
+        StoreBytes::from(&[layout as u8][..]),
+    )?;
+    Ok(())
+}
+
+/// Maps that never chose a codec compress with [`ChunkCodec::Lz4Frame`].
+pub fn read_chunk_codec(txn: &impl TreeTxn) -> Result<ChunkCodec, UnabortableTransactionError> {
+    let data = txn.get(CHUNK_CODEC_KEY.as_bytes())?;
+    Ok(data
+        .and_then(|b| b.first().copied())
+        .and_then(ChunkCodec::from_u8)
+        .unwrap_or_default())
+}
+
+pub fn write_chunk_codec(
+    txn: &impl TreeTxn,
+    codec: ChunkCodec,
+) -> Result<(), UnabortableTransactionError> {
+    txn.insert(
+        CHUNK_CODEC_KEY.as_bytes(),
+        StoreBytes::from(&[codec as u8][..]),
+    )?;
+    Ok(())
+}
+
+/// The ID that the next tile inserted into the map will get. Maps without any tiles have no entry.
+pub fn read_next_tile_id(txn: &impl TreeTxn) -> Result<TileId, UnabortableTransactionError> {
+    let data = txn.get(NEXT_TILE_ID_KEY.as_bytes())?;
+    Ok(data.map_or(TileId(0), |b| TileId::from_sled_key(&b)))
+}
+
+pub fn write_next_tile_id(
+    txn: &impl TreeTxn,
+    next: TileId,
+) -> Result<(), UnabortableTransactionError> {
+    txn.insert(
+        NEXT_TILE_ID_KEY.as_bytes(),
+        StoreBytes::from(&next.into_sled_key()[..]),
+    )?;
+    Ok(())
+}
+
+/// A version only stores a [`MaterialRegistry`] if it changed the registry of its parent, keyed by the version. Other versions
+/// inherit the registry of their nearest ancestor that stores one.
+pub fn material_registry_key(version: Version) -> [u8; 17] {
+    let mut key = [0; 17];
+    key[..9].copy_from_slice(MATERIALS_KEY_PREFIX);
+    key[9..].copy_from_slice(&version.into_sled_key());
+    key
+}
+
+/// The inverse of [`material_registry_key`].
+pub fn material_registry_version(key: &[u8]) -> Option<Version> {
+    key.strip_prefix(MATERIALS_KEY_PREFIX.as_slice())
+        .and_then(Version::try_from_sled_key)
+}
+
+pub fn write_material_registry(
+    txn: &impl TreeTxn,
+    version: Version,
+    registry: &MaterialRegistry,
+) -> Result<(), UnabortableTransactionError> {
+    let mut serializer = NoSharedAllocSerializer::<1024>::default();
+    serializer.serialize_value(registry).unwrap();
+    let bytes = serializer.into_serializer().into_inner();
+    txn.insert(
+        &material_registry_key(version),
+        StoreBytes::from(bytes.as_ref()),
+    )?;
+    Ok(())
+}
+
+/// Aborts with [`AbortReason::InvalidMaterialRegistry`] if the stored bytes are not a valid [`MaterialRegistry`].
+pub fn read_material_registry(
+    txn: &impl TreeTxn,
+    version: Version,
+) -> Result<Option<MaterialRegistry>, ConflictableTransactionError<AbortReason>> {
+    match txn.get(&material_registry_key(version))? {
+        Some(bytes) => match ArchivedIVec::<MaterialRegistry>::try_new(bytes) {
+            Ok(registry) => Ok(Some(registry.deserialize())),
+            Err(_) => abort(AbortReason::InvalidMaterialRegistry { version }),
+        },
+        None => Ok(None),
+    }
+}
+
+pub fn remove_material_registry(
+    txn: &impl TreeTxn,
+    version: Version,
+) -> Result<(), UnabortableTransactionError> {
+    txn.remove(&material_registry_key(version))?;
+    Ok(())
 }
 
 // ████████╗███████╗███████╗████████╗
@@ -81,22 +241,60 @@ mod tests {
     #[test]
     fn open_write_and_reopen_meta_tree() {
         let db = sled::Config::default().temporary(true).open().unwrap();
-        let (tree, cached_meta) = open_meta_tree("mymap", &db).unwrap();
+        let (tree, cached_meta, layout) =
+            open_meta_tree("mymap", &db, ChunkLayout::Palette16).unwrap();
 
         assert_eq!(cached_meta, MapDbMetadata::default());
+        assert_eq!(layout, ChunkLayout::Palette16);
 
         let new_meta = MapDbMetadata {
             grandparent_version: None,
             parent_version: Some(Version::new(20)),
             working_version: Version::new(18),
         };
-        let _: Result<(), TransactionError<()>> = tree.transaction(|txn| {
-            write_meta(txn, &new_meta)?;
-            Ok(())
-        });
+        let _: Result<(), TransactionError<()>> =
+            <sled::Db as MapStore>::transaction([&tree], |[txn]| {
+                write_meta(txn, &new_meta)?;
+                Ok(())
+            });
 
         // Re-open to make sure we can refresh the cached value.
-        let (_tree, cached_meta) = open_meta_tree("mymap", &db).unwrap();
+        // The layout is only chosen when the map is created.
+        let (_tree, cached_meta, layout) =
+            open_meta_tree("mymap", &db, ChunkLayout::Palette8).unwrap();
         assert_eq!(cached_meta, new_meta);
+        assert_eq!(layout, ChunkLayout::Palette16);
+    }
+
+    #[test]
+    fn write_read_and_remove_material_registry() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let (tree, _, _) = open_meta_tree("mymap", &db, ChunkLayout::Palette8).unwrap();
+
+        let mut registry = MaterialRegistry::default();
+        registry.add("stone", Default::default()).unwrap();
+
+        let v1 = Version::new(1);
+        let v2 = Version::new(2);
+        let result: Result<_, TransactionError<AbortReason>> =
+            <sled::Db as MapStore>::transaction([&tree], |[txn]| {
+                assert_eq!(read_material_registry(txn, v1)?, None);
+                write_material_registry(txn, v1, &registry)?;
+                write_material_registry(txn, v2, &registry)?;
+                remove_material_registry(txn, v2)?;
+                Ok((
+                    read_material_registry(txn, v1)?,
+                    read_material_registry(txn, v2)?,
+                ))
+            });
+        let (r1, r2) = result.unwrap();
+        assert_eq!(r1.as_ref(), Some(&registry));
+        assert_eq!(r2, None);
+
+        assert_eq!(
+            material_registry_version(&material_registry_key(v2)),
+            Some(v2)
+        );
+        assert_eq!(material_registry_version(META_KEY.as_bytes()), None);
     }
 }
@@ -0,0 +1,290 @@
+use super::backup_tree::open_backup_tree;
+use super::meta_tree::{read_format_version, write_format_version, META_KEY};
+use super::tile_tree::open_tile_tree;
+use super::version_change_tree::open_version_change_tree;
+use super::version_graph_tree::open_version_graph_tree;
+use super::working_tree::open_working_tree;
+use super::{
+    abort, AbortReason, ConflictableTransactionError, MapStore, TransactionError, TreeTxn,
+};
+
+/// The version of the on-disk format of a [`MapDb`](super::MapDb) written by this build. Maps in an older format are migrated in
+/// place when they are opened, and maps in a newer format are refused with [`AbortReason::UnsupportedFormat`].
+///
+/// - 0: Maps created before the format was versioned.
+/// - 1: The meta tree has a format version entry.
+///
+/// When bumping this, add a migration from the previous format to [`migrate_from`] and check in a fixture of the migrated map.
+pub const MAP_FORMAT_VERSION: u32 = 1;
+
+/// Upgrades `map_name` to [`MAP_FORMAT_VERSION`] if it was stored in an older format. Nothing happens for new maps.
+///
+/// All migrations run in a single transaction, so a map is never left in an intermediate format.
+pub fn migrate<S: MapStore>(
+    map_name: &str,
+    store: &S,
+) -> Result<(), TransactionError<AbortReason>> {
+    let trees = [
+        store.open_tree(&format!("{}-meta", map_name))?,
+        open_working_tree(map_name, store)?,
+        open_backup_tree(map_name, store)?.0,
+        open_version_change_tree(map_name, store)?,
+        open_version_graph_tree(map_name, store)?,
+        open_tile_tree(map_name, store)?,
+    ];
+    let [meta_tree, working_tree, backup_tree, change_tree, graph_tree, tile_tree] = &trees;
+    S::transaction(
+        [
+            meta_tree,
+            working_tree,
+            backup_tree,
+            change_tree,
+            graph_tree,
+            tile_tree,
+        ],
+        |txns| {
+            let stored_version = match read_stored_format_version(&txns[0])? {
+                Some(v) => v,
+                None => return Ok(()),
+            };
+            if stored_version > MAP_FORMAT_VERSION {
+                return abort(AbortReason::UnsupportedFormat {
+                    format_version: stored_version,
+                });
+            }
+            for format_version in stored_version..MAP_FORMAT_VERSION {
+                log::info!(
+                    "Migrating map {} from format {} to {}",
+                    map_name,
+                    format_version,
+                    format_version + 1
+                );
+                migrate_from(format_version, txns)?;
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Upgrades a map from `format_version` to the next format. `txns` are the meta, working, backup, version change, version graph,
+/// and tile trees, in that order.
+///
+/// Each migration must write the exact layout of the format it upgrades to, so it can't use a type whose layout may change
+/// later. Freeze a copy of the old type here instead.
+fn migrate_from<T: TreeTxn>(
+    format_version: u32,
+    txns: &[T; 6],
+) -> Result<(), ConflictableTransactionError<AbortReason>> {
+    match format_version {
+        0 => {
+            write_format_version(&txns[0], 1)?;
+            Ok(())
+        }
+        _ => unreachable!("No migration from format {}", format_version),
+    }
+}
+
+/// Reads the format of the map, or `None` if the map is new.
+///
+/// Only the format version entry decides the format. A map without one is in format 0, since every later format writes it.
+fn read_stored_format_version(
+    meta_txn: &impl TreeTxn,
+) -> Result<Option<u32>, ConflictableTransactionError<AbortReason>> {
+    if meta_txn.get(META_KEY.as_bytes())?.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(read_format_version(meta_txn)?.unwrap_or(0)))
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::database::catalog::MAP_TREE_SUFFIXES;
+    use crate::database::meta_tree::MapDbMetadata;
+    use crate::database::{ChunkDbKey, MapDb, MemStore, StoreBytes, StoreTree, Version};
+    use crate::sdf::Sd8;
+
+    /// Fixtures of the same map in every format, indexed by format version.
+    const FIXTURES: [&str; MAP_FORMAT_VERSION as usize + 1] = [
+        include_str!("../../fixtures/map_format0.txt"),
+        include_str!("../../fixtures/map_format1.txt"),
+    ];
+
+    /// Every `(tree suffix, key, value)` entry of a fixture, sorted.
+    fn parse_fixture(fixture: &str) -> Vec<(String, Vec<u8>, Vec<u8>)> {
+        let mut entries: Vec<_> = fixture
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                let tree_name = fields.next().unwrap().to_string();
+                let key = parse_hex(fields.next().unwrap());
+                let value = parse_hex(fields.next().unwrap());
+                (tree_name, key, value)
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    /// Writes every entry of a fixture into the trees of `map_name`.
+    fn load_fixture<S: MapStore>(store: &S, map_name: &str, fixture: &str) {
+        for (tree_name, key, value) in parse_fixture(fixture) {
+            let tree = store
+                .open_tree(&format!("{}-{}", map_name, tree_name))
+                .unwrap();
+            let result: Result<(), TransactionError<()>> = S::transaction([&tree], |[txn]| {
+                txn.insert(&key, StoreBytes::from(value.as_slice()))?;
+                Ok(())
+            });
+            result.unwrap();
+        }
+    }
+
+    /// Every entry in the trees of `map_name`, in the same form as [`parse_fixture`].
+    fn dump_map<S: MapStore>(store: &S, map_name: &str) -> Vec<(String, Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        for suffix in MAP_TREE_SUFFIXES {
+            let tree = store
+                .open_tree(&format!("{}-{}", map_name, suffix))
+                .unwrap();
+            for iter_result in tree.iter() {
+                let (key, value) = iter_result.unwrap();
+                entries.push((suffix.to_string(), key.to_vec(), value.to_vec()));
+            }
+        }
+        entries.sort();
+        entries
+    }
+
+    fn parse_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fixture_chunk() -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk.sdf = [Sd8(-10); 4096];
+        chunk.palette_ids = [7; 4096];
+        chunk
+    }
+
+    fn stored_format_version<S: MapStore>(store: &S, map_name: &str) -> Option<u32> {
+        let meta_tree = store.open_tree(&format!("{}-meta", map_name)).unwrap();
+        let result: Result<_, TransactionError<AbortReason>> =
+            S::transaction([&meta_tree], |[txn]| read_stored_format_version(txn));
+        result.unwrap()
+    }
+
+    #[test]
+    fn every_fixture_opens_as_the_current_format() {
+        open_every_fixture(MemStore::default());
+        open_every_fixture(sled::Config::default().temporary(true).open().unwrap());
+    }
+
+    fn open_every_fixture<S: MapStore>(store: S) {
+        for (format_version, fixture) in FIXTURES.iter().enumerate() {
+            let map_name = format!("format{}", format_version);
+            load_fixture(&store, &map_name, fixture);
+            assert_eq!(
+                stored_format_version(&store, &map_name),
+                Some(format_version as u32)
+            );
+
+            // Reopening must not migrate again.
+            for _ in 0..2 {
+                let map = MapDb::open(&store, &map_name).unwrap();
+                assert_eq!(
+                    stored_format_version(&store, &map_name),
+                    Some(MAP_FORMAT_VERSION)
+                );
+                assert_eq!(
+                    map.cached_meta(),
+                    &MapDbMetadata {
+                        grandparent_version: None,
+                        parent_version: Some(Version::new(0)),
+                        working_version: Version::new(1),
+                    }
+                );
+                let key = ChunkDbKey::from_sled_key(&[0; 13]);
+                assert_eq!(map.read_working_chunk(key).unwrap(), Some(fixture_chunk()));
+                assert_eq!(map.fsck().unwrap(), vec![]);
+            }
+        }
+    }
+
+    /// The fixture of each format must be exactly what migrating the fixture of the previous format writes, so only the oldest
+    /// fixture is written by hand.
+    #[test]
+    fn migrating_each_fixture_produces_the_next() {
+        let store = MemStore::default();
+        for (format_version, pair) in FIXTURES.windows(2).enumerate() {
+            let map_name = format!("format{}", format_version);
+            load_fixture(&store, &map_name, pair[0]);
+            migrate(&map_name, &store).unwrap();
+            assert_eq!(dump_map(&store, &map_name), parse_fixture(pair[1]));
+        }
+    }
+
+    #[test]
+    fn newer_format_is_refused() {
+        let store = MemStore::default();
+        load_fixture(&store, "mymap", FIXTURES[MAP_FORMAT_VERSION as usize]);
+        let meta_tree = store.open_tree("mymap-meta").unwrap();
+        let result: Result<(), TransactionError<()>> =
+            MemStore::transaction([&meta_tree], |[txn]| {
+                write_format_version(txn, MAP_FORMAT_VERSION + 1)?;
+                Ok(())
+            });
+        result.unwrap();
+        let before = dump_map(&store, "mymap");
+
+        assert!(matches!(
+            MapDb::open(&store, "mymap"),
+            Err(TransactionError::Abort(AbortReason::UnsupportedFormat { format_version }))
+                if format_version == MAP_FORMAT_VERSION + 1
+        ));
+        // The map is left untouched.
+        assert_eq!(dump_map(&store, "mymap"), before);
+    }
+
+    #[test]
+    fn format_is_only_read_from_its_own_entry() {
+        let store = MemStore::default();
+        assert_eq!(stored_format_version(&store, "mymap"), None);
+
+        // The length of the metadata doesn't matter.
+        let meta_tree = store.open_tree("mymap-meta").unwrap();
+        let insert = |key: &str, value: &[u8]| {
+            let result: Result<(), TransactionError<()>> =
+                MemStore::transaction([&meta_tree], |[txn]| {
+                    txn.insert(key.as_bytes(), StoreBytes::from(value))?;
+                    Ok(())
+                });
+            result.unwrap();
+        };
+        insert(META_KEY, &[0xFF; 48]);
+        assert_eq!(stored_format_version(&store, "mymap"), Some(0));
+
+        insert("FORMAT_VERSION", &7u32.to_le_bytes());
+        assert_eq!(stored_format_version(&store, "mymap"), Some(7));
+
+        insert("FORMAT_VERSION", &[1]);
+        let result: Result<_, TransactionError<AbortReason>> =
+            MemStore::transaction([&meta_tree], |[txn]| read_stored_format_version(txn));
+        assert_eq!(
+            result,
+            Err(TransactionError::Abort(AbortReason::InvalidMetadata))
+        );
+    }
+}
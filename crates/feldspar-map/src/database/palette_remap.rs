@@ -0,0 +1,194 @@
+use super::{
+    AbortReason, ArchivedChangeIVec, Change, ChangeEncoder, ChunkDbKey, MapDb, MapStore, ReadError,
+    StoreBytes, StoreTree, TransactionError,
+};
+use crate::chunk::{Chunk, ChunkLayout, CompressedChunk};
+use crate::palette::PaletteId8;
+
+/// The number of voxels that reference each [`PaletteId8`] in the working version, summed over all levels of detail.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PaletteUsage {
+    pub voxel_counts: [u64; 256],
+}
+
+impl Default for PaletteUsage {
+    fn default() -> Self {
+        Self {
+            voxel_counts: [0; 256],
+        }
+    }
+}
+
+impl PaletteUsage {
+    pub fn add_chunk(&mut self, chunk: &Chunk) {
+        for &id in chunk.palette_ids.iter() {
+            self.voxel_counts[id as usize] += 1;
+        }
+    }
+
+    /// The IDs that no voxel references, which can be reclaimed.
+    pub fn unused_ids(&self) -> impl Iterator<Item = PaletteId8> + '_ {
+        self.voxel_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count == 0)
+            .map(|(id, _)| id as PaletteId8)
+    }
+
+    /// A mapping for [`MapDb::remap_palette`] that packs the used IDs into `0..n` while preserving their order.
+    pub fn compacting_mapping(&self) -> [PaletteId8; 256] {
+        let mut mapping = [0; 256];
+        let mut next_id = 0;
+        for (id, &count) in self.voxel_counts.iter().enumerate() {
+            if count > 0 {
+                mapping[id] = next_id;
+                next_id = next_id.saturating_add(1);
+            }
+        }
+        mapping
+    }
+}
+
+impl<S: MapStore> MapDb<S> {
+    /// Scans every chunk of the working version, at every level of detail, and counts the voxels using each [`PaletteId8`].
+    pub fn palette_usage(&self) -> Result<PaletteUsage, ReadError> {
+        self.require_layout(ChunkLayout::Palette8)?;
+        let mut usage = PaletteUsage::default();
+        for entry in self.working_tree.iter() {
+            let (key_bytes, value_bytes) = entry?;
+            let key = ChunkDbKey::from_sled_key(&key_bytes);
+            if let Some(chunk) = self.decompress_working_value(key, value_bytes)? {
+                usage.add_chunk(&chunk);
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Replaces every palette ID `i` with `mapping[i]` in every chunk of the working version, at every level of detail, and in
+    /// the [`MaterialRegistry`](crate::material_registry::MaterialRegistry). Registry entries of IDs that are in use take
+    /// priority when several IDs are merged.
+    ///
+    /// The working version is committed before and after the remap, so the remap is a single version of its own that can be
+    /// reverted with [`MapDb::branch_from_version`]. Only chunks with changed IDs are rewritten. Any chunks loaded in a
+    /// [`ChunkClipMap`](crate::clipmap::ChunkClipMap) must be reloaded afterwards.
+    ///
+    /// Tiles are shared with older versions, so they are never rewritten. Instead, each instance of a tile with changed IDs
+    /// is replaced by a remapped copy of the tile.
+    pub fn remap_palette(
+        &mut self,
+        mapping: &[PaletteId8; 256],
+    ) -> Result<(), TransactionError<AbortReason>> {
+        self.require_layout(ChunkLayout::Palette8)?;
+        self.commit_working_version()?;
+
+        let mut usage = PaletteUsage::default();
+        let mut encoder = ChangeEncoder::default();
+        for entry in self.working_tree.iter() {
+            let (key_bytes, value_bytes) = entry?;
+            let key = ChunkDbKey::from_sled_key(&key_bytes);
+            if let Some(mut chunk) = self.decompress_working_value(key, value_bytes)? {
+                usage.add_chunk(&chunk);
+                if chunk.remap_palette(mapping) {
+                    encoder.add_compressed_change(
+                        key,
+                        Change::Insert(chunk.compress_with(self.chunk_codec)),
+                    );
+                }
+            }
+        }
+        self.write_working_version(encoder.encode())?;
+
+        if !self.material_registry().is_empty() {
+            let remapped = self
+                .material_registry()
+                .remap(mapping, |id| usage.voxel_counts[id as usize] > 0);
+            self.write_material_registry(remapped)?;
+        }
+
+        self.commit_working_version()
+    }
+
+    fn decompress_working_value(
+        &self,
+        key: ChunkDbKey,
+        bytes: StoreBytes,
+    ) -> Result<Option<Chunk>, ReadError> {
+        let change = ArchivedChangeIVec::<CompressedChunk>::try_new(bytes)
+            .map_err(|_| ReadError::InvalidArchive { key })?;
+        match change.as_ref().get_insert_data() {
+            Some(chunk) => self.decompress_stored_chunk(key, &chunk.bytes),
+            None => Ok(None),
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::glam::IVec3;
+    use crate::database::MemStore;
+    use crate::material_registry::MaterialRegistry;
+
+    fn write_chunk(map: &mut MapDb<MemStore>, key: ChunkDbKey, palette_id: PaletteId8) {
+        let mut chunk = Chunk::default();
+        chunk.palette_ids = [palette_id; 4096];
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+    }
+
+    #[test]
+    fn compact_ids_across_levels() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let key0 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key1 = ChunkDbKey::new(1, IVec3::ONE.into());
+        write_chunk(&mut map, key0, 3);
+        write_chunk(&mut map, key1, 7);
+
+        let mut registry = MaterialRegistry::default();
+        for i in 0..8 {
+            registry.add(i.to_string(), Default::default()).unwrap();
+        }
+        map.write_material_registry(registry).unwrap();
+
+        let usage = map.palette_usage().unwrap();
+        assert_eq!(usage.voxel_counts[3], 4096);
+        assert_eq!(usage.voxel_counts[7], 4096);
+        assert_eq!(usage.unused_ids().count(), 254);
+
+        let mapping = usage.compacting_mapping();
+        assert_eq!((mapping[3], mapping[7]), (0, 1));
+
+        map.remap_palette(&mapping).unwrap();
+        let version_before_remap = map.cached_meta().grandparent_version.unwrap();
+
+        assert_eq!(
+            map.read_working_chunk(key0).unwrap().unwrap().palette_ids[0],
+            0
+        );
+        assert_eq!(
+            map.read_working_chunk(key1).unwrap().unwrap().palette_ids[0],
+            1
+        );
+        assert_eq!(map.material_registry().get(0).unwrap().name, "3");
+        assert_eq!(map.material_registry().get(1).unwrap().name, "7");
+        assert_eq!(map.material_registry().len(), 2);
+
+        // The remap is its own version.
+        map.branch_from_version(version_before_remap).unwrap();
+        assert_eq!(
+            map.read_working_chunk(key0).unwrap().unwrap().palette_ids[0],
+            3
+        );
+        assert_eq!(map.material_registry().len(), 8);
+    }
+}
@@ -0,0 +1,1064 @@
+use super::meta_tree::{
+    material_registry_key, material_registry_version, write_chunk_layout, write_material_registry,
+    write_meta, MapDbMetadata,
+};
+use super::tile_tree::restore_tile;
+use super::version_change_tree::archive_version;
+use super::version_graph_tree::{link_version, VersionNode};
+use super::{
+    read_stored_material_registry, AbortReason, ArchivedChangeIVec, ArchivedIVec, Change,
+    ChangeEncoder, ChunkDbKey, MapDb, MapStore, ReadError, StoreError, StoreResult, StoreTree,
+    TransactionError, TreeTxn, Version, VersionChanges,
+};
+use crate::chunk::{ChunkLayout, CompressedChunk, TileId};
+use crate::material_registry::MaterialRegistry;
+use crate::voxel_attributes::{MaterialId, VoxelAttributes};
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+/// Every portable map starts with these bytes.
+const MAGIC: [u8; 8] = *b"FELDSPAR";
+
+/// Incremented whenever the portable format changes in a way that older readers can't understand.
+pub const PORTABLE_FORMAT_VERSION: u32 = 4;
+
+/// Set in the header flags when the file contains the backup tree and the version graph.
+const FLAG_HAS_HISTORY: u32 = 1;
+
+/// The maximum number of chunks written in a single transaction during import.
+const IMPORT_BATCH_SIZE: usize = 1024;
+
+/// Records with longer payloads are rejected, so a corrupt length can't make the reader allocate without bound.
+const MAX_PAYLOAD_LEN: u32 = 1 << 30;
+
+/// # Portable Map Format
+///
+/// A portable map is a single stream that starts with a 16-byte header:
+///
+/// ```text
+/// magic: [u8; 8] = "FELDSPAR"
+/// format_version: u32
+/// flags: u32
+/// ```
+///
+/// followed by a sequence of records:
+///
+/// ```text
+/// tag: u8
+/// payload_len: u32 (at most 1 GiB)
+/// payload: [u8; payload_len]
+/// crc32: u32 (of tag, payload_len, and payload)
+/// ```
+///
+/// The last record is always an [`End`](RecordTag::End) record that holds the number of records before it, so truncated files
+/// are detected. All integers are little-endian. Payloads never contain `rkyv` archives, since those layouts are not stable
+/// across versions of feldspar.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+enum RecordTag {
+    /// `num_records: u64`
+    End = 0,
+    /// `grandparent_version: Option<u64>, parent_version: Option<u64>, working_version: u64, chunk_layout: u8`
+    ///
+    /// Format version 1 has no `chunk_layout`, which means [`ChunkLayout::Palette8`].
+    Meta = 1,
+    /// `key: [u8; 13], compressed_chunk: Bytes`
+    ///
+    /// Chunks keep the [`ChunkCodec`](crate::codec::ChunkCodec) they were written with. The codec a map writes new chunks
+    /// with is not exported.
+    Chunk = 2,
+    /// `key: [u8; 13], change: Change`
+    Backup = 3,
+    /// `version: u64, parent_version: Option<u64>`
+    VersionNode = 4,
+    /// `version: u64, num_changes: u32, [key: [u8; 13], change: Change; num_changes]`
+    ///
+    /// Inserted chunks may be deltas against the neighboring version, exactly as they are stored in the version change tree.
+    VersionChanges = 5,
+    /// `tile_id: u64, compressed_chunk: Bytes`
+    ///
+    /// Only in format version 3 and later. Tiles keep their IDs, since chunks reference them.
+    Tile = 6,
+    /// `version: u64, num_entries: u32, [name: Bytes, is_collidable: u8, material_id: u8, retired: u8; num_entries]`
+    ///
+    /// Only in format version 4 and later. Without history, there is at most one, for the working version.
+    MaterialRegistry = 7,
+}
+
+impl RecordTag {
+    fn from_u8(tag: u8) -> Option<Self> {
+        Some(match tag {
+            0 => Self::End,
+            1 => Self::Meta,
+            2 => Self::Chunk,
+            3 => Self::Backup,
+            4 => Self::VersionNode,
+            5 => Self::VersionChanges,
+            6 => Self::Tile,
+            7 => Self::MaterialRegistry,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PortableMapError {
+    Io(io::Error),
+    Transaction(TransactionError<AbortReason>),
+    /// The stream does not start with the portable map header.
+    NotAPortableMap,
+    /// The stream was written by a newer version of feldspar.
+    UnsupportedFormatVersion(u32),
+    /// The stream ended before the end record.
+    Truncated,
+    /// A record failed its CRC32 check.
+    ChecksumMismatch {
+        record_index: u64,
+    },
+    /// A record passed its CRC32 check, but its payload could not be decoded.
+    MalformedRecord {
+        record_index: u64,
+    },
+    /// A record is longer than the format allows.
+    RecordTooLarge {
+        record_index: u64,
+        payload_len: u64,
+    },
+    /// The map being exported has a key of the wrong length, e.g. because the database was corrupted. See
+    /// [`MapDb::fsck`].
+    InvalidStoredKey {
+        key: Vec<u8>,
+    },
+    /// Portable maps can only be imported into an empty map.
+    MapNotEmpty,
+}
+
+impl From<io::Error> for PortableMapError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Self::Truncated
+        } else {
+            Self::Io(e)
+        }
+    }
+}
+
+impl From<TransactionError<AbortReason>> for PortableMapError {
+    fn from(e: TransactionError<AbortReason>) -> Self {
+        Self::Transaction(e)
+    }
+}
+
+impl From<StoreError> for PortableMapError {
+    fn from(e: StoreError) -> Self {
+        Self::Transaction(TransactionError::Storage(e))
+    }
+}
+
+impl From<ReadError> for PortableMapError {
+    fn from(e: ReadError) -> Self {
+        Self::Transaction(e.into())
+    }
+}
+
+impl<S: MapStore> MapDb<S> {
+    /// Writes the map into `writer` using the portable map format.
+    ///
+    /// Every chunk in the working version is always written. If `include_history` is `true`, then the backup tree and the whole
+    /// version graph are also written, so that every version can be restored by [`MapDb::import`].
+    pub fn export(
+        &self,
+        writer: impl Write,
+        include_history: bool,
+    ) -> Result<(), PortableMapError> {
+        let flags = if include_history { FLAG_HAS_HISTORY } else { 0 };
+        let mut records = RecordWriter::new(writer, flags)?;
+        let mut payload = PayloadWriter::default();
+
+        payload.meta(&self.cached_meta);
+        payload.u8(self.chunk_layout as u8);
+        records.write_record(RecordTag::Meta, &mut payload)?;
+
+        if include_history {
+            let registries = self.meta_tree.range(
+                &material_registry_key(Version::new(0)),
+                &material_registry_key(Version::new(u64::MAX)),
+            );
+            for iter_result in registries {
+                let (key_bytes, value_bytes) = iter_result?;
+                let version = parse_key(&key_bytes, material_registry_version)?;
+                let registry = ArchivedIVec::<MaterialRegistry>::try_new(value_bytes)
+                    .map_err(|_| ReadError::InvalidMaterialRegistry { version })?
+                    .deserialize();
+                payload.version(version);
+                payload.material_registry(&registry);
+                records.write_record(RecordTag::MaterialRegistry, &mut payload)?;
+            }
+        } else if !self.cached_material_registry.is_empty() {
+            payload.version(self.cached_meta.working_version);
+            payload.material_registry(&self.cached_material_registry);
+            records.write_record(RecordTag::MaterialRegistry, &mut payload)?;
+        }
+
+        for iter_result in self.tile_tree.iter() {
+            let (id_bytes, tile_bytes) = iter_result?;
+            payload.u64(parse_key(&id_bytes, TileId::try_from_sled_key)?.0);
+            payload.bytes(&tile_bytes);
+            records.write_record(RecordTag::Tile, &mut payload)?;
+        }
+
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value_bytes) = iter_result?;
+            let key = parse_key(&key_bytes, ChunkDbKey::try_from_sled_key)?;
+            let change = ArchivedChangeIVec::<CompressedChunk>::try_new(value_bytes)
+                .map_err(|_| ReadError::InvalidArchive { key })?;
+            if let Some(chunk) = change.as_ref().get_insert_data() {
+                payload.key(key);
+                payload.bytes(&*chunk.bytes);
+                records.write_record(RecordTag::Chunk, &mut payload)?;
+            }
+        }
+
+        if include_history {
+            for iter_result in self.backup_tree.iter() {
+                let (key_bytes, value_bytes) = iter_result?;
+                let key = parse_key(&key_bytes, ChunkDbKey::try_from_sled_key)?;
+                let change = ArchivedChangeIVec::<CompressedChunk>::try_new(value_bytes)
+                    .map_err(|_| ReadError::InvalidArchive { key })?;
+                payload.key(key);
+                payload.change(&change.deserialize());
+                records.write_record(RecordTag::Backup, &mut payload)?;
+            }
+
+            for iter_result in self.version_graph_tree.iter() {
+                let (key_bytes, value_bytes) = iter_result?;
+                let version = parse_key(&key_bytes, Version::try_from_sled_key)?;
+                let node = ArchivedIVec::<VersionNode>::try_new(value_bytes)
+                    .map_err(|_| ReadError::InvalidVersionNode { version })?
+                    .deserialize();
+                payload.version(version);
+                payload.option_version(node.parent_version);
+                records.write_record(RecordTag::VersionNode, &mut payload)?;
+            }
+
+            for iter_result in self.version_change_tree.iter() {
+                let (key_bytes, value_bytes) = iter_result?;
+                let version = parse_key(&key_bytes, Version::try_from_sled_key)?;
+                let changes = ArchivedIVec::<VersionChanges>::try_new(value_bytes)
+                    .map_err(|_| {
+                        TransactionError::Abort(AbortReason::InvalidVersionArchive { version })
+                    })?
+                    .deserialize();
+                payload.version(version);
+                payload.u32(changes.changes.len() as u32);
+                for (key, change) in changes.changes.iter() {
+                    payload.key(*key);
+                    payload.change(change);
+                }
+                records.write_record(RecordTag::VersionChanges, &mut payload)?;
+            }
+        }
+
+        records.finish()?;
+        Ok(())
+    }
+
+    /// Creates the map called `map_name` in `store` from a portable map that was written by [`MapDb::export`].
+    ///
+    /// If the map already exists, it must be empty. The stream is imported in batches of chunks, so a failed import may leave a
+    /// partially imported map behind.
+    ///
+    /// Without history, all chunks are written to the working version as new changes. With history, the working version and
+    /// every archived version are restored, but they are given new [`Version`] numbers that are unique within `store`.
+    pub fn import(store: &S, map_name: &str, reader: impl Read) -> Result<Self, PortableMapError> {
+        let mut map = Self::open(store, map_name)?;
+        if map.cached_meta.parent_version.is_some()
+            || !map.backup_key_cache.keys.is_empty()
+            || map.working_tree.iter().next().is_some()
+        {
+            return Err(PortableMapError::MapNotEmpty);
+        }
+
+        let (mut records, format_version, flags) = RecordReader::new(reader)?;
+        let has_history = flags & FLAG_HAS_HISTORY != 0;
+
+        let mut version_map = BTreeMap::new();
+        let mut imported_meta = None;
+        let mut working_batch = ChangeEncoder::default();
+        let mut backup_batch = ChangeEncoder::default();
+        let mut working_batch_len = 0;
+        let mut backup_batch_len = 0;
+
+        while let Some(record) = records.next_record()? {
+            let Record {
+                tag,
+                index,
+                payload,
+            } = record;
+            let malformed = || PortableMapError::MalformedRecord {
+                record_index: index,
+            };
+            let mut payload = PayloadReader { bytes: payload };
+
+            match tag {
+                RecordTag::End => unreachable!(),
+                RecordTag::Meta => {
+                    imported_meta = Some(payload.meta().ok_or_else(malformed)?);
+                    let layout = if format_version >= 2 {
+                        payload.chunk_layout().ok_or_else(malformed)?
+                    } else {
+                        ChunkLayout::Palette8
+                    };
+                    map.set_empty_map_chunk_layout(layout)?;
+                }
+                RecordTag::Tile => {
+                    let id = TileId(payload.u64().ok_or_else(malformed)?);
+                    let bytes = payload.bytes().ok_or_else(malformed)?;
+                    let result: Result<(), TransactionError<AbortReason>> =
+                        S::transaction([&map.meta_tree, &map.tile_tree], |[meta_txn, tile_txn]| {
+                            restore_tile(meta_txn, tile_txn, id, bytes)?;
+                            Ok(())
+                        });
+                    result?;
+                }
+                RecordTag::MaterialRegistry => {
+                    let version = payload.version().ok_or_else(malformed)?;
+                    let registry = payload.material_registry().ok_or_else(malformed)?;
+                    let version = if has_history {
+                        remap_version(store, &mut version_map, version)?
+                    } else {
+                        map.cached_meta.working_version
+                    };
+                    let result: Result<(), TransactionError<AbortReason>> =
+                        S::transaction([&map.meta_tree], |[meta_txn]| {
+                            write_material_registry(meta_txn, version, &registry)?;
+                            Ok(())
+                        });
+                    result?;
+                }
+                RecordTag::Chunk => {
+                    let key = payload.key().ok_or_else(malformed)?;
+                    let bytes = payload.bytes().ok_or_else(malformed)?;
+                    working_batch.add_compressed_change(
+                        key,
+                        Change::Insert(CompressedChunk {
+                            bytes: bytes.into(),
+                        }),
+                    );
+                    working_batch_len += 1;
+                    if working_batch_len == IMPORT_BATCH_SIZE {
+                        map.import_working_batch(&mut working_batch, has_history)?;
+                        working_batch_len = 0;
+                    }
+                }
+                RecordTag::Backup if has_history => {
+                    let key = payload.key().ok_or_else(malformed)?;
+                    let change = payload.change().ok_or_else(malformed)?;
+                    backup_batch.add_compressed_change(key, change);
+                    map.backup_key_cache.keys.insert(key);
+                    backup_batch_len += 1;
+                    if backup_batch_len == IMPORT_BATCH_SIZE {
+                        map.import_backup_batch(&mut backup_batch)?;
+                        backup_batch_len = 0;
+                    }
+                }
+                RecordTag::VersionNode if has_history => {
+                    let version = payload.version().ok_or_else(malformed)?;
+                    let parent_version = payload.option_version().ok_or_else(malformed)?;
+                    let version = remap_version(store, &mut version_map, version)?;
+                    let parent_version = parent_version
+                        .map(|v| remap_version(store, &mut version_map, v))
+                        .transpose()?;
+                    let result: Result<(), TransactionError<AbortReason>> =
+                        S::transaction([&map.version_graph_tree], |[graph_txn]| {
+                            link_version(graph_txn, version, VersionNode { parent_version })?;
+                            Ok(())
+                        });
+                    result?;
+                }
+                RecordTag::VersionChanges if has_history => {
+                    let version = payload.version().ok_or_else(malformed)?;
+                    let num_changes = payload.u32().ok_or_else(malformed)?;
+                    let mut changes = BTreeMap::new();
+                    for _ in 0..num_changes {
+                        let key = payload.key().ok_or_else(malformed)?;
+                        let change = payload.change().ok_or_else(malformed)?;
+                        changes.insert(key, change);
+                    }
+                    let version = remap_version(store, &mut version_map, version)?;
+                    let changes = VersionChanges::new(changes);
+                    let result: Result<(), TransactionError<AbortReason>> =
+                        S::transaction([&map.version_change_tree], |[change_txn]| {
+                            archive_version(change_txn, version, &changes)?;
+                            Ok(())
+                        });
+                    result?;
+                }
+                RecordTag::Backup | RecordTag::VersionNode | RecordTag::VersionChanges => {
+                    // History records in a file without the history flag.
+                    return Err(malformed());
+                }
+            }
+
+            if !payload.bytes.is_empty() {
+                return Err(malformed());
+            }
+        }
+
+        map.import_working_batch(&mut working_batch, has_history)?;
+
+        if has_history {
+            map.import_backup_batch(&mut backup_batch)?;
+
+            let meta =
+                imported_meta.ok_or(PortableMapError::MalformedRecord { record_index: 0 })?;
+            let mut remap = |v| remap_version(store, &mut version_map, v);
+            let new_meta = MapDbMetadata {
+                grandparent_version: meta.grandparent_version.map(&mut remap).transpose()?,
+                parent_version: meta.parent_version.map(&mut remap).transpose()?,
+                working_version: remap(meta.working_version)?,
+            };
+            let result: Result<(), TransactionError<AbortReason>> =
+                S::transaction([&map.meta_tree], |[meta_txn]| {
+                    write_meta(meta_txn, &new_meta)?;
+                    Ok(())
+                });
+            result?;
+            map.cached_meta = new_meta;
+        }
+
+        let registry = read_stored_material_registry(
+            &map.meta_tree,
+            &map.version_graph_tree,
+            &map.cached_meta,
+        )?;
+        map.set_cached_material_registry(registry);
+
+        Ok(map)
+    }
+
+    /// Only valid before any chunks are written.
+    fn set_empty_map_chunk_layout(
+        &mut self,
+        layout: ChunkLayout,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        S::transaction([&self.meta_tree], |[meta_txn]| {
+            write_chunk_layout(meta_txn, layout)?;
+            Ok(())
+        })?;
+        self.chunk_layout = layout;
+        Ok(())
+    }
+
+    /// With history, chunks are restored directly into the working tree, since the backup tree is restored separately.
+    /// Otherwise they are new changes to the working version.
+    fn import_working_batch(
+        &mut self,
+        batch: &mut ChangeEncoder,
+        has_history: bool,
+    ) -> Result<(), PortableMapError> {
+        let changes = std::mem::take(batch).encode();
+        if has_history {
+            let result: Result<(), TransactionError<AbortReason>> =
+                S::transaction([&self.working_tree], |[working_txn]| {
+                    for (key_bytes, change) in changes.changes.iter() {
+                        working_txn.insert(key_bytes, change.as_bytes().into())?;
+                    }
+                    Ok(())
+                });
+            result?;
+        } else {
+            self.write_working_version(changes)?;
+        }
+        Ok(())
+    }
+
+    fn import_backup_batch(&mut self, batch: &mut ChangeEncoder) -> Result<(), PortableMapError> {
+        let changes = std::mem::take(batch).encode();
+        let result: Result<(), TransactionError<AbortReason>> =
+            S::transaction([&self.backup_tree], |[backup_txn]| {
+                for (key_bytes, change) in changes.changes.iter() {
+                    backup_txn.insert(key_bytes, change.as_bytes().into())?;
+                }
+                Ok(())
+            });
+        result?;
+        Ok(())
+    }
+}
+
+fn parse_key<T>(key: &[u8], parse: impl Fn(&[u8]) -> Option<T>) -> Result<T, PortableMapError> {
+    parse(key).ok_or_else(|| PortableMapError::InvalidStoredKey { key: key.to_vec() })
+}
+
+/// Version numbers are only unique within a single store, so every imported version gets a new number.
+fn remap_version<S: MapStore>(
+    store: &S,
+    version_map: &mut BTreeMap<Version, Version>,
+    version: Version,
+) -> StoreResult<Version> {
+    if let Some(&new_version) = version_map.get(&version) {
+        return Ok(new_version);
+    }
+    let new_version = Version::new(store.generate_id()?);
+    version_map.insert(version, new_version);
+    Ok(new_version)
+}
+
+struct RecordWriter<W> {
+    writer: W,
+    num_records: u64,
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn new(mut writer: W, flags: u32) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&PORTABLE_FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&flags.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            num_records: 0,
+        })
+    }
+
+    /// Writes and clears `payload`.
+    fn write_record(
+        &mut self,
+        tag: RecordTag,
+        payload: &mut PayloadWriter,
+    ) -> Result<(), PortableMapError> {
+        if payload.bytes.len() > MAX_PAYLOAD_LEN as usize {
+            return Err(PortableMapError::RecordTooLarge {
+                record_index: self.num_records,
+                payload_len: payload.bytes.len() as u64,
+            });
+        }
+        let mut header = [0; 5];
+        header[0] = tag as u8;
+        header[1..].copy_from_slice(&(payload.bytes.len() as u32).to_le_bytes());
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header);
+        hasher.update(&payload.bytes);
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&payload.bytes)?;
+        self.writer.write_all(&hasher.finalize().to_le_bytes())?;
+
+        payload.bytes.clear();
+        self.num_records += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), PortableMapError> {
+        let mut payload = PayloadWriter::default();
+        payload.u64(self.num_records);
+        self.write_record(RecordTag::End, &mut payload)?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+struct RecordReader<R> {
+    reader: R,
+    num_records: u64,
+    payload: Vec<u8>,
+}
+
+struct Record<'a> {
+    tag: RecordTag,
+    index: u64,
+    payload: &'a [u8],
+}
+
+impl<R: Read> RecordReader<R> {
+    /// Returns the reader, the format version, and the header flags.
+    fn new(mut reader: R) -> Result<(Self, u32, u32), PortableMapError> {
+        let mut header = [0; 16];
+        reader.read_exact(&mut header)?;
+        if header[..8] != MAGIC {
+            return Err(PortableMapError::NotAPortableMap);
+        }
+        let format_version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if format_version > PORTABLE_FORMAT_VERSION {
+            return Err(PortableMapError::UnsupportedFormatVersion(format_version));
+        }
+        let flags = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        Ok((
+            Self {
+                reader,
+                num_records: 0,
+                payload: Vec::new(),
+            },
+            format_version,
+            flags,
+        ))
+    }
+
+    /// Returns `None` after reading the end record.
+    fn next_record(&mut self) -> Result<Option<Record<'_>>, PortableMapError> {
+        let index = self.num_records;
+
+        let mut header = [0; 5];
+        self.reader.read_exact(&mut header)?;
+        let payload_len = u32::from_le_bytes(header[1..].try_into().unwrap());
+        if payload_len > MAX_PAYLOAD_LEN {
+            return Err(PortableMapError::RecordTooLarge {
+                record_index: index,
+                payload_len: payload_len as u64,
+            });
+        }
+        // The buffer only grows as bytes actually arrive, so a truncated stream can't claim a huge payload either.
+        self.payload.clear();
+        let num_read = (&mut self.reader)
+            .take(payload_len as u64)
+            .read_to_end(&mut self.payload)?;
+        if num_read != payload_len as usize {
+            return Err(PortableMapError::Truncated);
+        }
+        let mut crc = [0; 4];
+        self.reader.read_exact(&mut crc)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header);
+        hasher.update(&self.payload);
+        if hasher.finalize() != u32::from_le_bytes(crc) {
+            return Err(PortableMapError::ChecksumMismatch {
+                record_index: index,
+            });
+        }
+
+        let malformed = PortableMapError::MalformedRecord {
+            record_index: index,
+        };
+        let tag = RecordTag::from_u8(header[0]).ok_or(malformed)?;
+        if tag == RecordTag::End {
+            let mut payload = PayloadReader {
+                bytes: &self.payload,
+            };
+            return if payload.u64() == Some(index) && payload.bytes.is_empty() {
+                Ok(None)
+            } else {
+                Err(PortableMapError::MalformedRecord {
+                    record_index: index,
+                })
+            };
+        }
+
+        self.num_records += 1;
+        Ok(Some(Record {
+            tag,
+            index,
+            payload: &self.payload,
+        }))
+    }
+}
+
+#[derive(Default)]
+struct PayloadWriter {
+    bytes: Vec<u8>,
+}
+
+impl PayloadWriter {
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn key(&mut self, key: ChunkDbKey) {
+        self.bytes.extend_from_slice(&key.into_sled_key());
+    }
+
+    fn version(&mut self, version: Version) {
+        self.u64(version.number);
+    }
+
+    fn option_version(&mut self, version: Option<Version>) {
+        if let Some(version) = version {
+            self.u8(1);
+            self.version(version);
+        } else {
+            self.u8(0);
+        }
+    }
+
+    fn change(&mut self, change: &Change<CompressedChunk>) {
+        match change {
+            Change::Insert(chunk) => {
+                self.u8(1);
+                self.bytes(&chunk.bytes);
+            }
+            Change::Remove => self.u8(0),
+        }
+    }
+
+    fn material_registry(&mut self, registry: &MaterialRegistry) {
+        self.u32(registry.len() as u32);
+        for (_, entry) in registry.iter() {
+            self.bytes(entry.name.as_bytes());
+            self.u8(entry.attributes.is_collidable as u8);
+            self.u8(entry.attributes.material_id.0);
+            self.u8(entry.retired as u8);
+        }
+    }
+
+    fn meta(&mut self, meta: &MapDbMetadata) {
+        self.option_version(meta.grandparent_version);
+        self.option_version(meta.parent_version);
+        self.version(meta.working_version);
+    }
+}
+
+struct PayloadReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()?;
+        self.take(len as usize)
+    }
+
+    fn key(&mut self) -> Option<ChunkDbKey> {
+        self.take(13).and_then(ChunkDbKey::try_from_sled_key)
+    }
+
+    fn version(&mut self) -> Option<Version> {
+        self.u64().map(Version::new)
+    }
+
+    fn option_version(&mut self) -> Option<Option<Version>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.version().map(Some),
+            _ => None,
+        }
+    }
+
+    fn change(&mut self) -> Option<Change<CompressedChunk>> {
+        match self.u8()? {
+            0 => Some(Change::Remove),
+            1 => self.bytes().map(|bytes| {
+                Change::Insert(CompressedChunk {
+                    bytes: bytes.into(),
+                })
+            }),
+            _ => None,
+        }
+    }
+
+    /// Entries get the same IDs they were exported with, since they are added in order.
+    fn material_registry(&mut self) -> Option<MaterialRegistry> {
+        let num_entries = self.u32()?;
+        let mut registry = MaterialRegistry::default();
+        for _ in 0..num_entries {
+            let name = std::str::from_utf8(self.bytes()?).ok()?;
+            let attributes = VoxelAttributes {
+                is_collidable: self.bool()?,
+                material_id: MaterialId(self.u8()?),
+            };
+            let retired = self.bool()?;
+            let id = registry.add(name, attributes).ok()?;
+            if retired {
+                registry.retire(id).ok()?;
+            }
+        }
+        Some(registry)
+    }
+
+    fn chunk_layout(&mut self) -> Option<ChunkLayout> {
+        ChunkLayout::from_u8(self.u8()?)
+    }
+
+    fn meta(&mut self) -> Option<MapDbMetadata> {
+        Some(MapDbMetadata {
+            grandparent_version: self.option_version()?,
+            parent_version: self.option_version()?,
+            working_version: self.version()?,
+        })
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{Chunk, Chunk16};
+    use crate::core::glam::IVec3;
+    use crate::database::MemStore;
+
+    fn registry_with(names: &[&str]) -> MaterialRegistry {
+        let mut registry = MaterialRegistry::default();
+        for (i, name) in names.iter().enumerate() {
+            let attributes = VoxelAttributes {
+                is_collidable: i % 2 == 0,
+                material_id: MaterialId(i as u8 + 1),
+            };
+            registry.add(*name, attributes).unwrap();
+        }
+        registry
+    }
+
+    fn read_insert<S: MapStore>(map: &MapDb<S>, key: ChunkDbKey) -> Option<CompressedChunk> {
+        map.read_working_version(key)
+            .unwrap()
+            .map(|c| c.deserialize().unwrap_insert())
+    }
+
+    #[test]
+    fn export_and_import_with_history() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let chunk = Chunk::default().compress();
+        let key1 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key2 = ChunkDbKey::new(0, IVec3::ONE.into());
+        let key3 = ChunkDbKey::new(1, IVec3::ZERO.into());
+
+        let first_registry = registry_with(&["stone"]);
+        let mut last_registry = registry_with(&["stone", "dirt"]);
+        last_registry.retire(0).unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key1, Change::Insert(chunk.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.write_material_registry(first_registry.clone()).unwrap();
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key1, Change::Remove);
+        encoder.add_compressed_change(key2, Change::Insert(chunk.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        // Leave some uncommitted changes in the working version.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key3, Change::Insert(chunk.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        map.write_material_registry(last_registry.clone()).unwrap();
+
+        let mut file = Vec::new();
+        map.export(&mut file, true).unwrap();
+
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let mut imported = MapDb::import(&db, "imported", file.as_slice()).unwrap();
+
+        assert_eq!(read_insert(&imported, key1), None);
+        assert_eq!(read_insert(&imported, key2), Some(chunk.clone()));
+        assert_eq!(read_insert(&imported, key3), Some(chunk.clone()));
+        assert_eq!(imported.material_registry(), &last_registry);
+
+        // The whole history came along, so we can go back to the first version.
+        let first_version = imported.cached_meta().grandparent_version.unwrap();
+        imported.branch_from_version(first_version).unwrap();
+
+        assert_eq!(read_insert(&imported, key1), Some(chunk.clone()));
+        assert_eq!(read_insert(&imported, key2), None);
+        assert_eq!(read_insert(&imported, key3), None);
+        assert_eq!(imported.material_registry(), &first_registry);
+    }
+
+    #[test]
+    fn export_and_import_without_history() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let chunk = Chunk::default().compress();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.clone()));
+        map.write_working_version(encoder.encode()).unwrap();
+        let registry = registry_with(&["stone", "dirt"]);
+        map.write_material_registry(registry.clone()).unwrap();
+        map.commit_working_version().unwrap();
+
+        let mut file = Vec::new();
+        map.export(&mut file, false).unwrap();
+
+        // The registry is inherited from the committed version, but it's still exported with the working version.
+        let imported = MapDb::import(&store, "imported", file.as_slice()).unwrap();
+        assert_eq!(read_insert(&imported, key), Some(chunk));
+        assert_eq!(imported.cached_meta().parent_version, None);
+        assert_eq!(imported.material_registry(), &registry);
+        drop(imported);
+        let reopened = MapDb::open(&store, "imported").unwrap();
+        assert_eq!(reopened.material_registry(), &registry);
+
+        // Can't import on top of existing data.
+        assert!(matches!(
+            MapDb::import(&store, "imported", file.as_slice()),
+            Err(PortableMapError::MapNotEmpty)
+        ));
+    }
+
+    #[test]
+    fn export_and_import_keep_chunk_layout() {
+        let store = MemStore::default();
+        let mut map = MapDb::open_with_layout(&store, "mymap", ChunkLayout::Palette16).unwrap();
+
+        let mut chunk = Chunk16::default();
+        chunk.palette_ids[0] = 1000;
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let mut file = Vec::new();
+        map.export(&mut file, false).unwrap();
+
+        let imported = MapDb::import(&store, "imported", file.as_slice()).unwrap();
+        assert_eq!(imported.chunk_layout(), ChunkLayout::Palette16);
+        assert_eq!(imported.read_working_chunk16(key).unwrap(), Some(chunk));
+
+        // The layout is persisted, not just cached.
+        drop(imported);
+        let reopened = MapDb::open(&store, "imported").unwrap();
+        assert_eq!(reopened.chunk_layout(), ChunkLayout::Palette16);
+    }
+
+    #[test]
+    fn export_and_import_keep_tiles() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let mut tile_chunk = Chunk::default();
+        tile_chunk.palette_ids[0] = 4;
+        map.insert_tile(&Chunk::default()).unwrap();
+        let tile = map.insert_tile(&tile_chunk).unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        map.instance_tile(tile, [key]).unwrap();
+
+        let mut file = Vec::new();
+        map.export(&mut file, true).unwrap();
+
+        let imported = MapDb::import(&store, "imported", file.as_slice()).unwrap();
+        assert_eq!(imported.read_working_chunk(key).unwrap(), Some(tile_chunk));
+        let stored = imported.read_working_version(key).unwrap().unwrap();
+        assert_eq!(stored.deserialize().unwrap_insert().tile_id(), Some(tile));
+
+        // New tiles don't reuse imported IDs.
+        assert!(imported.insert_tile(&Chunk::default()).unwrap() > tile);
+    }
+
+    #[test]
+    fn import_detects_corruption_and_truncation() {
+        let store = MemStore::default();
+        let map = MapDb::open(&store, "mymap").unwrap();
+
+        let mut file = Vec::new();
+        map.export(&mut file, true).unwrap();
+
+        // Flip a bit in the payload of the first record.
+        let mut corrupted = file.clone();
+        corrupted[16 + 5] ^= 1;
+        assert!(matches!(
+            MapDb::import(&store, "corrupted", corrupted.as_slice()),
+            Err(PortableMapError::ChecksumMismatch { record_index: 0 })
+        ));
+
+        // Drop the end record.
+        let truncated = &file[..file.len() - 1];
+        assert!(matches!(
+            MapDb::import(&store, "truncated", truncated),
+            Err(PortableMapError::Truncated)
+        ));
+
+        // A corrupt length is rejected before anything is allocated for the payload.
+        let mut huge_record = file[..16].to_vec();
+        huge_record.push(RecordTag::Meta as u8);
+        huge_record.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            MapDb::import(&store, "huge", huge_record.as_slice()),
+            Err(PortableMapError::RecordTooLarge {
+                record_index: 0,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            MapDb::import(&store, "garbage", &b"not a map"[..]),
+            Err(PortableMapError::Truncated)
+        ));
+        assert!(matches!(
+            MapDb::import(&store, "garbage", &b"definitely not a map"[..]),
+            Err(PortableMapError::NotAPortableMap)
+        ));
+    }
+
+    #[test]
+    fn export_reports_invalid_keys() {
+        let store = MemStore::default();
+        let map = MapDb::open(&store, "mymap").unwrap();
+
+        let chunk_bytes = {
+            let mut encoder = ChangeEncoder::default();
+            encoder.add_compressed_change(
+                ChunkDbKey::new(0, IVec3::ZERO.into()),
+                Change::Insert(Chunk::default().compress()),
+            );
+            encoder.encode().changes.pop().unwrap().1.take_bytes()
+        };
+        let bad_key = [1, 2, 3];
+        let result: Result<(), TransactionError<AbortReason>> =
+            MemStore::transaction([&map.working_tree], |[working_txn]| {
+                working_txn.insert(&bad_key, chunk_bytes.clone())?;
+                Ok(())
+            });
+        result.unwrap();
+
+        let mut file = Vec::new();
+        assert!(matches!(
+            map.export(&mut file, false),
+            Err(PortableMapError::InvalidStoredKey { key }) if key == bad_key
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+use super::version_graph_tree::VersionNode;
+use super::{
+    ArchivedChangeIVec, ArchivedIVec, ChunkDbKey, MapDb, MapStore, StoreError, StoreTree, Version,
+    VersionChanges,
+};
+use crate::chunk::{ChunkLayout, CompressedChunk};
+use crate::clipmap::Level;
+use crate::core::glam::IVec3;
+use crate::core::ilattice::prelude::Extent;
+use crate::units::ChunkUnits;
+
+use std::collections::BTreeMap;
+
+/// Storage statistics of a map, from [`MapDb::stats`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapStats {
+    /// Statistics of the working version for each level of detail that has any chunks.
+    pub levels: BTreeMap<Level, LevelStats>,
+    /// Every node of the version graph, in version order.
+    pub versions: Vec<VersionStats>,
+    /// The entries of the backup tree, i.e. the chunks changed by the working version.
+    pub backup_entries: u64,
+    pub backup_bytes: u64,
+    pub tile_count: u64,
+    pub tile_bytes: u64,
+}
+
+/// Statistics of the working version at one level of detail.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    pub chunk_count: u64,
+    /// The chunks that are stored as a reference to a shared tile.
+    pub tile_references: u64,
+    /// The total size of the stored chunks. A tile reference only counts the size of the reference.
+    pub stored_bytes: u64,
+    /// The smallest extent that contains every chunk.
+    pub bounds: Option<ChunkUnits<Extent<IVec3>>>,
+}
+
+impl LevelStats {
+    /// The uncompressed size of all chunks divided by their stored size.
+    pub fn compression_ratio(&self, layout: ChunkLayout) -> f64 {
+        if self.stored_bytes == 0 {
+            return 0.0;
+        }
+        (self.chunk_count * layout.chunk_size_bytes() as u64) as f64 / self.stored_bytes as f64
+    }
+
+    fn add_chunk(&mut self, key: ChunkDbKey, bytes: &[u8]) {
+        self.chunk_count += 1;
+        self.stored_bytes += bytes.len() as u64;
+        if CompressedChunk::referenced_tile(bytes).is_some() {
+            self.tile_references += 1;
+        }
+        let coords = IVec3::from(key.morton);
+        self.bounds = Some(ChunkUnits(match self.bounds {
+            Some(ChunkUnits(bounds)) => {
+                Extent::from_min_and_max(bounds.minimum.min(coords), bounds.max().max(coords))
+            }
+            None => Extent::from_min_and_max(coords, coords),
+        }));
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VersionStats {
+    pub version: Version,
+    /// `None` if the node is a root or is not a valid archive.
+    pub parent_version: Option<Version>,
+    /// The number of archived chunk changes, or `None` if the version has no valid [`VersionChanges`]. The parent of the
+    /// working version never has any, because its chunks are in the working tree.
+    pub archived_changes: Option<usize>,
+}
+
+impl<S: MapStore> MapDb<S> {
+    /// Scans every tree of the map to collect [`MapStats`]. Chunks are not decompressed, and invalid entries are skipped.
+    pub fn stats(&self) -> Result<MapStats, StoreError> {
+        let mut levels = BTreeMap::<Level, LevelStats>::new();
+        for iter_result in self.working_tree.iter() {
+            let (key_bytes, value_bytes) = iter_result?;
+            let key = ChunkDbKey::from_sled_key(&key_bytes);
+            if let Ok(change) = ArchivedChangeIVec::<CompressedChunk>::try_new(value_bytes) {
+                if let Some(chunk) = change.as_ref().get_insert_data() {
+                    levels
+                        .entry(key.level)
+                        .or_default()
+                        .add_chunk(key, &chunk.bytes);
+                }
+            }
+        }
+
+        let mut versions = Vec::new();
+        for iter_result in self.version_graph_tree.iter() {
+            let (key_bytes, node_bytes) = iter_result?;
+            let version = Version::from_sled_key(&key_bytes);
+            let parent_version = ArchivedIVec::<VersionNode>::try_new(node_bytes)
+                .ok()
+                .and_then(|node| node.deserialize().parent_version);
+            let archived_changes = self
+                .version_change_tree
+                .get(&version.into_sled_key())?
+                .and_then(|bytes| ArchivedIVec::<VersionChanges>::try_new(bytes).ok())
+                .map(|changes| changes.as_ref().changes.len());
+            versions.push(VersionStats {
+                version,
+                parent_version,
+                archived_changes,
+            });
+        }
+
+        let (backup_entries, backup_bytes) = count_entries(&self.backup_tree)?;
+        let (tile_count, tile_bytes) = count_entries(&self.tile_tree)?;
+        Ok(MapStats {
+            levels,
+            versions,
+            backup_entries,
+            backup_bytes,
+            tile_count,
+            tile_bytes,
+        })
+    }
+}
+
+/// The number of entries in `tree` and the total size of their values.
+fn count_entries(tree: &impl StoreTree) -> Result<(u64, u64), StoreError> {
+    let mut count = 0;
+    let mut bytes = 0;
+    for iter_result in tree.iter() {
+        let (_, value) = iter_result?;
+        count += 1;
+        bytes += value.len() as u64;
+    }
+    Ok((count, bytes))
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::database::{Change, ChangeEncoder, MemStore};
+    use crate::sdf::Sd8;
+
+    #[test]
+    fn stats_of_levels_and_versions() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let mut chunk = Chunk::default();
+        chunk.set_voxel(IVec3::ZERO, 1, Sd8::MIN);
+        let mut encoder = ChangeEncoder::default();
+        for coords in [IVec3::new(-1, 2, 0), IVec3::new(3, -4, 5)] {
+            encoder.add_compressed_change(
+                ChunkDbKey::new(0, coords.into()),
+                Change::Insert(chunk.compress()),
+            );
+        }
+        encoder.add_compressed_change(
+            ChunkDbKey::new(2, IVec3::ZERO.into()),
+            Change::Insert(chunk.compress()),
+        );
+        map.write_working_version(encoder.encode()).unwrap();
+        let first = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(ChunkDbKey::new(2, IVec3::ZERO.into()), Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+        let second = map.cached_meta().working_version;
+        map.commit_working_version().unwrap();
+
+        let stats = map.stats().unwrap();
+        assert_eq!(stats.levels.keys().copied().collect::<Vec<_>>(), [0]);
+        let level0 = &stats.levels[&0];
+        assert_eq!(level0.chunk_count, 2);
+        assert_eq!(level0.tile_references, 0);
+        assert_eq!(
+            level0.bounds,
+            Some(ChunkUnits(Extent::from_min_and_max(
+                IVec3::new(-1, -4, 0),
+                IVec3::new(3, 2, 5)
+            )))
+        );
+        assert!(level0.compression_ratio(ChunkLayout::Palette8) > 1.0);
+
+        assert_eq!(
+            stats.versions,
+            [
+                VersionStats {
+                    version: first,
+                    parent_version: None,
+                    archived_changes: Some(3),
+                },
+                VersionStats {
+                    version: second,
+                    parent_version: Some(first),
+                    archived_changes: None,
+                },
+            ]
+        );
+        assert_eq!(stats.backup_entries, 0);
+    }
+}
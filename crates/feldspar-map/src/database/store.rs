@@ -0,0 +1,196 @@
+mod memory;
+mod sled_store;
+
+pub use memory::{MemStore, MemTree, MemTreeTxn};
+
+use std::borrow::Borrow;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// An iterator over the key-value pairs of a [`StoreTree`].
+pub type StoreIter = Box<dyn Iterator<Item = StoreResult<(StoreBytes, StoreBytes)>>>;
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// The storage backend for a [`MapDb`](crate::database::MapDb).
+///
+/// A store is a namespace of ordered key-value trees that support atomic transactions over multiple trees at once. The API
+/// deliberately mirrors the subset of [`sled`] that the map database needs, so `sled` is the reference implementation, but
+/// there is also an in-memory [`MemStore`] that is useful for tests. Only the types of this module cross the trait boundary,
+/// so other backends don't need to depend on `sled`.
+pub trait MapStore {
+    type Tree: StoreTree;
+    type Txn: TreeTxn;
+
+    /// Opens the tree called `name`, creating it if it doesn't exist.
+    fn open_tree(&self, name: &str) -> StoreResult<Self::Tree>;
+
+    /// The names of all trees in the store.
+    fn tree_names(&self) -> StoreResult<Vec<String>>;
+
+    /// Deletes the tree called `name` and all of its entries. Returns `false` if there was no such tree.
+    fn drop_tree(&self, name: &str) -> StoreResult<bool>;
+
+    /// Generates a monotonically increasing ID that is unique across the entire store.
+    fn generate_id(&self) -> StoreResult<u64>;
+
+    /// Runs `f` as a single atomic transaction over all `trees`. `f` may run multiple times if there are conflicts.
+    ///
+    /// The writes of a transaction become visible to non-transactional reads all at once, but a reader that reads several
+    /// trees one after another may still see a transaction commit between its reads.
+    fn transaction<R, E, const N: usize>(
+        trees: [&Self::Tree; N],
+        f: impl Fn(&[Self::Txn; N]) -> ConflictableTransactionResult<R, E>,
+    ) -> TransactionResult<R, E>;
+}
+
+/// Non-transactional reads of a single tree in a [`MapStore`].
+pub trait StoreTree {
+    fn get(&self, key: &[u8]) -> StoreResult<Option<StoreBytes>>;
+
+    /// Iterates over all entries with keys in the inclusive range `[start, end]`.
+    fn range(&self, start: &[u8], end: &[u8]) -> StoreIter;
+
+    fn iter(&self) -> StoreIter;
+}
+
+/// The view of a single tree inside of a [`MapStore::transaction`].
+pub trait TreeTxn {
+    fn get(&self, key: &[u8]) -> Result<Option<StoreBytes>, UnabortableTransactionError>;
+
+    fn insert(
+        &self,
+        key: &[u8],
+        value: StoreBytes,
+    ) -> Result<Option<StoreBytes>, UnabortableTransactionError>;
+
+    fn remove(&self, key: &[u8]) -> Result<Option<StoreBytes>, UnabortableTransactionError>;
+
+    /// Generates a monotonically increasing ID that is unique across the entire store.
+    fn generate_id(&self) -> Result<u64, UnabortableTransactionError>;
+}
+
+/// An immutable, cheaply cloned byte string stored in a [`MapStore`].
+///
+/// It owns its bytes, independent of any backend, so backends convert their own byte strings to and from it.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StoreBytes(Arc<[u8]>);
+
+impl Deref for StoreBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for StoreBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Borrow<[u8]> for StoreBytes {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for StoreBytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self(Arc::from(bytes))
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for StoreBytes {
+    fn from(bytes: &[u8; N]) -> Self {
+        Self(Arc::from(&bytes[..]))
+    }
+}
+
+impl From<Vec<u8>> for StoreBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(Arc::from(bytes))
+    }
+}
+
+impl From<Box<[u8]>> for StoreBytes {
+    fn from(bytes: Box<[u8]>) -> Self {
+        Self(Arc::from(bytes))
+    }
+}
+
+/// An error from the storage backend of a [`MapStore`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StoreError {
+    /// The backend failed to read or write its files.
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+    /// The backend found its own files to be corrupt.
+    Corruption(String),
+    /// Any other failure, as described by the backend.
+    Other(String),
+}
+
+/// An error from a [`MapStore::transaction`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TransactionError<E> {
+    /// The transaction function returned this error with [`abort`].
+    Abort(E),
+    Storage(StoreError),
+}
+
+pub type TransactionResult<T, E> = Result<T, TransactionError<E>>;
+
+/// An error returned from inside of a [`MapStore::transaction`] function.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConflictableTransactionError<E> {
+    Abort(E),
+    Storage(StoreError),
+    /// The transaction conflicted with another one and will be retried. This should be propagated, not handled.
+    Conflict,
+}
+
+pub type ConflictableTransactionResult<T, E> = Result<T, ConflictableTransactionError<E>>;
+
+/// An error from a single operation of a [`TreeTxn`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UnabortableTransactionError {
+    Storage(StoreError),
+    /// See [`ConflictableTransactionError::Conflict`].
+    Conflict,
+}
+
+/// Aborts a transaction with `reason`, which becomes [`TransactionError::Abort`].
+pub fn abort<T, E>(reason: E) -> ConflictableTransactionResult<T, E> {
+    Err(ConflictableTransactionError::Abort(reason))
+}
+
+impl<E> From<StoreError> for TransactionError<E> {
+    fn from(e: StoreError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl<E> From<StoreError> for ConflictableTransactionError<E> {
+    fn from(e: StoreError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl<E> From<UnabortableTransactionError> for ConflictableTransactionError<E> {
+    fn from(e: UnabortableTransactionError) -> Self {
+        match e {
+            UnabortableTransactionError::Storage(e) => Self::Storage(e),
+            UnabortableTransactionError::Conflict => Self::Conflict,
+        }
+    }
+}
+
+impl From<StoreError> for UnabortableTransactionError {
+    fn from(e: StoreError) -> Self {
+        Self::Storage(e)
+    }
+}
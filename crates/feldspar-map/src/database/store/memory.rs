@@ -0,0 +1,178 @@
+use super::{
+    ConflictableTransactionError, ConflictableTransactionResult, MapStore, StoreBytes, StoreIter,
+    StoreResult, StoreTree, TransactionError, TransactionResult, TreeTxn,
+    UnabortableTransactionError,
+};
+
+use parking_lot::{Mutex, RwLock};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A [`MapStore`] that keeps every tree in a [`BTreeMap`]. Nothing is persisted.
+///
+/// Transactions are serialized by a single store-wide lock, so they never conflict. A transaction holds the write locks of all of
+/// its trees while it applies its writes, so non-transactional readers see all of them or none of them.
+#[derive(Clone, Default)]
+pub struct MemStore {
+    shared: Arc<MemStoreShared>,
+    trees: Arc<Mutex<BTreeMap<String, MemTree>>>,
+}
+
+#[derive(Default)]
+struct MemStoreShared {
+    txn_lock: Mutex<()>,
+    next_id: AtomicU64,
+}
+
+/// One tree in a [`MemStore`].
+#[derive(Clone)]
+pub struct MemTree {
+    shared: Arc<MemStoreShared>,
+    entries: Arc<RwLock<BTreeMap<StoreBytes, StoreBytes>>>,
+}
+
+/// The view of a [`MemTree`] inside of a transaction. Writes are buffered until the transaction succeeds.
+pub struct MemTreeTxn {
+    tree: MemTree,
+    /// `None` marks a removed key.
+    writes: RefCell<BTreeMap<StoreBytes, Option<StoreBytes>>>,
+}
+
+impl MapStore for MemStore {
+    type Tree = MemTree;
+    type Txn = MemTreeTxn;
+
+    fn open_tree(&self, name: &str) -> StoreResult<Self::Tree> {
+        let mut trees = self.trees.lock();
+        let tree = trees.entry(name.to_owned()).or_insert_with(|| MemTree {
+            shared: self.shared.clone(),
+            entries: Default::default(),
+        });
+        Ok(tree.clone())
+    }
+
+    fn tree_names(&self) -> StoreResult<Vec<String>> {
+        Ok(self.trees.lock().keys().cloned().collect())
+    }
+
+    /// Handles to the dropped tree keep working, but they are detached from the store.
+    fn drop_tree(&self, name: &str) -> StoreResult<bool> {
+        Ok(self.trees.lock().remove(name).is_some())
+    }
+
+    fn generate_id(&self) -> StoreResult<u64> {
+        Ok(self.shared.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    fn transaction<R, E, const N: usize>(
+        trees: [&Self::Tree; N],
+        f: impl Fn(&[Self::Txn; N]) -> ConflictableTransactionResult<R, E>,
+    ) -> TransactionResult<R, E> {
+        // All trees in a transaction must come from the same store, so any of them can provide the lock.
+        let shared = trees.first().map(|t| t.shared.clone());
+        let _txn_guard = shared.as_ref().map(|s| s.txn_lock.lock());
+
+        let txns = trees.map(|tree| MemTreeTxn {
+            tree: tree.clone(),
+            writes: RefCell::default(),
+        });
+        match f(&txns) {
+            Ok(result) => {
+                // Lock every tree before applying any writes, so readers never see part of the transaction. The same tree can
+                // be passed more than once, but it can only be locked once.
+                let mut locks = Vec::with_capacity(N);
+                let mut lock_indices = [0; N];
+                for (i, txn) in txns.iter().enumerate() {
+                    let same_tree = txns[..i]
+                        .iter()
+                        .position(|t| Arc::ptr_eq(&t.tree.entries, &txn.tree.entries));
+                    lock_indices[i] = match same_tree {
+                        Some(j) => lock_indices[j],
+                        None => {
+                            locks.push(txn.tree.entries.write());
+                            locks.len() - 1
+                        }
+                    };
+                }
+                for (txn, lock_index) in txns.iter().zip(lock_indices) {
+                    let entries = &mut locks[lock_index];
+                    for (key, value) in std::mem::take(&mut *txn.writes.borrow_mut()) {
+                        if let Some(value) = value {
+                            entries.insert(key, value);
+                        } else {
+                            entries.remove(&key);
+                        }
+                    }
+                }
+                Ok(result)
+            }
+            Err(ConflictableTransactionError::Abort(e)) => Err(TransactionError::Abort(e)),
+            Err(ConflictableTransactionError::Storage(e)) => Err(TransactionError::Storage(e)),
+            Err(ConflictableTransactionError::Conflict) => {
+                unreachable!("MemStore transactions are serialized")
+            }
+        }
+    }
+}
+
+impl StoreTree for MemTree {
+    fn get(&self, key: &[u8]) -> StoreResult<Option<StoreBytes>> {
+        Ok(self.entries.read().get(key).cloned())
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> StoreIter {
+        let start = StoreBytes::from(start);
+        let end = StoreBytes::from(end);
+        // Take a snapshot so we don't hold the lock while iterating.
+        let snapshot: Vec<_> = self
+            .entries
+            .read()
+            .range(start..=end)
+            .map(|(k, v)| Ok((k.clone(), v.clone())))
+            .collect();
+        Box::new(snapshot.into_iter())
+    }
+
+    fn iter(&self) -> StoreIter {
+        let snapshot: Vec<_> = self
+            .entries
+            .read()
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), v.clone())))
+            .collect();
+        Box::new(snapshot.into_iter())
+    }
+}
+
+impl TreeTxn for MemTreeTxn {
+    fn get(&self, key: &[u8]) -> Result<Option<StoreBytes>, UnabortableTransactionError> {
+        if let Some(written) = self.writes.borrow().get(key) {
+            return Ok(written.clone());
+        }
+        Ok(self.tree.entries.read().get(key).cloned())
+    }
+
+    fn insert(
+        &self,
+        key: &[u8],
+        value: StoreBytes,
+    ) -> Result<Option<StoreBytes>, UnabortableTransactionError> {
+        let old_value = self.get(key)?;
+        self.writes
+            .borrow_mut()
+            .insert(StoreBytes::from(key), Some(value));
+        Ok(old_value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<StoreBytes>, UnabortableTransactionError> {
+        let old_value = self.get(key)?;
+        self.writes.borrow_mut().insert(StoreBytes::from(key), None);
+        Ok(old_value)
+    }
+
+    fn generate_id(&self) -> Result<u64, UnabortableTransactionError> {
+        Ok(self.tree.shared.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,142 @@
+//! The reference implementation of [`MapStore`] on [`sled`].
+
+use super::{
+    ConflictableTransactionError, ConflictableTransactionResult, MapStore, StoreBytes, StoreError,
+    StoreIter, StoreResult, StoreTree, TransactionError, TransactionResult, TreeTxn,
+    UnabortableTransactionError,
+};
+
+use sled::transaction::{self as sled_txn, Transactional, TransactionalTree};
+
+impl From<sled::Error> for StoreError {
+    fn from(e: sled::Error) -> Self {
+        match e {
+            sled::Error::Io(e) => Self::Io {
+                kind: e.kind(),
+                message: e.to_string(),
+            },
+            e @ sled::Error::Corruption { .. } => Self::Corruption(e.to_string()),
+            e => Self::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<sled_txn::UnabortableTransactionError> for UnabortableTransactionError {
+    fn from(e: sled_txn::UnabortableTransactionError) -> Self {
+        match e {
+            sled_txn::UnabortableTransactionError::Storage(e) => Self::Storage(e.into()),
+            sled_txn::UnabortableTransactionError::Conflict => Self::Conflict,
+        }
+    }
+}
+
+impl From<sled::IVec> for StoreBytes {
+    fn from(bytes: sled::IVec) -> Self {
+        Self::from(bytes.as_ref())
+    }
+}
+
+impl From<StoreBytes> for sled::IVec {
+    fn from(bytes: StoreBytes) -> Self {
+        sled::IVec::from(bytes.as_ref())
+    }
+}
+
+/// How a transaction function failed, smuggled through [`sled_txn::ConflictableTransactionError::Abort`] because sled can only
+/// carry its own storage errors.
+enum Failure<E> {
+    Abort(E),
+    Storage(StoreError),
+}
+
+impl MapStore for sled::Db {
+    type Tree = sled::Tree;
+    type Txn = TransactionalTree;
+
+    fn open_tree(&self, name: &str) -> StoreResult<Self::Tree> {
+        Ok(sled::Db::open_tree(self, name)?)
+    }
+
+    fn tree_names(&self) -> StoreResult<Vec<String>> {
+        Ok(sled::Db::tree_names(self)
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    fn drop_tree(&self, name: &str) -> StoreResult<bool> {
+        Ok(sled::Db::drop_tree(self, name)?)
+    }
+
+    fn generate_id(&self) -> StoreResult<u64> {
+        Ok(sled::Db::generate_id(self)?)
+    }
+
+    fn transaction<R, E, const N: usize>(
+        trees: [&Self::Tree; N],
+        f: impl Fn(&[Self::Txn; N]) -> ConflictableTransactionResult<R, E>,
+    ) -> TransactionResult<R, E> {
+        let trees: &[&sled::Tree] = &trees;
+        let result = trees.transaction(|txns: &Vec<TransactionalTree>| {
+            f(txns.as_slice().try_into().unwrap()).map_err(|e| match e {
+                ConflictableTransactionError::Abort(e) => {
+                    sled_txn::ConflictableTransactionError::Abort(Failure::Abort(e))
+                }
+                ConflictableTransactionError::Storage(e) => {
+                    sled_txn::ConflictableTransactionError::Abort(Failure::Storage(e))
+                }
+                ConflictableTransactionError::Conflict => {
+                    sled_txn::ConflictableTransactionError::Conflict
+                }
+            })
+        });
+        result.map_err(|e| match e {
+            sled_txn::TransactionError::Abort(Failure::Abort(e)) => TransactionError::Abort(e),
+            sled_txn::TransactionError::Abort(Failure::Storage(e)) => TransactionError::Storage(e),
+            sled_txn::TransactionError::Storage(e) => TransactionError::Storage(e.into()),
+        })
+    }
+}
+
+impl StoreTree for sled::Tree {
+    fn get(&self, key: &[u8]) -> StoreResult<Option<StoreBytes>> {
+        Ok(sled::Tree::get(self, key)?.map(StoreBytes::from))
+    }
+
+    fn range(&self, start: &[u8], end: &[u8]) -> StoreIter {
+        Box::new(sled::Tree::range(self, start..=end).map(into_store_entry))
+    }
+
+    fn iter(&self) -> StoreIter {
+        Box::new(sled::Tree::iter(self).map(into_store_entry))
+    }
+}
+
+fn into_store_entry(
+    entry: sled::Result<(sled::IVec, sled::IVec)>,
+) -> StoreResult<(StoreBytes, StoreBytes)> {
+    let (key, value) = entry?;
+    Ok((key.into(), value.into()))
+}
+
+impl TreeTxn for TransactionalTree {
+    fn get(&self, key: &[u8]) -> Result<Option<StoreBytes>, UnabortableTransactionError> {
+        Ok(TransactionalTree::get(self, key)?.map(StoreBytes::from))
+    }
+
+    fn insert(
+        &self,
+        key: &[u8],
+        value: StoreBytes,
+    ) -> Result<Option<StoreBytes>, UnabortableTransactionError> {
+        Ok(TransactionalTree::insert(self, key, sled::IVec::from(value))?.map(StoreBytes::from))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<StoreBytes>, UnabortableTransactionError> {
+        Ok(TransactionalTree::remove(self, key)?.map(StoreBytes::from))
+    }
+
+    fn generate_id(&self) -> Result<u64, UnabortableTransactionError> {
+        Ok(TransactionalTree::generate_id(self)?)
+    }
+}
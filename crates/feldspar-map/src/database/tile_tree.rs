@@ -0,0 +1,411 @@
+use super::meta_tree::{read_next_tile_id, write_next_tile_id};
+use super::{
+    AbortReason, ArchivedChangeIVec, ArchivedIVec, Change, ChangeEncoder, ChunkDbKey, MapDb,
+    MapStore, ReadError, StoreBytes, StoreError, StoreResult, StoreTree, TransactionError, TreeTxn,
+    UnabortableTransactionError, Version, VersionChanges,
+};
+use crate::chunk::{Chunk, ChunkData, ChunkLayout, CompressedChunk, Tile, TileId};
+use crate::clipmap::LoadedChunk;
+use crate::core::SmallKeyHashMap;
+use crate::palette::PaletteId8;
+use crate::sdf::{Sd16, Sd8, SdfValue};
+
+use bytemuck::Pod;
+use parking_lot::Mutex;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Weak};
+
+pub fn open_tile_tree<S: MapStore>(map_name: &str, store: &S) -> StoreResult<S::Tree> {
+    store.open_tree(&format!("{}-tiles", map_name))
+}
+
+/// Inserts a tile with the compressed `bytes` under a new [`TileId`].
+pub fn insert_tile(
+    meta_txn: &impl TreeTxn,
+    tile_txn: &impl TreeTxn,
+    bytes: &[u8],
+) -> Result<TileId, UnabortableTransactionError> {
+    let id = read_next_tile_id(meta_txn)?;
+    write_next_tile_id(meta_txn, TileId(id.0 + 1))?;
+    tile_txn.insert(&id.into_sled_key(), StoreBytes::from(bytes))?;
+    Ok(id)
+}
+
+/// Inserts a tile that already has an `id`, e.g. when importing a map. Tiles inserted later get IDs greater than `id`.
+pub fn restore_tile(
+    meta_txn: &impl TreeTxn,
+    tile_txn: &impl TreeTxn,
+    id: TileId,
+    bytes: &[u8],
+) -> Result<(), UnabortableTransactionError> {
+    if read_next_tile_id(meta_txn)? <= id {
+        write_next_tile_id(meta_txn, TileId(id.0 + 1))?;
+    }
+    tile_txn.insert(&id.into_sled_key(), StoreBytes::from(bytes))?;
+    Ok(())
+}
+
+/// The decompressed tiles that are currently in use. A tile is dropped from memory when its last user drops it.
+#[derive(Default)]
+pub struct SharedTileCache {
+    tiles: Mutex<SmallKeyHashMap<TileId, Weak<Tile>>>,
+}
+
+impl SharedTileCache {
+    fn get(&self, id: TileId) -> Option<Arc<Tile>> {
+        self.tiles.lock().get(&id).and_then(Weak::upgrade)
+    }
+
+    /// Returns the existing shared tile if another thread decompressed `tile` first.
+    fn insert(&self, tile: Tile) -> Arc<Tile> {
+        let mut tiles = self.tiles.lock();
+        if let Some(existing) = tiles.get(&tile.id).and_then(Weak::upgrade) {
+            return existing;
+        }
+        tiles.retain(|_, weak| weak.strong_count() > 0);
+        let id = tile.id;
+        let tile = Arc::new(tile);
+        tiles.insert(id, Arc::downgrade(&tile));
+        tile
+    }
+}
+
+impl<S: MapStore> MapDb<S> {
+    /// Adds `tile` to the tile table after converting it to this map's [`ChunkLayout`](crate::chunk::ChunkLayout), and returns
+    /// its new ID.
+    ///
+    /// Tiles are never modified. They are only removed by [`MapDb::collect_unused_tiles`] once no version of the map
+    /// references them.
+    pub fn insert_tile(&self, tile: &Chunk) -> Result<TileId, TransactionError<AbortReason>> {
+        let compressed = self.compress_in_layout(tile);
+        S::transaction([&self.meta_tree, &self.tile_tree], |[meta_txn, tile_txn]| {
+            Ok(insert_tile(meta_txn, tile_txn, &compressed.bytes)?)
+        })
+    }
+
+    /// Removes every tile that isn't referenced by the working version, the backup of its parent, or any archived version.
+    /// Returns the number of tiles that were removed.
+    ///
+    /// Tile references are only found in stored chunks, so edits that are still waiting in a
+    /// [`WriteBehindMapDb`](super::WriteBehindMapDb) must be flushed first. Removed tiles that are still shared in memory stay
+    /// valid until their last user drops them.
+    pub fn collect_unused_tiles(&mut self) -> Result<usize, TransactionError<AbortReason>> {
+        let mut referenced = BTreeSet::new();
+        for tree in [&self.working_tree, &self.backup_tree] {
+            for iter_result in tree.iter() {
+                let (key_bytes, value) = iter_result?;
+                let change =
+                    ArchivedChangeIVec::<CompressedChunk>::try_new(value).map_err(|_| {
+                        AbortReason::InvalidChunkArchive {
+                            key: ChunkDbKey::from_sled_key(&key_bytes),
+                        }
+                    })?;
+                if let Some(chunk) = change.as_ref().get_insert_data() {
+                    referenced.extend(CompressedChunk::referenced_tile(&chunk.bytes));
+                }
+            }
+        }
+        for iter_result in self.version_change_tree.iter() {
+            let (key_bytes, value) = iter_result?;
+            let archived = ArchivedIVec::<VersionChanges>::try_new(value).map_err(|_| {
+                AbortReason::InvalidVersionArchive {
+                    version: Version::from_sled_key(&key_bytes),
+                }
+            })?;
+            for (_, change) in archived.as_ref().changes.iter() {
+                if let Some(chunk) = change.get_insert_data() {
+                    referenced.extend(CompressedChunk::referenced_tile(&chunk.bytes));
+                }
+            }
+        }
+
+        let mut unused = Vec::new();
+        for iter_result in self.tile_tree.iter() {
+            let (key_bytes, _) = iter_result?;
+            let tile = TileId::from_sled_key(&key_bytes);
+            if !referenced.contains(&tile) {
+                unused.push(tile);
+            }
+        }
+        if unused.is_empty() {
+            return Ok(0);
+        }
+        log::debug!("Removing {} unused tiles", unused.len());
+        S::transaction([&self.tile_tree], |[tile_txn]| {
+            for tile in unused.iter() {
+                tile_txn.remove(&tile.into_sled_key())?;
+            }
+            Ok(())
+        })?;
+        Ok(unused.len())
+    }
+
+    /// Reads the compressed bytes of `tile`.
+    pub fn read_tile(&self, tile: TileId) -> Result<Option<CompressedChunk>, StoreError> {
+        Ok(self
+            .tile_tree
+            .get(&tile.into_sled_key())?
+            .map(|bytes| CompressedChunk {
+                bytes: bytes.as_ref().into(),
+            }))
+    }
+
+    /// Reads and decompresses `tile` as any [`ChunkData`] type, which must match the map's layout.
+    pub fn read_tile_as<Sd: SdfValue, L: Pod>(
+        &self,
+        tile: TileId,
+    ) -> Result<Option<ChunkData<Sd, L>>, ReadError> {
+        match self.tile_tree.get(&tile.into_sled_key())? {
+            Some(bytes) => ChunkData::try_from_compressed_bytes(&bytes)
+                .map(Some)
+                .ok_or(ReadError::InvalidTile { tile }),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads and decompresses `tile` for a map with the [`ChunkLayout::Palette8`](crate::chunk::ChunkLayout::Palette8) layout.
+    ///
+    /// The decompressed tile is shared with every other caller that is still holding it, so a tile that is instanced by many
+    /// chunks only needs to be in memory once.
+    pub fn read_shared_tile(&self, tile: TileId) -> Result<Option<Arc<Tile>>, ReadError> {
+        self.require_layout(ChunkLayout::Palette8)?;
+        if let Some(shared) = self.shared_tiles.get(tile) {
+            return Ok(Some(shared));
+        }
+        Ok(self
+            .read_tile_as(tile)?
+            .map(|chunk| self.shared_tiles.insert(Tile { id: tile, chunk })))
+    }
+
+    /// Writes a reference to `tile` at each of `keys` in the working version, replacing any existing chunks.
+    ///
+    /// Reads of these chunks return the voxels of the tile. Edits like [`MapDb::import_vox_scene`] read the tile and write back a
+    /// copy of the edited chunk, so the tile and its other instances are not affected.
+    pub fn instance_tile(
+        &mut self,
+        tile: TileId,
+        keys: impl IntoIterator<Item = ChunkDbKey>,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        if self.tile_tree.get(&tile.into_sled_key())?.is_none() {
+            return Err(TransactionError::Abort(AbortReason::MissingTile { tile }));
+        }
+        let mut encoder = ChangeEncoder::default();
+        for key in keys {
+            encoder
+                .add_compressed_change(key, Change::Insert(CompressedChunk::tile_reference(tile)));
+        }
+        self.write_working_version(encoder.encode())
+    }
+
+    /// Reads the chunk at `key` of the working version for a [`ChunkClipMap`](crate::clipmap::ChunkClipMap), converted from
+    /// the map's [`ChunkLayout`]. In maps with the default layout, all instances of a tile share one decompressed [`Tile`].
+    pub fn read_working_loaded_chunk(
+        &self,
+        key: ChunkDbKey,
+    ) -> Result<Option<LoadedChunk>, ReadError> {
+        match self.read_working_version(key)? {
+            Some(change) => match change.deserialize() {
+                Change::Insert(chunk) => self.load_chunk(key, chunk),
+                Change::Remove => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Resolves a stored chunk, which may be a tile reference, into a [`LoadedChunk`].
+    ///
+    /// The [`ChunkClipMap`](crate::clipmap::ChunkClipMap) only holds [`Chunk`]s, so the conversion depends on the map's
+    /// [`ChunkLayout`]. Signed distances of [`ChunkLayout::Sd16Palette8`] chunks are rounded to [`Sd8`], and each instance of a tile
+    /// gets its own converted copy. Layouts with 16-bit palette IDs can't be loaded.
+    ///
+    /// Chunks are decompressed once here to detect [`LoadedChunk::Uniform`] chunks, so the loader does that work instead of the
+    /// thread that inserts them into the clipmap.
+    pub(crate) fn load_chunk(
+        &self,
+        key: ChunkDbKey,
+        chunk: CompressedChunk,
+    ) -> Result<Option<LoadedChunk>, ReadError> {
+        match self.chunk_layout {
+            ChunkLayout::Palette8 => Ok(match chunk.tile_id() {
+                Some(tile) => self.read_shared_tile(tile)?.map(LoadedChunk::Tile),
+                None => match chunk.try_decompress_as() {
+                    Some(decompressed) => Some(LoadedChunk::new(&decompressed, chunk)),
+                    None => return Err(ReadError::InvalidArchive { key }),
+                },
+            }),
+            ChunkLayout::Sd16Palette8 => Ok(self
+                .decompress_stored_chunk::<Sd16, PaletteId8>(key, &chunk.bytes)?
+                .map(|chunk| {
+                    let chunk = chunk.convert::<Sd8, PaletteId8>();
+                    LoadedChunk::new(&chunk, chunk.compress_with(self.chunk_codec))
+                })),
+            layout @ (ChunkLayout::Palette16 | ChunkLayout::Sd16Palette16) => {
+                Err(ReadError::UnsupportedLayout { layout })
+            }
+        }
+    }
+
+    /// Decompresses the bytes of the chunk stored at `key`, which may be a tile reference. A reference to a missing tile reads as
+    /// no chunk.
+    pub(crate) fn decompress_stored_chunk<Sd: SdfValue, L: Pod>(
+        &self,
+        key: ChunkDbKey,
+        bytes: &[u8],
+    ) -> Result<Option<ChunkData<Sd, L>>, ReadError> {
+        match CompressedChunk::referenced_tile(bytes) {
+            Some(tile) => self.read_tile_as(tile),
+            None => ChunkData::try_from_compressed_bytes(bytes)
+                .map(Some)
+                .ok_or(ReadError::InvalidArchive { key }),
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{Chunk16, ChunkSd16, UniformChunk};
+    use crate::core::glam::IVec3;
+    use crate::database::tests::single_voxel_scene;
+    use crate::database::MemStore;
+    use crate::units::VoxelUnits;
+
+    #[test]
+    fn instances_share_a_tile_until_edited() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+
+        let mut tile_chunk = Chunk::default();
+        tile_chunk.set_voxel(IVec3::splat(5), 2, Sd8::MIN);
+        let tile = map.insert_tile(&tile_chunk).unwrap();
+        assert_ne!(map.insert_tile(&Chunk::default()).unwrap(), tile);
+
+        let keys: Vec<_> = (0..4)
+            .map(|x| ChunkDbKey::new(0, IVec3::new(x, 0, 0).into()))
+            .collect();
+        map.instance_tile(tile, keys.iter().copied()).unwrap();
+
+        // Instances are only references.
+        let stored = map.read_working_version(keys[0]).unwrap().unwrap();
+        assert_eq!(stored.deserialize().unwrap_insert().tile_id(), Some(tile));
+        assert_eq!(map.read_working_chunk(keys[1]).unwrap(), Some(tile_chunk));
+
+        let loaded: Vec<_> = keys
+            .iter()
+            .map(|&key| match map.read_working_loaded_chunk(key).unwrap() {
+                Some(LoadedChunk::Tile(shared)) => shared,
+                _ => panic!("Expected a shared tile"),
+            })
+            .collect();
+        assert!(Arc::ptr_eq(&loaded[0], &loaded[3]));
+        assert_eq!(loaded[0].chunk, tile_chunk);
+
+        // Editing one instance copies the tile.
+        let scene = single_voxel_scene(3);
+        map.import_vox_scene(&scene, VoxelUnits(IVec3::ONE), 0)
+            .unwrap();
+        let stored = map.read_working_version(keys[0]).unwrap().unwrap();
+        assert_eq!(stored.deserialize().unwrap_insert().tile_id(), None);
+        assert_ne!(map.read_working_chunk(keys[0]).unwrap(), Some(tile_chunk));
+        assert_eq!(map.read_working_chunk(keys[1]).unwrap(), Some(tile_chunk));
+        assert_eq!(map.read_tile_as(tile).unwrap(), Some(tile_chunk));
+    }
+
+    #[test]
+    fn loaded_chunks_are_converted_from_the_map_layout() {
+        let store = MemStore::default();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+
+        let mut map = MapDb::open_with_layout(&store, "sd16", ChunkLayout::Sd16Palette8).unwrap();
+        let mut chunk = ChunkData::<Sd16, PaletteId8>::default();
+        chunk.set_voxel(IVec3::splat(5), 2, Sd16::MIN);
+        chunk.set_voxel(IVec3::splat(6), 3, Sd16::from(-0.5));
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+
+        match map.read_working_loaded_chunk(key).unwrap() {
+            Some(LoadedChunk::Compressed(loaded)) => {
+                assert_eq!(loaded.decompress(), chunk.convert::<Sd8, PaletteId8>());
+            }
+            _ => panic!("Expected a compressed chunk"),
+        }
+
+        // Uniform chunks are detected on load.
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(ChunkSd16::default().compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+        assert!(matches!(
+            map.read_working_loaded_chunk(key).unwrap(),
+            Some(LoadedChunk::Uniform(UniformChunk::AMBIENT))
+        ));
+
+        // The clipmap can't hold 16-bit palette IDs.
+        let mut map = MapDb::open_with_layout(&store, "palette16", ChunkLayout::Palette16).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(Chunk16::default().compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+        assert!(matches!(
+            map.read_working_loaded_chunk(key),
+            Err(ReadError::UnsupportedLayout {
+                layout: ChunkLayout::Palette16
+            })
+        ));
+    }
+
+    #[test]
+    fn unreferenced_tiles_are_collected() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key0 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key1 = ChunkDbKey::new(0, IVec3::X.into());
+
+        let mut tile_chunk = Chunk::default();
+        tile_chunk.set_voxel(IVec3::splat(5), 2, Sd8::MIN);
+        let unused = map.insert_tile(&Chunk::default()).unwrap();
+        let replaced = map.insert_tile(&tile_chunk).unwrap();
+        let archived = map.insert_tile(&tile_chunk).unwrap();
+
+        map.instance_tile(archived, [key0]).unwrap();
+        map.instance_tile(replaced, [key1]).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key1, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+
+        assert_eq!(map.collect_unused_tiles().unwrap(), 2);
+        assert_eq!(map.read_tile(unused).unwrap(), None);
+        assert_eq!(map.read_tile(replaced).unwrap(), None);
+
+        // Once the instance is removed, only the archived version references the tile.
+        map.commit_working_version().unwrap();
+        let v0 = map.cached_meta().parent_version.unwrap();
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key0, Change::Remove);
+        map.write_working_version(encoder.encode()).unwrap();
+        map.commit_working_version().unwrap();
+
+        assert_eq!(map.collect_unused_tiles().unwrap(), 0);
+        map.branch_from_version(v0).unwrap();
+        assert_eq!(map.read_working_chunk(key0).unwrap(), Some(tile_chunk));
+    }
+
+    #[test]
+    fn instancing_missing_tile_aborts() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        assert!(matches!(
+            map.instance_tile(TileId(5), [key]),
+            Err(TransactionError::Abort(AbortReason::MissingTile {
+                tile: TileId(5)
+            }))
+        ));
+    }
+}
@@ -1,19 +1,24 @@
-use super::{ArchivedIVec, Change, ChunkDbKey, EncodedChanges, Version};
+use super::{
+    abort, AbortReason, ArchivedIVec, Change, ChunkDbKey, ConflictableTransactionError,
+    EncodedChanges, MapStore, StoreBytes, StoreResult, TreeTxn, UnabortableTransactionError,
+    Version,
+};
 use crate::chunk::CompressedChunk;
-use crate::core::NoSharedAllocSerializer;
 use crate::core::rkyv::ser::Serializer;
 use crate::core::rkyv::{Archive, Deserialize, Serialize};
+use crate::core::NoSharedAllocSerializer;
 
-use sled::transaction::TransactionalTree;
-use sled::{transaction::UnabortableTransactionError, Tree};
+use bytecheck::CheckBytes;
 use std::collections::BTreeMap;
 
 #[derive(Archive, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
 pub struct VersionChanges {
     /// The full set of changes made between `parent_version` and this version.
     ///
-    /// Kept in a btree map to be efficiently searchable by readers of the archive.
+    /// Kept in a btree map to be efficiently searchable by readers of the archive. Once archived, inserted chunks may be deltas
+    /// against the version that this version was archived next to.
     pub changes: BTreeMap<ChunkDbKey, Change<CompressedChunk>>,
 }
 
@@ -36,28 +41,52 @@ impl From<&EncodedChanges<CompressedChunk>> for VersionChanges {
     }
 }
 
-pub fn open_version_change_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
-    db.open_tree(format!("{}-version-changes", map_name))
+pub fn open_version_change_tree<S: MapStore>(map_name: &str, store: &S) -> StoreResult<S::Tree> {
+    store.open_tree(&format!("{}-version-changes", map_name))
 }
 
 pub fn archive_version(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     version: Version,
     changes: &VersionChanges,
 ) -> Result<(), UnabortableTransactionError> {
     let mut serializer = NoSharedAllocSerializer::<8192>::default();
     serializer.serialize_value(changes).unwrap();
     let changes_bytes = serializer.into_serializer().into_inner();
-    txn.insert(&version.into_sled_key(), changes_bytes.as_ref())?;
+    txn.insert(
+        &version.into_sled_key(),
+        StoreBytes::from(changes_bytes.as_ref()),
+    )?;
     Ok(())
 }
 
+/// Aborts with [`AbortReason::InvalidVersionArchive`] if the stored bytes are not valid [`VersionChanges`].
+pub fn read_archived_version(
+    txn: &impl TreeTxn,
+    version: Version,
+) -> Result<Option<ArchivedIVec<VersionChanges>>, ConflictableTransactionError<AbortReason>> {
+    match txn.get(&version.into_sled_key())? {
+        Some(bytes) => match ArchivedIVec::<VersionChanges>::try_new(bytes) {
+            Ok(changes) => Ok(Some(changes)),
+            Err(_) => abort(AbortReason::InvalidVersionArchive { version }),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Aborts with [`AbortReason::InvalidVersionArchive`] if the stored bytes are not valid [`VersionChanges`], e.g. because the
+/// database was corrupted.
 pub fn remove_archived_version(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     version: Version,
-) -> Result<Option<ArchivedIVec<VersionChanges>>, UnabortableTransactionError> {
-    let bytes = txn.remove(&version.into_sled_key())?;
-    Ok(bytes.map(|b| unsafe { ArchivedIVec::<VersionChanges>::new(b) }))
+) -> Result<Option<ArchivedIVec<VersionChanges>>, ConflictableTransactionError<AbortReason>> {
+    match txn.remove(&version.into_sled_key())? {
+        Some(bytes) => match ArchivedIVec::<VersionChanges>::try_new(bytes) {
+            Ok(changes) => Ok(Some(changes)),
+            Err(_) => abort(AbortReason::InvalidVersionArchive { version }),
+        },
+        None => Ok(None),
+    }
 }
 
 // ████████╗███████╗███████╗████████╗
@@ -74,8 +103,7 @@ mod tests {
     use crate::chunk::Chunk;
     use crate::core::glam::IVec3;
     use crate::core::rkyv::option::ArchivedOption;
-
-    use sled::transaction::TransactionError;
+    use crate::database::TransactionError;
 
     #[test]
     fn open_archive_and_get() {
@@ -91,18 +119,39 @@ mod tests {
         original_changes.insert(ChunkDbKey::new(2, IVec3::ZERO.into()), Change::Remove);
         let changes = VersionChanges::new(original_changes.clone());
 
-        let changes: Result<VersionChanges, TransactionError> = tree.transaction(|txn| {
-            assert!(
-                remove_archived_version(txn, v0).unwrap()
-                    == ArchivedOption::<ArchivedIVec<VersionChanges>>::None
-            );
+        let changes: Result<VersionChanges, TransactionError<AbortReason>> =
+            <sled::Db as MapStore>::transaction([&tree], |[txn]| {
+                assert!(
+                    remove_archived_version(txn, v0).unwrap()
+                        == ArchivedOption::<ArchivedIVec<VersionChanges>>::None
+                );
 
-            archive_version(txn, v0, &changes).unwrap();
+                archive_version(txn, v0, &changes).unwrap();
 
-            let owned_archive = remove_archived_version(txn, Version::new(0))?.unwrap();
+                let owned_archive = remove_archived_version(txn, Version::new(0))?.unwrap();
 
-            Ok(owned_archive.deserialize())
-        });
+                Ok(owned_archive.deserialize())
+            });
         assert_eq!(changes.unwrap(), VersionChanges::new(original_changes));
     }
+
+    #[test]
+    fn removing_corrupt_archive_aborts() {
+        let db = sled::Config::default().temporary(true).open().unwrap();
+        let tree = db.open_tree("mymap-changes").unwrap();
+        let v0 = Version::new(0);
+        tree.insert(&v0.into_sled_key(), &[0xFF; 12][..]).unwrap();
+
+        let result: Result<(), TransactionError<AbortReason>> =
+            <sled::Db as MapStore>::transaction([&tree], |[txn]| {
+                remove_archived_version(txn, v0)?;
+                Ok(())
+            });
+        assert_eq!(
+            result,
+            Err(TransactionError::Abort(
+                AbortReason::InvalidVersionArchive { version: v0 }
+            ))
+        );
+    }
 }
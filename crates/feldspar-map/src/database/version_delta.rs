@@ -0,0 +1,191 @@
+use super::{
+    abort, AbortReason, ArchivedChangeIVec, Change, ChunkDbKey, ConflictableTransactionError,
+    TreeTxn, VersionChanges,
+};
+use crate::chunk::CompressedChunk;
+use crate::codec::{self, ChunkCodec, CodecHeader};
+
+/// Starts the bytes of an archived chunk that is stored as a delta. Compressed voxels always start with the LZ4 frame magic
+/// number or a [`CodecHeader`], so archives written before deltas existed are still readable.
+///
+/// The magic is followed by the [`CodecHeader`] of the old value, unless it was an LZ4 frame, and then the LZ4 frame of the XOR.
+/// So the old value can be compressed again with the same codec, byte for byte.
+const DELTA_MAGIC: [u8; 4] = *b"XDLT";
+
+/// Encodes `old` as the LZ4-compressed XOR of its voxels with the voxels of `base`.
+///
+/// Returns `None` unless both are compressed voxels of the same layout, or if the delta would not be smaller than `old`.
+pub fn encode_delta(old: &[u8], base: &[u8]) -> Option<Box<[u8]>> {
+    let (header, mut xor) = codec::decode(old)?;
+    let (_, base) = codec::decode(base)?;
+    if xor.len() != base.len() {
+        return None;
+    }
+    for (x, b) in xor.iter_mut().zip(base.iter()) {
+        *x ^= b;
+    }
+    let mut delta = DELTA_MAGIC.to_vec();
+    if header.codec != ChunkCodec::Lz4Frame {
+        delta.extend_from_slice(&header.to_bytes());
+    }
+    codec::compress_frame(&xor, &mut delta);
+    (delta.len() < old.len()).then(|| delta.into_boxed_slice())
+}
+
+/// Reconstructs the compressed voxels that were encoded as `delta` against `base`. Returns `None` if `base` is not the same
+/// value that the delta was encoded against.
+pub fn decode_delta(delta: &[u8], base: &[u8]) -> Option<CompressedChunk> {
+    let (_, mut voxels) = codec::decode(base)?;
+    let rest = delta.strip_prefix(&DELTA_MAGIC)?;
+    let header = CodecHeader::read(rest)?;
+    let frame = match header.codec {
+        ChunkCodec::Lz4Frame => rest,
+        _ => &rest[CodecHeader::LEN..],
+    };
+    let xor = codec::decompress_frame(frame)?;
+    if xor.len() != voxels.len() {
+        return None;
+    }
+    for (v, x) in voxels.iter_mut().zip(xor.iter()) {
+        *v ^= x;
+    }
+    Some(CompressedChunk {
+        bytes: codec::encode(header, &voxels).into_boxed_slice(),
+    })
+}
+
+pub fn is_delta(bytes: &[u8]) -> bool {
+    bytes.starts_with(&DELTA_MAGIC)
+}
+
+pub fn is_delta_change(change: &Change<CompressedChunk>) -> bool {
+    matches!(change, Change::Insert(chunk) if is_delta(&chunk.bytes))
+}
+
+/// Replaces each inserted chunk in `changes` with a delta against the value of the same key in the working tree, when that
+/// makes it smaller.
+///
+/// An archived version is always applied to the working tree while the working tree holds the version that it was archived
+/// next to, so the working tree has the base of every delta at that time.
+pub fn encode_version_deltas(
+    working_txn: &impl TreeTxn,
+    changes: &mut VersionChanges,
+) -> Result<(), ConflictableTransactionError<AbortReason>> {
+    for (&key, change) in changes.changes.iter_mut() {
+        if let Change::Insert(old) = change {
+            if let Change::Insert(base) = read_working_change(working_txn, key)? {
+                if let Some(delta) = encode_delta(&old.bytes, &base.bytes) {
+                    old.bytes = delta;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reconstructs `change` if it is a delta against `base`. Returns `None` if `base` is not the value it was encoded against.
+pub fn resolve_delta(
+    change: Change<CompressedChunk>,
+    base: &Change<CompressedChunk>,
+) -> Option<Change<CompressedChunk>> {
+    match (change, base) {
+        (Change::Insert(chunk), Change::Insert(base)) if is_delta(&chunk.bytes) => {
+            decode_delta(&chunk.bytes, &base.bytes).map(Change::Insert)
+        }
+        (Change::Insert(chunk), Change::Remove) if is_delta(&chunk.bytes) => None,
+        (change, _) => Some(change),
+    }
+}
+
+/// A missing key is equivalent to [`Change::Remove`]. Aborts with [`AbortReason::InvalidChunkArchive`] if the stored bytes are
+/// not a valid change.
+pub fn read_working_change(
+    working_txn: &impl TreeTxn,
+    key: ChunkDbKey,
+) -> Result<Change<CompressedChunk>, ConflictableTransactionError<AbortReason>> {
+    match working_txn.get(&key.into_sled_key())? {
+        Some(bytes) => match ArchivedChangeIVec::<CompressedChunk>::try_new(bytes) {
+            Ok(change) => Ok(change.deserialize()),
+            Err(_) => abort(AbortReason::InvalidChunkArchive { key }),
+        },
+        None => Ok(Change::Remove),
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{Chunk, TileId};
+    use crate::core::glam::IVec3;
+    use crate::database::tests::noise_chunk;
+    use crate::sdf::Sd8;
+
+    #[test]
+    fn delta_round_trip() {
+        let base = noise_chunk(12345);
+        let mut old = base;
+        old.set_voxel(IVec3::new(3, 4, 5), 9, Sd8::MIN);
+
+        let old_bytes = old.compress().bytes;
+        let base_bytes = base.compress().bytes;
+        let delta = encode_delta(&old_bytes, &base_bytes).unwrap();
+        assert!(is_delta(&delta));
+        assert!(delta.len() < old_bytes.len() / 4);
+
+        let decoded = decode_delta(&delta, &base_bytes).unwrap();
+        assert_eq!(decoded.decompress(), old);
+
+        let resolved = resolve_delta(
+            Change::Insert(CompressedChunk { bytes: delta }),
+            &Change::Insert(base.compress()),
+        );
+        assert_eq!(resolved.unwrap().unwrap_insert().decompress(), old);
+    }
+
+    #[test]
+    fn deltas_keep_the_codec_of_the_old_value() {
+        let base = noise_chunk(12345);
+        let mut old = base;
+        old.set_voxel(IVec3::new(3, 4, 5), 9, Sd8::MIN);
+
+        for codec in [
+            ChunkCodec::Lz4Block,
+            ChunkCodec::SdfPredictive,
+            ChunkCodec::PaletteRle,
+        ] {
+            let old_bytes = old.compress_with(codec).bytes;
+            // The base may have been written with a different codec.
+            let base_bytes = base.compress().bytes;
+            let delta = encode_delta(&old_bytes, &base_bytes).unwrap();
+            assert_eq!(decode_delta(&delta, &base_bytes).unwrap().bytes, old_bytes);
+        }
+    }
+
+    #[test]
+    fn only_voxels_are_delta_encoded() {
+        let chunk = Chunk::default().compress();
+        let tile = CompressedChunk::tile_reference(TileId(1));
+        assert_eq!(encode_delta(&chunk.bytes, &tile.bytes), None);
+        assert_eq!(encode_delta(&tile.bytes, &chunk.bytes), None);
+
+        // A delta without its base can't be resolved.
+        let base = noise_chunk(12345);
+        let mut old = base;
+        old.palette_ids[0] = 7;
+        let delta = encode_delta(&old.compress().bytes, &base.compress().bytes).unwrap();
+        assert_eq!(
+            resolve_delta(
+                Change::Insert(CompressedChunk { bytes: delta }),
+                &Change::Remove
+            ),
+            None
+        );
+    }
+}
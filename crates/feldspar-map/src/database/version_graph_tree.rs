@@ -1,18 +1,17 @@
-use super::{AbortReason, ArchivedIVec, Version};
+use super::{
+    abort, AbortReason, ArchivedIVec, ConflictableTransactionError, MapStore, StoreBytes,
+    StoreResult, TreeTxn, UnabortableTransactionError, Version,
+};
 use crate::core::rkyv::{
     ser::{serializers::CoreSerializer, Serializer},
     AlignedBytes, Archive, Deserialize, Serialize,
 };
 
-use sled::{
-    transaction::{
-        abort, ConflictableTransactionError, TransactionalTree, UnabortableTransactionError,
-    },
-    Tree,
-};
+use bytecheck::CheckBytes;
 
 #[derive(Archive, Debug, Deserialize, Serialize)]
 #[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
 pub struct VersionNode {
     /// The version immediately before this one.
     pub parent_version: Option<Version>,
@@ -26,17 +25,17 @@ impl VersionNode {
     }
 }
 
-pub fn open_version_graph_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
-    db.open_tree(format!("{}-version-graph", map_name))
+pub fn open_version_graph_tree<S: MapStore>(map_name: &str, store: &S) -> StoreResult<S::Tree> {
+    store.open_tree(&format!("{}-version-graph", map_name))
 }
 
 pub fn link_version(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     version: Version,
     node: VersionNode,
 ) -> Result<(), UnabortableTransactionError> {
     let key_bytes = version.into_sled_key();
-    txn.insert(&key_bytes, node.serialize().as_ref())?;
+    txn.insert(&key_bytes, StoreBytes::from(node.serialize().as_ref()))?;
     Ok(())
 }
 
@@ -48,19 +47,59 @@ pub struct VersionPath {
 }
 
 pub fn find_path_between_versions(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     start_version: Version,
     end_version: Version,
 ) -> Result<VersionPath, ConflictableTransactionError<AbortReason>> {
+    let CommonAncestorPaths {
+        start_path,
+        mut end_path,
+        end_parent,
+    } = find_common_ancestor_paths(txn, start_version, end_version)?;
+
+    // The common ancestor is the last element of both paths.
+    let mut path = start_path;
+    end_path.pop();
+    end_path.reverse();
+    path.extend_from_slice(&end_path);
+
+    Ok(VersionPath { path, end_parent })
+}
+
+/// The two paths that lead from a pair of versions up to their nearest common ancestor.
+pub struct CommonAncestorPaths {
+    /// The path from `start_version` to the nearest common ancestor, inclusive.
+    pub start_path: Vec<Version>,
+    /// The path from `end_version` to the nearest common ancestor, inclusive.
+    pub end_path: Vec<Version>,
+    /// The parent of `end_version`.
+    pub end_parent: Option<Version>,
+}
+
+impl CommonAncestorPaths {
+    pub fn common_ancestor(&self) -> Version {
+        *self.start_path.last().unwrap()
+    }
+}
+
+pub fn find_common_ancestor_paths(
+    txn: &impl TreeTxn,
+    start_version: Version,
+    end_version: Version,
+) -> Result<CommonAncestorPaths, ConflictableTransactionError<AbortReason>> {
     // First we search through the ancestors of start_version until hitting the root.
     let (path_result, start_path) = find_ancestor_path(txn, start_version, end_version)?;
     if let PathResult::FoundEnd = path_result {
-        return Ok(start_path);
+        return Ok(CommonAncestorPaths {
+            start_path: start_path.path,
+            end_path: vec![end_version],
+            end_parent: start_path.end_parent,
+        });
     }
 
     // If we didn't see the end_version, then it's not an ancestor, so we need to find the nearest common ancestor.
     let start_root_version = *start_path.path.last().unwrap();
-    let (_path_result, mut end_path) = find_ancestor_path(txn, end_version, start_root_version)?;
+    let (_path_result, end_path) = find_ancestor_path(txn, end_version, start_root_version)?;
     let end_root_version = *end_path.path.last().unwrap();
 
     if start_root_version != end_root_version {
@@ -86,21 +125,21 @@ pub fn find_path_between_versions(
         finish_join = i2;
     }
 
-    let mut path = start_path.path[..=start_join].to_vec();
-    let further_slice = &mut end_path.path[..finish_join];
-    further_slice.reverse();
-    path.extend_from_slice(further_slice);
+    // end_version is not an ancestor of start_version, so it can't be the common ancestor, and its parent must be on the
+    // path.
+    let end_parent = Some(end_path.path[1]);
 
-    Ok(VersionPath {
-        path,
-        end_parent: end_path.end_parent,
+    Ok(CommonAncestorPaths {
+        start_path: start_path.path[..=start_join].to_vec(),
+        end_path: end_path.path[..=finish_join].to_vec(),
+        end_parent,
     })
 }
 
 /// Finds a path along only ancestors, starting at `start_version` and ending at either `end_version` or the root ancestor,
 /// whichever comes first.
 pub fn find_ancestor_path(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     start_version: Version,
     end_version: Version,
 ) -> Result<(PathResult, VersionPath), ConflictableTransactionError<AbortReason>> {
@@ -108,8 +147,15 @@ pub fn find_ancestor_path(
 
     // First we search through the ancestors of start_version until hitting the root.
     let mut current_version = start_version;
-    while let Some(node_bytes) = txn.get(current_version.into_sled_key())? {
-        let node = unsafe { ArchivedIVec::<VersionNode>::new(node_bytes) }.deserialize();
+    while let Some(node_bytes) = txn.get(&current_version.into_sled_key())? {
+        let node = match ArchivedIVec::<VersionNode>::try_new(node_bytes) {
+            Ok(node) => node.deserialize(),
+            Err(_) => {
+                return abort(AbortReason::InvalidVersionNode {
+                    version: current_version,
+                })
+            }
+        };
         if current_version == end_version {
             return Ok((
                 PathResult::FoundEnd,
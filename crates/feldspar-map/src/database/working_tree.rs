@@ -1,26 +1,26 @@
 use super::{
-    ArchivedChange, ArchivedChangeIVec, ArchivedIVec, BackupKeyCache, Change, ChunkDbKey,
-    EncodedChanges,
+    abort, AbortReason, ArchivedChange, ArchivedChangeIVec, ArchivedIVec, BackupKeyCache, Change,
+    ChunkDbKey, ConflictableTransactionError, EncodedChanges, MapStore, StoreBytes, StoreResult,
+    TreeTxn,
 };
 use crate::chunk::CompressedChunk;
 
-use sled::transaction::{TransactionalTree, UnabortableTransactionError};
-use sled::{IVec, Tree};
-
-pub fn open_working_tree(map_name: &str, db: &sled::Db) -> sled::Result<Tree> {
-    db.open_tree(format!("{}-working", map_name))
+pub fn open_working_tree<S: MapStore>(map_name: &str, store: &S) -> StoreResult<S::Tree> {
+    store.open_tree(&format!("{}-working", map_name))
 }
 
 /// Inserts any previously unseen entries from `changes` into the backup tree (`txn`) and returns the [`EncodedChanges`] that
 /// can reverse the transformation.
+///
+/// Aborts with [`AbortReason::InvalidChunkArchive`] if a replaced value is not a valid change, since it could not be archived.
 pub fn write_changes_to_working_tree(
-    txn: &TransactionalTree,
+    txn: &impl TreeTxn,
     backup_key_cache: &BackupKeyCache,
     changes: EncodedChanges<CompressedChunk>,
-) -> Result<EncodedChanges<CompressedChunk>, UnabortableTransactionError> {
+) -> Result<EncodedChanges<CompressedChunk>, ConflictableTransactionError<AbortReason>> {
     let mut reverse_changes = Vec::with_capacity(changes.changes.len());
     let remove_bytes = unsafe {
-        ArchivedIVec::new(IVec::from(
+        ArchivedIVec::new(StoreBytes::from(
             Change::<CompressedChunk>::serialize_remove::<12>().as_ref(),
         ))
     };
@@ -38,9 +38,10 @@ pub fn write_changes_to_working_tree(
         }
 
         if let Some(old_value) = old_value {
-            reverse_changes.push((key_bytes, unsafe {
-                ArchivedChangeIVec::<CompressedChunk>::new(old_value)
-            }));
+            match ArchivedChangeIVec::<CompressedChunk>::try_new(old_value) {
+                Ok(old_change) => reverse_changes.push((key_bytes, old_change)),
+                Err(_) => return abort(AbortReason::InvalidChunkArchive { key }),
+            }
         } else {
             reverse_changes.push((key_bytes, remove_bytes.clone()));
         }
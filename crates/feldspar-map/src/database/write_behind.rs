@@ -0,0 +1,642 @@
+use super::{
+    AbortReason, ArchivedChange, ArchivedChangeIVec, Change, ChunkDbKey, EncodedChanges, MapDb,
+    MapStore, ReadError, StoreBytes, TransactionError,
+};
+use crate::chunk::{ChunkData, CompressedChunk};
+use crate::clipmap::LoadedChunk;
+use crate::material_registry::MaterialRegistry;
+use crate::sdf::SdfValue;
+
+use bytemuck::Pod;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct WriteBehindConfig {
+    /// How long the writer waits for more changes after receiving the first change of a batch.
+    pub coalesce_window: Duration,
+    /// A batch is committed early once it has this many distinct keys.
+    pub max_batch_keys: usize,
+}
+
+impl Default for WriteBehindConfig {
+    fn default() -> Self {
+        Self {
+            coalesce_window: Duration::from_millis(50),
+            max_batch_keys: 4096,
+        }
+    }
+}
+
+/// Writes to the working version of a [`MapDb`] on a background thread, so game systems never block on a transaction.
+///
+/// Changes sent with [`write`](Self::write) are collected for [`WriteBehindConfig::coalesce_window`]. Changes to the same
+/// [`ChunkDbKey`] are coalesced, keeping the latest, and the whole batch is committed with a single call to
+/// [`MapDb::write_working_version`]. Until then, the changes are kept in memory, and reads through this handle see them.
+///
+/// Any other access to the map, like committing the working version, should go through [`flushed_db`](Self::flushed_db).
+/// Dropping the handle commits all pending changes and stops the writer thread.
+///
+/// The writer needs exclusive access to commit, so a thread must not flush while it holds a [`MapDbGuard`]. That would
+/// deadlock, so [`flush`](Self::flush) and [`flushed_db`](Self::flushed_db) panic instead.
+pub struct WriteBehindMapDb<S: MapStore = sled::Db> {
+    db: Arc<RwLock<MapDb<S>>>,
+    pending: Arc<Mutex<PendingWrites>>,
+    sender: Option<Sender<Command>>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+/// Changes that were sent to the writer but haven't been committed yet.
+#[derive(Default)]
+struct PendingWrites {
+    next_batch: u64,
+    /// The latest change for each key, tagged with the number of the [`Command::Write`] that sent it.
+    changes: BTreeMap<ChunkDbKey, (u64, ArchivedChangeIVec<CompressedChunk>)>,
+}
+
+enum Command {
+    Write {
+        batch: u64,
+        changes: EncodedChanges<CompressedChunk>,
+    },
+    Flush(Sender<Result<(), TransactionError<AbortReason>>>),
+}
+
+impl<S: MapStore> WriteBehindMapDb<S>
+where
+    S: 'static,
+    MapDb<S>: Send + Sync,
+{
+    pub fn new(db: MapDb<S>, config: WriteBehindConfig) -> Self {
+        let db = Arc::new(RwLock::new(db));
+        let pending = Arc::new(Mutex::new(PendingWrites::default()));
+        let (sender, receiver) = channel();
+        let writer = Writer {
+            db: db.clone(),
+            pending: pending.clone(),
+            receiver,
+            config,
+            error: None,
+            failed: Batch::new(),
+            failed_attempts: 0,
+        };
+        let writer_thread = std::thread::Builder::new()
+            .name("map-writer".into())
+            .spawn(move || writer.run())
+            .expect("Failed to spawn map writer thread");
+        Self {
+            db,
+            pending,
+            sender: Some(sender),
+            writer_thread: Some(writer_thread),
+        }
+    }
+}
+
+impl<S: MapStore> WriteBehindMapDb<S> {
+    /// Queues `changes` to be written to the working version. Returns immediately.
+    pub fn write(&self, changes: EncodedChanges<CompressedChunk>) {
+        if changes.changes.is_empty() {
+            return;
+        }
+        // Send while holding the lock, so batches arrive in the order of their numbers.
+        let mut pending = self.pending.lock();
+        let batch = pending.next_batch;
+        pending.next_batch += 1;
+        for (key_bytes, change) in changes.changes.iter() {
+            pending.changes.insert(
+                ChunkDbKey::from_sled_key(key_bytes),
+                (batch, change.clone()),
+            );
+        }
+        self.sender()
+            .send(Command::Write { batch, changes })
+            .expect("Map writer thread stopped");
+    }
+
+    /// Blocks until every change written so far is committed.
+    ///
+    /// Returns the first error from a commit since the last flush. The changes of a failed commit stay pending, and they are
+    /// retried by the next commit. After [`MAX_COMMIT_ATTEMPTS`] failed commits in a row, the changes are committed one key at
+    /// a time, and the changes that still fail are dropped.
+    ///
+    /// # Panics
+    ///
+    /// If the current thread holds a [`MapDbGuard`].
+    pub fn flush(&self) -> Result<(), TransactionError<AbortReason>> {
+        assert_eq!(
+            HELD_GUARDS.with(Cell::get),
+            0,
+            "Flushing while holding a lock on the map would deadlock"
+        );
+        let (reply_sender, reply_receiver) = channel();
+        self.sender()
+            .send(Command::Flush(reply_sender))
+            .expect("Map writer thread stopped");
+        reply_receiver.recv().expect("Map writer thread stopped")
+    }
+
+    /// Like [`MapDb::read_working_version`], but pending changes take precedence over the database.
+    pub fn read_working_version(
+        &self,
+        key: ChunkDbKey,
+    ) -> Result<Option<ArchivedChangeIVec<CompressedChunk>>, ReadError> {
+        if let Some(change) = self.read_pending(key) {
+            return Ok(match change.as_ref() {
+                ArchivedChange::Insert(_) => Some(change),
+                ArchivedChange::Remove => None,
+            });
+        }
+        self.db.read_recursive().read_working_version(key)
+    }
+
+    /// Like [`MapDb::read_working_chunk_as`], but pending changes take precedence over the database.
+    pub fn read_working_chunk_as<Sd: SdfValue, L: Pod>(
+        &self,
+        key: ChunkDbKey,
+    ) -> Result<Option<ChunkData<Sd, L>>, ReadError> {
+        // Recursive, so that reading doesn't deadlock with a waiting writer while the caller holds a guard from `db`.
+        let db = self.db.read_recursive();
+        let change = match self.read_pending(key) {
+            Some(change) => change,
+            None => return db.read_working_chunk_as(key),
+        };
+        match change.as_ref().get_insert_data() {
+            Some(chunk) => db.decompress_stored_chunk(key, &chunk.bytes),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`MapDb::read_working_loaded_chunk`], but pending changes take precedence over the database.
+    pub fn read_working_loaded_chunk(
+        &self,
+        key: ChunkDbKey,
+    ) -> Result<Option<LoadedChunk>, ReadError> {
+        let db = self.db.read_recursive();
+        match self.read_pending(key) {
+            Some(change) => match change.deserialize() {
+                Change::Insert(chunk) => db.load_chunk(key, chunk),
+                Change::Remove => Ok(None),
+            },
+            None => db.read_working_loaded_chunk(key),
+        }
+    }
+
+    /// Like [`MapDb::write_material_registry`]. Pending chunk changes don't need to be flushed first, since the registry is
+    /// stored separately.
+    pub fn write_material_registry(
+        &self,
+        registry: MaterialRegistry,
+    ) -> Result<(), TransactionError<AbortReason>> {
+        self.db.write().write_material_registry(registry)
+    }
+
+    /// Read access to the map. Pending changes are not visible through it.
+    pub fn db(&self) -> MapDbGuard<RwLockReadGuard<'_, MapDb<S>>> {
+        MapDbGuard::new(self.db.read())
+    }
+
+    /// Flushes, then locks the map for exclusive access. Writes sent while the lock is held wait for it to be released.
+    ///
+    /// # Panics
+    ///
+    /// Like [`flush`](Self::flush).
+    pub fn flushed_db(
+        &self,
+    ) -> Result<MapDbGuard<RwLockWriteGuard<'_, MapDb<S>>>, TransactionError<AbortReason>> {
+        self.flush()?;
+        Ok(MapDbGuard::new(self.db.write()))
+    }
+
+    /// Commits all pending changes, stops the writer thread, and returns the map.
+    pub fn shutdown(mut self) -> Result<MapDb<S>, TransactionError<AbortReason>> {
+        let result = self.flush();
+        self.stop_writer();
+        let db = self.db.clone();
+        drop(self);
+        result.map(|()| {
+            Arc::try_unwrap(db)
+                .unwrap_or_else(|_| unreachable!("Writer thread has stopped"))
+                .into_inner()
+        })
+    }
+
+    fn read_pending(&self, key: ChunkDbKey) -> Option<ArchivedChangeIVec<CompressedChunk>> {
+        self.pending
+            .lock()
+            .changes
+            .get(&key)
+            .map(|(_, change)| change.clone())
+    }
+
+    fn sender(&self) -> &Sender<Command> {
+        self.sender.as_ref().unwrap()
+    }
+
+    fn stop_writer(&mut self) {
+        // Disconnecting the channel makes the writer commit what it has and exit.
+        self.sender.take();
+        if let Some(thread) = self.writer_thread.take() {
+            if thread.join().is_err() {
+                log::error!("Map writer thread panicked");
+            }
+        }
+    }
+}
+
+impl<S: MapStore> Drop for WriteBehindMapDb<S> {
+    fn drop(&mut self) {
+        self.stop_writer();
+    }
+}
+
+thread_local! {
+    /// The number of [`MapDbGuard`]s held by the current thread.
+    static HELD_GUARDS: Cell<usize> = Cell::new(0);
+}
+
+/// A lock on the map of a [`WriteBehindMapDb`], from [`WriteBehindMapDb::db`] or [`WriteBehindMapDb::flushed_db`]. The
+/// writer can't commit while it is held.
+pub struct MapDbGuard<G> {
+    guard: G,
+}
+
+impl<G> MapDbGuard<G> {
+    fn new(guard: G) -> Self {
+        HELD_GUARDS.with(|held| held.set(held.get() + 1));
+        Self { guard }
+    }
+}
+
+impl<G> Drop for MapDbGuard<G> {
+    fn drop(&mut self) {
+        HELD_GUARDS.with(|held| held.set(held.get() - 1));
+    }
+}
+
+impl<G: Deref> Deref for MapDbGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for MapDbGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+struct Writer<S: MapStore> {
+    db: Arc<RwLock<MapDb<S>>>,
+    pending: Arc<Mutex<PendingWrites>>,
+    receiver: Receiver<Command>,
+    config: WriteBehindConfig,
+    /// The first commit error since the last flush.
+    error: Option<TransactionError<AbortReason>>,
+    /// The changes of a failed commit, which are retried before any later changes.
+    failed: Batch,
+    /// The number of commits in a row that have failed.
+    failed_attempts: u32,
+}
+
+/// How many times in a row committing a batch can fail before its keys are committed one at a time. That way a change that
+/// can never be committed is dropped, instead of being retried forever along with every later change.
+pub const MAX_COMMIT_ATTEMPTS: u32 = 3;
+
+/// The latest change for each key in a batch, tagged like [`PendingWrites::changes`].
+type Batch = BTreeMap<StoreBytes, (u64, ArchivedChangeIVec<CompressedChunk>)>;
+
+impl<S: MapStore> Writer<S> {
+    fn run(mut self) {
+        while let Ok(first_command) = self.receiver.recv() {
+            // Later changes to the same keys replace the failed ones.
+            let mut batch = std::mem::take(&mut self.failed);
+            let mut flushes = Vec::new();
+            let mut disconnected = false;
+            add_command(first_command, &mut batch, &mut flushes);
+
+            let deadline = Instant::now() + self.config.coalesce_window;
+            while flushes.is_empty() && batch.len() < self.config.max_batch_keys {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match self.receiver.recv_timeout(timeout) {
+                    Ok(command) => add_command(command, &mut batch, &mut flushes),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        disconnected = true;
+                        break;
+                    }
+                }
+            }
+
+            self.commit(batch);
+            for reply in flushes {
+                // The flushing thread may have given up waiting.
+                let _ = reply.send(self.error.take().map_or(Ok(()), Err));
+            }
+            if disconnected {
+                break;
+            }
+        }
+        // There won't be another retry, so save what can be saved.
+        let failed = std::mem::take(&mut self.failed);
+        self.commit_each(failed);
+        if let Some(e) = self.error {
+            log::error!("Failed to write map changes: {:?}", e);
+        }
+    }
+
+    fn commit(&mut self, batch: Batch) {
+        if batch.is_empty() {
+            return;
+        }
+        log::trace!("Committing {} coalesced changes", batch.len());
+        match self.write(&batch) {
+            Ok(()) => {
+                self.failed_attempts = 0;
+                self.remove_pending(batch);
+            }
+            Err(e) => {
+                self.error.get_or_insert(e);
+                self.failed_attempts += 1;
+                if self.failed_attempts < MAX_COMMIT_ATTEMPTS {
+                    // The changes stay pending, so reads still see them until the retry.
+                    self.failed = batch;
+                } else {
+                    self.failed_attempts = 0;
+                    self.commit_each(batch);
+                }
+            }
+        }
+    }
+
+    /// Commits each key of `batch` separately, and drops the changes that fail.
+    fn commit_each(&mut self, batch: Batch) {
+        for (key_bytes, entry) in batch {
+            let key = ChunkDbKey::from_sled_key(&key_bytes);
+            let single = Batch::from([(key_bytes, entry)]);
+            if let Err(e) = self.write(&single) {
+                log::error!("Dropping change to {:?} that failed to commit", key);
+                self.error.get_or_insert(e);
+            }
+            self.remove_pending(single);
+        }
+    }
+
+    fn write(&self, batch: &Batch) -> Result<(), TransactionError<AbortReason>> {
+        let changes = EncodedChanges {
+            changes: batch
+                .iter()
+                .map(|(key_bytes, (_, change))| (key_bytes.clone(), change.clone()))
+                .collect(),
+        };
+        self.db.write().write_working_version(changes)
+    }
+
+    /// Removes the changes of `batch` from the pending writes, unless they were replaced by later changes.
+    fn remove_pending(&self, batch: Batch) {
+        let mut pending = self.pending.lock();
+        for (key_bytes, (batch, _)) in batch {
+            let key = ChunkDbKey::from_sled_key(&key_bytes);
+            if matches!(pending.changes.get(&key), Some((pending_batch, _)) if *pending_batch <= batch)
+            {
+                pending.changes.remove(&key);
+            }
+        }
+    }
+}
+
+fn add_command(
+    command: Command,
+    batch: &mut Batch,
+    flushes: &mut Vec<Sender<Result<(), TransactionError<AbortReason>>>>,
+) {
+    match command {
+        Command::Write {
+            batch: batch_number,
+            changes,
+        } => {
+            for (key_bytes, change) in changes.changes.into_iter() {
+                batch.insert(key_bytes, (batch_number, change));
+            }
+        }
+        Command::Flush(reply) => flushes.push(reply),
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::core::glam::IVec3;
+    use crate::database::{ChangeEncoder, MemStore};
+    use crate::sdf::Sd8;
+
+    fn chunk(palette_id: u8) -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk.set_voxel(IVec3::ZERO, palette_id, Sd8::MIN);
+        chunk
+    }
+
+    fn insert(key: ChunkDbKey, chunk: &Chunk) -> EncodedChanges<CompressedChunk> {
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+        encoder.encode()
+    }
+
+    fn remove(key: ChunkDbKey) -> EncodedChanges<CompressedChunk> {
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Remove);
+        encoder.encode()
+    }
+
+    fn read_chunk<S: MapStore>(db: &WriteBehindMapDb<S>, key: ChunkDbKey) -> Option<Chunk> {
+        db.read_working_chunk_as(key).unwrap()
+    }
+
+    #[test]
+    fn pending_writes_are_readable_and_coalesced() {
+        let store = MemStore::default();
+        let config = WriteBehindConfig {
+            // Long enough that nothing is committed before the flush.
+            coalesce_window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let db = WriteBehindMapDb::new(MapDb::open(&store, "mymap").unwrap(), config);
+
+        let key1 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key2 = ChunkDbKey::new(0, IVec3::ONE.into());
+        db.write(insert(key1, &chunk(1)));
+        db.write(insert(key2, &chunk(2)));
+        db.write(insert(key1, &chunk(3)));
+        db.write(remove(key2));
+
+        assert_eq!(read_chunk(&db, key1), Some(chunk(3)));
+        assert_eq!(read_chunk(&db, key2), None);
+        assert!(db.read_working_version(key2).unwrap().is_none());
+        assert!(db.db().read_working_version(key1).unwrap().is_none());
+
+        db.flush().unwrap();
+        assert_eq!(db.db().read_working_chunk(key1).unwrap(), Some(chunk(3)));
+        assert!(db.db().read_working_version(key2).unwrap().is_none());
+        assert!(db.pending.lock().changes.is_empty());
+
+        let mut map = db.shutdown().unwrap();
+        assert_eq!(map.backup_key_cache.keys.len(), 2);
+        map.commit_working_version().unwrap();
+        assert!(map.fsck().unwrap().is_empty());
+    }
+
+    #[test]
+    fn drop_commits_pending_writes() {
+        let store = MemStore::default();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        {
+            let map = MapDb::open(&store, "mymap").unwrap();
+            let db = WriteBehindMapDb::new(map, WriteBehindConfig::default());
+            for palette_id in 0..10 {
+                db.write(insert(key, &chunk(palette_id)));
+            }
+        }
+        let map = MapDb::open(&store, "mymap").unwrap();
+        assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk(9)));
+    }
+
+    #[test]
+    fn flushed_db_allows_exclusive_access() {
+        let store = MemStore::default();
+        let map = MapDb::open(&store, "mymap").unwrap();
+        let db = WriteBehindMapDb::new(map, WriteBehindConfig::default());
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        db.write(insert(key, &chunk(1)));
+        db.flushed_db().unwrap().commit_working_version().unwrap();
+        let committed = db.db().cached_meta().parent_version.unwrap();
+
+        db.write(insert(key, &chunk(2)));
+        assert_eq!(read_chunk(&db, key), Some(chunk(2)));
+        let mut map = db.flushed_db().unwrap();
+        assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk(2)));
+        map.branch_from_version(committed).unwrap();
+        assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk(1)));
+    }
+
+    #[test]
+    fn failed_commits_stay_pending_and_are_retried() {
+        let store = MemStore::default();
+        let map = MapDb::open(&store, "mymap").unwrap();
+        let key1 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key2 = ChunkDbKey::new(0, IVec3::ONE.into());
+        let set_stored_value = |map: &MapDb<MemStore>, value: Option<&[u8]>| {
+            let result: Result<(), TransactionError<()>> =
+                MemStore::transaction([&map.working_tree], |[txn]| {
+                    match value {
+                        Some(value) => {
+                            txn.insert(&key1.into_sled_key(), StoreBytes::from(value))?
+                        }
+                        None => txn.remove(&key1.into_sled_key())?,
+                    };
+                    Ok(())
+                });
+            result.unwrap();
+        };
+        // Replacing a corrupt value fails, since it can't be backed up.
+        set_stored_value(&map, Some(&[0xFF; 7][..]));
+        let config = WriteBehindConfig {
+            coalesce_window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let db = WriteBehindMapDb::new(map, config);
+
+        db.write(insert(key1, &chunk(1)));
+        db.write(insert(key2, &chunk(2)));
+        assert_eq!(
+            db.flush(),
+            Err(TransactionError::Abort(AbortReason::InvalidChunkArchive {
+                key: key1
+            }))
+        );
+        assert_eq!(read_chunk(&db, key1), Some(chunk(1)));
+        assert_eq!(read_chunk(&db, key2), Some(chunk(2)));
+        assert!(db.db().read_working_version(key2).unwrap().is_none());
+
+        set_stored_value(&db.db(), None);
+        db.write(insert(key2, &chunk(3)));
+        db.flush().unwrap();
+        assert_eq!(db.db().read_working_chunk(key1).unwrap(), Some(chunk(1)));
+        assert_eq!(db.db().read_working_chunk(key2).unwrap(), Some(chunk(3)));
+        assert!(db.pending.lock().changes.is_empty());
+    }
+
+    #[test]
+    fn changes_that_never_commit_are_dropped() {
+        let store = MemStore::default();
+        let map = MapDb::open(&store, "mymap").unwrap();
+        let key1 = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let key2 = ChunkDbKey::new(0, IVec3::ONE.into());
+        // Replacing a corrupt value fails, since it can't be backed up.
+        let result: Result<(), TransactionError<()>> =
+            MemStore::transaction([&map.working_tree], |[txn]| {
+                txn.insert(&key1.into_sled_key(), StoreBytes::from(&[0xFF; 7][..]))?;
+                Ok(())
+            });
+        result.unwrap();
+        let config = WriteBehindConfig {
+            coalesce_window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let db = WriteBehindMapDb::new(map, config);
+        let key1_error = || {
+            Err(TransactionError::Abort(AbortReason::InvalidChunkArchive {
+                key: key1,
+            }))
+        };
+
+        db.write(insert(key1, &chunk(1)));
+        db.write(insert(key2, &chunk(2)));
+        for _ in 1..MAX_COMMIT_ATTEMPTS {
+            assert_eq!(db.flush(), key1_error());
+            assert!(db.db().read_working_version(key2).unwrap().is_none());
+        }
+        // The last attempt commits the keys one at a time.
+        assert_eq!(db.flush(), key1_error());
+        assert_eq!(db.db().read_working_chunk(key2).unwrap(), Some(chunk(2)));
+        assert!(db.pending.lock().changes.is_empty());
+
+        // The dropped change doesn't hold up later ones.
+        db.write(insert(key2, &chunk(3)));
+        db.flush().unwrap();
+        assert_eq!(db.db().read_working_chunk(key2).unwrap(), Some(chunk(3)));
+
+        // Changes that can be committed are saved when the writer stops, even if their batch failed.
+        db.write(insert(key1, &chunk(4)));
+        db.write(insert(key2, &chunk(5)));
+        drop(db);
+        let map = MapDb::open(&store, "mymap").unwrap();
+        assert_eq!(map.read_working_chunk(key2).unwrap(), Some(chunk(5)));
+    }
+
+    #[test]
+    #[should_panic(expected = "would deadlock")]
+    fn flushing_while_holding_the_map_panics() {
+        let store = MemStore::default();
+        let map = MapDb::open(&store, "mymap").unwrap();
+        let db = WriteBehindMapDb::new(map, WriteBehindConfig::default());
+        let _map = db.db();
+        let _ = db.flush();
+    }
+}
@@ -0,0 +1,493 @@
+//! Import of heightmap images into SDF [`Chunk`]s.
+//!
+//! A heightmap describes a solid slab of terrain: every column of voxels is solid from [`HeightmapConfig::base_height`] up
+//! to the height of the corresponding pixel. The signed distance is the vertical distance to the surface, scaled by the local
+//! slope so that it approximates the true Euclidean distance, then clamped into [`Sd8`]'s range of one voxel.
+//!
+//! Decoding image containers like PNG is left to the caller; [`Heightmap::new`] accepts raw samples. For convenience, we can
+//! read the uncompressed formats that terrain tools commonly export: headerless 16-bit RAW (`.r16`) and binary PGM.
+
+use crate::chunk::{Chunk, ChunkShape, CHUNK_SHAPE_IVEC3, CHUNK_SHAPE_LOG2_IVEC3};
+use crate::clipmap::Level;
+use crate::codec::ChunkCodec;
+use crate::coordinates::chunk_min;
+use crate::core::glam::{IVec2, IVec3, Vec2};
+use crate::core::ilattice::prelude::Extent;
+use crate::database::{Change, ChangeEncoder, ChunkDbKey};
+use crate::palette::PaletteId8;
+use crate::sdf::Sd8;
+use crate::units::*;
+
+use ndshape::ConstShape;
+use std::io::{self, Read};
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(io::Error),
+    /// The image header could not be parsed.
+    InvalidHeader,
+    /// The number of samples doesn't match the dimensions.
+    WrongSampleCount {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl From<io::Error> for HeightmapError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A 2D grid of height samples. The X axis of the image maps to the X axis of the map, and the Y axis of the image maps to the
+/// Z axis of the map.
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    /// Row-major, so the sample for pixel `(x, z)` is at `z * width + x`.
+    samples: Vec<u16>,
+}
+
+/// A 2D grid of [`PaletteId8`]s with the same layout as a [`Heightmap`].
+#[derive(Clone, Debug)]
+pub struct SplatMap {
+    width: usize,
+    depth: usize,
+    palette_ids: Vec<PaletteId8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct HeightmapConfig {
+    /// The LOD0 voxel coordinates (X, Z) of pixel `(0, 0)`.
+    pub offset: IVec2,
+    /// The LOD0 voxel Y coordinate of sample value 0. This is also the bottom of the imported terrain.
+    pub base_height: f32,
+    /// The number of LOD0 voxels per unit of sample value.
+    pub vertical_scale: f32,
+    /// Every level in `0..num_levels` is written.
+    pub num_levels: Level,
+    /// Used for every voxel when there is no [`SplatMap`].
+    pub palette_id: PaletteId8,
+}
+
+impl Default for HeightmapConfig {
+    fn default() -> Self {
+        Self {
+            offset: IVec2::ZERO,
+            base_height: 0.0,
+            vertical_scale: 1.0,
+            num_levels: 1,
+            palette_id: 0,
+        }
+    }
+}
+
+impl Heightmap {
+    pub fn new(width: usize, depth: usize, samples: Vec<u16>) -> Result<Self, HeightmapError> {
+        check_sample_count(width, depth, samples.len())?;
+        Ok(Self {
+            width,
+            depth,
+            samples,
+        })
+    }
+
+    /// Reads a headerless grid of little-endian 16-bit samples.
+    pub fn read_r16(
+        mut reader: impl Read,
+        width: usize,
+        depth: usize,
+    ) -> Result<Self, HeightmapError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        check_sample_count(width, depth, bytes.len() / 2)?;
+        let samples = bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Self::new(width, depth, samples)
+    }
+
+    /// Reads a binary (`P5`) PGM image with 8 or 16 bits per sample. Sample values are not rescaled by the image's maximum
+    /// value.
+    pub fn read_pgm(mut reader: impl Read) -> Result<Self, HeightmapError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let mut header = PgmHeaderParser { bytes: &bytes };
+        if header.token() != Some(b"P5".as_slice()) {
+            return Err(HeightmapError::InvalidHeader);
+        }
+        let width = header.number().ok_or(HeightmapError::InvalidHeader)?;
+        let depth = header.number().ok_or(HeightmapError::InvalidHeader)?;
+        let max_value = header.number().ok_or(HeightmapError::InvalidHeader)?;
+        // Exactly one whitespace byte separates the header from the samples.
+        let data = header.bytes.get(1..).ok_or(HeightmapError::InvalidHeader)?;
+
+        let samples = match max_value {
+            1..=255 => data.iter().map(|&b| b as u16).collect(),
+            256..=65535 => data
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect(),
+            _ => return Err(HeightmapError::InvalidHeader),
+        };
+        Self::new(width, depth, samples)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> u16 {
+        self.samples[z * self.width + x]
+    }
+
+    /// Bilinear interpolation of the samples at pixel coordinates `p`, clamped to the edges of the image.
+    pub fn sample_bilinear(&self, p: Vec2) -> f32 {
+        let max = Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+        let p = p.max(Vec2::ZERO).min(max);
+        let p0 = p.floor();
+        let t = p - p0;
+        let (x0, z0) = (p0.x as usize, p0.y as usize);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+
+        let lerp = |a: u16, b: u16, t: f32| a as f32 + (b as f32 - a as f32) * t;
+        let top = lerp(self.get(x0, z0), self.get(x1, z0), t.x);
+        let bottom = lerp(self.get(x0, z1), self.get(x1, z1), t.x);
+        top + (bottom - top) * t.y
+    }
+}
+
+impl SplatMap {
+    pub fn new(
+        width: usize,
+        depth: usize,
+        palette_ids: Vec<PaletteId8>,
+    ) -> Result<Self, HeightmapError> {
+        check_sample_count(width, depth, palette_ids.len())?;
+        Ok(Self {
+            width,
+            depth,
+            palette_ids,
+        })
+    }
+
+    /// The [`PaletteId8`] of the pixel nearest to `p`, clamped to the edges of the image.
+    pub fn sample_nearest(&self, p: Vec2) -> PaletteId8 {
+        let x = (p.x.round().max(0.0) as usize).min(self.width - 1);
+        let z = (p.y.round().max(0.0) as usize).min(self.depth - 1);
+        self.palette_ids[z * self.width + x]
+    }
+}
+
+fn check_sample_count(width: usize, depth: usize, actual: usize) -> Result<(), HeightmapError> {
+    let expected = width * depth;
+    if expected == 0 || actual != expected {
+        return Err(HeightmapError::WrongSampleCount { expected, actual });
+    }
+    Ok(())
+}
+
+struct PgmHeaderParser<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PgmHeaderParser<'a> {
+    /// Skips whitespace and comments, then returns the next whitespace-delimited token.
+    fn token(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.bytes.first()? {
+                b if b.is_ascii_whitespace() => self.bytes = &self.bytes[1..],
+                b'#' => {
+                    let line_end = self.bytes.iter().position(|&b| b == b'\n')?;
+                    self.bytes = &self.bytes[line_end..];
+                }
+                _ => break,
+            }
+        }
+        let end = self
+            .bytes
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(self.bytes.len());
+        let (token, rest) = self.bytes.split_at(end);
+        self.bytes = rest;
+        Some(token)
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        std::str::from_utf8(self.token()?).ok()?.parse().ok()
+    }
+}
+
+/// Adds a [`Change::Insert`] to `encoder` for every chunk of the terrain described by `heightmap`, compressed with `codec`.
+///
+/// The chunks replace whatever was stored before. To combine the terrain with existing chunks, use
+/// [`visit_heightmap_chunks`] instead.
+pub fn add_heightmap_changes(
+    heightmap: &Heightmap,
+    splat_map: Option<&SplatMap>,
+    config: &HeightmapConfig,
+    codec: ChunkCodec,
+    encoder: &mut ChangeEncoder,
+) {
+    let result: Result<(), ()> =
+        visit_heightmap_chunks(heightmap, splat_map, config, |key, chunk| {
+            encoder.add_compressed_change(key, Change::Insert(chunk.compress_with(codec)));
+            Ok(())
+        });
+    result.unwrap();
+}
+
+/// Calls `visit` for every chunk of the terrain described by `heightmap` at every level in `0..config.num_levels`. Voxels
+/// outside of the heightmap's footprint are ambient.
+///
+/// Coarse levels are sampled directly from the heightmap (at the centers of coarse voxels) rather than downsampled, so we
+/// never need to hold more than one chunk in memory. Voxels take their [`PaletteId8`] from the nearest pixel of `splat_map`,
+/// if provided.
+pub fn visit_heightmap_chunks<E>(
+    heightmap: &Heightmap,
+    splat_map: Option<&SplatMap>,
+    config: &HeightmapConfig,
+    mut visit: impl FnMut(ChunkDbKey, Chunk) -> Result<(), E>,
+) -> Result<(), E> {
+    for level in 0..config.num_levels {
+        visit_heightmap_chunks_at_level(heightmap, splat_map, config, level, &mut visit)?;
+    }
+    Ok(())
+}
+
+/// The number of voxel columns in one column of chunks.
+const COLUMNS_PER_CHUNK: usize = (ChunkShape::ARRAY[0] * ChunkShape::ARRAY[2]) as usize;
+
+fn visit_heightmap_chunks_at_level<E>(
+    heightmap: &Heightmap,
+    splat_map: Option<&SplatMap>,
+    config: &HeightmapConfig,
+    level: Level,
+    visit: &mut impl FnMut(ChunkDbKey, Chunk) -> Result<(), E>,
+) -> Result<(), E> {
+    let column_shape = IVec2::new(CHUNK_SHAPE_IVEC3.x, CHUNK_SHAPE_IVEC3.z);
+    let voxel_size = (1 << level) as f32;
+    // The LOD0 position of the center of a voxel at this level, relative to the voxel's minimum.
+    let center_offset = (voxel_size - 1.0) / 2.0;
+    let lod0_xz = |p: IVec2| (p << level as i32).as_vec2() + Vec2::splat(center_offset);
+
+    let footprint_min = config.offset;
+    let footprint_max =
+        config.offset + IVec2::new(heightmap.width as i32, heightmap.depth as i32) - IVec2::ONE;
+    let chunk_shift = CHUNK_SHAPE_LOG2_IVEC3.x + level as i32;
+    let column_extent =
+        Extent::from_min_and_max(footprint_min >> chunk_shift, footprint_max >> chunk_shift);
+
+    for chunk_column in column_extent.iter2() {
+        // Find the surface height and slope factor for every voxel column in this chunk column.
+        let mut columns = [None; COLUMNS_PER_CHUNK];
+        let mut max_height = f32::NEG_INFINITY;
+        for z in 0..column_shape.y {
+            for x in 0..column_shape.x {
+                let voxel_xz = (chunk_column << CHUNK_SHAPE_LOG2_IVEC3.x) + IVec2::new(x, z);
+                let lod0 = lod0_xz(voxel_xz);
+                let pixel = lod0 - config.offset.as_vec2();
+                if pixel.cmplt(Vec2::ZERO).any()
+                    || pixel.x > (heightmap.width - 1) as f32
+                    || pixel.y > (heightmap.depth - 1) as f32
+                {
+                    continue;
+                }
+
+                let height = |p: Vec2| {
+                    config.base_height + config.vertical_scale * heightmap.sample_bilinear(p)
+                };
+                let h = height(pixel);
+                let dx = (height(pixel + Vec2::X * voxel_size)
+                    - height(pixel - Vec2::X * voxel_size))
+                    / (2.0 * voxel_size);
+                let dz = (height(pixel + Vec2::Y * voxel_size)
+                    - height(pixel - Vec2::Y * voxel_size))
+                    / (2.0 * voxel_size);
+                // Vertical distance is scaled by the cosine of the slope to approximate the distance to the tangent plane.
+                let slope_factor = 1.0 / (1.0 + dx * dx + dz * dz).sqrt();
+                let palette_id = splat_map.map_or(config.palette_id, |s| s.sample_nearest(pixel));
+
+                columns[(z * column_shape.x + x) as usize] = Some((h, slope_factor, palette_id));
+                max_height = max_height.max(h);
+            }
+        }
+        if max_height == f32::NEG_INFINITY {
+            continue;
+        }
+
+        // Allocate every chunk from the base up to one voxel above the highest point.
+        let min_y = (config.base_height - voxel_size).floor() as i32 >> level;
+        let max_y = (max_height + voxel_size).ceil() as i32 >> level;
+        for chunk_y in (min_y >> CHUNK_SHAPE_LOG2_IVEC3.y)..=(max_y >> CHUNK_SHAPE_LOG2_IVEC3.y) {
+            let chunk_coords = IVec3::new(chunk_column.x, chunk_y, chunk_column.y);
+            let VoxelUnits(min) = chunk_min(ChunkUnits(chunk_coords));
+
+            let mut chunk = Chunk::default();
+            for p in Extent::from_min_and_shape(IVec3::ZERO, CHUNK_SHAPE_IVEC3).iter3() {
+                let (h, slope_factor, palette_id) =
+                    if let Some(column) = columns[(p.z * column_shape.x + p.x) as usize] {
+                        column
+                    } else {
+                        continue;
+                    };
+                let y = ((min.y + p.y) << level) as f32 + center_offset;
+                let to_surface = (y - h) * slope_factor;
+                let to_base = config.base_height - y;
+                // Intersection of the half spaces below the surface and above the base.
+                let distance = to_surface.max(to_base) / voxel_size;
+
+                let index = ChunkShape::linearize(p.to_array()) as usize;
+                chunk.sdf[index] = Sd8::from(distance);
+                chunk.palette_ids[index] = palette_id;
+            }
+
+            visit(ChunkDbKey::new(level, chunk_coords.into()), chunk)?;
+        }
+    }
+    Ok(())
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::CompressedChunk;
+    use crate::database::EncodedChanges;
+
+    fn decode(changes: EncodedChanges<CompressedChunk>) -> Vec<(ChunkDbKey, Chunk)> {
+        changes
+            .changes
+            .into_iter()
+            .map(|(key, change)| {
+                (
+                    ChunkDbKey::from_sled_key(&key),
+                    change.deserialize().unwrap_insert().decompress(),
+                )
+            })
+            .collect()
+    }
+
+    fn chunk_at(chunks: &[(ChunkDbKey, Chunk)], level: Level, coords: IVec3) -> &Chunk {
+        let key = ChunkDbKey::new(level, coords.into());
+        &chunks.iter().find(|(k, _)| *k == key).unwrap().1
+    }
+
+    fn sdf_at(chunk: &Chunk, p: IVec3) -> Sd8 {
+        chunk.sdf[ChunkShape::linearize(p.to_array()) as usize]
+    }
+
+    #[test]
+    fn flat_heightmap_all_levels() {
+        let heightmap = Heightmap::new(4, 4, vec![10; 16]).unwrap();
+        let config = HeightmapConfig {
+            num_levels: 2,
+            palette_id: 7,
+            ..Default::default()
+        };
+        let mut encoder = ChangeEncoder::default();
+        add_heightmap_changes(
+            &heightmap,
+            None,
+            &config,
+            ChunkCodec::default(),
+            &mut encoder,
+        );
+        let chunks = decode(encoder.encode());
+
+        // One voxel below the base lands in chunk -1 at both levels.
+        assert_eq!(chunks.len(), 4);
+        chunk_at(&chunks, 0, IVec3::new(0, -1, 0));
+        chunk_at(&chunks, 1, IVec3::new(0, -1, 0));
+
+        let lod0 = chunk_at(&chunks, 0, IVec3::ZERO);
+        assert_eq!(sdf_at(lod0, IVec3::new(0, 5, 0)), Sd8::MIN);
+        assert_eq!(sdf_at(lod0, IVec3::new(1, 10, 3)), Sd8::ZERO);
+        assert_eq!(sdf_at(lod0, IVec3::new(2, 11, 2)), Sd8::MAX);
+        // The base is also a surface.
+        assert_eq!(sdf_at(lod0, IVec3::new(0, 0, 0)), Sd8::ZERO);
+        // Outside of the heightmap's footprint.
+        assert_eq!(sdf_at(lod0, IVec3::new(4, 5, 0)), Sd8::MAX);
+        assert_eq!(lod0.palette_ids[0], 7);
+
+        // LOD1 voxel 5 is centered at LOD0 Y = 10.5.
+        let lod1 = chunk_at(&chunks, 1, IVec3::ZERO);
+        assert_eq!(sdf_at(lod1, IVec3::new(0, 5, 0)), Sd8::from(0.25));
+    }
+
+    #[test]
+    fn sloped_heightmap_uses_euclidean_distance() {
+        let samples = (0..8 * 8).map(|i| (i % 8) as u16).collect();
+        let heightmap = Heightmap::new(8, 8, samples).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        add_heightmap_changes(
+            &heightmap,
+            None,
+            &HeightmapConfig::default(),
+            ChunkCodec::default(),
+            &mut encoder,
+        );
+        let chunks = decode(encoder.encode());
+
+        let chunk = chunk_at(&chunks, 0, IVec3::ZERO);
+        assert_eq!(
+            sdf_at(chunk, IVec3::new(4, 5, 4)),
+            Sd8::from(1.0 / 2f32.sqrt())
+        );
+    }
+
+    #[test]
+    fn splat_map_sets_palette_ids() {
+        let heightmap = Heightmap::new(2, 1, vec![3, 3]).unwrap();
+        let splat_map = SplatMap::new(2, 1, vec![1, 2]).unwrap();
+        let mut encoder = ChangeEncoder::default();
+        add_heightmap_changes(
+            &heightmap,
+            Some(&splat_map),
+            &HeightmapConfig::default(),
+            ChunkCodec::default(),
+            &mut encoder,
+        );
+        let chunks = decode(encoder.encode());
+        let chunk = chunk_at(&chunks, 0, IVec3::ZERO);
+        let id_at = |p: IVec3| chunk.palette_ids[ChunkShape::linearize(p.to_array()) as usize];
+        assert_eq!(id_at(IVec3::new(0, 2, 0)), 1);
+        assert_eq!(id_at(IVec3::new(1, 2, 0)), 2);
+    }
+
+    #[test]
+    fn read_pgm_and_r16() {
+        let mut pgm = b"P5\n# comment\n2 1\n65535\n".to_vec();
+        pgm.extend_from_slice(&[0x01, 0x02, 0xff, 0xff]);
+        let heightmap = Heightmap::read_pgm(pgm.as_slice()).unwrap();
+        assert_eq!((heightmap.width(), heightmap.depth()), (2, 1));
+        assert_eq!(heightmap.get(0, 0), 0x0102);
+        assert_eq!(heightmap.get(1, 0), 0xffff);
+
+        let r16 = [0x02, 0x01, 0xff, 0xff];
+        let heightmap = Heightmap::read_r16(r16.as_slice(), 2, 1).unwrap();
+        assert_eq!(heightmap.get(0, 0), 0x0102);
+
+        assert!(matches!(
+            Heightmap::read_r16(r16.as_slice(), 3, 1),
+            Err(HeightmapError::WrongSampleCount {
+                expected: 3,
+                actual: 2
+            })
+        ));
+    }
+}
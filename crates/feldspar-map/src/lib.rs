@@ -11,19 +11,26 @@
 //! A signed distance field (SDF) determines the terrain geometry. The maximum distance value (one voxel edge length) at LOD0 is
 //! approximately 1 meter. SDF values ([`Sd8`](crate::Sd8)) have 8-bit precision at all LODs. This implies that the minimum
 //! signed distance value at LOD0 is `1 / 2^8` meters. SDF voxels can be downsampled for LOD purposes. LZ4 compression is
-//! effective on SDF voxel chunks.
+//! effective on SDF voxel chunks, and a map can choose another [`ChunkCodec`](crate::codec::ChunkCodec) for the chunks it stores.
+//! See `benches/chunk_codecs.rs` for how they compare.
+//!
+//! Gentle slopes can show banding at 8 bits, so a map can instead be created with a [`ChunkLayout`](crate::chunk::ChunkLayout)
+//! that stores [`Sd16`](crate::sdf::Sd16) values. See `benches/sdf_precision.rs` for the memory and compression costs.
 //!
 //! ## Material Voxels
 //!
 //! A voxel's [`PaletteId8`](crate::PaletteId8) is used to look up arbitrary attributes about a voxel via a `Palette8`. Only 256
-//! materials are supported in a single map. The attributes often consist of textures and physical properties like chemical
-//! makeup.
+//! materials are supported in a single map, unless it uses a [`ChunkLayout`](crate::chunk::ChunkLayout) with 16-bit palette
+//! IDs. The attributes often consist of textures and physical properties like chemical
+//! makeup. Each map version stores a [`MaterialRegistry`](crate::material_registry::MaterialRegistry) that names these
+//! materials.
 //!
 //! ## Tile Voxels
 //!
 //! During the process of procedural generation, it can be useful to think of entire chunks as "tiles." In this way, data can be
 //! shared between multiple instances of a tile. When a chunk is edited, it needs to copy the original tile's chunk before
-//! modification. This introduces another layer of indirection for reads as well.
+//! modification. This introduces another layer of indirection for reads as well. See
+//! [`MapDb::instance_tile`](crate::database::MapDb::instance_tile) and [`Tile`](crate::chunk::Tile).
 //!
 //! # Database
 //!
@@ -32,6 +39,9 @@
 //! version being modified. By the structure of the version tree and transitivity, every version is reachable from the current
 //! one.
 //!
+//! The database is generic over its [`MapStore`](crate::database::MapStore). [`sled`] is the default store, and there is also an
+//! in-memory [`MemStore`](crate::database::MemStore) for tests.
+//!
 //! # Multiresolution Streaming
 //!
 //! All voxel chunks in "observable range" are stored in the [`ChunkClipMap`] in either their raw or compressed representation.
@@ -57,10 +67,16 @@
 //! other Bevy ECS systems to both edit and query the currently loaded map without having to worry about the details of
 //! streaming data and managing transactions.
 
+pub mod brush;
 pub mod chunk;
 pub mod clipmap;
+pub mod codec;
 pub mod coordinates;
+pub mod csg;
 pub mod database;
+pub mod heightmap;
+pub mod material_registry;
+pub mod mesh_import;
 pub mod ndview;
 pub mod palette;
 pub mod sampling;
@@ -78,3 +94,5 @@ pub use plugin::*;
 pub use bevy;
 
 pub use feldspar_core as core;
+pub use ndshape;
+pub use sled;
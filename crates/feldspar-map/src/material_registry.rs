@@ -0,0 +1,278 @@
+use crate::core::rkyv::{Archive, Deserialize, Serialize};
+use crate::palette::{Palette8, PaletteId8};
+use crate::voxel_attributes::VoxelAttributes;
+
+use bytecheck::CheckBytes;
+
+/// One named entry of a [`MaterialRegistry`].
+#[derive(
+    Archive,
+    Clone,
+    Debug,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Serialize,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
+pub struct MaterialEntry {
+    pub name: String,
+    pub attributes: VoxelAttributes,
+    /// Retired entries are kept so that voxels still referencing them look the same, but they shouldn't be used for new edits.
+    pub retired: bool,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum MaterialRegistryError {
+    /// All 256 [`PaletteId8`]s are taken. Retired entries still take up an ID.
+    Full,
+    DuplicateName,
+    UnknownId(PaletteId8),
+}
+
+/// The named materials of a map, indexed by [`PaletteId8`].
+///
+/// A [`MapDb`](crate::database::MapDb) stores one registry per version. Since chunks only store [`PaletteId8`]s, attributes and
+/// names can change without rewriting any chunks. IDs are never reused, so entries are retired rather than removed.
+#[derive(
+    Archive,
+    Clone,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Serialize,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
+pub struct MaterialRegistry {
+    entries: Vec<MaterialEntry>,
+}
+
+impl MaterialRegistry {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: PaletteId8) -> Option<&MaterialEntry> {
+        self.entries.get(id as usize)
+    }
+
+    /// Finds the entry called `name`, including retired entries.
+    pub fn find(&self, name: &str) -> Option<PaletteId8> {
+        self.entries
+            .iter()
+            .position(|e| e.name == name)
+            .map(|i| i as PaletteId8)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PaletteId8, &MaterialEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (i as PaletteId8, e))
+    }
+
+    /// Adds a new entry with the next unused ID.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        attributes: VoxelAttributes,
+    ) -> Result<PaletteId8, MaterialRegistryError> {
+        let name = name.into();
+        if self.find(&name).is_some() {
+            return Err(MaterialRegistryError::DuplicateName);
+        }
+        if self.entries.len() == 256 {
+            return Err(MaterialRegistryError::Full);
+        }
+        self.entries.push(MaterialEntry {
+            name,
+            attributes,
+            retired: false,
+        });
+        Ok((self.entries.len() - 1) as PaletteId8)
+    }
+
+    pub fn rename(
+        &mut self,
+        id: PaletteId8,
+        name: impl Into<String>,
+    ) -> Result<(), MaterialRegistryError> {
+        let name = name.into();
+        match self.find(&name) {
+            Some(other) if other != id => return Err(MaterialRegistryError::DuplicateName),
+            _ => {}
+        }
+        self.entry_mut(id)?.name = name;
+        Ok(())
+    }
+
+    pub fn set_attributes(
+        &mut self,
+        id: PaletteId8,
+        attributes: VoxelAttributes,
+    ) -> Result<(), MaterialRegistryError> {
+        self.entry_mut(id)?.attributes = attributes;
+        Ok(())
+    }
+
+    /// Marks the entry as retired. Its ID and attributes stay valid for existing voxels.
+    pub fn retire(&mut self, id: PaletteId8) -> Result<(), MaterialRegistryError> {
+        self.entry_mut(id)?.retired = true;
+        Ok(())
+    }
+
+    /// The attributes of every entry, including retired ones.
+    pub fn palette(&self) -> Palette8<VoxelAttributes> {
+        Palette8::new(self.entries.iter().map(|e| e.attributes).collect())
+    }
+
+    /// Moves each entry `i` to `mapping[i]`, as if every voxel's ID was remapped the same way.
+    ///
+    /// When several entries map to the same ID, the one with the lowest old ID where `is_used` is `true` is kept, or else the one
+    /// with the lowest old ID. Entries for unused IDs only take up a new ID if no used entry maps there. IDs that no entry maps to,
+    /// but that are below the highest new ID, get retired placeholder entries.
+    pub fn remap(&self, mapping: &[PaletteId8; 256], is_used: impl Fn(PaletteId8) -> bool) -> Self {
+        let mut new_entries: Vec<Option<(MaterialEntry, bool)>> = Vec::new();
+        for (old_id, entry) in self.iter() {
+            let new_id = mapping[old_id as usize] as usize;
+            let used = is_used(old_id);
+            if new_entries.len() <= new_id {
+                new_entries.resize(new_id + 1, None);
+            }
+            match &new_entries[new_id] {
+                Some((_, true)) => {}
+                Some((_, false)) if !used => {}
+                _ => new_entries[new_id] = Some((entry.clone(), used)),
+            }
+        }
+
+        let mut remapped = Self::default();
+        let mut holes = Vec::new();
+        for (id, entry) in new_entries.into_iter().enumerate() {
+            remapped
+                .entries
+                .push(entry.map(|(e, _)| e).unwrap_or_else(|| {
+                    holes.push(id);
+                    MaterialEntry {
+                        name: String::new(),
+                        attributes: VoxelAttributes::default(),
+                        retired: true,
+                    }
+                }));
+        }
+        for id in holes {
+            remapped.entries[id].name = remapped.unique_placeholder_name(id as PaletteId8);
+        }
+        remapped
+    }
+
+    fn unique_placeholder_name(&self, id: PaletteId8) -> String {
+        let mut name = format!("unused-{}", id);
+        while self.find(&name).is_some() {
+            name.push('_');
+        }
+        name
+    }
+
+    fn entry_mut(&mut self, id: PaletteId8) -> Result<&mut MaterialEntry, MaterialRegistryError> {
+        self.entries
+            .get_mut(id as usize)
+            .ok_or(MaterialRegistryError::UnknownId(id))
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_attributes::MaterialId;
+
+    fn attributes(material: u8) -> VoxelAttributes {
+        VoxelAttributes {
+            is_collidable: true,
+            material_id: MaterialId(material),
+        }
+    }
+
+    #[test]
+    fn add_rename_and_retire() {
+        let mut registry = MaterialRegistry::default();
+        let stone = registry.add("stone", attributes(0)).unwrap();
+        let dirt = registry.add("dirt", attributes(1)).unwrap();
+        assert_eq!((stone, dirt), (0, 1));
+        assert_eq!(
+            registry.add("stone", attributes(2)),
+            Err(MaterialRegistryError::DuplicateName)
+        );
+
+        assert_eq!(
+            registry.rename(dirt, "stone"),
+            Err(MaterialRegistryError::DuplicateName)
+        );
+        registry.rename(dirt, "mud").unwrap();
+        assert_eq!(registry.find("mud"), Some(dirt));
+        assert_eq!(registry.find("dirt"), None);
+
+        registry.retire(stone).unwrap();
+        assert!(registry.get(stone).unwrap().retired);
+        assert_eq!(registry.retire(5), Err(MaterialRegistryError::UnknownId(5)));
+
+        // Retired IDs are not reused.
+        assert_eq!(registry.add("sand", attributes(3)), Ok(2));
+
+        let palette = registry.palette();
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette[stone], attributes(0));
+    }
+
+    #[test]
+    fn remap_merges_and_fills_holes() {
+        let mut registry = MaterialRegistry::default();
+        for name in ["a", "b", "c"] {
+            registry.add(name, attributes(0)).unwrap();
+        }
+        registry.add("d", attributes(0)).unwrap();
+        let mut mapping = [0; 256];
+        mapping[0] = 3;
+        mapping[1] = 1;
+        mapping[2] = 1;
+        mapping[3] = 3;
+
+        // "d" is used, so it wins over "a".
+        let remapped = registry.remap(&mapping, |id| id == 3);
+        let names: Vec<_> = remapped.iter().map(|(_, e)| e.name.as_str()).collect();
+        assert_eq!(names, ["unused-0", "b", "unused-2", "d"]);
+        assert!(remapped.get(0).unwrap().retired);
+        assert!(!remapped.get(3).unwrap().retired);
+    }
+
+    #[test]
+    fn full_registry() {
+        let mut registry = MaterialRegistry::default();
+        for i in 0..256 {
+            registry.add(i.to_string(), attributes(0)).unwrap();
+        }
+        assert_eq!(
+            registry.add("one too many", attributes(0)),
+            Err(MaterialRegistryError::Full)
+        );
+    }
+}
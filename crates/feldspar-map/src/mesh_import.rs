@@ -0,0 +1,436 @@
+//! Voxelization of triangle meshes into SDF [`Chunk`]s.
+//!
+//! Meshes are read from OBJ or binary STL files into a [`TriangleMesh`], then [`voxelize_mesh`] samples the signed distance to
+//! the mesh at every voxel in the mesh's bounding box:
+//!
+//! - The unsigned distance is only computed in a narrow band of one voxel around each triangle, since [`Sd8`] saturates there.
+//! - The sign comes from casting rays along +X through each row of voxels and counting the triangles they cross, either by
+//!   winding number or by parity. See [`SignMode`].
+//!
+//! The resulting chunks act as a brush that is combined into the map with a [`CsgOp`].
+
+mod obj;
+mod stl;
+
+pub use obj::read_obj;
+pub use stl::read_stl;
+
+use crate::chunk::{Chunk, ChunkShape};
+use crate::clipmap::Level;
+use crate::coordinates::{chunk_extent_ivec3, in_chunk_extent};
+use crate::core::glam::{IVec2, IVec3, Vec3A};
+use crate::core::ilattice::prelude::Extent;
+use crate::core::SmallKeyHashMap;
+use crate::csg::CsgOp;
+use crate::palette::PaletteId8;
+use crate::sdf::Sd8;
+use crate::units::*;
+
+use ndshape::ConstShape;
+use std::io;
+
+#[derive(Debug)]
+pub enum MeshImportError {
+    Io(io::Error),
+    /// A line of an OBJ file could not be parsed. Lines are numbered from 1.
+    Malformed {
+        line: usize,
+    },
+    /// A binary STL file ended in the middle of the triangle with this index, or in the header if `None`.
+    TruncatedStl {
+        triangle: Option<usize>,
+    },
+    /// A face references a vertex that doesn't exist.
+    InvalidIndex {
+        line: usize,
+    },
+}
+
+impl From<io::Error> for MeshImportError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3A>,
+    pub triangles: Vec<[u32; 3]>,
+    /// The index into `group_names` for each triangle.
+    pub triangle_groups: Vec<u32>,
+    /// For OBJ files, these are the material names from `usemtl`. Triangles without a material are in a group named `""`.
+    pub group_names: Vec<String>,
+}
+
+impl TriangleMesh {
+    pub fn triangle_positions(&self, triangle: usize) -> [Vec3A; 3] {
+        self.triangles[triangle].map(|i| self.positions[i as usize])
+    }
+}
+
+/// How to decide whether a voxel is inside of a mesh.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum SignMode {
+    /// Inside if the ray crosses more front faces than back faces. This is robust to overlapping, self-intersecting parts but
+    /// requires consistent counter-clockwise winding.
+    WindingNumber,
+    /// Inside if the ray crosses an odd number of triangles. This ignores winding order but fails for overlapping parts.
+    RayParity,
+}
+
+#[derive(Clone, Debug)]
+pub struct VoxelizeConfig {
+    /// Mesh positions are scaled by this factor, then translated by `translation`, to get LOD0 voxel coordinates.
+    pub scale: f32,
+    pub translation: Vec3A,
+    pub level: Level,
+    pub sign_mode: SignMode,
+    /// The [`PaletteId8`] for each group in [`TriangleMesh::group_names`]. Groups without an entry use `default_palette_id`.
+    pub group_palette_ids: Vec<PaletteId8>,
+    /// Also used for interior voxels that are farther than one voxel from the surface.
+    pub default_palette_id: PaletteId8,
+    pub csg_op: CsgOp,
+}
+
+impl Default for VoxelizeConfig {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            translation: Vec3A::ZERO,
+            level: 0,
+            sign_mode: SignMode::WindingNumber,
+            group_palette_ids: Vec::new(),
+            default_palette_id: 0,
+            csg_op: CsgOp::Union,
+        }
+    }
+}
+
+/// Samples the signed distance to `mesh` in every voxel of its bounding box (plus one voxel of padding) at `config.level`.
+///
+/// Voxels outside of the bounding box are left at the ambient value, so the chunks are suitable as a brush for
+/// [`CsgOp::apply`]. The sample for voxel `p` at level `L` is at the center of the voxel's LOD0 footprint.
+pub fn voxelize_mesh(
+    mesh: &TriangleMesh,
+    config: &VoxelizeConfig,
+) -> SmallKeyHashMap<ChunkUnits<IVec3>, Chunk> {
+    let mut chunks = SmallKeyHashMap::default();
+    if mesh.triangles.is_empty() {
+        return chunks;
+    }
+
+    // Transform into voxel coordinates at the target level, where voxel samples are at integer coordinates.
+    let voxel_size = (1 << config.level) as f32;
+    let center_offset = (voxel_size - 1.0) / 2.0;
+    let positions: Vec<Vec3A> = mesh
+        .positions
+        .iter()
+        .map(|&p| {
+            (p * config.scale + config.translation - Vec3A::splat(center_offset)) / voxel_size
+        })
+        .collect();
+    let triangle = |t: usize| mesh.triangles[t].map(|i| positions[i as usize]);
+
+    let (min, max) = positions.iter().fold(
+        (Vec3A::splat(f32::INFINITY), Vec3A::splat(f32::NEG_INFINITY)),
+        |(min, max), &p| (min.min(p), max.max(p)),
+    );
+    let bounds = Extent::from_min_and_max(
+        min.floor().as_ivec3() - IVec3::ONE,
+        max.ceil().as_ivec3() + IVec3::ONE,
+    );
+
+    // Unsigned distance and nearest triangle group in the narrow band.
+    let mut band: SmallKeyHashMap<IVec3, (f32, u32)> = SmallKeyHashMap::default();
+    for t in 0..mesh.triangles.len() {
+        let [a, b, c] = triangle(t);
+        let tri_bounds = Extent::from_min_and_max(
+            (a.min(b).min(c) - Vec3A::ONE).ceil().as_ivec3(),
+            (a.max(b).max(c) + Vec3A::ONE).floor().as_ivec3(),
+        );
+        for p in tri_bounds.iter3() {
+            let distance = p
+                .as_vec3a()
+                .distance(closest_point_on_triangle(p.as_vec3a(), a, b, c));
+            if distance >= 1.0 {
+                continue;
+            }
+            let group = mesh.triangle_groups.get(t).copied().unwrap_or(0);
+            let entry = band.entry(p).or_insert((f32::INFINITY, group));
+            if distance < entry.0 {
+                *entry = (distance, group);
+            }
+        }
+    }
+
+    // Ray crossings for each row of voxels along +X, keyed by (Y, Z).
+    let mut crossings: SmallKeyHashMap<IVec2, Vec<(f32, i32)>> = SmallKeyHashMap::default();
+    for t in 0..mesh.triangles.len() {
+        let [a, b, c] = triangle(t);
+        let min_yz = IVec2::new(
+            a.y.min(b.y).min(c.y).ceil() as i32,
+            a.z.min(b.z).min(c.z).ceil() as i32,
+        );
+        let max_yz = IVec2::new(
+            a.y.max(b.y).max(c.y).floor() as i32,
+            a.z.max(b.z).max(c.z).floor() as i32,
+        );
+        for z in min_yz.y..=max_yz.y {
+            for y in min_yz.x..=max_yz.x {
+                if let Some(crossing) = ray_crossing(y as f32, z as f32, a, b, c) {
+                    crossings
+                        .entry(IVec2::new(y, z))
+                        .or_default()
+                        .push(crossing);
+                }
+            }
+        }
+    }
+    for row in crossings.values_mut() {
+        row.sort_by(|(x1, _), (x2, _)| x1.total_cmp(x2));
+    }
+
+    let palette_id_for_group = |group: u32| {
+        config
+            .group_palette_ids
+            .get(group as usize)
+            .copied()
+            .unwrap_or(config.default_palette_id)
+    };
+
+    let ChunkUnits(chunk_extent) = in_chunk_extent(VoxelUnits(bounds));
+    for chunk_coords in chunk_extent.iter3() {
+        let chunk_coords = ChunkUnits(chunk_coords);
+        let VoxelUnits(this_chunk_extent) = chunk_extent_ivec3(chunk_coords);
+        let sample_extent = this_chunk_extent.intersection(&bounds);
+        let chunk: &mut Chunk = chunks.entry(chunk_coords).or_default();
+
+        let min = sample_extent.minimum;
+        let max = sample_extent.max();
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                let row = crossings
+                    .get(&IVec2::new(y, z))
+                    .map_or(&[][..], |r| r.as_slice());
+                let mut next_crossing = 0;
+                let mut winding = 0;
+                let mut num_crossed = 0;
+                for x in min.x..=max.x {
+                    while next_crossing < row.len() && row[next_crossing].0 < x as f32 {
+                        winding += row[next_crossing].1;
+                        num_crossed += 1;
+                        next_crossing += 1;
+                    }
+                    let inside = match config.sign_mode {
+                        SignMode::WindingNumber => winding != 0,
+                        SignMode::RayParity => num_crossed % 2 == 1,
+                    };
+
+                    let p = IVec3::new(x, y, z);
+                    let (distance, palette_id) = match band.get(&p) {
+                        Some(&(distance, group)) => (distance, palette_id_for_group(group)),
+                        None => (1.0, config.default_palette_id),
+                    };
+                    let index =
+                        ChunkShape::linearize((p - this_chunk_extent.minimum).to_array()) as usize;
+                    if inside {
+                        chunk.sdf[index] = Sd8::from(-distance);
+                        chunk.palette_ids[index] = palette_id;
+                    } else {
+                        chunk.sdf[index] = Sd8::from(distance);
+                        // Outside voxels near the surface also get the mesh's material so that it blends in when meshing.
+                        if distance < 1.0 {
+                            chunk.palette_ids[index] = palette_id;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    chunks
+}
+
+/// If the ray starting at `(-∞, y, z)` in direction +X crosses triangle `abc`, returns the X coordinate of the crossing and
+/// `+1` if the ray enters the front side of the triangle (`-1` otherwise).
+fn ray_crossing(y: f32, z: f32, a: Vec3A, b: Vec3A, c: Vec3A) -> Option<(f32, i32)> {
+    // Nudge the ray by an amount much smaller than a voxel so that it doesn't pass exactly through shared edges and vertices,
+    // which would count crossings twice or not at all.
+    let y = y + 1.2345e-4;
+    let z = z + 2.3456e-4;
+
+    // Twice the signed area of the triangle projected onto the YZ plane, which is also the X component of its normal.
+    let normal_x = (b.y - a.y) * (c.z - a.z) - (c.y - a.y) * (b.z - a.z);
+    if normal_x == 0.0 {
+        return None;
+    }
+    let wb = ((y - a.y) * (c.z - a.z) - (c.y - a.y) * (z - a.z)) / normal_x;
+    let wc = ((b.y - a.y) * (z - a.z) - (y - a.y) * (b.z - a.z)) / normal_x;
+    let wa = 1.0 - wb - wc;
+    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+        return None;
+    }
+    let x = wa * a.x + wb * b.x + wc * c.x;
+    // A front face with a normal pointing toward -X is where the ray enters the solid.
+    Some((x, if normal_x < 0.0 { 1 } else { -1 }))
+}
+
+/// From "Real-Time Collision Detection" by Christer Ericson, section 5.1.5.
+fn closest_point_on_triangle(p: Vec3A, a: Vec3A, b: Vec3A, c: Vec3A) -> Vec3A {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An axis-aligned cube from `min` to `max` with counter-clockwise winding.
+    fn cube_mesh(min: Vec3A, max: Vec3A) -> TriangleMesh {
+        let positions = (0..8)
+            .map(|i| {
+                Vec3A::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                )
+            })
+            .collect();
+        let quads = [
+            [0, 4, 6, 2], // -X
+            [1, 3, 7, 5], // +X
+            [0, 1, 5, 4], // -Y
+            [2, 6, 7, 3], // +Y
+            [0, 2, 3, 1], // -Z
+            [4, 5, 7, 6], // +Z
+        ];
+        let triangles: Vec<_> = quads
+            .iter()
+            .flat_map(|[a, b, c, d]| [[*a, *b, *c], [*a, *c, *d]])
+            .collect();
+        TriangleMesh {
+            positions,
+            triangle_groups: vec![0; triangles.len()],
+            triangles,
+            group_names: vec![String::new()],
+        }
+    }
+
+    fn sdf_at(chunks: &SmallKeyHashMap<ChunkUnits<IVec3>, Chunk>, p: IVec3) -> Sd8 {
+        let coords = crate::coordinates::in_chunk(VoxelUnits(p));
+        let VoxelUnits(extent) = chunk_extent_ivec3(coords);
+        chunks[&coords].sdf[ChunkShape::linearize((p - extent.minimum).to_array()) as usize]
+    }
+
+    #[test]
+    fn voxelize_cube() {
+        let mesh = cube_mesh(Vec3A::splat(2.5), Vec3A::splat(9.5));
+        for sign_mode in [SignMode::WindingNumber, SignMode::RayParity] {
+            let config = VoxelizeConfig {
+                sign_mode,
+                ..Default::default()
+            };
+            let chunks = voxelize_mesh(&mesh, &config);
+            assert_eq!(chunks.len(), 1);
+
+            assert_eq!(sdf_at(&chunks, IVec3::splat(6)), Sd8::MIN);
+            assert_eq!(sdf_at(&chunks, IVec3::new(3, 6, 6)), Sd8::from(-0.5));
+            assert_eq!(sdf_at(&chunks, IVec3::new(2, 6, 6)), Sd8::from(0.5));
+            assert_eq!(sdf_at(&chunks, IVec3::new(1, 6, 6)), Sd8::MAX);
+            assert_eq!(
+                sdf_at(&chunks, IVec3::new(10, 10, 10)),
+                Sd8::from(0.75f32.sqrt())
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_parts() {
+        let mut mesh = cube_mesh(Vec3A::splat(2.5), Vec3A::splat(9.5));
+        let other = cube_mesh(Vec3A::new(6.5, 2.5, 2.5), Vec3A::new(13.5, 9.5, 9.5));
+        let first = mesh.positions.len() as u32;
+        mesh.positions.extend(other.positions);
+        mesh.triangles
+            .extend(other.triangles.iter().map(|t| t.map(|i| i + first)));
+        mesh.triangle_groups.extend(other.triangle_groups);
+
+        let overlap = IVec3::new(8, 6, 6);
+        let winding = voxelize_mesh(&mesh, &VoxelizeConfig::default());
+        assert_eq!(sdf_at(&winding, overlap), Sd8::MIN);
+
+        let parity = voxelize_mesh(
+            &mesh,
+            &VoxelizeConfig {
+                sign_mode: SignMode::RayParity,
+                ..Default::default()
+            },
+        );
+        assert_eq!(sdf_at(&parity, overlap), Sd8::MAX);
+    }
+
+    #[test]
+    fn closest_point_regions() {
+        let [a, b, c] = [Vec3A::ZERO, Vec3A::X, Vec3A::Y];
+        assert_eq!(
+            closest_point_on_triangle(Vec3A::new(-1.0, -1.0, 0.0), a, b, c),
+            a
+        );
+        assert_eq!(
+            closest_point_on_triangle(Vec3A::new(0.5, -1.0, 0.0), a, b, c),
+            Vec3A::new(0.5, 0.0, 0.0)
+        );
+        assert_eq!(
+            closest_point_on_triangle(Vec3A::new(0.25, 0.25, 3.0), a, b, c),
+            Vec3A::new(0.25, 0.25, 0.0)
+        );
+        assert_eq!(
+            closest_point_on_triangle(Vec3A::new(1.0, 1.0, 0.0), a, b, c),
+            Vec3A::new(0.5, 0.5, 0.0)
+        );
+    }
+}
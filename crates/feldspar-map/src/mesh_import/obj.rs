@@ -0,0 +1,133 @@
+use super::{MeshImportError, TriangleMesh};
+use crate::core::glam::Vec3A;
+use crate::core::SmallKeyHashMap;
+
+use std::io::BufRead;
+
+/// Reads vertex positions and faces from a Wavefront OBJ file.
+///
+/// Polygons are triangulated as fans. Each `usemtl` material becomes a group in [`TriangleMesh::group_names`]. Normals, texture
+/// coordinates, and all other statements are ignored.
+pub fn read_obj(reader: impl BufRead) -> Result<TriangleMesh, MeshImportError> {
+    let mut mesh = TriangleMesh::default();
+    let mut group_ids: SmallKeyHashMap<String, u32> = SmallKeyHashMap::default();
+    let mut current_group = None;
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = line_index + 1;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut coords = [0.0; 3];
+                for c in coords.iter_mut() {
+                    *c = tokens
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or(MeshImportError::Malformed { line: line_number })?;
+                }
+                mesh.positions.push(Vec3A::from(coords));
+            }
+            Some("f") => {
+                let num_positions = mesh.positions.len();
+                let indices = tokens
+                    .map(|t| parse_face_index(t, num_positions, line_number))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if indices.len() < 3 {
+                    return Err(MeshImportError::Malformed { line: line_number });
+                }
+                let group = *current_group
+                    .get_or_insert_with(|| group_id(&mut mesh.group_names, &mut group_ids, ""));
+                for i in 1..indices.len() - 1 {
+                    mesh.triangles
+                        .push([indices[0], indices[i], indices[i + 1]]);
+                    mesh.triangle_groups.push(group);
+                }
+            }
+            Some("usemtl") => {
+                let name = tokens.next().unwrap_or("");
+                current_group = Some(group_id(&mut mesh.group_names, &mut group_ids, name));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(mesh)
+}
+
+fn group_id(
+    group_names: &mut Vec<String>,
+    group_ids: &mut SmallKeyHashMap<String, u32>,
+    name: &str,
+) -> u32 {
+    *group_ids.entry(name.to_owned()).or_insert_with(|| {
+        group_names.push(name.to_owned());
+        group_names.len() as u32 - 1
+    })
+}
+
+/// Parses the position index from a face vertex like `i`, `i/t`, `i//n`, or `i/t/n`. Negative indices are relative to the end of
+/// the vertex list.
+fn parse_face_index(
+    token: &str,
+    num_positions: usize,
+    line: usize,
+) -> Result<u32, MeshImportError> {
+    let index: i64 = token
+        .split('/')
+        .next()
+        .and_then(|i| i.parse().ok())
+        .ok_or(MeshImportError::Malformed { line })?;
+    let resolved = if index < 0 {
+        num_positions as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= num_positions as i64 {
+        return Err(MeshImportError::InvalidIndex { line });
+    }
+    Ok(resolved as u32)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_quads_with_materials() {
+        let obj = "\
+# comment
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+f 1 2 3
+usemtl stone
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl grass
+f -4//1 -2//1 -1//1
+";
+        let mesh = read_obj(obj.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.group_names, ["", "stone", "grass"]);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 1, 2], [0, 2, 3], [0, 2, 3]]);
+        assert_eq!(mesh.triangle_groups, [0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let obj = "v 0 0 0\nf 1 2 3\n";
+        assert!(matches!(
+            read_obj(obj.as_bytes()),
+            Err(MeshImportError::InvalidIndex { line: 2 })
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use super::{MeshImportError, TriangleMesh};
+use crate::core::glam::Vec3A;
+
+use std::io::{self, Read};
+
+/// Reads a binary STL file. ASCII STL is not supported.
+///
+/// Vertices are not deduplicated, so every triangle has its own three positions. All triangles are in a single group named
+/// `""`.
+pub fn read_stl(mut reader: impl Read) -> Result<TriangleMesh, MeshImportError> {
+    let mut header = [0; 84];
+    read_exact(&mut reader, &mut header, None)?;
+    let num_triangles = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+
+    let mut mesh = TriangleMesh {
+        group_names: vec![String::new()],
+        ..Default::default()
+    };
+    let mut record = [0; 50];
+    for t in 0..num_triangles {
+        read_exact(&mut reader, &mut record, Some(t))?;
+        // Skip the 12-byte facet normal. We only rely on the vertex winding.
+        for v in 0..3 {
+            let offset = 12 + 12 * v;
+            let f = |i: usize| {
+                f32::from_le_bytes(
+                    record[offset + 4 * i..offset + 4 * i + 4]
+                        .try_into()
+                        .unwrap(),
+                )
+            };
+            mesh.positions.push(Vec3A::new(f(0), f(1), f(2)));
+        }
+        let first = 3 * t as u32;
+        mesh.triangles.push([first, first + 1, first + 2]);
+        mesh.triangle_groups.push(0);
+    }
+
+    Ok(mesh)
+}
+
+fn read_exact(
+    reader: &mut impl Read,
+    buf: &mut [u8],
+    triangle: Option<usize>,
+) -> Result<(), MeshImportError> {
+    reader.read_exact(buf).map_err(|e| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            MeshImportError::TruncatedStl { triangle }
+        } else {
+            MeshImportError::Io(e)
+        }
+    })
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_one_triangle() {
+        let mut bytes = vec![0; 80];
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for f in [
+            0.0f32, 0.0, 1.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0,
+        ] {
+            bytes.extend_from_slice(&f.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 2]);
+
+        let mesh = read_stl(bytes.as_slice()).unwrap();
+        assert_eq!(
+            mesh.positions,
+            [
+                Vec3A::new(1.0, 2.0, 3.0),
+                Vec3A::new(4.0, 5.0, 6.0),
+                Vec3A::new(7.0, 8.0, 9.0)
+            ]
+        );
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = vec![0; 80];
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 50]);
+        assert!(matches!(
+            read_stl(bytes.as_slice()),
+            Err(MeshImportError::TruncatedStl { triangle: Some(1) })
+        ));
+        assert!(matches!(
+            read_stl(&bytes[..40]),
+            Err(MeshImportError::TruncatedStl { triangle: None })
+        ));
+    }
+}
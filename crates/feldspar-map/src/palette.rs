@@ -3,10 +3,18 @@ use std::ops::{Index, IndexMut};
 /// An identifier for one of the values in a given [`Palette8`].
 pub type PaletteId8 = u8;
 
+/// A palette ID for maps with the [`ChunkLayout::Palette16`](crate::chunk::ChunkLayout::Palette16) layout.
+pub type PaletteId16 = u16;
+
 /// A mapping from [`PaletteId8`] to type `T`. This can store up to 256 values.
 ///
 /// Use [`Index`] and [`IndexMut`] traits for access.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "Vec<T>", into = "Vec<T>")]
+#[serde(bound(
+    serialize = "T: Clone + serde::Serialize",
+    deserialize = "T: serde::Deserialize<'de>"
+))]
 pub struct Palette8<T> {
     types: Vec<T>,
 }
@@ -26,3 +34,48 @@ impl<T> IndexMut<PaletteId8> for Palette8<T> {
         self.types.index_mut(id as usize)
     }
 }
+
+impl<T> Palette8<T> {
+    /// # Panics
+    ///
+    /// If there are more than 256 `types`.
+    pub fn new(types: Vec<T>) -> Self {
+        assert!(types.len() <= 256, "Palette8 can hold at most 256 types");
+        Self { types }
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PaletteId8, &T)> {
+        self.types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (i as PaletteId8, t))
+    }
+}
+
+impl<T> TryFrom<Vec<T>> for Palette8<T> {
+    type Error = String;
+
+    fn try_from(types: Vec<T>) -> Result<Self, Self::Error> {
+        if types.len() > 256 {
+            return Err(format!(
+                "Palette8 can hold at most 256 types, got {}",
+                types.len()
+            ));
+        }
+        Ok(Self { types })
+    }
+}
+
+impl<T> From<Palette8<T>> for Vec<T> {
+    fn from(palette: Palette8<T>) -> Self {
+        palette.types
+    }
+}
@@ -1,19 +1,21 @@
 mod config;
 mod loader;
+mod materials;
 mod witness;
 
-use std::sync::Arc;
 pub use config::MapConfig;
-pub use loader::LoaderConfig;
+pub use loader::{LoaderConfig, ReloadMap};
 pub use witness::Witness;
 
-use loader::loader_system;
+use loader::{loader_system, reload_system};
+use materials::material_palette_system;
 use witness::witness_system;
 
-use bevy::prelude::{Commands, CoreStage, Plugin, Res};
+use bevy::prelude::{Commands, CoreStage, ParallelSystemDescriptorCoercion, Plugin, Res};
 use bevy::tasks::{IoTaskPool, TaskPoolBuilder};
+use std::sync::Arc;
 use crate::clipmap::ChunkClipMap;
-use crate::database::MapDb;
+use crate::database::{AbortReason, MapDb, MapStore, TransactionError, Version, WriteBehindMapDb};
 use crate::plugin::loader::PendingLoadTasks;
 
 #[derive(Default)]
@@ -30,27 +32,117 @@ impl MapPlugin {
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(self.config.clone())
+            .add_event::<ReloadMap>()
             .add_startup_system(plugin_startup)
+            .add_system_to_stage(CoreStage::Update, reload_system.before(loader_system))
             .add_system_to_stage(CoreStage::Update, loader_system)
+            .add_system_to_stage(CoreStage::PreUpdate, material_palette_system)
             .add_system_to_stage(CoreStage::Last, witness_system);
     }
 }
 
 fn plugin_startup(mut commands: Commands, config: Res<MapConfig>) {
     let db = sled::Config::default()
-        .path("tmp".to_owned())
+        .path(&config.db_path)
         .use_compression(false)
         .mode(sled::Mode::LowSpace)
         .open()
-        .expect("Failed to open world DB");
+        .unwrap_or_else(|e| panic!("Failed to open world DB {:?}: {:?}", config.db_path, e));
 
-    let mapdb = MapDb::open(&db, "main").expect("Failed to load main level");
-    commands.insert_resource(
-        Arc::new(mapdb)
-    );
+    let mapdb = open_map(&db, &config)
+        .unwrap_or_else(|e| panic!("Failed to load map {}: {:?}", config.map_name, e));
+    // The clipmap only holds 8-bit palette IDs. See `MapDb::read_working_loaded_chunk`.
+    let layout = mapdb.chunk_layout();
+    if layout.has_palette16() {
+        panic!("Map {} has the {:?} layout, which the map plugin doesn't support", config.map_name, layout);
+    }
+    // Kept in sync with the registry by `material_palette_system`.
+    commands.insert_resource(mapdb.material_registry().palette());
+    // Edits are written through this resource, so they never block a frame on a transaction.
+    commands.insert_resource(Arc::new(WriteBehindMapDb::new(mapdb, config.write_behind)));
+    // For managing the maps of the database with a `MapCatalog`.
+    commands.insert_resource(db);
     let chunk_clip_map = ChunkClipMap::new(config.num_lods, config.streaming);
     commands.insert_resource(chunk_clip_map);
 
     let task_pool = PendingLoadTasks::new();
     commands.insert_resource(task_pool);
-}
\ No newline at end of file
+}
+
+/// Opens the map of `config`, checking out [`MapConfig::checkout_version`] if the working version isn't already based on it.
+fn open_map<S: MapStore>(
+    store: &S,
+    config: &MapConfig,
+) -> Result<MapDb<S>, TransactionError<AbortReason>> {
+    let mut mapdb = MapDb::open(store, &config.map_name)?;
+    if let Some(number) = config.checkout_version {
+        let version = Version::new(number);
+        if mapdb.cached_meta().parent_version != Some(version) {
+            mapdb.branch_from_version(version)?;
+        }
+    }
+    Ok(mapdb)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::core::glam::IVec3;
+    use crate::database::{Change, ChangeEncoder, ChunkDbKey, MemStore};
+    use crate::sdf::Sd8;
+
+    fn write_chunk(map: &mut MapDb<MemStore>, key: ChunkDbKey, palette_id: u8) -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk.set_voxel(IVec3::ZERO, palette_id, Sd8::MIN);
+        let mut encoder = ChangeEncoder::default();
+        encoder.add_compressed_change(key, Change::Insert(chunk.compress()));
+        map.write_working_version(encoder.encode()).unwrap();
+        chunk
+    }
+
+    #[test]
+    fn map_is_only_changed_when_checking_out_a_version() {
+        let store = MemStore::default();
+        let key = ChunkDbKey::new(0, IVec3::ZERO.into());
+        let (v1, chunk1, working_meta, chunk2) = {
+            let mut map = MapDb::open(&store, "main").unwrap();
+            let chunk1 = write_chunk(&mut map, key, 1);
+            map.commit_working_version().unwrap();
+            let v1 = map.cached_meta().parent_version.unwrap();
+            let chunk2 = write_chunk(&mut map, key, 2);
+            (v1, chunk1, *map.cached_meta(), chunk2)
+        };
+
+        // Without a version, and with the version that the working version is already based on, nothing changes.
+        let mut config = MapConfig::default();
+        for checkout_version in [None, Some(v1.number)] {
+            config.checkout_version = checkout_version;
+            let map = open_map(&store, &config).unwrap();
+            assert_eq!(map.cached_meta(), &working_meta);
+            assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk2));
+        }
+
+        // Checking out the first version commits the edits on top of it.
+        config.checkout_version = Some(v1.number);
+        let v2 = {
+            let mut map = MapDb::open(&store, "main").unwrap();
+            map.commit_working_version().unwrap();
+            map.cached_meta().parent_version.unwrap()
+        };
+        let map = open_map(&store, &config).unwrap();
+        assert_eq!(map.cached_meta().parent_version, Some(v1));
+        assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk1));
+        drop(map);
+        config.checkout_version = Some(v2.number);
+        let map = open_map(&store, &config).unwrap();
+        assert_eq!(map.read_working_chunk(key).unwrap(), Some(chunk2));
+    }
+}
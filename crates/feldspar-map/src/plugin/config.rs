@@ -1,21 +1,37 @@
 use super::LoaderConfig;
 use crate::clipmap::StreamingConfig;
+use crate::database::WriteBehindConfig;
 
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
-#[derive(Clone, Copy, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct MapConfig {
+    /// The directory of the sled database that stores the map.
+    pub db_path: PathBuf,
+    /// Which map of the database to open. See [`MapCatalog`](crate::database::MapCatalog).
+    pub map_name: String,
+    /// The number of a committed version to check out when the map is opened. This changes the stored map like
+    /// [`MapDb::branch_from_version`](crate::database::MapDb::branch_from_version): the working version is committed, and a
+    /// new working version is branched from this one. By default, editing continues on the map's existing working version.
+    pub checkout_version: Option<u64>,
     pub num_lods: u8,
     pub loader: LoaderConfig,
     pub streaming: StreamingConfig,
+    pub write_behind: WriteBehindConfig,
 }
 
 impl Default for MapConfig {
     fn default() -> Self {
         Self {
+            db_path: PathBuf::from("tmp"),
+            map_name: "main".to_owned(),
+            checkout_version: None,
             num_lods: 10,
             loader: LoaderConfig::default(),
             streaming: StreamingConfig::default(),
+            write_behind: WriteBehindConfig::default(),
         }
     }
 }
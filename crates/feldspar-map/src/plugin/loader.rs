@@ -1,7 +1,7 @@
 use super::config::MapConfig;
 use super::Witness;
 use crate::clipmap::{ChunkClipMap, PendingLoad};
-use crate::database::MapDb;
+use crate::database::WriteBehindMapDb;
 use crate::units::VoxelUnits;
 
 use feldspar_core::glam::Vec3A;
@@ -14,6 +14,7 @@ use std::collections::VecDeque;
 use std::sync::Arc;
 
 #[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
 pub struct LoaderConfig {
     /// The number of chunks to start loading in a single frame (batch).
     pub load_batch_size: usize,
@@ -50,7 +51,7 @@ pub fn loader_system(
     config: Res<MapConfig>,
     witness_transforms: Query<(&Witness, &Transform)>,
     // io_pool: Res<IoTaskPool>,
-    db: Res<Arc<MapDb>>, // PERF: better option than Arc?
+    db: Res<Arc<WriteBehindMapDb>>, // PERF: better option than Arc?
     mut clipmap: ResMut<ChunkClipMap>,
     mut load_tasks: ResMut<PendingLoadTasks>,
 ) {
@@ -72,41 +73,69 @@ pub fn loader_system(
 
     // PERF: this does a bunch of redundant work when the clip spheres of multiple witnesses overlap
     for (witness, tfm) in witness_transforms.iter() {
-        if let Some(prev_tfm) = witness.previous_transform.as_ref() {
-            // TODO: use .as_vec3a()
-            let old_witness_pos = VoxelUnits(Vec3A::from(prev_tfm.translation.to_array()));
-            let new_witness_pos = VoxelUnits(Vec3A::from(tfm.translation.to_array()));
+        // A witness without a previous transform has no clip sphere yet, so every root node in its clip sphere is new.
+        // TODO: use .as_vec3a()
+        let old_witness_pos = VoxelUnits(
+            witness
+                .previous_transform
+                .as_ref()
+                .map_or(Vec3A::splat(f32::INFINITY), |prev_tfm| {
+                    Vec3A::from(prev_tfm.translation.to_array())
+                }),
+        );
+        let new_witness_pos = VoxelUnits(Vec3A::from(tfm.translation.to_array()));
 
-            // Insert new root nodes that intersect the clip sphere.
-            clipmap.broad_phase_load_search(old_witness_pos, new_witness_pos);
+        // Insert new root nodes that intersect the clip sphere.
+        clipmap.broad_phase_load_search(old_witness_pos, new_witness_pos);
 
-            if tasks.len() >= config.loader.max_pending_load_tasks {
-                continue;
+        if tasks.len() >= config.loader.max_pending_load_tasks {
+            continue;
+        }
+
+        // Find a batch of nodes to load.
+        let search = clipmap.near_phase_load_search(new_witness_pos);
+        let pending_loads: Vec<_> = search.take(config.loader.load_batch_size).collect();
+
+        // Spawn a new task to load those nodes.
+        let db_clone = db.clone();
+        let io_pool = IoTaskPool::get();
+        let load_task = io_pool.spawn(async move {
+            // PERF: Should this batch be a single task?
+            LoadedBatch {
+                reads: pending_loads
+                    .into_iter()
+                    .map(move |mut pending_load| {
+                        pending_load.chunk = db_clone
+                            .read_working_loaded_chunk(pending_load.loaded_key.into())
+                            .unwrap();
+                        pending_load
+                    })
+                    .collect(),
             }
+        });
+        tasks.push_back(load_task);
+    }
+}
 
-            // Find a batch of nodes to load.
-            let search = clipmap.near_phase_load_search(new_witness_pos);
-            let pending_loads: Vec<_> = search.take(config.loader.load_batch_size).collect();
-
-            // Spawn a new task to load those nodes.
-            let db_clone = db.clone();
-            let io_pool = IoTaskPool::get();
-            let load_task = io_pool.spawn(async move {
-                // PERF: Should this batch be a single task?
-                LoadedBatch {
-                    reads: pending_loads
-                        .into_iter()
-                        .map(move |mut pending_load| {
-                            pending_load.chunk = db_clone
-                                .read_working_version(pending_load.loaded_key.into())
-                                .unwrap()
-                                .map(|c| c.deserialize().unwrap_insert());
-                            pending_load
-                        })
-                        .collect(),
-                }
-            });
-            tasks.push_back(load_task);
-        }
+/// Send this event after the working version of the map changes other than by edits that were also written to the
+/// [`ChunkClipMap`], e.g. after [`MapDb::branch_from_version`](crate::database::MapDb::branch_from_version) or opening another
+/// map. Every chunk is loaded again.
+pub struct ReloadMap;
+
+pub fn reload_system(
+    mut events: EventReader<ReloadMap>,
+    config: Res<MapConfig>,
+    mut witnesses: Query<&mut Witness>,
+    mut clipmap: ResMut<ChunkClipMap>,
+    mut load_tasks: ResMut<PendingLoadTasks>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+    // Dropping the tasks cancels them, since they would complete into the old clipmap.
+    load_tasks.tasks.clear();
+    *clipmap = ChunkClipMap::new(config.num_lods, config.streaming);
+    for mut witness in witnesses.iter_mut() {
+        witness.previous_transform = None;
     }
 }
@@ -0,0 +1,21 @@
+use crate::database::WriteBehindMapDb;
+use crate::palette::Palette8;
+use crate::voxel_attributes::VoxelAttributes;
+
+use bevy::prelude::*;
+use std::sync::Arc;
+
+/// Keeps the `Palette8<VoxelAttributes>` resource in sync with the [`MaterialRegistry`](crate::material_registry::MaterialRegistry)
+/// of the map, e.g. after [`WriteBehindMapDb::write_material_registry`], changing versions, or replacing the map resource.
+pub fn material_palette_system(
+    db: Res<Arc<WriteBehindMapDb>>,
+    mut palette: ResMut<Palette8<VoxelAttributes>>,
+    mut seen_revision: Local<Option<u64>>,
+) {
+    let db = db.db();
+    let revision = db.material_registry_revision();
+    if *seen_revision != Some(revision) {
+        *palette = db.material_registry().palette();
+        *seen_revision = Some(revision);
+    }
+}
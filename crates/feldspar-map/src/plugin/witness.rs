@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 
 /// An entity (usually a camera) that gets a clip sphere in the clipmap.
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct Witness {
     pub(crate) previous_transform: Option<Transform>,
 }
@@ -1,4 +1,5 @@
-use crate::chunk::{ChunkShape, PaletteIdChunk, SdfChunk, CHUNK_SHAPE_IVEC3};
+use crate::chunk::{ChunkShape, CHUNK_SHAPE_IVEC3, CHUNK_SIZE};
+use crate::sdf::SdfValue;
 
 use crate::core::glam::IVec3;
 use crate::core::ilattice::prelude::Extent;
@@ -7,7 +8,6 @@ use std::mem;
 
 pub struct OctantKernel {
     strides: [usize; 8],
-    mode_counter: OctantModeCounter,
 }
 
 impl Default for OctantKernel {
@@ -30,14 +30,16 @@ impl OctantKernel {
             }
         }
 
-        Self {
-            strides,
-            mode_counter: OctantModeCounter::default(),
-        }
+        Self { strides }
     }
 
     /// Takes the **mean** of each octant in `src` to achieve half resolution; result is written to `dst`.
-    pub fn downsample_sdf(&self, src: &SdfChunk, dst_offset: usize, dst: &mut SdfChunk) {
+    pub fn downsample_sdf<S: SdfValue>(
+        &self,
+        src: &[S; CHUNK_SIZE],
+        dst_offset: usize,
+        dst: &mut [S; CHUNK_SIZE],
+    ) {
         // Not only do we get the mean signed distance value by dividing by the octant volume, but we also re-normalize by
         // dividing by 2.
         const RESCALE: f32 = 1.0 / (2.0 * 8.0);
@@ -47,82 +49,79 @@ impl OctantKernel {
             let dst_i = ChunkShape::linearize(p.to_array()) as usize;
             let src_i = dst_i << 1;
 
-            let mut sum = 0.0;
+            let mut sum = 0.0f32;
             for stride in self.strides {
-                sum += f32::from(src[src_i + stride]);
+                sum += Into::<f32>::into(src[src_i + stride]);
             }
-            dst[dst_offset + dst_i] = (sum * RESCALE).into();
+            dst[dst_offset + dst_i] = S::from(sum * RESCALE);
         }
     }
 
     /// Takes the **mode** of each octant to achieve half resolution.
-    pub fn downsample_labels(
-        &mut self,
-        src: &PaletteIdChunk,
+    ///
+    /// Works for any label type, e.g. both [`PaletteIdChunk`](crate::chunk::PaletteIdChunk) and
+    /// [`PaletteId16Chunk`](crate::chunk::PaletteId16Chunk).
+    pub fn downsample_labels<L: Copy + Eq>(
+        &self,
+        src: &[L; CHUNK_SIZE],
         dst_offset: usize,
-        dst: &mut PaletteIdChunk,
+        dst: &mut [L; CHUNK_SIZE],
     ) {
+        let mut mode_counter = OctantModeCounter::default();
         let iter_extent = Extent::from_min_and_shape(IVec3::ZERO, CHUNK_SHAPE_IVEC3 >> 1);
         for p in iter_extent.iter3() {
             let dst_i = ChunkShape::linearize(p.to_array()) as usize;
             let src_i = dst_i << 1;
 
             for stride in self.strides {
-                self.mode_counter.add(src[src_i + stride]);
+                mode_counter.add(src[src_i + stride]);
             }
-            dst[dst_offset + dst_i] = self.mode_counter.get_mode_and_reset().label;
+            dst[dst_offset + dst_i] = mode_counter.get_mode_and_reset().label;
         }
     }
 }
 
-type Label = u8;
-
 type Slot = u8;
-const NULL_SLOT: Slot = Slot::MAX;
 
-/// Counts occurrences of [`Label`]s in a population of exactly 8. Calculates the mode in linear time while only scanning an
-/// array of 8 elements.
+/// Counts occurrences of labels in a population of exactly 8. Calculates the mode in linear time while only scanning an array
+/// of 8 elements.
+///
+/// Since there are at most 8 distinct labels, finding a label's slot is a linear scan, which works for labels of any width.
 ///
-/// Attempts to use more than 8 [`Label`]s per counter will panic.
-struct OctantModeCounter {
-    counts: [Option<LabelCount>; 8],
-    /// An array map determines which count slot, if any, each label is assigned.
-    label_to_slot: [Slot; 256],
+/// Attempts to use more than 8 labels per counter will panic.
+struct OctantModeCounter<L> {
+    counts: [Option<LabelCount<L>>; 8],
     /// Should only go up to 8!
     slots_vended: Slot,
 }
 
-impl Default for OctantModeCounter {
+impl<L: Copy> Default for OctantModeCounter<L> {
     fn default() -> Self {
         Self {
             counts: [None; 8],
-            label_to_slot: [NULL_SLOT; 256],
             slots_vended: 0,
         }
     }
 }
 
-impl OctantModeCounter {
-    pub fn add(&mut self, label: Label) {
-        let label_i = label as usize;
-
-        let mut slot = self.label_to_slot[label_i];
-        if slot == NULL_SLOT {
-            slot = self.slots_vended;
-            self.label_to_slot[label_i] = slot;
-            self.counts[slot as usize] = Some(LabelCount { label, count: 0 });
-            self.slots_vended += 1;
+impl<L: Copy + Eq> OctantModeCounter<L> {
+    pub fn add(&mut self, label: L) {
+        let vended = &mut self.counts[..self.slots_vended as usize];
+        if let Some(count) = vended.iter_mut().flatten().find(|c| c.label == label) {
+            count.count += 1;
+            return;
         }
 
-        self.counts[slot as usize].as_mut().unwrap().count += 1;
+        self.counts[self.slots_vended as usize] = Some(LabelCount { label, count: 1 });
+        self.slots_vended += 1;
     }
 
-    pub fn get_mode_and_reset(&mut self) -> LabelCount {
-        let old_counts = mem::take(&mut self.counts);
+    pub fn get_mode_and_reset(&mut self) -> LabelCount<L> {
+        let old_counts = mem::replace(&mut self.counts, [None; 8]);
+        self.slots_vended = 0;
         let mut max_count = 0;
         let mut max_elem = None;
         for elem in old_counts.into_iter().flatten() {
-            self.label_to_slot[elem.label as usize] = NULL_SLOT;
             if elem.count > max_count {
                 max_count = elem.count;
                 max_elem = Some(elem);
@@ -133,9 +132,9 @@ impl OctantModeCounter {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct LabelCount {
+struct LabelCount<L> {
     pub count: usize,
-    pub label: Label,
+    pub label: L,
 }
 
 // ████████╗███████╗███████╗████████╗
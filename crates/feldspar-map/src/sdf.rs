@@ -1,9 +1,10 @@
 use bytemuck::{Pod, Zeroable};
+use std::ops::Neg;
 
 macro_rules! impl_fixed_precision {
     (name: $name:ident, doc: $docstr:expr, primitive: $primitive:ty, float: $float:ty, max: $max:literal) => {
         #[doc = $docstr]
-        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
         pub struct $name(pub $primitive);
 
         impl $name {
@@ -27,14 +28,59 @@ macro_rules! impl_fixed_precision {
                 $name((Self::RESOLUTION * s.min($max).max(-$max)) as $primitive)
             }
         }
+
+        impl std::ops::Neg for $name {
+            type Output = Self;
+
+            #[inline]
+            fn neg(self) -> Self {
+                // Never overflows, since MIN is -MAX.
+                $name(-self.0)
+            }
+        }
     };
 }
 
+/// A fixed-precision signed distance that can be stored in a [`ChunkData`](crate::chunk::ChunkData).
+pub trait SdfValue: Pod + Ord + Neg<Output = Self> + From<f32> + Into<f32> {
+    /// "As far *outside* of the terrain surface as possible."
+    const AMBIENT: Self;
+}
+
 impl_fixed_precision!(name: Sd8, doc: "An 8-bit value in the range `[-1.0, 1.0]`.", primitive: i8, float: f32, max: 1.0);
 
 unsafe impl Zeroable for Sd8 {}
 unsafe impl Pod for Sd8 {}
 
+impl SdfValue for Sd8 {
+    const AMBIENT: Self = Self::MAX;
+}
+
+impl_fixed_precision!(name: Sd16, doc: "A 16-bit value in the range `[-1.0, 1.0]`. For maps where [`Sd8`] is too coarse, e.g. to avoid banding on gentle slopes.", primitive: i16, float: f32, max: 1.0);
+
+unsafe impl Zeroable for Sd16 {}
+unsafe impl Pod for Sd16 {}
+
+impl SdfValue for Sd16 {
+    const AMBIENT: Self = Self::MAX;
+}
+
+impl From<Sd8> for Sd16 {
+    #[inline]
+    fn from(x: Sd8) -> Self {
+        // Exact at the extremes, unlike converting through f32.
+        Self((x.0 as i32 * Sd16::MAX.0 as i32 / Sd8::MAX.0 as i32) as i16)
+    }
+}
+
+impl From<Sd16> for Sd8 {
+    /// Rounds to the nearest [`Sd8`], so this loses precision.
+    #[inline]
+    fn from(x: Sd16) -> Self {
+        Self((x.0 as f32 * Sd8::MAX.0 as f32 / Sd16::MAX.0 as f32).round() as i8)
+    }
+}
+
 #[cfg(test)]
 mod test {
     // An 8-bit value in the range [-1.0, 1.0].
@@ -66,4 +112,23 @@ mod test {
         assert_eq!(F16::from(0.0), F16::ZERO);
         assert_eq!(F16::from(2.0), F16::MAX);
     }
+
+    #[test]
+    fn test_sd16() {
+        use super::{Sd16, Sd8};
+
+        assert_eq!(Sd16::from(Sd8::MIN), Sd16::MIN);
+        assert_eq!(Sd16::from(Sd8::MAX), Sd16::MAX);
+        assert_eq!(Sd8::from(Sd16::MIN), Sd8::MIN);
+        assert_eq!(Sd8::from(Sd16::MAX), Sd8::MAX);
+        assert_eq!(Sd8::from(Sd16::from(Sd8(-3))), Sd8(-3));
+        assert_eq!(-Sd16::MIN, Sd16::MAX);
+
+        // 256 times finer than Sd8.
+        let x = 0.3;
+        let err8 = (f32::from(Sd8::from(x)) - x).abs();
+        let err16 = (f32::from(Sd16::from(x)) - x).abs();
+        assert!(err16 < Sd16::PRECISION);
+        assert!(err8 > 100.0 * err16);
+    }
 }
@@ -1,20 +1,490 @@
-use crate::core::SmallKeyHashMap;
-use crate::core::glam::IVec3;
-use crate::chunk::Chunk;
+//! Import and export of MagicaVoxel `.vox` files.
+//!
+//! Files are read with [`read_vox_scene`] and written with [`write_vox_scene`], which both understand the scene graph. Every
+//! shape node in the scene graph becomes a [`VoxInstance`] of one [`VoxModel`], with the transforms of all ancestor nodes
+//! already applied.
+//!
+//! MagicaVoxel voxels are binary (occupied or empty), so we generate a signed distance field that places the surface exactly on
+//! the faces between occupied and empty voxels. See [`occupancy_to_chunks`]. Exporting goes the other way by thresholding the
+//! SDF at zero. See [`chunks_to_vox_scene`].
+
+mod reader;
+mod writer;
+
+pub use reader::{read_vox_scene, VoxError};
+pub use writer::write_vox_scene;
+
+use crate::chunk::{Chunk, ChunkShape};
 use crate::coordinates::*;
+use crate::core::glam::IVec3;
+use crate::core::ilattice::prelude::Extent;
+use crate::core::SmallKeyHashMap;
+use crate::palette::{Palette8, PaletteId8};
 use crate::sdf::Sd8;
 use crate::units::*;
 
-use vox_format::types::{ColorIndex, Model, Voxel};
+use ndshape::ConstShape;
+
+/// An RGBA color.
+pub type VoxColor = [u8; 4];
+
+/// The contents of a `.vox` file.
+#[derive(Clone, Debug)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    /// Every placement of a model in the scene. If the file has no scene graph, then each model is placed once with the
+    /// identity transform.
+    pub instances: Vec<VoxInstance>,
+    /// Indexed by color index. Index 0 means "empty" and is never referenced by a voxel.
+    pub palette: [VoxColor; 256],
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VoxModel {
+    pub size: IVec3,
+    /// Local voxel coordinates in `[0, size)` and their color index.
+    pub voxels: Vec<(IVec3, PaletteId8)>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VoxInstance {
+    /// Index into [`VoxScene::models`].
+    pub model: usize,
+    pub transform: VoxTransform,
+}
+
+/// A rigid transform with a rotation that only permutes and negates axes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VoxTransform {
+    /// Row `i` of the rotation matrix has a single nonzero entry in column `axes[i]`.
+    pub axes: [usize; 3],
+    /// Row `i` of the rotation matrix has the entry `signs[i]`.
+    pub signs: [i32; 3],
+    pub translation: IVec3,
+}
+
+impl Default for VoxTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl VoxTransform {
+    pub const IDENTITY: Self = Self {
+        axes: [0, 1, 2],
+        signs: [1, 1, 1],
+        translation: IVec3::ZERO,
+    };
+
+    /// Decodes the `_r` byte of a MagicaVoxel transform node frame.
+    ///
+    /// Bits 0-1 are the column of the nonzero entry in the first row, bits 2-3 are the column for the second row, and bits 4-6
+    /// are the signs of the rows (set means negative).
+    pub fn from_rotation_byte(r: u8, translation: IVec3) -> Option<Self> {
+        let a0 = (r & 3) as usize;
+        let a1 = ((r >> 2) & 3) as usize;
+        if a0 > 2 || a1 > 2 || a0 == a1 {
+            return None;
+        }
+        let a2 = 3 - a0 - a1;
+        let sign = |bit: u8| if r & (1 << bit) == 0 { 1 } else { -1 };
+        Some(Self {
+            axes: [a0, a1, a2],
+            signs: [sign(4), sign(5), sign(6)],
+            translation,
+        })
+    }
+
+    /// Encodes the rotation as the `_r` byte of a MagicaVoxel transform node frame.
+    pub fn rotation_byte(&self) -> u8 {
+        let sign_bit = |row: usize| u8::from(self.signs[row] < 0) << (4 + row);
+        self.axes[0] as u8 | (self.axes[1] as u8) << 2 | sign_bit(0) | sign_bit(1) | sign_bit(2)
+    }
+
+    pub fn rotate(&self, v: IVec3) -> IVec3 {
+        let v = v.to_array();
+        IVec3::new(
+            self.signs[0] * v[self.axes[0]],
+            self.signs[1] * v[self.axes[1]],
+            self.signs[2] * v[self.axes[2]],
+        )
+    }
+
+    /// Rotates the size of a box, so the result is always positive.
+    pub fn rotate_size(&self, size: IVec3) -> IVec3 {
+        let s = size.to_array();
+        IVec3::new(s[self.axes[0]], s[self.axes[1]], s[self.axes[2]])
+    }
+
+    /// Returns the transform that applies `child` first, then `self`.
+    pub fn then_apply(&self, child: &Self) -> Self {
+        Self {
+            axes: self.axes.map(|col| child.axes[col]),
+            signs: [0, 1, 2].map(|row| self.signs[row] * child.signs[self.axes[row]]),
+            translation: self.rotate(child.translation) + self.translation,
+        }
+    }
+
+    /// Transforms the voxel at local coordinates `v` of a model with shape `size` into scene coordinates.
+    ///
+    /// MagicaVoxel rotates models about their center and places the center at `translation`. When the (rotated) model has an
+    /// odd size along an axis, the center is in the middle of a voxel, so it's offset by half a voxel from `translation`.
+    pub fn transform_model_voxel(&self, size: IVec3, v: IVec3) -> IVec3 {
+        // Double everything so that voxel centers are integers.
+        let centered = 2 * v + IVec3::ONE - size;
+        let rotated_size = self.rotate_size(size);
+        let parity = IVec3::new(rotated_size.x & 1, rotated_size.y & 1, rotated_size.z & 1);
+        let doubled = self.rotate(centered) + 2 * self.translation + parity;
+        IVec3::new(
+            doubled.x.div_euclid(2),
+            doubled.y.div_euclid(2),
+            doubled.z.div_euclid(2),
+        )
+    }
+}
+
+impl VoxScene {
+    /// A [`Palette8`] of colors where each [`PaletteId8`] is the same as the vox color index.
+    pub fn palette8(&self) -> Palette8<VoxColor> {
+        Palette8::new(self.palette.to_vec())
+    }
 
-pub fn convert_vox_model_to_chunks(model: &Model) -> SmallKeyHashMap<ChunkUnits<IVec3>, Chunk> {
+    /// Places every voxel of every instance in the scene, translated by `offset`. Later instances overwrite earlier ones.
+    pub fn occupancy(&self, offset: VoxelUnits<IVec3>) -> SmallKeyHashMap<IVec3, PaletteId8> {
+        let mut occupied = SmallKeyHashMap::default();
+        for instance in self.instances.iter() {
+            let model = &self.models[instance.model];
+            for &(v, color_index) in model.voxels.iter() {
+                let p = instance.transform.transform_model_voxel(model.size, v) + offset.0;
+                occupied.insert(p, color_index);
+            }
+        }
+        occupied
+    }
+}
+
+/// MagicaVoxel models can't be larger than this along any axis.
+pub const MAX_VOX_MODEL_EDGE_LENGTH: i32 = 256;
+
+/// Converts the voxels of `extent` into a [`VoxScene`] by thresholding the SDF: voxels with negative distance are occupied.
+///
+/// `get_chunk` provides the chunk at the given coordinates, if there is one. The region is split into models of at most
+/// [`MAX_VOX_MODEL_EDGE_LENGTH`] voxels per axis, and the models are placed so that the minimum of `extent` is at the origin of
+/// the scene. Importing the scene at an offset of `extent.minimum` restores the original coordinates.
+///
+/// Each [`PaletteId8`] becomes the same vox color index, and colors come from `palette` (or the default vox palette where
+/// `palette` has no entry). Vox color index 0 means "empty," so occupied voxels with [`PaletteId8`] 0 are written with color
+/// index 1.
+pub fn chunks_to_vox_scene(
+    extent: VoxelUnits<Extent<IVec3>>,
+    palette: &Palette8<VoxColor>,
+    mut get_chunk: impl FnMut(ChunkUnits<IVec3>) -> Option<Chunk>,
+) -> VoxScene {
+    let VoxelUnits(extent) = extent;
+    let model_shape = IVec3::splat(MAX_VOX_MODEL_EDGE_LENGTH);
+
+    let mut models: SmallKeyHashMap<IVec3, VoxModel> = SmallKeyHashMap::default();
+    for chunk_coords in in_chunk_extent(VoxelUnits(extent)).0.iter3() {
+        let chunk_coords = ChunkUnits(chunk_coords);
+        let chunk = if let Some(chunk) = get_chunk(chunk_coords) {
+            chunk
+        } else {
+            continue;
+        };
+        let VoxelUnits(chunk_extent) = chunk_extent_ivec3(chunk_coords);
+        for p in chunk_extent.intersection(&extent).iter3() {
+            let index = ChunkShape::linearize((p - chunk_extent.minimum).to_array()) as usize;
+            if chunk.sdf[index].0 >= 0 {
+                continue;
+            }
+            let color_index = chunk.palette_ids[index].max(1);
+
+            let scene_p = p - extent.minimum;
+            let model_coords = scene_p / model_shape;
+            let model = models.entry(model_coords).or_insert_with(|| {
+                let model_min = model_coords * model_shape;
+                VoxModel {
+                    size: model_shape.min(extent.shape - model_min),
+                    voxels: Vec::new(),
+                }
+            });
+            model
+                .voxels
+                .push((scene_p - model_coords * model_shape, color_index));
+        }
+    }
+
+    let mut models: Vec<_> = models.into_iter().collect();
+    models.sort_by_key(|(coords, _)| coords.to_array());
+
+    let instances = models
+        .iter()
+        .enumerate()
+        .map(|(i, (model_coords, model))| VoxInstance {
+            model: i,
+            transform: VoxTransform {
+                // Inverse of `VoxTransform::transform_model_voxel` for the identity rotation.
+                translation: *model_coords * model_shape + model.size / 2,
+                ..VoxTransform::IDENTITY
+            },
+        })
+        .collect();
+
+    let mut vox_palette = default_vox_palette();
+    for (id, &color) in palette.iter().skip(1) {
+        vox_palette[id as usize] = color;
+    }
+
+    VoxScene {
+        models: models.into_iter().map(|(_, model)| model).collect(),
+        instances,
+        palette: vox_palette,
+    }
+}
+
+/// The palette that MagicaVoxel uses for files without an `RGBA` chunk.
+///
+/// It's a 6x6x6 color cube (without black) followed by red, green, blue, and gray ramps.
+pub fn default_vox_palette() -> [VoxColor; 256] {
+    const CUBE_STEPS: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP_STEPS: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = [[0; 4]; 256];
+    let mut i = 1;
+    for r in CUBE_STEPS {
+        for g in CUBE_STEPS {
+            for b in CUBE_STEPS {
+                if i < 216 {
+                    palette[i] = [r, g, b, 0xff];
+                    i += 1;
+                }
+            }
+        }
+    }
+    for ramp in [[1, 0, 0], [0, 1, 0], [0, 0, 1], [1, 1, 1]] {
+        for step in RAMP_STEPS {
+            palette[i] = [ramp[0] * step, ramp[1] * step, ramp[2] * step, 0xff];
+            i += 1;
+        }
+    }
+    palette
+}
+
+/// Converts the voxels of `model` into [`Chunk`]s, with the model's minimum corner at the origin.
+pub fn convert_vox_model_to_chunks(model: &VoxModel) -> SmallKeyHashMap<ChunkUnits<IVec3>, Chunk> {
+    occupancy_to_chunks(&model.voxels.iter().copied().collect())
+}
+
+/// Converts a set of occupied voxels into [`Chunk`]s with a signed distance field.
+///
+/// The surface lies on the faces between occupied and empty voxels, so each voxel's distance is determined by its 26
+/// neighbors: half a voxel across a face, `√2 / 2` across an edge, and `√3 / 2` across a corner. Anything farther is
+/// saturated at [`Sd8::MAX`] or [`Sd8::MIN`]. Empty voxels near the surface take the [`PaletteId8`] of their nearest occupied
+/// neighbor, so that materials blend sensibly when meshing.
+///
+/// Chunks are allocated for every empty voxel that needs a non-ambient distance, so the result may contain chunks without any
+/// occupied voxels.
+pub fn occupancy_to_chunks(
+    occupied: &SmallKeyHashMap<IVec3, PaletteId8>,
+) -> SmallKeyHashMap<ChunkUnits<IVec3>, Chunk> {
     let mut chunks = SmallKeyHashMap::default();
-    for Voxel { point: p, color_index: ColorIndex(palette_id) } in model.voxels.iter() {
-        let p = IVec3::new(p.x.into(), p.y.into(), p.z.into());
-        let chunk_coords = in_chunk(VoxelUnits(p));
-        let chunk = chunks.entry(chunk_coords).or_insert_with(Chunk::default);
-        let VoxelUnits(chunk_min) = chunk_min(chunk_coords);
-        chunk.set_voxel(p - chunk_min, *palette_id, Sd8::MAX);
+    for (&p, &palette_id) in occupied.iter() {
+        let mut nearest_empty = 1.0f32;
+        for (offset, distance) in neighbor_offsets() {
+            let n = p + offset;
+            if occupied.contains_key(&n) {
+                continue;
+            }
+            nearest_empty = nearest_empty.min(distance);
+
+            let (chunk, index) = voxel_mut(&mut chunks, n);
+            if distance < f32::from(chunk.sdf[index]) {
+                chunk.sdf[index] = Sd8::from(distance);
+                chunk.palette_ids[index] = palette_id;
+            }
+        }
+
+        let (chunk, index) = voxel_mut(&mut chunks, p);
+        chunk.sdf[index] = Sd8::from(-nearest_empty);
+        chunk.palette_ids[index] = palette_id;
     }
     chunks
 }
+
+/// All 26 neighbor offsets and the distance from the center of the neighbor to the nearest point on the central voxel.
+fn neighbor_offsets() -> impl Iterator<Item = (IVec3, f32)> {
+    (-1..=1)
+        .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| IVec3::new(x, y, z))))
+        .filter(|&offset| offset != IVec3::ZERO)
+        .map(|offset| {
+            let num_nonzero = offset.x.abs() + offset.y.abs() + offset.z.abs();
+            (offset, 0.5 * (num_nonzero as f32).sqrt())
+        })
+}
+
+fn voxel_mut(
+    chunks: &mut SmallKeyHashMap<ChunkUnits<IVec3>, Chunk>,
+    p: IVec3,
+) -> (&mut Chunk, usize) {
+    let chunk_coords = in_chunk(VoxelUnits(p));
+    let VoxelUnits(chunk_min) = chunk_min(chunk_coords);
+    let chunk = chunks.entry(chunk_coords).or_insert_with(Chunk::default);
+    let index = ChunkShape::linearize((p - chunk_min).to_array()) as usize;
+    (chunk, index)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::AMBIENT_SD8;
+
+    fn sdf_at(chunks: &SmallKeyHashMap<ChunkUnits<IVec3>, Chunk>, p: IVec3) -> Sd8 {
+        let chunk_coords = in_chunk(VoxelUnits(p));
+        let VoxelUnits(min) = chunk_min(chunk_coords);
+        chunks.get(&chunk_coords).map_or(AMBIENT_SD8, |c| {
+            c.sdf[ChunkShape::linearize((p - min).to_array()) as usize]
+        })
+    }
+
+    #[test]
+    fn single_voxel_has_surface_on_its_faces() {
+        let mut occupied = SmallKeyHashMap::default();
+        occupied.insert(IVec3::new(15, 0, 0), 7);
+        let chunks = occupancy_to_chunks(&occupied);
+
+        // The face neighbor at x = 16 lives in another chunk.
+        assert_eq!(chunks.len(), 8);
+
+        assert_eq!(sdf_at(&chunks, IVec3::new(15, 0, 0)), Sd8::from(-0.5));
+        assert_eq!(sdf_at(&chunks, IVec3::new(16, 0, 0)), Sd8::from(0.5));
+        assert_eq!(
+            sdf_at(&chunks, IVec3::new(16, 1, 0)),
+            Sd8::from(0.5 * 2f32.sqrt())
+        );
+        assert_eq!(
+            sdf_at(&chunks, IVec3::new(16, 1, 1)),
+            Sd8::from(0.5 * 3f32.sqrt())
+        );
+        assert_eq!(sdf_at(&chunks, IVec3::new(17, 0, 0)), AMBIENT_SD8);
+    }
+
+    #[test]
+    fn interior_voxels_saturate() {
+        let mut occupied = SmallKeyHashMap::default();
+        for z in 0..3 {
+            for y in 0..3 {
+                for x in 0..3 {
+                    occupied.insert(IVec3::new(x, y, z), 1);
+                }
+            }
+        }
+        let chunks = occupancy_to_chunks(&occupied);
+        assert_eq!(sdf_at(&chunks, IVec3::ONE), Sd8::MIN);
+        assert_eq!(sdf_at(&chunks, IVec3::ZERO), Sd8::from(-0.5));
+    }
+
+    #[test]
+    fn transform_composition_matches_sequential_application() {
+        let parent = VoxTransform::from_rotation_byte(0b0010001, IVec3::new(1, 2, 3)).unwrap();
+        let child = VoxTransform::from_rotation_byte(0b1000100, IVec3::new(-4, 5, 0)).unwrap();
+        let combined = parent.then_apply(&child);
+        let v = IVec3::new(7, -3, 2);
+        assert_eq!(
+            combined.rotate(v) + combined.translation,
+            parent.rotate(child.rotate(v) + child.translation) + parent.translation
+        );
+    }
+
+    #[test]
+    fn model_voxels_are_centered_on_translation() {
+        let t = VoxTransform::IDENTITY;
+        // Odd size: the middle voxel lands on the translation.
+        assert_eq!(
+            t.transform_model_voxel(IVec3::splat(3), IVec3::ONE),
+            IVec3::ZERO
+        );
+        // Even size: the translation is on the corner between the middle voxels.
+        assert_eq!(
+            t.transform_model_voxel(IVec3::splat(2), IVec3::ZERO),
+            -IVec3::ONE
+        );
+        assert_eq!(
+            t.transform_model_voxel(IVec3::splat(2), IVec3::ONE),
+            IVec3::ZERO
+        );
+
+        // Rotating a 2x1x1 model by 180 degrees about Z swaps its voxels in place.
+        let flip = VoxTransform::from_rotation_byte(0b0110100, IVec3::ZERO).unwrap();
+        let size = IVec3::new(2, 1, 1);
+        assert_eq!(
+            flip.transform_model_voxel(size, IVec3::ZERO),
+            IVec3::new(0, 0, 0)
+        );
+        assert_eq!(
+            flip.transform_model_voxel(size, IVec3::X),
+            IVec3::new(-1, 0, 0)
+        );
+    }
+
+    #[test]
+    fn export_then_import_round_trip() {
+        let mut occupied = SmallKeyHashMap::default();
+        occupied.insert(IVec3::new(-10, 0, 0), 3);
+        occupied.insert(IVec3::new(290, 1, 2), 4);
+        occupied.insert(IVec3::new(291, 1, 2), 0);
+        let chunks = occupancy_to_chunks(&occupied);
+
+        let extent = VoxelUnits(Extent::from_min_and_max(
+            IVec3::new(-10, 0, 0),
+            IVec3::new(300, 5, 5),
+        ));
+        let mut palette = vec![[0; 4]; 5];
+        palette[3] = [1, 2, 3, 255];
+        let scene = chunks_to_vox_scene(extent, &Palette8::new(palette), |coords| {
+            chunks.get(&coords).cloned()
+        });
+
+        // The extent is wider than one model.
+        assert_eq!(scene.models.len(), 2);
+        assert_eq!(scene.models[0].size, IVec3::new(256, 6, 6));
+        assert_eq!(scene.models[1].size, IVec3::new(55, 6, 6));
+        assert_eq!(scene.palette[3], [1, 2, 3, 255]);
+        assert_eq!(scene.palette[5], default_vox_palette()[5]);
+
+        let mut file = Vec::new();
+        write_vox_scene(&scene, &mut file).unwrap();
+        let imported = read_vox_scene(file.as_slice()).unwrap();
+
+        let mut expected = occupied.clone();
+        // Palette ID 0 means "empty" in vox files.
+        expected.insert(IVec3::new(291, 1, 2), 1);
+        assert_eq!(imported.occupancy(VoxelUnits(extent.0.minimum)), expected);
+    }
+
+    #[test]
+    fn rotation_byte_round_trip() {
+        for r in 0..128 {
+            if let Some(t) = VoxTransform::from_rotation_byte(r, IVec3::ZERO) {
+                assert_eq!(t.rotation_byte(), r);
+            }
+        }
+    }
+
+    #[test]
+    fn default_palette_matches_magica_voxel() {
+        let palette = default_vox_palette();
+        assert_eq!(palette[0], [0, 0, 0, 0]);
+        assert_eq!(palette[1], [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(palette[2], [0xff, 0xff, 0xcc, 0xff]);
+        assert_eq!(palette[215], [0x00, 0x00, 0x33, 0xff]);
+        assert_eq!(palette[216], [0xee, 0x00, 0x00, 0xff]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+}
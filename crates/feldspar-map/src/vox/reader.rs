@@ -0,0 +1,484 @@
+use super::{default_vox_palette, VoxColor, VoxInstance, VoxModel, VoxScene, VoxTransform};
+use crate::core::glam::IVec3;
+use crate::core::SmallKeyHashMap;
+
+use std::io::{self, Read};
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(io::Error),
+    /// The file doesn't start with `"VOX "`.
+    NotAVoxFile,
+    /// The file ended in the middle of a chunk.
+    Truncated,
+    /// The contents of a chunk don't match the `.vox` spec.
+    MalformedChunk {
+        id: [u8; 4],
+    },
+    /// A scene graph node references a node or model that doesn't exist, or the graph has a cycle.
+    InvalidSceneGraph,
+}
+
+impl From<io::Error> for VoxError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Reads every model, the palette, and the scene graph from a `.vox` file.
+///
+/// Hidden nodes and nodes on hidden layers are skipped. Only the first frame of animated transforms is used.
+pub fn read_vox_scene(mut reader: impl Read) -> Result<VoxScene, VoxError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let mut file = Cursor { bytes: &bytes };
+    if file.take(4) != Some(b"VOX ".as_slice()) {
+        return Err(VoxError::NotAVoxFile);
+    }
+    let _version = file.i32().ok_or(VoxError::Truncated)?;
+    let main = file.chunk().ok_or(VoxError::Truncated)?;
+    if &main.id != b"MAIN" {
+        return Err(VoxError::MalformedChunk { id: main.id });
+    }
+
+    let mut models = Vec::new();
+    let mut palette = None;
+    let mut nodes = SmallKeyHashMap::default();
+    let mut hidden_layers = Vec::new();
+
+    let mut size = None;
+    let mut children = Cursor {
+        bytes: main.children,
+    };
+    while !children.bytes.is_empty() {
+        let chunk = children.chunk().ok_or(VoxError::Truncated)?;
+        let mut content = Cursor {
+            bytes: chunk.content,
+        };
+        let parsed = match &chunk.id {
+            b"SIZE" => content.ivec3().map(|s| size = Some(s)),
+            b"XYZI" => size.take().and_then(|size| {
+                models.push(content.model(size)?);
+                Some(())
+            }),
+            b"RGBA" => content.palette().map(|p| palette = Some(p)),
+            b"nTRN" | b"nGRP" | b"nSHP" => content.node(&chunk.id).map(|(id, node)| {
+                nodes.insert(id, node);
+            }),
+            b"LAYR" => content.layer().map(|(id, hidden)| {
+                if hidden {
+                    hidden_layers.push(id);
+                }
+            }),
+            // Materials, cameras, notes, etc. aren't needed.
+            _ => Some(()),
+        };
+        if parsed.is_none() {
+            return Err(VoxError::MalformedChunk { id: chunk.id });
+        }
+    }
+
+    let instances = if nodes.is_empty() {
+        (0..models.len())
+            .map(|model| VoxInstance {
+                model,
+                transform: VoxTransform::IDENTITY,
+            })
+            .collect()
+    } else {
+        let mut instances = Vec::new();
+        let graph = SceneGraph {
+            nodes: &nodes,
+            hidden_layers: &hidden_layers,
+            num_models: models.len(),
+        };
+        graph.visit(0, VoxTransform::IDENTITY, 0, &mut instances)?;
+        instances
+    };
+
+    Ok(VoxScene {
+        models,
+        instances,
+        palette: palette.unwrap_or_else(default_vox_palette),
+    })
+}
+
+enum SceneNode {
+    Transform {
+        child: i32,
+        layer: i32,
+        hidden: bool,
+        transform: VoxTransform,
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<i32>,
+    },
+}
+
+struct SceneGraph<'a> {
+    nodes: &'a SmallKeyHashMap<i32, SceneNode>,
+    hidden_layers: &'a [i32],
+    num_models: usize,
+}
+
+impl SceneGraph<'_> {
+    fn visit(
+        &self,
+        node_id: i32,
+        parent_transform: VoxTransform,
+        depth: usize,
+        instances: &mut Vec<VoxInstance>,
+    ) -> Result<(), VoxError> {
+        // A path longer than the number of nodes must contain a cycle.
+        if depth > self.nodes.len() {
+            return Err(VoxError::InvalidSceneGraph);
+        }
+        let node = self
+            .nodes
+            .get(&node_id)
+            .ok_or(VoxError::InvalidSceneGraph)?;
+        match node {
+            SceneNode::Transform {
+                child,
+                layer,
+                hidden,
+                transform,
+            } => {
+                if *hidden || self.hidden_layers.contains(layer) {
+                    return Ok(());
+                }
+                let transform = parent_transform.then_apply(transform);
+                self.visit(*child, transform, depth + 1, instances)?;
+            }
+            SceneNode::Group { children } => {
+                for &child in children.iter() {
+                    self.visit(child, parent_transform, depth + 1, instances)?;
+                }
+            }
+            SceneNode::Shape { models } => {
+                for &model in models.iter() {
+                    let model = usize::try_from(model)
+                        .ok()
+                        .filter(|&m| m < self.num_models)
+                        .ok_or(VoxError::InvalidSceneGraph)?;
+                    instances.push(VoxInstance {
+                        model,
+                        transform: parent_transform,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RawChunk<'a> {
+    id: [u8; 4],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+type Dict<'a> = Vec<(&'a [u8], &'a [u8])>;
+
+fn dict_get<'a>(dict: &Dict<'a>, key: &str) -> Option<&'a [u8]> {
+    dict.iter()
+        .find(|(k, _)| *k == key.as_bytes())
+        .map(|(_, v)| *v)
+}
+
+fn dict_flag(dict: &Dict, key: &str) -> bool {
+    dict_get(dict, key) == Some(b"1".as_slice())
+}
+
+/// All parse methods return `None` when the bytes run out or don't make sense.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.take(4)
+            .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        self.i32().and_then(|n| usize::try_from(n).ok())
+    }
+
+    fn ivec3(&mut self) -> Option<IVec3> {
+        Some(IVec3::new(self.i32()?, self.i32()?, self.i32()?))
+    }
+
+    fn chunk(&mut self) -> Option<RawChunk<'a>> {
+        let id = self.take(4)?.try_into().unwrap();
+        let content_len = self.count()?;
+        let children_len = self.count()?;
+        Some(RawChunk {
+            id,
+            content: self.take(content_len)?,
+            children: self.take(children_len)?,
+        })
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.count()?;
+        self.take(len)
+    }
+
+    fn dict(&mut self) -> Option<Dict<'a>> {
+        let num_pairs = self.count()?;
+        (0..num_pairs)
+            .map(|_| Some((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    fn model(&mut self, size: IVec3) -> Option<VoxModel> {
+        let num_voxels = self.count()?;
+        let voxels = (0..num_voxels)
+            .map(|_| {
+                let [x, y, z, color_index]: [u8; 4] = self.take(4)?.try_into().unwrap();
+                Some((IVec3::new(x.into(), y.into(), z.into()), color_index))
+            })
+            .collect::<Option<_>>()?;
+        Some(VoxModel { size, voxels })
+    }
+
+    fn palette(&mut self) -> Option<[VoxColor; 256]> {
+        // Color i in the chunk is for color index i + 1. The last color is unused.
+        let mut palette = [[0; 4]; 256];
+        for color in palette[1..].iter_mut() {
+            *color = self.take(4)?.try_into().unwrap();
+        }
+        Some(palette)
+    }
+
+    fn layer(&mut self) -> Option<(i32, bool)> {
+        let id = self.i32()?;
+        let attributes = self.dict()?;
+        Some((id, dict_flag(&attributes, "_hidden")))
+    }
+
+    fn node(&mut self, chunk_id: &[u8; 4]) -> Option<(i32, SceneNode)> {
+        let id = self.i32()?;
+        let attributes = self.dict()?;
+        let node = match chunk_id {
+            b"nTRN" => {
+                let child = self.i32()?;
+                let _reserved = self.i32()?;
+                let layer = self.i32()?;
+                let num_frames = self.count()?;
+                let frames = (0..num_frames)
+                    .map(|_| self.dict())
+                    .collect::<Option<Vec<_>>>()?;
+                let transform = match frames.first() {
+                    Some(frame) => parse_frame_transform(frame)?,
+                    None => VoxTransform::IDENTITY,
+                };
+                SceneNode::Transform {
+                    child,
+                    layer,
+                    hidden: dict_flag(&attributes, "_hidden"),
+                    transform,
+                }
+            }
+            b"nGRP" => {
+                let num_children = self.count()?;
+                let children = (0..num_children)
+                    .map(|_| self.i32())
+                    .collect::<Option<_>>()?;
+                SceneNode::Group { children }
+            }
+            b"nSHP" => {
+                let num_models = self.count()?;
+                let models = (0..num_models)
+                    .map(|_| {
+                        let model = self.i32()?;
+                        let _model_attributes = self.dict()?;
+                        Some(model)
+                    })
+                    .collect::<Option<_>>()?;
+                SceneNode::Shape { models }
+            }
+            _ => return None,
+        };
+        Some((id, node))
+    }
+}
+
+fn parse_frame_transform(frame: &Dict) -> Option<VoxTransform> {
+    let rotation = match dict_get(frame, "_r") {
+        Some(r) => std::str::from_utf8(r).ok()?.trim().parse().ok()?,
+        None => 0b0000100, // identity
+    };
+    let translation = match dict_get(frame, "_t") {
+        Some(t) => {
+            let mut components = std::str::from_utf8(t)
+                .ok()?
+                .split_whitespace()
+                .map(|c| c.parse::<i32>().ok());
+            let t = IVec3::new(
+                components.next()??,
+                components.next()??,
+                components.next()??,
+            );
+            if components.next().is_some() {
+                return None;
+            }
+            t
+        }
+        None => IVec3::ZERO,
+    };
+    VoxTransform::from_rotation_byte(rotation, translation)
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes.extend_from_slice(children);
+        bytes
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn dict(pairs: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = ints(&[pairs.len() as i32]);
+        for (k, v) in pairs {
+            bytes.extend(ints(&[k.len() as i32]));
+            bytes.extend_from_slice(k.as_bytes());
+            bytes.extend(ints(&[v.len() as i32]));
+            bytes.extend_from_slice(v.as_bytes());
+        }
+        bytes
+    }
+
+    fn file(children: Vec<u8>) -> Vec<u8> {
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(ints(&[150]));
+        bytes.extend(chunk(b"MAIN", &[], &children));
+        bytes
+    }
+
+    fn one_voxel_model() -> Vec<u8> {
+        let mut bytes = chunk(b"SIZE", &ints(&[1, 1, 1]), &[]);
+        let mut xyzi = ints(&[1]);
+        xyzi.extend_from_slice(&[0, 0, 0, 5]);
+        bytes.extend(chunk(b"XYZI", &xyzi, &[]));
+        bytes
+    }
+
+    #[test]
+    fn read_models_without_scene_graph() {
+        let scene = read_vox_scene(file(one_voxel_model()).as_slice()).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].voxels, vec![(IVec3::ZERO, 5)]);
+        assert_eq!(
+            scene.instances,
+            vec![VoxInstance {
+                model: 0,
+                transform: VoxTransform::IDENTITY
+            }]
+        );
+        assert_eq!(scene.palette, default_vox_palette());
+    }
+
+    #[test]
+    fn read_scene_graph_transforms() {
+        let mut children = one_voxel_model();
+
+        // Root transform -> group -> two transforms -> one shape each.
+        let mut root = ints(&[0]);
+        root.extend(dict(&[]));
+        root.extend(ints(&[1, -1, 0, 1]));
+        root.extend(dict(&[("_t", "10 0 0")]));
+        children.extend(chunk(b"nTRN", &root, &[]));
+
+        let mut group = ints(&[1]);
+        group.extend(dict(&[]));
+        group.extend(ints(&[2, 2, 4]));
+        children.extend(chunk(b"nGRP", &group, &[]));
+
+        let mut left = ints(&[2]);
+        left.extend(dict(&[]));
+        left.extend(ints(&[3, -1, 0, 1]));
+        left.extend(dict(&[("_t", "0 5 0"), ("_r", "17")]));
+        children.extend(chunk(b"nTRN", &left, &[]));
+
+        let mut right = ints(&[4]);
+        right.extend(dict(&[("_hidden", "1")]));
+        right.extend(ints(&[3, -1, 0, 1]));
+        right.extend(dict(&[]));
+        children.extend(chunk(b"nTRN", &right, &[]));
+
+        let mut shape = ints(&[3]);
+        shape.extend(dict(&[]));
+        shape.extend(ints(&[1, 0]));
+        shape.extend(dict(&[]));
+        children.extend(chunk(b"nSHP", &shape, &[]));
+
+        let scene = read_vox_scene(file(children).as_slice()).unwrap();
+
+        // The hidden branch is skipped.
+        assert_eq!(scene.instances.len(), 1);
+        let instance = scene.instances[0];
+        assert_eq!(instance.model, 0);
+        assert_eq!(instance.transform.translation, IVec3::new(10, 5, 0));
+        assert_eq!(
+            instance.transform,
+            VoxTransform::from_rotation_byte(17, IVec3::new(10, 5, 0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn reject_bad_files() {
+        assert!(matches!(
+            read_vox_scene(b"NOPE".as_slice()),
+            Err(VoxError::NotAVoxFile)
+        ));
+
+        let mut truncated = file(one_voxel_model());
+        truncated.pop();
+        assert!(matches!(
+            read_vox_scene(truncated.as_slice()),
+            Err(VoxError::Truncated)
+        ));
+
+        // A transform node that points at itself.
+        let mut cycle = ints(&[0]);
+        cycle.extend(dict(&[]));
+        cycle.extend(ints(&[0, -1, 0, 1]));
+        cycle.extend(dict(&[]));
+        let children = chunk(b"nTRN", &cycle, &[]);
+        assert!(matches!(
+            read_vox_scene(file(children).as_slice()),
+            Err(VoxError::InvalidSceneGraph)
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+use super::{VoxScene, VoxTransform};
+use crate::core::glam::IVec3;
+
+use std::io::{self, Write};
+
+/// Writes `scene` as a `.vox` file.
+///
+/// Every instance gets its own transform node under a single group, so the scene graph always has the shape that
+/// [`read_vox_scene`](super::read_vox_scene) expects. Model sizes must not exceed 256 along any axis.
+pub fn write_vox_scene(scene: &VoxScene, mut writer: impl Write) -> io::Result<()> {
+    let mut children = ChunkWriter::default();
+
+    for model in scene.models.iter() {
+        debug_assert!(model.size.cmple(IVec3::splat(256)).all());
+        children.chunk(b"SIZE", |c| {
+            c.i32(model.size.x);
+            c.i32(model.size.y);
+            c.i32(model.size.z);
+        });
+        children.chunk(b"XYZI", |c| {
+            c.i32(model.voxels.len() as i32);
+            for &(v, color_index) in model.voxels.iter() {
+                c.bytes
+                    .extend_from_slice(&[v.x as u8, v.y as u8, v.z as u8, color_index]);
+            }
+        });
+    }
+
+    // Root transform (0) -> group (1) -> instance transforms (2, 4, ...) -> shapes (3, 5, ...).
+    children.chunk(b"nTRN", |c| {
+        c.transform_node(0, 1, -1, &VoxTransform::IDENTITY);
+    });
+    children.chunk(b"nGRP", |c| {
+        c.i32(1);
+        c.dict(&[]);
+        c.i32(scene.instances.len() as i32);
+        for i in 0..scene.instances.len() {
+            c.i32(2 + 2 * i as i32);
+        }
+    });
+    for (i, instance) in scene.instances.iter().enumerate() {
+        let transform_id = 2 + 2 * i as i32;
+        children.chunk(b"nTRN", |c| {
+            c.transform_node(transform_id, transform_id + 1, 0, &instance.transform);
+        });
+        children.chunk(b"nSHP", |c| {
+            c.i32(transform_id + 1);
+            c.dict(&[]);
+            c.i32(1);
+            c.i32(instance.model as i32);
+            c.dict(&[]);
+        });
+    }
+
+    children.chunk(b"RGBA", |c| {
+        // Color i in the chunk is for color index i + 1. The last color is unused.
+        for color in scene.palette[1..].iter() {
+            c.bytes.extend_from_slice(color);
+        }
+        c.bytes.extend_from_slice(&[0; 4]);
+    });
+
+    let mut file = ChunkWriter::default();
+    file.bytes.extend_from_slice(b"VOX ");
+    file.i32(150);
+    file.bytes.extend_from_slice(b"MAIN");
+    file.i32(0);
+    file.i32(children.bytes.len() as i32);
+    file.bytes.extend_from_slice(&children.bytes);
+
+    writer.write_all(&file.bytes)?;
+    writer.flush()
+}
+
+#[derive(Default)]
+struct ChunkWriter {
+    bytes: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn i32(&mut self, x: i32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.i32(s.len() as i32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn dict(&mut self, pairs: &[(&str, &str)]) {
+        self.i32(pairs.len() as i32);
+        for (key, value) in pairs {
+            self.string(key);
+            self.string(value);
+        }
+    }
+
+    /// Writes a chunk without children.
+    fn chunk(&mut self, id: &[u8; 4], write_content: impl FnOnce(&mut Self)) {
+        let mut content = Self::default();
+        write_content(&mut content);
+        self.bytes.extend_from_slice(id);
+        self.i32(content.bytes.len() as i32);
+        self.i32(0);
+        self.bytes.extend_from_slice(&content.bytes);
+    }
+
+    fn transform_node(&mut self, id: i32, child: i32, layer: i32, transform: &VoxTransform) {
+        self.i32(id);
+        self.dict(&[]);
+        self.i32(child);
+        self.i32(-1);
+        self.i32(layer);
+        self.i32(1);
+
+        let rotation = transform.rotation_byte().to_string();
+        let t = transform.translation;
+        let translation = format!("{} {} {}", t.x, t.y, t.z);
+        self.dict(&[("_r", &rotation), ("_t", &translation)]);
+    }
+}
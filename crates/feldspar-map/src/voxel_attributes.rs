@@ -1,8 +1,43 @@
+use crate::core::rkyv::{Archive, Deserialize, Serialize};
+
+use bytecheck::CheckBytes;
+
 /// The data stored for each *type* of voxel, i.e. inside of a [`Palette8`](crate::palette::Palette8) for each
 /// [`PaletteId8`](crate::palette::PaletteId8).
+#[derive(
+    Archive,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Serialize,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
 pub struct VoxelAttributes {
     pub is_collidable: bool,
     pub material_id: MaterialId,
 }
 
+#[derive(
+    Archive,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    Hash,
+    PartialEq,
+    Serialize,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[archive(crate = "crate::core::rkyv")]
+#[archive_attr(derive(CheckBytes))]
 pub struct MaterialId(pub u8);
@@ -0,0 +1,375 @@
+//! Export of terrain geometry to files for offline tools.
+//!
+//! [`extract_terrain_mesh`] runs the same Surface Nets mesher that the renderer uses over every chunk in a region, then welds
+//! the chunk meshes into a single [`TerrainMesh`] that can be written as [OBJ](write_obj) or [binary glTF](write_glb). Regions
+//! can come from a [`MapDb`] with [`extract_map_terrain_mesh`] or from a [`ChunkClipMap`] with
+//! [`extract_clipmap_terrain_mesh`].
+
+mod gltf;
+mod obj;
+
+pub use gltf::write_glb;
+pub use obj::write_obj;
+
+use crate::mesher::{mesh_padded_chunk, padded_chunk_min, PaddedChunk};
+
+use feldspar_map::{
+    chunk::{ChunkData, ChunkLayout, ChunkShape},
+    clipmap::{ChunkClipMap, Level},
+    coordinates::{chunk_extent_ivec3, in_chunk_extent},
+    core::glam::{IVec3, Vec3A},
+    core::ilattice::prelude::Extent,
+    core::SmallKeyHashMap,
+    database::{ChunkDbKey, MapDb, MapStore, ReadError},
+    ndshape::ConstShape as _,
+    palette::PaletteId8,
+    sdf::{Sd16, Sd8, SdfValue},
+    units::*,
+};
+
+use fast_surface_nets::SurfaceNetsBuffer;
+
+/// A welded triangle mesh with one vertex per surface cell of the voxel grid.
+#[derive(Clone, Debug, Default)]
+pub struct TerrainMesh {
+    /// In LOD0 voxel coordinates, so meshes from different levels line up.
+    pub positions: Vec<Vec3A>,
+    /// Unit length.
+    pub normals: Vec<Vec3A>,
+    pub palette_ids: Vec<PaletteId8>,
+    /// Triangles grouped by material, sorted by palette ID.
+    pub material_groups: Vec<MaterialGroup>,
+}
+
+/// The triangles whose vertices mostly have the same palette ID.
+#[derive(Clone, Debug, Default)]
+pub struct MaterialGroup {
+    pub palette_id: PaletteId8,
+    /// Counter-clockwise triangles, three indices each.
+    pub indices: Vec<u32>,
+}
+
+impl TerrainMesh {
+    pub fn num_triangles(&self) -> usize {
+        self.material_groups
+            .iter()
+            .map(|g| g.indices.len() / 3)
+            .sum()
+    }
+}
+
+/// Meshes the voxels of `extent` at `level`.
+///
+/// Voxels outside of `extent` are treated as ambient, so the mesh is closed off along the boundary of the region and the
+/// result is watertight as long as the terrain is. `get_chunk` is called at most once for each chunk coordinate at `level`;
+/// missing chunks are ambient.
+///
+/// Adjacent chunk meshes overlap in their padding, so vertices are welded by their grid cell and each triangle is only kept
+/// by one chunk.
+///
+/// Chunks can have any [`SdfValue`] precision, e.g. [`Chunk`](feldspar_map::chunk::Chunk) or
+/// [`ChunkSd16`](feldspar_map::chunk::ChunkSd16).
+pub fn extract_terrain_mesh<S: SdfValue>(
+    level: Level,
+    extent: VoxelUnits<Extent<IVec3>>,
+    mut get_chunk: impl FnMut(ChunkUnits<IVec3>) -> Option<ChunkData<S, PaletteId8>>,
+) -> TerrainMesh {
+    let VoxelUnits(extent) = extent;
+    let padded_extent =
+        Extent::from_min_and_max(extent.minimum - IVec3::ONE, extent.max() + IVec3::ONE);
+    let ChunkUnits(chunk_extent) = in_chunk_extent(VoxelUnits(padded_extent));
+
+    // Load every chunk once and clip it to the region.
+    let mut chunks = SmallKeyHashMap::default();
+    let neighborhood_extent = Extent::from_min_and_max(
+        chunk_extent.minimum - IVec3::ONE,
+        chunk_extent.max() + IVec3::ONE,
+    );
+    for coords in neighborhood_extent.iter3() {
+        if let Some(mut chunk) = get_chunk(ChunkUnits(coords)) {
+            clip_chunk(&mut chunk, coords, &extent);
+            chunks.insert(coords, chunk);
+        }
+    }
+
+    let voxel_size = (1 << level) as f32;
+    let center_offset = Vec3A::splat((voxel_size - 1.0) / 2.0);
+
+    let mut mesh = TerrainMesh::default();
+    let mut cell_to_vertex: SmallKeyHashMap<IVec3, u32> = SmallKeyHashMap::default();
+    let mut triangles = Vec::new();
+    let mut buffer = SurfaceNetsBuffer::default();
+    for chunk_coords in chunk_extent.iter3() {
+        let padded = PaddedChunk::copy_neighborhood(chunk_coords, |c| chunks.get(&c).copied());
+        let vertex_palette_ids = mesh_padded_chunk(&padded, &mut buffer);
+        let padded_min = padded_chunk_min(chunk_coords);
+
+        let welded: Vec<u32> = buffer
+            .surface_points
+            .iter()
+            .enumerate()
+            .map(|(i, &point)| {
+                let cell = padded_min + IVec3::from(point.map(|c| c as i32));
+                *cell_to_vertex.entry(cell).or_insert_with(|| {
+                    let position = padded_min.as_vec3a() + Vec3A::from(buffer.positions[i]);
+                    mesh.positions.push(position * voxel_size + center_offset);
+                    mesh.normals
+                        .push(Vec3A::from(buffer.normals[i]).normalize_or_zero());
+                    mesh.palette_ids.push(vertex_palette_ids[i]);
+                    mesh.positions.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let VoxelUnits(owned_extent) = chunk_extent_ivec3(ChunkUnits(chunk_coords));
+        for tri in buffer.indices.chunks_exact(3) {
+            // Neighboring chunks generate the same quads in their overlapping padding. Each quad comes from a sign change on
+            // one voxel edge, and the maximum of the quad's cells (or of any 3 of them) is the lower voxel of that edge, so only
+            // keep the triangles whose edge starts in this chunk.
+            let cells = [0, 1, 2].map(|j| buffer.surface_points[tri[j] as usize]);
+            let edge_min = padded_min
+                + IVec3::from(
+                    [0, 1, 2].map(|axis| cells.iter().map(|c| c[axis]).max().unwrap() as i32),
+                );
+            if !owned_extent.contains(edge_min) {
+                continue;
+            }
+
+            let [a, b, c] = [0, 1, 2].map(|j| welded[tri[j] as usize]);
+            let ids = [a, b, c].map(|v| mesh.palette_ids[v as usize]);
+            let palette_id = if ids[1] == ids[2] { ids[1] } else { ids[0] };
+            triangles.push(([a, b, c], palette_id));
+        }
+    }
+
+    triangles.sort_unstable_by_key(|&(key, palette_id)| (palette_id, key));
+    for (key, palette_id) in triangles {
+        if mesh.material_groups.last().map(|g| g.palette_id) != Some(palette_id) {
+            mesh.material_groups.push(MaterialGroup {
+                palette_id,
+                indices: Vec::new(),
+            });
+        }
+        mesh.material_groups
+            .last_mut()
+            .unwrap()
+            .indices
+            .extend_from_slice(&key);
+    }
+
+    mesh
+}
+
+/// Meshes the voxels of `extent` at `level` of the working version of `map`. See [`extract_terrain_mesh`].
+///
+/// Only [`ChunkLayout`]s with 8-bit palette IDs are supported.
+pub fn extract_map_terrain_mesh<S: MapStore>(
+    map: &MapDb<S>,
+    level: Level,
+    extent: VoxelUnits<Extent<IVec3>>,
+) -> Result<TerrainMesh, ReadError> {
+    match map.chunk_layout() {
+        ChunkLayout::Palette8 => extract_map_terrain_mesh_as::<_, Sd8>(map, level, extent),
+        ChunkLayout::Sd16Palette8 => extract_map_terrain_mesh_as::<_, Sd16>(map, level, extent),
+        layout => Err(ReadError::UnsupportedLayout { layout }),
+    }
+}
+
+fn extract_map_terrain_mesh_as<S: MapStore, Sd: SdfValue>(
+    map: &MapDb<S>,
+    level: Level,
+    extent: VoxelUnits<Extent<IVec3>>,
+) -> Result<TerrainMesh, ReadError> {
+    let mut chunks = SmallKeyHashMap::default();
+    for coords in in_chunk_extent(extent).0.iter3() {
+        let key = ChunkDbKey::new(level, coords.into());
+        if let Some(chunk) = map.read_working_chunk_as::<Sd, PaletteId8>(key)? {
+            chunks.insert(ChunkUnits(coords), chunk);
+        }
+    }
+    Ok(extract_terrain_mesh(level, extent, |coords| {
+        chunks.remove(&coords)
+    }))
+}
+
+/// Meshes the voxels of `extent` at `level` of `clipmap`. Only chunks that are currently loaded are included. See
+/// [`extract_terrain_mesh`].
+pub fn extract_clipmap_terrain_mesh(
+    clipmap: &ChunkClipMap,
+    level: Level,
+    extent: VoxelUnits<Extent<IVec3>>,
+) -> TerrainMesh {
+    let mut chunks = clipmap.loaded_chunks(level, in_chunk_extent(extent));
+    extract_terrain_mesh(level, extent, |coords| chunks.remove(&coords))
+}
+
+/// Sets every voxel of `chunk` outside of `extent` to ambient.
+fn clip_chunk<S: SdfValue>(
+    chunk: &mut ChunkData<S, PaletteId8>,
+    chunk_coords: IVec3,
+    extent: &Extent<IVec3>,
+) {
+    let VoxelUnits(chunk_extent) = chunk_extent_ivec3(ChunkUnits(chunk_coords));
+    let chunk_min = chunk_extent.minimum;
+    if extent.contains(chunk_extent.minimum) && extent.contains(chunk_extent.max()) {
+        return;
+    }
+    for p in chunk_extent.iter3() {
+        if !extent.contains(p) {
+            let i = ChunkShape::linearize((p - chunk_min).to_array()) as usize;
+            chunk.sdf[i] = S::AMBIENT;
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use feldspar_map::chunk::{Chunk, CHUNK_SHAPE_IVEC3};
+    use feldspar_map::coordinates::chunk_min;
+    use feldspar_map::database::{Change, ChangeEncoder, MemStore};
+    use feldspar_map::sdf::{Sd16, Sd8};
+
+    /// A solid sphere of radius 10 centered at the origin, which spans 8 chunks.
+    fn sphere_chunk(ChunkUnits(coords): ChunkUnits<IVec3>) -> Option<Chunk> {
+        let mut chunk = Chunk::default();
+        let VoxelUnits(min) = chunk_min(ChunkUnits(coords));
+        for p in Extent::from_min_and_shape(IVec3::ZERO, CHUNK_SHAPE_IVEC3).iter3() {
+            let d = (min + p).as_vec3a().length() - 10.0;
+            chunk.set_voxel(p, (coords.x.rem_euclid(2) + 1) as u8, Sd8::from(d));
+        }
+        Some(chunk)
+    }
+
+    fn region() -> VoxelUnits<Extent<IVec3>> {
+        VoxelUnits(Extent::from_min_and_shape(
+            IVec3::splat(-32),
+            IVec3::splat(64),
+        ))
+    }
+
+    /// Every edge of a closed, consistently oriented mesh is used exactly once in each direction.
+    fn assert_watertight(mesh: &TerrainMesh) {
+        let mut edges = SmallKeyHashMap::default();
+        for group in mesh.material_groups.iter() {
+            for tri in group.indices.chunks_exact(3) {
+                for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+                    *edges.entry((a, b)).or_insert(0) += 1;
+                }
+            }
+        }
+        for (&(a, b), &count) in edges.iter() {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn sphere_mesh_is_watertight() {
+        let mesh = extract_terrain_mesh(0, region(), sphere_chunk);
+        assert!(mesh.num_triangles() > 0);
+
+        assert_watertight(&mesh);
+
+        for (p, n) in mesh.positions.iter().zip(mesh.normals.iter()) {
+            assert!((p.length() - 10.0).abs() < 1.0);
+            assert!(n.dot(p.normalize()) > 0.5);
+        }
+
+        let group_ids: Vec<_> = mesh.material_groups.iter().map(|g| g.palette_id).collect();
+        assert_eq!(group_ids, [1, 2]);
+    }
+
+    #[test]
+    fn region_boundary_is_closed() {
+        // Cut the sphere in half at X = 0.
+        let half = VoxelUnits(Extent::from_min_and_shape(
+            IVec3::new(0, -32, -32),
+            IVec3::new(32, 64, 64),
+        ));
+        let mesh = extract_terrain_mesh(0, half, sphere_chunk);
+        assert!(mesh.num_triangles() > 0);
+        assert!(mesh.positions.iter().all(|p| p.x > -1.0));
+
+        assert_watertight(&mesh);
+    }
+
+    /// A plane with a slope of 1/40, which rises by less than one `Sd8` step between most neighboring voxels.
+    fn gentle_slope<S: SdfValue>(
+        ChunkUnits(coords): ChunkUnits<IVec3>,
+    ) -> Option<ChunkData<S, PaletteId8>> {
+        let mut chunk = ChunkData::<S, PaletteId8>::default();
+        let VoxelUnits(min) = chunk_min(ChunkUnits(coords));
+        for p in Extent::from_min_and_shape(IVec3::ZERO, CHUNK_SHAPE_IVEC3).iter3() {
+            let q = (min + p).as_vec3a();
+            let d = (q.y - 0.3 - q.x / 40.0) / (1.0f32 + 1.0 / 1600.0).sqrt();
+            chunk.set_voxel(p, 1, S::from(d));
+        }
+        Some(chunk)
+    }
+
+    #[test]
+    fn sd16_reduces_banding() {
+        let region = VoxelUnits(Extent::from_min_and_shape(
+            IVec3::new(0, -8, 0),
+            IVec3::new(32, 16, 16),
+        ));
+        // Only measure the slope, not the walls that close off the region.
+        let max_error = |mesh: &TerrainMesh| {
+            mesh.positions
+                .iter()
+                .filter(|p| p.x > 2.0 && p.x < 29.0 && p.z > 2.0 && p.z < 13.0)
+                .map(|p| (p.y - 0.3 - p.x / 40.0).abs())
+                .fold(0.0, f32::max)
+        };
+        let mesh8 = extract_terrain_mesh(0, region, gentle_slope::<Sd8>);
+        let mesh16 = extract_terrain_mesh(0, region, gentle_slope::<Sd16>);
+        assert!(mesh8.num_triangles() > 0);
+        assert!(mesh16.num_triangles() > 0);
+        assert!(max_error(&mesh16) < 0.1 * max_error(&mesh8));
+    }
+
+    #[test]
+    fn level_scales_positions() {
+        let lod0 = extract_terrain_mesh(0, region(), sphere_chunk);
+        let lod1 = extract_terrain_mesh(1, region(), sphere_chunk);
+        let max_radius =
+            |m: &TerrainMesh| m.positions.iter().map(|p| p.length()).fold(0.0, f32::max);
+        assert!(max_radius(&lod1) > 1.8 * max_radius(&lod0));
+    }
+
+    #[test]
+    fn map_entry_point_matches_chunk_source() {
+        let store = MemStore::default();
+        let mut map = MapDb::open(&store, "mymap").unwrap();
+        let mut encoder = ChangeEncoder::default();
+        for coords in in_chunk_extent(region()).0.iter3() {
+            let chunk = sphere_chunk(ChunkUnits(coords)).unwrap();
+            encoder.add_compressed_change(
+                ChunkDbKey::new(0, coords.into()),
+                Change::Insert(chunk.compress()),
+            );
+        }
+        map.write_working_version(encoder.encode()).unwrap();
+
+        let expected = extract_terrain_mesh(0, region(), sphere_chunk);
+        let mesh = extract_map_terrain_mesh(&map, 0, region()).unwrap();
+        assert_eq!(mesh.positions, expected.positions);
+        assert_eq!(mesh.num_triangles(), expected.num_triangles());
+
+        let map16 = MapDb::open_with_layout(&store, "map16", ChunkLayout::Palette16).unwrap();
+        assert_eq!(
+            extract_map_terrain_mesh(&map16, 0, region()).unwrap_err(),
+            ReadError::UnsupportedLayout {
+                layout: ChunkLayout::Palette16
+            }
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use super::TerrainMesh;
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const JSON_CHUNK_TYPE: &[u8; 4] = b"JSON";
+const BIN_CHUNK_TYPE: &[u8; 4] = b"BIN\0";
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Writes `mesh` as a binary glTF 2.0 (`.glb`) file.
+///
+/// The mesh has one primitive per [`MaterialGroup`](super::MaterialGroup), each with a material named `palette_<ID>`. Vertices
+/// have `POSITION` and `NORMAL` attributes, plus the palette ID of each vertex as a float in the custom `_PALETTE_ID` attribute.
+pub fn write_glb(mesh: &TerrainMesh, mut writer: impl Write) -> io::Result<()> {
+    let num_vertices = mesh.positions.len();
+
+    let mut bin = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut push_view = |bin: &mut Vec<u8>, bytes: &[u8], target: u32| {
+        buffer_views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+            bin.len(),
+            bytes.len(),
+            target
+        ));
+        bin.extend_from_slice(bytes);
+    };
+
+    push_view(
+        &mut bin,
+        &f32_bytes(mesh.positions.iter().flat_map(|p| p.to_array())),
+        ARRAY_BUFFER,
+    );
+    push_view(
+        &mut bin,
+        &f32_bytes(mesh.normals.iter().flat_map(|n| n.to_array())),
+        ARRAY_BUFFER,
+    );
+    push_view(
+        &mut bin,
+        &f32_bytes(mesh.palette_ids.iter().map(|&id| id as f32)),
+        ARRAY_BUFFER,
+    );
+    for group in mesh.material_groups.iter() {
+        let indices: Vec<u8> = group.indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        push_view(&mut bin, &indices, ELEMENT_ARRAY_BUFFER);
+    }
+
+    let (min, max) = mesh.positions.iter().fold(
+        ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+        |(min, max), p| (p.min(min.into()).to_array(), p.max(max.into()).to_array()),
+    );
+    let mut accessors = vec![
+        format!(
+            r#"{{"bufferView":0,"componentType":{FLOAT},"count":{num_vertices},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            min[0], min[1], min[2], max[0], max[1], max[2]
+        ),
+        format!(
+            r#"{{"bufferView":1,"componentType":{FLOAT},"count":{num_vertices},"type":"VEC3"}}"#
+        ),
+        format!(
+            r#"{{"bufferView":2,"componentType":{FLOAT},"count":{num_vertices},"type":"SCALAR"}}"#
+        ),
+    ];
+    let mut materials = Vec::new();
+    let mut primitives = Vec::new();
+    for (i, group) in mesh.material_groups.iter().enumerate() {
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{UNSIGNED_INT},"count":{},"type":"SCALAR"}}"#,
+            3 + i,
+            group.indices.len()
+        ));
+        materials.push(format!(r#"{{"name":"palette_{}"}}"#, group.palette_id));
+        primitives.push(format!(
+            r#"{{"attributes":{{"POSITION":0,"NORMAL":1,"_PALETTE_ID":2}},"indices":{},"material":{i}}}"#,
+            3 + i
+        ));
+    }
+
+    let mut json = String::new();
+    write!(
+        json,
+        r#"{{"asset":{{"version":"2.0","generator":"feldspar"}},"scene":0,"#
+    )
+    .unwrap();
+    if primitives.is_empty() {
+        // glTF doesn't allow empty accessors, so an empty mesh is just an empty node.
+        json.push_str(r#""scenes":[{"nodes":[0]}],"nodes":[{}]}"#);
+    } else {
+        write!(
+            json,
+            r#""scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+            primitives.join(","),
+            materials.join(","),
+            accessors.join(","),
+            buffer_views.join(","),
+            bin.len()
+        )
+        .unwrap();
+    }
+
+    // Chunks must be 4-byte aligned. JSON is padded with spaces, binary with zeros.
+    let mut json = json.into_bytes();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    while bin.len() % 4 != 0 {
+        bin.push(0);
+    }
+
+    let mut total_length = 12 + 8 + json.len();
+    if !primitives.is_empty() {
+        total_length += 8 + bin.len();
+    }
+    writer.write_all(GLB_MAGIC)?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(total_length as u32).to_le_bytes())?;
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(JSON_CHUNK_TYPE)?;
+    writer.write_all(&json)?;
+    if !primitives.is_empty() {
+        writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+        writer.write_all(BIN_CHUNK_TYPE)?;
+        writer.write_all(&bin)?;
+    }
+    writer.flush()
+}
+
+fn f32_bytes(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    values.flat_map(f32::to_le_bytes).collect()
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MaterialGroup;
+
+    use feldspar_map::core::glam::Vec3A;
+
+    fn chunk(bytes: &[u8], offset: usize) -> (&[u8], &[u8]) {
+        let length = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        (
+            &bytes[offset + 4..offset + 8],
+            &bytes[offset + 8..offset + 8 + length],
+        )
+    }
+
+    #[test]
+    fn write_one_triangle() {
+        let mesh = TerrainMesh {
+            positions: vec![Vec3A::ZERO, Vec3A::X, Vec3A::Y],
+            normals: vec![Vec3A::Z; 3],
+            palette_ids: vec![7; 3],
+            material_groups: vec![MaterialGroup {
+                palette_id: 7,
+                indices: vec![0, 1, 2],
+            }],
+        };
+        let mut bytes = Vec::new();
+        write_glb(&mesh, &mut bytes).unwrap();
+
+        assert_eq!(&bytes[0..4], GLB_MAGIC);
+        assert_eq!(
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            bytes.len()
+        );
+
+        let (json_type, json) = chunk(&bytes, 12);
+        assert_eq!(json_type, JSON_CHUNK_TYPE);
+        let json = std::str::from_utf8(json).unwrap();
+        assert!(json.contains(r#""name":"palette_7""#));
+        assert!(json.contains(r#""min":[0,0,0],"max":[1,1,0]"#));
+
+        let (bin_type, bin) = chunk(&bytes, 20 + json.len());
+        assert_eq!(bin_type, BIN_CHUNK_TYPE);
+        // 3 positions, 3 normals, 3 palette IDs, and 3 indices.
+        assert_eq!(bin.len(), 36 + 36 + 12 + 12);
+        assert_eq!(&bin[84..], [0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn write_empty_mesh() {
+        let mut bytes = Vec::new();
+        write_glb(&TerrainMesh::default(), &mut bytes).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            bytes.len()
+        );
+        assert_eq!(bytes.len() % 4, 0);
+    }
+}
@@ -0,0 +1,70 @@
+use super::TerrainMesh;
+
+use std::io::{self, Write};
+
+/// Writes `mesh` as a Wavefront OBJ file with positions and normals.
+///
+/// Each [`MaterialGroup`](super::MaterialGroup) starts with `usemtl palette_<ID>`. No material library is written, so tools
+/// can bind their own materials by name.
+pub fn write_obj(mesh: &TerrainMesh, writer: impl Write) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    writeln!(writer, "# feldspar terrain")?;
+    for p in mesh.positions.iter() {
+        writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+    for n in mesh.normals.iter() {
+        writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    for group in mesh.material_groups.iter() {
+        writeln!(writer, "usemtl palette_{}", group.palette_id)?;
+        for tri in group.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0] + 1, tri[1] + 1, tri[2] + 1];
+            writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+    }
+    writer.flush()
+}
+
+// ████████╗███████╗███████╗████████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝
+//    ██║   █████╗  ███████╗   ██║
+//    ██║   ██╔══╝  ╚════██║   ██║
+//    ██║   ███████╗███████║   ██║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MaterialGroup;
+
+    use feldspar_map::core::glam::Vec3A;
+
+    #[test]
+    fn write_one_triangle() {
+        let mesh = TerrainMesh {
+            positions: vec![Vec3A::ZERO, Vec3A::X, Vec3A::Y],
+            normals: vec![Vec3A::Z; 3],
+            palette_ids: vec![7; 3],
+            material_groups: vec![MaterialGroup {
+                palette_id: 7,
+                indices: vec![0, 1, 2],
+            }],
+        };
+        let mut bytes = Vec::new();
+        write_obj(&mesh, &mut bytes).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "\
+# feldspar terrain
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vn 0 0 1
+vn 0 0 1
+vn 0 0 1
+usemtl palette_7
+f 1//1 2//2 3//3
+"
+        );
+    }
+}
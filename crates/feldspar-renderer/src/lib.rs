@@ -5,6 +5,11 @@
 //! All geometry is generated by the [`fast-surface-nets`](https://github.com/bonsairobo/fast-surface-nets-rs) crate, which uses
 //! a dense, padded chunk of voxels to estimate mesh vertex positions and normals.
 //!
+//! # Export
+//!
+//! [`extract_terrain_mesh`] uses the same mesher on the CPU to produce a single watertight mesh for a region of the map, which
+//! can be written to OBJ or binary glTF files for offline tools.
+//!
 //! # Biplanar Texture Mapping (work in progress)
 //!
 //! Each voxel type can have a specific set of material textures. Rather than specifying texture UV coordinates as a mesh vertex
@@ -23,9 +28,11 @@
 //! vertices of adjacent levels of detail together in the vertex shader, based on the distance from the camera to the vertex.
 
 mod config;
+mod export;
 mod mesher;
 mod plugin;
 
 pub use config::*;
+pub use export::*;
 pub use mesher::*;
 pub use plugin::*;
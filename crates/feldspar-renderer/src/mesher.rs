@@ -1,7 +1,135 @@
-use feldspar_map::clipmap::ChunkClipMap;
+use feldspar_map::{
+    chunk::{
+        ChunkData, ChunkShape, PaddedChunkShape, CHUNK_SHAPE_IVEC3, PADDED_CHUNK_SHAPE_IVEC3,
+        PADDED_CHUNK_SIZE,
+    },
+    clipmap::ChunkClipMap,
+    coordinates::chunk_min,
+    core::glam::IVec3,
+    ndshape::ConstShape as _,
+    palette::PaletteId8,
+    sdf::SdfValue,
+    units::*,
+};
 
 use bevy::prelude::*;
+use fast_surface_nets::{
+    ndshape::{ConstShape as _, ConstShape3u32},
+    surface_nets, SurfaceNetsBuffer,
+};
 
 pub fn mesher_system(clipmap: Res<ChunkClipMap>) {
     todo!()
 }
+
+/// [`PaddedChunkShape`] for the version of `ndshape` that `fast-surface-nets` uses.
+type SurfaceNetsShape = ConstShape3u32<
+    { PaddedChunkShape::ARRAY[0] as u32 },
+    { PaddedChunkShape::ARRAY[1] as u32 },
+    { PaddedChunkShape::ARRAY[2] as u32 },
+>;
+
+/// The minimum corner of the padded extent around the chunk at `chunk_coords`.
+pub fn padded_chunk_min(chunk_coords: IVec3) -> IVec3 {
+    chunk_min(ChunkUnits(chunk_coords)).0 - IVec3::ONE
+}
+
+/// The voxels of a chunk plus one voxel of padding on every side, which is exactly the input that Surface Nets needs to mesh the
+/// chunk without gaps between it and its neighbors.
+pub struct PaddedChunk {
+    pub sdf: Vec<f32>,
+    pub palette_ids: Vec<PaletteId8>,
+}
+
+impl PaddedChunk {
+    /// Copies the voxels in the padded extent around the chunk at `chunk_coords`.
+    ///
+    /// `get_chunk` is called with the coordinates of each of the 27 chunks in the neighborhood. Missing chunks are ambient. Any
+    /// [`SdfValue`] precision works, since distances are converted to `f32` here.
+    pub fn copy_neighborhood<S: SdfValue>(
+        chunk_coords: IVec3,
+        mut get_chunk: impl FnMut(IVec3) -> Option<ChunkData<S, PaletteId8>>,
+    ) -> Self {
+        let mut sdf = vec![S::AMBIENT.into(); PADDED_CHUNK_SIZE];
+        let mut palette_ids = vec![0; PADDED_CHUNK_SIZE];
+
+        let padded_min = padded_chunk_min(chunk_coords);
+        let padded_max = padded_min + PADDED_CHUNK_SHAPE_IVEC3 - IVec3::ONE;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let neighbor_coords = chunk_coords + IVec3::new(dx, dy, dz);
+                    let neighbor = match get_chunk(neighbor_coords) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    let VoxelUnits(neighbor_min) = chunk_min(ChunkUnits(neighbor_coords));
+                    let overlap_min = neighbor_min.max(padded_min);
+                    let overlap_max =
+                        (neighbor_min + CHUNK_SHAPE_IVEC3 - IVec3::ONE).min(padded_max);
+                    for z in overlap_min.z..=overlap_max.z {
+                        for y in overlap_min.y..=overlap_max.y {
+                            for x in overlap_min.x..=overlap_max.x {
+                                let p = IVec3::new(x, y, z);
+                                let src =
+                                    ChunkShape::linearize((p - neighbor_min).to_array()) as usize;
+                                let dst = PaddedChunkShape::linearize((p - padded_min).to_array())
+                                    as usize;
+                                sdf[dst] = neighbor.sdf[src].into();
+                                palette_ids[dst] = neighbor.palette_ids[src];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Self { sdf, palette_ids }
+    }
+}
+
+/// Runs Surface Nets on `padded` and returns the palette ID of each vertex in `buffer`.
+///
+/// Positions in `buffer` are relative to the minimum of the padded extent, i.e. one voxel below the chunk's minimum. Normals
+/// are not normalized.
+///
+/// Each vertex lies in a cell of 8 voxels, and it takes the most common palette ID among the solid voxels of that cell.
+pub fn mesh_padded_chunk(padded: &PaddedChunk, buffer: &mut SurfaceNetsBuffer) -> Vec<PaletteId8> {
+    let max = (PADDED_CHUNK_SHAPE_IVEC3 - IVec3::ONE)
+        .to_array()
+        .map(|c| c as u32);
+    surface_nets(&padded.sdf, &SurfaceNetsShape {}, [0; 3], max, buffer);
+
+    buffer
+        .surface_points
+        .iter()
+        .map(|&[x, y, z]| {
+            let mut counts = [0u8; 8];
+            let mut ids = [0; 8];
+            let mut num_ids = 0;
+            for corner in 0..8 {
+                let p = [
+                    x + (corner & 1),
+                    y + ((corner >> 1) & 1),
+                    z + ((corner >> 2) & 1),
+                ];
+                let i = SurfaceNetsShape::linearize(p) as usize;
+                if padded.sdf[i] >= 0.0 {
+                    continue;
+                }
+                let id = padded.palette_ids[i];
+                match ids[..num_ids].iter().position(|&other| other == id) {
+                    Some(j) => counts[j] += 1,
+                    None => {
+                        ids[num_ids] = id;
+                        counts[num_ids] = 1;
+                        num_ids += 1;
+                    }
+                }
+            }
+            (0..num_ids)
+                .max_by_key(|&j| (counts[j], std::cmp::Reverse(ids[j])))
+                .map_or(0, |j| ids[j])
+        })
+        .collect()
+}
@@ -1,9 +1,27 @@
-use bevy::prelude::Plugin;
+use crate::RenderConfig;
 
-pub struct RenderPlugin;
+use bevy::pbr::wireframe::{WireframeConfig, WireframePlugin};
+use bevy::prelude::{Msaa, Plugin};
+
+#[derive(Default)]
+pub struct RenderPlugin {
+    config: RenderConfig,
+}
+
+impl RenderPlugin {
+    pub fn new(config: RenderConfig) -> Self {
+        Self { config }
+    }
+}
 
 impl Plugin for RenderPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        todo!();
+        app.insert_resource(self.config).insert_resource(Msaa {
+            samples: self.config.msaa.unwrap_or(1),
+        });
+        if self.config.wireframes {
+            app.add_plugin(WireframePlugin)
+                .insert_resource(WireframeConfig { global: true });
+        }
     }
 }